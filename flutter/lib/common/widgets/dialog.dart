@@ -1352,6 +1352,89 @@ void showRestartRemoteDevice(PeerInfo pi, String id, SessionID sessionId,
   if (res == true) bind.sessionRestartRemoteDevice(sessionId: sessionId);
 }
 
+void showClipboardHistoryDialog(FFI ffi) {
+  final sessionId = ffi.sessionId;
+  List<Map<String, dynamic>> load() {
+    try {
+      final entries =
+          jsonDecode(bind.sessionGetClipboardHistory(sessionId: sessionId));
+      if (entries is List) {
+        return entries.cast<Map<String, dynamic>>();
+      }
+    } catch (e) {
+      debugPrint('Failed to load the clipboard history: $e');
+    }
+    return [];
+  }
+
+  var entries = load();
+  ffi.dialogManager.show((setState, close, context) {
+    Widget buildEntry(Map<String, dynamic> entry) {
+      final int id = entry['id'];
+      final formats = (entry['formats'] as List).join(', ');
+      final String preview = entry['preview'];
+      final time = DateTime.fromMillisecondsSinceEpoch(entry['time']);
+      return ListTile(
+        contentPadding: EdgeInsets.zero,
+        title: Text(preview.isEmpty ? formats : preview,
+            maxLines: 2, overflow: TextOverflow.ellipsis),
+        subtitle: Text(
+            '${entry['peer_id']}  $formats  ${time.toString().split('.').first}'),
+        trailing: Row(mainAxisSize: MainAxisSize.min, children: [
+          if (isDesktop)
+            IconButton(
+              tooltip: translate('Copy to local'),
+              icon: Icon(Icons.copy),
+              onPressed: () => bind.sessionCopyClipboardHistory(
+                  sessionId: sessionId, id: id),
+            ),
+          IconButton(
+            tooltip: translate('Paste to remote'),
+            icon: Icon(Icons.paste),
+            onPressed: () {
+              bind.sessionPasteClipboardHistory(sessionId: sessionId, id: id);
+              close();
+            },
+          ),
+          IconButton(
+            tooltip: translate('Delete'),
+            icon: Icon(Icons.delete_outline),
+            onPressed: () async {
+              await bind.mainRemoveClipboardHistory(id: id);
+              setState(() => entries = load());
+            },
+          ),
+        ]),
+      );
+    }
+
+    return CustomAlertDialog(
+      title: Text(translate('Clipboard history')),
+      content: SizedBox(
+        width: 480,
+        height: 360,
+        child: entries.isEmpty
+            ? Center(child: Text(translate('Empty')))
+            : ListView(children: entries.map(buildEntry).toList()),
+      ),
+      actions: [
+        dialogButton(
+          'Clear',
+          onPressed: entries.isEmpty
+              ? null
+              : () async {
+                  await bind.mainClearClipboardHistory();
+                  setState(() => entries = load());
+                },
+          isOutline: true,
+        ),
+        dialogButton('Close', onPressed: close),
+      ],
+      onCancel: close,
+    );
+  });
+}
+
 showSetOSPassword(
   SessionID sessionId,
   bool login,
@@ -149,6 +149,15 @@ List<TTextMenu> toolbarControls(BuildContext context, String id, FFI ffi) {
           }
         }));
   }
+  // clipboard history
+  if (isDefaultConn &&
+      !isWeb &&
+      ffiModel.keyboard &&
+      perms['clipboard'] != false) {
+    v.add(TTextMenu(
+        child: Text(translate('Clipboard history')),
+        onPressed: () => showClipboardHistoryDialog(ffi)));
+  }
   // reset canvas
   if (isDefaultConn && isMobile) {
     v.add(TTextMenu(
@@ -668,6 +677,25 @@ Future<List<TToggleMenu>> toolbarDisplayToggle(
             : null,
         child: Text(translate('Disable clipboard'))));
   }
+  // manual clipboard sync
+  if (isDefaultConn &&
+      !isWeb &&
+      ffiModel.keyboard &&
+      perms['clipboard'] != false) {
+    final enabled = !ffiModel.viewOnly;
+    final value = bind.sessionGetToggleOptionSync(
+        sessionId: sessionId, arg: kOptionManualClipboardSync);
+    v.add(TToggleMenu(
+        value: value,
+        onChanged: enabled
+            ? (value) {
+                if (value == null) return;
+                bind.sessionToggleOption(
+                    sessionId: sessionId, value: kOptionManualClipboardSync);
+              }
+            : null,
+        child: Text(translate('Manual clipboard sync'))));
+  }
   // lock after session end
   if (isDefaultConn && ffiModel.keyboard && !ffiModel.isPeerAndroid) {
     final enabled = !ffiModel.viewOnly;
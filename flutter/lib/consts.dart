@@ -99,6 +99,9 @@ const String kOptionAllowAutoDisconnect = "allow-auto-disconnect";
 const String kOptionAutoDisconnectTimeout = "auto-disconnect-timeout";
 const String kOptionEnableHwcodec = "enable-hwcodec";
 const String kOptionAllowAutoRecordIncoming = "allow-auto-record-incoming";
+const String kOptionAllowRecordTerminal = "allow-record-terminal";
+const String kOptionAllowRecordTerminalInput = "allow-record-terminal-input";
+const String kOptionTerminalAuditInput = "terminal-audit-input";
 const String kOptionAllowAutoRecordOutgoing = "allow-auto-record-outgoing";
 const String kOptionVideoSaveDirectory = "video-save-directory";
 const String kOptionAccessMode = "access-mode";
@@ -110,8 +113,14 @@ const String kOptionEnableFileTransfer = "enable-file-transfer";
 const String kOptionEnableAudio = "enable-audio";
 const String kOptionEnableCamera = "enable-camera";
 const String kOptionEnableTerminal = "enable-terminal";
+const String kOptionAllowRemoteExec = "allow-remote-exec";
 const String kOptionTerminalPersistent = "terminal-persistent";
 const String kOptionEnableTunnel = "enable-tunnel";
+const String kOptionAllowDynamicTunnel = "allow-dynamic-tunnel";
+const String kOptionAllowReverseTunnel = "allow-reverse-tunnel";
+const String kOptionAllowReverseTunnelAnyAddress =
+    "allow-reverse-tunnel-any-address";
+const String kOptionAllowWolRelay = "allow-wol-relay";
 const String kOptionEnableRemoteRestart = "enable-remote-restart";
 const String kOptionEnableBlockInput = "enable-block-input";
 const String kOptionAllowRemoteConfigModification =
@@ -158,6 +167,7 @@ const String kOptionDirectxCapture = "enable-directx-capture";
 const String kOptionAllowRemoteCmModification = "allow-remote-cm-modification";
 const String kOptionEnableUdpPunch = "enable-udp-punch";
 const String kOptionEnableIpv6Punch = "enable-ipv6-punch";
+const String kOptionEnableFileTransferDelta = "enable-file-transfer-delta";
 const String kOptionEnableTrustedDevices = "enable-trusted-devices";
 const String kOptionShowVirtualMouse = "show-virtual-mouse";
 const String kOptionVirtualMouseScale = "virtual-mouse-scale";
@@ -296,6 +306,9 @@ const String kValuePrinterIncomingJobSelected = 'selected';
 const String kKeyPrinterSelected = 'printer-selected-name';
 const String kKeyPrinterSave = 'allow-printer-dialog-save';
 const String kKeyPrinterAllowAutoPrint = 'allow-printer-auto-print';
+// Peer option, 'Y' prints the jobs of the peer without asking.
+const String kOptionPrinterAutoAccept = 'printer-auto-accept';
+const String kOptionManualClipboardSync = 'manual-clipboard-sync';
 
 double kNewWindowOffset = isWindows
     ? 56.0
@@ -77,7 +77,7 @@ class DesktopSettingPage extends StatefulWidget {
     if (!isWeb && !bind.isIncomingOnly() && bind.pluginFeatureIsEnabled())
       SettingsTabKey.plugin,
     if (!bind.isDisableAccount()) SettingsTabKey.account,
-    if (isWindows &&
+    if ((isWindows || isLinux) &&
         bind.mainGetBuildinOption(key: kOptionHideRemotePrinterSetting) != 'Y')
       SettingsTabKey.printer,
     SettingsTabKey.about,
@@ -554,6 +554,12 @@ class _GeneralState extends State<_General> {
             kOptionEnableIpv6Punch,
             isServer: false,
           ),
+          _OptionCheckBox(
+            context,
+            'Enable delta file transfer',
+            kOptionEnableFileTransferDelta,
+            isServer: false,
+          ),
         ],
       ],
     ];
@@ -695,6 +701,15 @@ class _GeneralState extends State<_General> {
         if (!bind.isOutgoingOnly())
           _OptionCheckBox(context, 'Automatically record incoming sessions',
               kOptionAllowAutoRecordIncoming),
+        if (!bind.isOutgoingOnly())
+          _OptionCheckBox(context, 'Record incoming terminal sessions',
+              kOptionAllowRecordTerminal),
+        if (!bind.isOutgoingOnly())
+          _OptionCheckBox(context, 'Include terminal input in recordings',
+              kOptionAllowRecordTerminalInput),
+        if (!bind.isOutgoingOnly())
+          _OptionCheckBox(context, 'Log commands entered in terminals',
+              kOptionTerminalAuditInput),
         if (!bind.isIncomingOnly())
           _OptionCheckBox(context, 'Automatically record outgoing sessions',
               kOptionAllowAutoRecordOutgoing,
@@ -1041,9 +1056,24 @@ class _SafetyState extends State<_Safety> with AutomaticKeepAliveClientMixin {
                 enabled: enabled, fakeValue: fakeValue),
             _OptionCheckBox(context, 'Enable terminal', kOptionEnableTerminal,
                 enabled: enabled, fakeValue: fakeValue),
+            _OptionCheckBox(context, 'Enable remote command execution',
+                kOptionAllowRemoteExec,
+                enabled: enabled, fakeValue: fakeValue),
             _OptionCheckBox(
                 context, 'Enable TCP tunneling', kOptionEnableTunnel,
                 enabled: enabled, fakeValue: fakeValue),
+            _OptionCheckBox(context, 'Enable dynamic TCP tunneling (SOCKS5)',
+                kOptionAllowDynamicTunnel,
+                enabled: enabled, fakeValue: fakeValue),
+            _OptionCheckBox(context, 'Enable reverse TCP tunneling',
+                kOptionAllowReverseTunnel,
+                enabled: enabled, fakeValue: fakeValue),
+            _OptionCheckBox(context, 'Allow reverse TCP tunneling on any address',
+                kOptionAllowReverseTunnelAnyAddress,
+                enabled: enabled, fakeValue: fakeValue),
+            _OptionCheckBox(
+                context, 'Enable Wake-on-LAN relay', kOptionAllowWolRelay,
+                enabled: enabled, fakeValue: fakeValue),
             _OptionCheckBox(
                 context, 'Enable remote restart', kOptionEnableRemoteRestart,
                 enabled: enabled, fakeValue: fakeValue),
@@ -2152,7 +2182,8 @@ class __PrinterState extends State<_Printer> {
     final scrollController = ScrollController();
     return ListView(controller: scrollController, children: [
       outgoing(context),
-      incoming(context),
+      // Only Windows prints the jobs of the peers.
+      if (isWindows) incoming(context),
     ]).marginOnly(bottom: _kListViewBottomMargin);
   }
 
@@ -553,11 +553,13 @@ class FfiModel with ChangeNotifier {
       Map<String, dynamic> evt, SessionID sessionId, String peerId) {
     final id = evt['id'];
     final path = evt['path'];
+    final jobOptions = _printJobOptions(evt);
     final dialogManager = parent.target!.dialogManager;
     dialogManager.show((setState, close, context) {
       PrinterOptions printerOptions = PrinterOptions.load();
       final saveSettings = mainGetLocalBoolOptionSync(kKeyPrinterSave).obs;
       final dontShowAgain = false.obs;
+      final autoAcceptPeer = false.obs;
       final Rx<String> selectedPrinterName = printerOptions.printerName.obs;
       final printerNames = printerOptions.printerNames;
       final defaultOrSelectedGroupValue =
@@ -586,10 +588,17 @@ class FfiModel with ChangeNotifier {
         if (dontShowAgain.value) {
           mainSetLocalBoolOption(kKeyPrinterAllowAutoPrint, true);
         }
+        if (autoAcceptPeer.value) {
+          bind.sessionPeerOption(
+              sessionId: sessionId,
+              name: kOptionPrinterAutoAccept,
+              value: 'Y');
+        }
         close();
       }
 
       onCancel() {
+        bind.sessionPrinterDismiss(sessionId: sessionId, id: id);
         if (dontShowAgain.value) {
           bind.mainSetLocalOption(
               key: kKeyPrinterIncomingJobAction,
@@ -604,6 +613,15 @@ class FfiModel with ChangeNotifier {
       final content = Column(
         children: [
           Text(translate('print-incoming-job-confirm-tip')),
+          if (jobOptions.isNotEmpty)
+            Padding(
+              padding: const EdgeInsets.symmetric(vertical: 8.0),
+              child: Align(
+                alignment: Alignment.centerLeft,
+                child: SelectableText(jobOptions.join('\n'),
+                    style: TextStyle(fontSize: 13)),
+              ),
+            ),
           Row(
             children: [
               Obx(() => Radio<String>(
@@ -706,6 +724,22 @@ class FfiModel with ChangeNotifier {
                   }),
             ],
           ),
+          Row(
+            children: [
+              Obx(() => Checkbox(
+                  value: autoAcceptPeer.value,
+                  onChanged: (value) {
+                    if (value != null) {
+                      autoAcceptPeer.value = value;
+                    }
+                  })),
+              GestureDetector(
+                  child: Text(translate('print-auto-accept-peer-tip')),
+                  onTap: () {
+                    autoAcceptPeer.value = !autoAcceptPeer.value;
+                  }),
+            ],
+          ),
         ],
       );
       return CustomAlertDialog(
@@ -721,6 +755,33 @@ class FfiModel with ChangeNotifier {
     });
   }
 
+  // The options of a print job shown before it is accepted, unknown ones are skipped.
+  List<String> _printJobOptions(Map<String, dynamic> evt) {
+    final options = <String>[];
+    final String title = evt['title'] ?? '';
+    if (title.isNotEmpty) options.add(title);
+    final int pages = evt['pages'] ?? 0;
+    if (pages > 0) options.add('${translate('Pages')}: $pages');
+    final String paperSize = evt['paper_size'] ?? '';
+    if (paperSize.isNotEmpty) {
+      options.add('${translate('Paper size')}: $paperSize');
+    }
+    final String colorMode = evt['color_mode'] ?? '';
+    if (colorMode.isNotEmpty) {
+      final mode = colorMode == 'monochrome' ? 'Monochrome' : 'Color';
+      options.add('${translate('Color mode')}: ${translate(mode)}');
+    }
+    final int copies = evt['copies'] ?? 0;
+    if (copies > 1) options.add('${translate('Copies')}: $copies');
+    final String format = evt['format'] ?? '';
+    if (format.isNotEmpty) {
+      final int size = evt['size'] ?? 0;
+      options.add(
+          '${translate('Format')}: $format, ${readableFileSize(size.toDouble())}');
+    }
+    return options;
+  }
+
   _handleUseTextureRender(
       Map<String, dynamic> evt, SessionID sessionId, String peerId) {
     parent.target?.imageModel.setUseTextureRender(evt['v'] == 'Y');
@@ -210,6 +210,29 @@ class RustdeskImpl {
         ]));
   }
 
+  String sessionGetClipboardHistory(
+      {required UuidValue sessionId, dynamic hint}) {
+    return '';
+  }
+
+  Future<void> sessionPasteClipboardHistory(
+      {required UuidValue sessionId, required int id, dynamic hint}) {
+    throw UnimplementedError("sessionPasteClipboardHistory");
+  }
+
+  Future<void> sessionCopyClipboardHistory(
+      {required UuidValue sessionId, required int id, dynamic hint}) {
+    throw UnimplementedError("sessionCopyClipboardHistory");
+  }
+
+  Future<void> mainRemoveClipboardHistory({required int id, dynamic hint}) {
+    throw UnimplementedError("mainRemoveClipboardHistory");
+  }
+
+  Future<void> mainClearClipboardHistory({dynamic hint}) {
+    throw UnimplementedError("mainClearClipboardHistory");
+  }
+
   Future<String?> sessionGetFlutterOption(
       {required UuidValue sessionId, required String k, dynamic hint}) {
     return Future(
@@ -1897,6 +1920,11 @@ class RustdeskImpl {
     throw UnimplementedError("sessionPrinterResponse");
   }
 
+  Future<void> sessionPrinterDismiss(
+      {required UuidValue sessionId, required int id, dynamic hint}) {
+    throw UnimplementedError("sessionPrinterDismiss");
+  }
+
   Future<String> mainGetCommon({required String key, dynamic hint}) {
     throw UnimplementedError("mainGetCommon");
   }
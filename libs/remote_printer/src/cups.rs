@@ -0,0 +1,299 @@
+//! The remote printer on Linux, a CUPS queue printing to a backend of the application.
+//!
+//! CUPS converts every job to PDF for the queue, then the backend leaves the PDF in the spool
+//! directory, where the service picks it up and sends it to the controlling side.
+//!
+//! The backend is a shell script run by CUPS as the `lp` user, it writes the job to a hidden
+//! temporary file and renames it, so only complete jobs are seen in the spool. The title, the
+//! copies and the options of the job are left next to it, copies are not made by CUPS.
+//!
+//! The spool is owned by `lp` and its group is the one of the user the service runs as,
+//! see [`grant_spool`], the jobs inherit the group and are readable by it.
+
+use hbb_common::{bail, log, ResultType};
+use std::{
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+pub const SPOOL_DIR: &str = "/var/spool/rustdesk-printer";
+const BACKEND_DIRS: [&str; 2] = ["/usr/lib/cups/backend", "/usr/libexec/cups/backend"];
+const JOB_EXT: &str = "pdf";
+const OPTIONS_EXT: &str = "opts";
+
+// CUPS filters everything to PDF and passes it to the backend unchanged.
+const PPD: &str = r#"*PPD-Adobe: "4.3"
+*FormatVersion: "4.3"
+*FileVersion: "1.0"
+*LanguageVersion: English
+*LanguageEncoding: ISOLatin1
+*PCFileName: "RDPRINT.PPD"
+*Manufacturer: "{app}"
+*Product: "({app} Printer)"
+*ModelName: "{app} Printer"
+*ShortNickName: "{app} Printer"
+*NickName: "{app} Printer, PDF"
+*PSVersion: "(3010.000) 0"
+*LanguageLevel: "3"
+*ColorDevice: True
+*DefaultColorSpace: RGB
+*FileSystem: False
+*Throughput: "1"
+*LandscapeOrientation: Plus90
+*TTRasterizer: Type42
+*cupsVersion: 2.2
+*cupsLanguages: "en"
+*cupsFilter2: "application/pdf application/vnd.cups-pdf 0 -"
+*cupsManualCopies: False
+*OpenUI *ColorModel/Color Mode: PickOne
+*OrderDependency: 10 AnySetup *ColorModel
+*DefaultColorModel: RGB
+*ColorModel RGB/Color: ""
+*ColorModel Gray/Grayscale: ""
+*CloseUI: *ColorModel
+*OpenUI *PageSize/Page Size: PickOne
+*OrderDependency: 10 AnySetup *PageSize
+*DefaultPageSize: Letter
+*PageSize Letter/US Letter: "<</PageSize[612 792]/ImagingBBox null>>setpagedevice"
+*PageSize A4/A4: "<</PageSize[595 842]/ImagingBBox null>>setpagedevice"
+*CloseUI: *PageSize
+*OpenUI *PageRegion: PickOne
+*OrderDependency: 10 AnySetup *PageRegion
+*DefaultPageRegion: Letter
+*PageRegion Letter/US Letter: "<</PageSize[612 792]/ImagingBBox null>>setpagedevice"
+*PageRegion A4/A4: "<</PageSize[595 842]/ImagingBBox null>>setpagedevice"
+*CloseUI: *PageRegion
+*DefaultImageableArea: Letter
+*ImageableArea Letter/US Letter: "0 0 612 792"
+*ImageableArea A4/A4: "0 0 595 842"
+*DefaultPaperDimension: Letter
+*PaperDimension Letter/US Letter: "612 792"
+*PaperDimension A4/A4: "595 842"
+"#;
+
+const BACKEND: &str = r#"#!/bin/sh
+# CUPS backend of {app}, the job is left in the spool directory for the {app} service.
+SPOOL='{spool}'
+if [ $# -eq 0 ]; then
+    echo 'direct {backend} "Unknown" "{app} Printer"'
+    exit 0
+fi
+if [ $# -lt 5 ]; then
+    echo "Usage: {backend} job-id user title copies options [file]" >&2
+    exit 1
+fi
+# Readable by the group of the spool, which is the one of the user the service runs as.
+umask 027
+# The options first, the job is picked up as soon as it is renamed.
+OPTS="$SPOOL/.$1.{opts}.tmp"
+printf '%s\n%s\n%s\n' "$3" "$4" "$5" > "$OPTS" && mv "$OPTS" "$SPOOL/$1.{opts}"
+TMP="$SPOOL/.$1.tmp"
+if [ -n "$6" ]; then
+    cat "$6" > "$TMP" || exit 1
+else
+    cat > "$TMP" || exit 1
+fi
+mv "$TMP" "$SPOOL/$1.{ext}" || exit 1
+exit 0
+"#;
+
+/// The name of the CUPS queue, without spaces which CUPS does not accept.
+fn get_printer_name(app_name: &str) -> String {
+    format!("{}_Printer", app_name.replace(' ', "_"))
+}
+
+fn get_backend_name(app_name: &str) -> String {
+    app_name.to_lowercase().replace(' ', "-")
+}
+
+fn get_backend_dir() -> ResultType<&'static str> {
+    match BACKEND_DIRS.iter().find(|d| Path::new(d).is_dir()) {
+        Some(dir) => Ok(dir),
+        None => bail!("CUPS is not installed, no backend directory is found"),
+    }
+}
+
+fn run(cmd: &str, args: &[&str]) -> ResultType<String> {
+    let output = match Command::new(cmd).args(args).output() {
+        Ok(output) => output,
+        Err(e) => bail!("Failed to run {}: {}", cmd, e),
+    };
+    if !output.status.success() {
+        bail!(
+            "{} failed: {}",
+            cmd,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+pub fn is_rd_printer_installed(app_name: &str) -> ResultType<bool> {
+    let printer_name = get_printer_name(app_name);
+    // `lpstat -p` fails if the printer does not exist.
+    Ok(Command::new("lpstat")
+        .args(["-p", &printer_name])
+        .output()
+        .map(|o| o.status.success())
+        .unwrap_or(false)
+        && Path::new(SPOOL_DIR).is_dir())
+}
+
+// Must be run as root.
+// Steps:
+// 1. Create the spool directory, owned by the `lp` user the backend runs as,
+//    new files inherit the group of the directory.
+// 2. Install the backend.
+// 3. Add or replace the queue with the PDF ppd.
+pub fn install_update_printer(app_name: &str) -> ResultType<()> {
+    let backend_name = get_backend_name(app_name);
+    let backend_path = PathBuf::from(get_backend_dir()?).join(&backend_name);
+
+    fs::create_dir_all(SPOOL_DIR)?;
+    run("chown", &["lp:lp", SPOOL_DIR])?;
+    run("chmod", &["2770", SPOOL_DIR])?;
+
+    let backend = BACKEND
+        .replace("{app}", app_name)
+        .replace("{backend}", &backend_name)
+        .replace("{spool}", SPOOL_DIR)
+        .replace("{ext}", JOB_EXT)
+        .replace("{opts}", OPTIONS_EXT);
+    fs::write(&backend_path, backend)?;
+    // CUPS runs backends which are not executable by others as root, 0755 runs it as `lp`.
+    run("chmod", &["0755", &backend_path.to_string_lossy()])?;
+
+    // Created exclusively with a random name and only readable by us, removed when dropped.
+    let mut ppd = tempfile::Builder::new()
+        .prefix(&backend_name)
+        .suffix(".ppd")
+        .tempfile()?;
+    ppd.write_all(PPD.replace("{app}", app_name).as_bytes())?;
+    ppd.flush()?;
+    run(
+        "lpadmin",
+        &[
+            "-p",
+            &get_printer_name(app_name),
+            "-E",
+            "-v",
+            &format!("{}:/", backend_name),
+            "-P",
+            &ppd.path().to_string_lossy(),
+            "-D",
+            &format!("{} Printer", app_name),
+            "-o",
+            "printer-is-shared=false",
+        ],
+    )?;
+    log::info!("Installed the CUPS printer of {}", app_name);
+    Ok(())
+}
+
+/// Give the primary group of `user` access to the spool, the service taking the jobs runs as it.
+/// Jobs spooled before keep the group of the previous user and are dropped unread.
+///
+/// Must be run as root.
+pub fn grant_spool(user: &str) -> ResultType<()> {
+    if !Path::new(SPOOL_DIR).is_dir() {
+        return Ok(());
+    }
+    let gid = run("id", &["-g", user])?;
+    run("chgrp", &[gid.trim(), SPOOL_DIR])?;
+    Ok(())
+}
+
+pub fn uninstall_printer(app_name: &str) {
+    if let Err(e) = run("lpadmin", &["-x", &get_printer_name(app_name)]) {
+        log::warn!("Failed to delete the CUPS printer: {}", e);
+    }
+    if let Ok(dir) = get_backend_dir() {
+        fs::remove_file(Path::new(dir).join(get_backend_name(app_name))).ok();
+    }
+    fs::remove_dir_all(SPOOL_DIR).ok();
+}
+
+/// A job taken out of the spool, the options are empty if the backend didn't leave them.
+#[derive(Debug, Default, PartialEq)]
+pub struct SpooledJob {
+    pub data: Vec<u8>,
+    pub title: String,
+    pub copies: String,
+    // e.g. "media=A4 ColorModel=Gray"
+    pub options: String,
+}
+
+/// The directory the backend leaves the jobs in.
+pub struct Spool {
+    dir: PathBuf,
+}
+
+impl Default for Spool {
+    fn default() -> Self {
+        Self::new(SPOOL_DIR)
+    }
+}
+
+impl Spool {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    pub fn exists(&self) -> bool {
+        self.dir.is_dir()
+    }
+
+    /// Take the oldest complete job out of the spool.
+    pub fn take_job(&self) -> ResultType<Option<SpooledJob>> {
+        let mut jobs: Vec<_> = fs::read_dir(&self.dir)?
+            .flatten()
+            .filter(|e| {
+                let name = e.file_name().to_string_lossy().to_string();
+                !name.starts_with('.') && name.ends_with(&format!(".{}", JOB_EXT))
+            })
+            .filter_map(|e| Some((e.metadata().ok()?.modified().ok()?, e.path())))
+            .collect();
+        jobs.sort();
+        let Some((_, path)) = jobs.into_iter().next() else {
+            return Ok(None);
+        };
+        let data = fs::read(&path);
+        // A job which can't be read is dropped, or it would block the spool.
+        fs::remove_file(&path)?;
+        let options_path = path.with_extension(OPTIONS_EXT);
+        let options = fs::read_to_string(&options_path).unwrap_or_default();
+        fs::remove_file(&options_path).ok();
+        let mut lines = options.lines().map(|l| l.to_owned());
+        Ok(Some(SpooledJob {
+            data: data?,
+            title: lines.next().unwrap_or_default(),
+            copies: lines.next().unwrap_or_default(),
+            options: lines.next().unwrap_or_default(),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_spool() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let spool = Spool::new(dir);
+        assert_eq!(spool.take_job().unwrap(), None);
+        fs::write(dir.join(".2.tmp"), b"incomplete").unwrap();
+        fs::write(dir.join("1.pdf"), b"%PDF-1").unwrap();
+        fs::write(dir.join("1.opts"), "Report\n2\nmedia=A4\n").unwrap();
+        let job = spool.take_job().unwrap().unwrap();
+        assert_eq!(job.data, b"%PDF-1");
+        assert_eq!(job.title, "Report");
+        assert_eq!(job.copies, "2");
+        assert_eq!(job.options, "media=A4");
+        assert!(!dir.join("1.opts").exists());
+        assert_eq!(spool.take_job().unwrap(), None);
+    }
+}
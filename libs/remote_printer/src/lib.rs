@@ -6,6 +6,14 @@ pub use setup::{
     setup::{install_update_printer, uninstall_printer},
 };
 
+#[cfg(target_os = "linux")]
+mod cups;
+#[cfg(target_os = "linux")]
+pub use cups::{
+    grant_spool, install_update_printer, is_rd_printer_installed, uninstall_printer, Spool,
+    SpooledJob,
+};
+
 #[cfg(target_os = "windows")]
 const RD_DRIVER_INF_PATH: &str = "drivers/RustDeskPrinterDriver/RustDeskPrinterDriver.inf";
 
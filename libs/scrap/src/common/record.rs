@@ -2,23 +2,47 @@ use crate::CodecFormat;
 #[cfg(feature = "hwcodec")]
 use hbb_common::anyhow::anyhow;
 use hbb_common::{
-    bail, chrono, log,
-    message_proto::{message, video_frame, EncodedVideoFrame, Message},
-    ResultType,
+    bail, chrono, get_time, log,
+    message_proto::{message, video_frame, AudioFrame, EncodedVideoFrame, Message},
+    serde_json, ResultType,
 };
 #[cfg(feature = "hwcodec")]
 use hwcodec::mux::{MuxContext, Muxer};
+use serde::{Deserialize, Serialize};
 use std::{
+    collections::HashMap,
     fs::{File, OpenOptions},
-    io,
+    io::{self, Seek, Write},
     ops::{Deref, DerefMut},
-    path::PathBuf,
-    sync::mpsc::Sender,
-    time::Instant,
+    path::{Path, PathBuf},
+    sync::{mpsc::Sender, Arc, Mutex},
+    time::{Duration, Instant},
 };
-use webm::mux::{self, Segment, Track, VideoTrack, Writer};
+use webm::mux::{self, AudioTrack, Segment, Track, VideoTrack, Writer};
+
+pub mod encrypt;
+pub mod retention;
 
 const MIN_SECS: u64 = 1;
+// Opus is always decoded at 48 kHz, whatever the sample rate of the encoder.
+// Mono packets are decoded to stereo, so one track fits the audio of all peers.
+const OPUS_SAMPLE_RATE: u32 = 48000;
+const OPUS_CHANNELS: u8 = 2;
+const MAX_AUDIO_DRIFT: Duration = Duration::from_millis(200);
+const MANIFEST_VERSION: u32 = 1;
+// A new recorder of the same display within this time continues the recording,
+// e.g. after the video service restarts.
+const MANIFEST_CONTINUE_TIMEOUT: Duration = Duration::from_secs(30);
+// How long an event is shown, the same event within it is written once.
+const EVENT_DURATION: i64 = 2000;
+// The encrypted events are rewritten as a whole, at most once in it.
+const ENCRYPTED_EVENTS_INTERVAL: Duration = Duration::from_secs(10);
+// The audio-only WebM next to a segment whose container has no audio track.
+const AUDIO_SIDECAR_EXTENSION: &str = "weba";
+
+lazy_static::lazy_static! {
+    static ref MANIFESTS: Mutex<HashMap<String, Arc<Mutex<ManifestState>>>> = Default::default();
+}
 
 #[derive(Debug, Clone)]
 pub struct RecorderContext {
@@ -36,6 +60,8 @@ pub struct RecorderContext2 {
     pub width: usize,
     pub height: usize,
     pub format: CodecFormat,
+    // Encrypt to this key if configured, the file name ends with `.enc` then.
+    pub public_key: Option<encrypt::PublicKey>,
 }
 
 impl RecorderContext2 {
@@ -43,7 +69,8 @@ impl RecorderContext2 {
         if !PathBuf::from(&ctx.dir).exists() {
             std::fs::create_dir_all(&ctx.dir)?;
         }
-        let file = if ctx.server { "incoming" } else { "outgoing" }.to_string()
+        self.public_key = encrypt::configured_public_key()?;
+        let mut file = if ctx.server { "incoming" } else { "outgoing" }.to_string()
             + "_"
             + &ctx.id.clone()
             + &chrono::Local::now().format("_%Y%m%d%H%M%S%3f_").to_string()
@@ -61,6 +88,9 @@ impl RecorderContext2 {
             } else {
                 ".mp4"
             };
+        if self.public_key.is_some() {
+            file = file + "." + encrypt::EXTENSION;
+        }
         self.filename = PathBuf::from(&ctx.dir)
             .join(file)
             .to_string_lossy()
@@ -69,6 +99,249 @@ impl RecorderContext2 {
     }
 }
 
+/// One recording of a display, which is split into segments whenever the resolution or the codec changes.
+/// It is written next to the segments, `rustdesk --merge-recording` joins them into one file.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub version: u32,
+    pub id: String,
+    pub server: bool,
+    pub display: usize,
+    pub camera: bool,
+    // Milliseconds since the epoch
+    pub start: i64,
+    pub segments: Vec<ManifestSegment>,
+    // The WebVTT file of the session events, in the directory of the manifest
+    #[serde(default)]
+    pub events: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ManifestSegment {
+    // The file name, in the directory of the manifest
+    pub file: String,
+    // Milliseconds since the start of the recording
+    pub start: i64,
+    pub duration: i64,
+    pub width: usize,
+    pub height: usize,
+    pub format: String,
+    pub audio: bool,
+    // The file of the audio if it is not in the segment, e.g. of the MP4 of the hardware codecs
+    #[serde(default)]
+    pub audio_file: String,
+}
+
+impl Manifest {
+    pub fn load(path: &Path) -> ResultType<Self> {
+        Ok(serde_json::from_slice(&std::fs::read(path)?)?)
+    }
+
+    /// The manifest of a segment, `incoming_<id>_<time>_display0_vp9.webm` is in `incoming_<id>_<time>_display0.json`.
+    pub fn path_of(segment: &str) -> PathBuf {
+        let path = Path::new(segment);
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+        let name = stem.rsplit_once('_').map(|(name, _)| name).unwrap_or(&stem);
+        path.with_file_name(format!("{}.json", name))
+    }
+}
+
+/// The audio of a segment which is written next to it, `<segment>.weba`.
+fn audio_sidecar_of(segment: &str) -> String {
+    Path::new(segment)
+        .with_extension(AUDIO_SIDECAR_EXTENSION)
+        .to_string_lossy()
+        .to_string()
+}
+
+struct ManifestState {
+    // Ends with `.enc` if encrypted
+    path: PathBuf,
+    manifest: Manifest,
+    updated: Instant,
+    // The time and the text of the last event
+    last_event: Option<(i64, String)>,
+    // The manifest and the events are encrypted to the key of the recording if it has one.
+    // The encrypted events can't be appended, they are kept to rewrite them.
+    public_key: Option<encrypt::PublicKey>,
+    events: Vec<u8>,
+    events_stored: Option<Instant>,
+}
+
+impl ManifestState {
+    fn open(ctx: &RecorderContext, ctx2: &RecorderContext2) -> Arc<Mutex<Self>> {
+        let key = format!(
+            "{}_{}_{}_{}_{}",
+            ctx.server, ctx.id, ctx.display_idx, ctx.camera, ctx.dir
+        );
+        let mut manifests = MANIFESTS.lock().unwrap();
+        manifests.retain(|_, s| {
+            Arc::strong_count(s) > 1
+                || s.lock().unwrap().updated.elapsed() < MANIFEST_CONTINUE_TIMEOUT
+        });
+        manifests
+            .entry(key)
+            .or_insert_with(|| {
+                let mut path = Manifest::path_of(&ctx2.filename);
+                if ctx2.public_key.is_some() {
+                    path = encrypt::path_of(&path);
+                }
+                Arc::new(Mutex::new(Self {
+                    path,
+                    manifest: Manifest {
+                        version: MANIFEST_VERSION,
+                        id: ctx.id.clone(),
+                        server: ctx.server,
+                        display: ctx.display_idx,
+                        camera: ctx.camera,
+                        start: get_time(),
+                        segments: vec![],
+                        events: Default::default(),
+                    },
+                    updated: Instant::now(),
+                    last_event: None,
+                    public_key: ctx2.public_key.as_ref().cloned(),
+                    events: vec![],
+                    events_stored: None,
+                }))
+            })
+            .clone()
+    }
+
+    fn store(&mut self) {
+        self.updated = Instant::now();
+        let res = if self.manifest.segments.is_empty() {
+            if !self.manifest.events.is_empty() {
+                std::fs::remove_file(self.path.with_file_name(&self.manifest.events)).ok();
+                self.manifest.events.clear();
+                self.events.clear();
+            }
+            std::fs::remove_file(&self.path).or_else(|e| {
+                if e.kind() == io::ErrorKind::NotFound {
+                    Ok(())
+                } else {
+                    Err(e)
+                }
+            })
+        } else {
+            self.store_events();
+            serde_json::to_vec_pretty(&self.manifest)
+                .map_err(|e| e.into())
+                .and_then(|data| match &self.public_key {
+                    Some(pk) => encrypt::write_file(&self.path, &data, pk),
+                    None => std::fs::write(&self.path, data),
+                })
+        };
+        if let Err(e) = res {
+            log::error!(
+                "Failed to store the recording manifest {:?}: {}",
+                self.path,
+                e
+            );
+        }
+    }
+
+    fn add_event(&mut self, text: &str) {
+        let time = get_time() - self.manifest.start;
+        if let Some((last, last_text)) = &self.last_event {
+            if last_text == text && time - last < EVENT_DURATION {
+                return;
+            }
+        }
+        self.last_event = Some((time, text.to_owned()));
+        let new = self.manifest.events.is_empty();
+        let path = self.events_path();
+        if self.public_key.is_some() {
+            if new {
+                self.events = b"WEBVTT\n\n".to_vec();
+            }
+            self.events
+                .extend_from_slice(event_cue(time, text).as_bytes());
+            if !new
+                && self
+                    .events_stored
+                    .is_some_and(|t| t.elapsed() < ENCRYPTED_EVENTS_INTERVAL)
+            {
+                return;
+            }
+        } else {
+            let res = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&path)
+                .and_then(|mut file| {
+                    if new {
+                        file.write_all(b"WEBVTT\n\n")?;
+                    }
+                    file.write_all(event_cue(time, text).as_bytes())
+                });
+            if let Err(e) = res {
+                log::error!("Failed to write the recording event to {:?}: {}", path, e);
+                return;
+            }
+        }
+        if new {
+            self.manifest.events = path
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .to_string();
+            self.store();
+        } else {
+            self.store_events();
+        }
+    }
+
+    // `.vtt` next to the manifest, with `.enc` if encrypted.
+    fn events_path(&self) -> PathBuf {
+        match self.public_key {
+            Some(_) => encrypt::path_of(&self.path.with_extension("").with_extension("vtt")),
+            None => self.path.with_extension("vtt"),
+        }
+    }
+
+    // Rewrite the encrypted events, the plain ones are written as they come.
+    fn store_events(&mut self) {
+        let Some(pk) = &self.public_key else {
+            return;
+        };
+        if self.events.is_empty() {
+            return;
+        }
+        let path = self.events_path();
+        if let Err(e) = encrypt::write_file(&path, &self.events, pk) {
+            log::error!("Failed to write the recording events to {:?}: {}", path, e);
+        }
+        self.events_stored = Some(Instant::now());
+    }
+}
+
+fn event_cue(time: i64, text: &str) -> String {
+    let timestamp = |ms: i64| {
+        format!(
+            "{:02}:{:02}:{:02}.{:03}",
+            ms / 3_600_000,
+            ms / 60_000 % 60,
+            ms / 1000 % 60,
+            ms % 1000
+        )
+    };
+    // A cue ends at an empty line, and its text must not contain "-->".
+    let text = text
+        .split(['\r', '\n'])
+        .filter(|l| !l.trim().is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+        .replace("-->", "->");
+    let time = time.max(0);
+    format!(
+        "{} --> {}\n{}\n\n",
+        timestamp(time),
+        timestamp(time + EVENT_DURATION),
+        text
+    )
+}
+
 unsafe impl Send for Recorder {}
 unsafe impl Sync for Recorder {}
 
@@ -77,6 +350,10 @@ pub trait RecorderApi {
     where
         Self: Sized;
     fn write_video(&mut self, frame: &EncodedVideoFrame) -> bool;
+    // `pts` in nanoseconds, on the same clock as the video.
+    fn write_audio(&mut self, _data: &[u8], _pts: u64) -> bool {
+        false
+    }
 }
 
 #[derive(Debug)]
@@ -92,7 +369,13 @@ pub struct Recorder {
     ctx: RecorderContext,
     ctx2: Option<RecorderContext2>,
     pts: Option<i64>,
+    audio: AudioClock,
     check_failed: bool,
+    manifest: Option<Arc<Mutex<ManifestState>>>,
+    // The file name of the current segment, and if audio is written to it
+    segment: Option<(String, bool)>,
+    segment_start: Instant,
+    max_segment_length: Option<Duration>,
 }
 
 impl Deref for Recorder {
@@ -111,12 +394,18 @@ impl DerefMut for Recorder {
 
 impl Recorder {
     pub fn new(ctx: RecorderContext) -> ResultType<Self> {
+        retention::start(&ctx.dir);
         Ok(Self {
             inner: None,
             ctx,
             ctx2: None,
             pts: None,
+            audio: Default::default(),
             check_failed: false,
+            manifest: None,
+            segment: None,
+            segment_start: Instant::now(),
+            max_segment_length: retention::Policy::load().max_segment_length,
         })
     }
 
@@ -129,6 +418,7 @@ impl Recorder {
                         height: h,
                         format,
                         filename: Default::default(),
+                        public_key: None,
                     };
                     ctx2.set_filename(&self.ctx)?;
                     self.ctx2 = Some(ctx2);
@@ -141,6 +431,7 @@ impl Recorder {
                     height: h,
                     format,
                     filename: Default::default(),
+                    public_key: None,
                 };
                 ctx2.set_filename(&self.ctx)?;
                 self.ctx2 = Some(ctx2);
@@ -151,6 +442,7 @@ impl Recorder {
             bail!("ctx2 is None");
         };
         if self.inner.is_none() {
+            self.end_segment();
             self.inner = match format {
                 CodecFormat::VP8 | CodecFormat::VP9 | CodecFormat::AV1 => Some(Box::new(
                     WebmRecorder::new(self.ctx.clone(), (*ctx2).clone())?,
@@ -165,11 +457,66 @@ impl Recorder {
             };
             // pts is None when new inner is created
             self.pts = None;
+            self.audio = Default::default();
             self.send_state(RecordState::NewFile(ctx2.filename.clone()));
+            let ctx2 = ctx2.clone();
+            self.begin_segment(&ctx2);
         }
         Ok(())
     }
 
+    fn begin_segment(&mut self, ctx2: &RecorderContext2) {
+        if self.manifest.is_none() {
+            self.manifest = Some(ManifestState::open(&self.ctx, ctx2));
+        }
+        let Some(manifest) = &self.manifest else {
+            return;
+        };
+        let file = Path::new(&ctx2.filename)
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string();
+        let mut state = manifest.lock().unwrap();
+        let start = get_time() - state.manifest.start;
+        state.manifest.segments.push(ManifestSegment {
+            file: file.clone(),
+            start,
+            width: ctx2.width,
+            height: ctx2.height,
+            format: ctx2.format.to_string(),
+            ..Default::default()
+        });
+        state.store();
+        self.segment = Some((file, false));
+        self.segment_start = Instant::now();
+    }
+
+    // After the segment is finalized, it is removed from the manifest if the recorder removed it.
+    fn end_segment(&mut self) {
+        let (Some(manifest), Some((file, audio))) = (&self.manifest, self.segment.take()) else {
+            return;
+        };
+        let mut state = manifest.lock().unwrap();
+        let exists = state.path.with_file_name(&file).exists();
+        let now = get_time() - state.manifest.start;
+        let sidecar = audio_sidecar_of(&file);
+        let has_sidecar = audio && state.path.with_file_name(&sidecar).exists();
+        let segments = &mut state.manifest.segments;
+        if let Some(i) = segments.iter().position(|s| s.file == file) {
+            if exists {
+                segments[i].duration = now - segments[i].start;
+                segments[i].audio = audio;
+                if has_sidecar {
+                    segments[i].audio_file = sidecar;
+                }
+            } else {
+                segments.remove(i);
+            }
+        }
+        state.store();
+    }
+
     pub fn write_message(&mut self, msg: &Message, w: usize, h: usize) {
         if let Some(message::Union::VideoFrame(vf)) = &msg.union {
             if let Some(frame) = &vf.union {
@@ -191,6 +538,17 @@ impl Recorder {
         if format == CodecFormat::Unknown {
             bail!("unsupported frame type");
         }
+        // A new segment starts with a key frame.
+        if self.inner.is_some()
+            && is_key_frame(frame)
+            && self
+                .max_segment_length
+                .is_some_and(|max| self.segment_start.elapsed() >= max)
+        {
+            log::info!("The recording segment is too long, start a new one");
+            self.inner = None;
+            self.ctx2 = None;
+        }
         let res = self.check(w, h, format);
         if res.is_err() {
             self.check_failed = true;
@@ -201,33 +559,33 @@ impl Recorder {
             video_frame::Union::Vp8s(vp8s) => {
                 for f in vp8s.frames.iter() {
                     self.check_pts(f.pts, f.key, w, h, format)?;
-                    self.as_mut().map(|x| x.write_video(f));
+                    self.write_video(f);
                 }
             }
             video_frame::Union::Vp9s(vp9s) => {
                 for f in vp9s.frames.iter() {
                     self.check_pts(f.pts, f.key, w, h, format)?;
-                    self.as_mut().map(|x| x.write_video(f));
+                    self.write_video(f);
                 }
             }
             video_frame::Union::Av1s(av1s) => {
                 for f in av1s.frames.iter() {
                     self.check_pts(f.pts, f.key, w, h, format)?;
-                    self.as_mut().map(|x| x.write_video(f));
+                    self.write_video(f);
                 }
             }
             #[cfg(feature = "hwcodec")]
             video_frame::Union::H264s(h264s) => {
                 for f in h264s.frames.iter() {
                     self.check_pts(f.pts, f.key, w, h, format)?;
-                    self.as_mut().map(|x| x.write_video(f));
+                    self.write_video(f);
                 }
             }
             #[cfg(feature = "hwcodec")]
             video_frame::Union::H265s(h265s) => {
                 for f in h265s.frames.iter() {
                     self.check_pts(f.pts, f.key, w, h, format)?;
-                    self.as_mut().map(|x| x.write_video(f));
+                    self.write_video(f);
                 }
             }
             _ => bail!("unsupported frame type"),
@@ -236,6 +594,40 @@ impl Recorder {
         Ok(())
     }
 
+    fn write_video(&mut self, frame: &EncodedVideoFrame) {
+        if self.as_mut().is_some_and(|x| x.write_video(frame)) {
+            self.audio.on_video(frame.pts, Instant::now());
+        }
+    }
+
+    /// Write an Opus packet of the session audio, it is dropped until the first video frame is written.
+    pub fn write_audio(&mut self, frame: &AudioFrame) {
+        let Some(duration) = opus_packet_duration(&frame.data) else {
+            return;
+        };
+        let Some(inner) = self.inner.as_mut() else {
+            return;
+        };
+        if let Some(pts) = self.audio.timestamp(Instant::now(), duration) {
+            if inner.write_audio(&frame.data, pts.as_nanos() as _) {
+                if let Some((_, audio)) = self.segment.as_mut() {
+                    *audio = true;
+                }
+            }
+        }
+    }
+
+    /// Add an event of the session, e.g. a file transfer, to the timeline of the recording.
+    /// It is dropped if no segment is being written.
+    pub fn write_event(&mut self, text: &str) {
+        if self.segment.is_none() {
+            return;
+        }
+        if let Some(manifest) = &self.manifest {
+            manifest.lock().unwrap().add_event(text);
+        }
+    }
+
     fn check_pts(
         &mut self,
         pts: i64,
@@ -270,9 +662,109 @@ impl Recorder {
     }
 }
 
+impl Drop for Recorder {
+    fn drop(&mut self) {
+        // Finalize the segment first.
+        self.inner = None;
+        self.end_segment();
+    }
+}
+
+fn is_key_frame(frame: &video_frame::Union) -> bool {
+    match frame {
+        video_frame::Union::Vp8s(frames)
+        | video_frame::Union::Vp9s(frames)
+        | video_frame::Union::Av1s(frames)
+        | video_frame::Union::H264s(frames)
+        | video_frame::Union::H265s(frames) => frames.frames.first().is_some_and(|f| f.key),
+        _ => false,
+    }
+}
+
+/// The timestamps of the audio packets, which have none of their own.
+/// A packet is placed after the video frame written last by the time it arrives,
+/// and right after the previous packet as long as that doesn't drift too far from it.
+#[derive(Debug, Default)]
+struct AudioClock {
+    // When the last video frame was written, and its pts in milliseconds
+    video: Option<(Instant, i64)>,
+    // The end of the previous packet
+    next: Option<Duration>,
+}
+
+impl AudioClock {
+    fn on_video(&mut self, pts: i64, now: Instant) {
+        self.video = Some((now, pts));
+    }
+
+    fn timestamp(&mut self, now: Instant, duration: Duration) -> Option<Duration> {
+        let (time, pts) = self.video?;
+        let expected = Duration::from_millis(pts.max(0) as _) + now.saturating_duration_since(time);
+        let pts = match self.next {
+            Some(next) if next.max(expected) - next.min(expected) <= MAX_AUDIO_DRIFT => next,
+            // After a gap, e.g. the silence is not sent. Never go back.
+            Some(next) => next.max(expected),
+            None => expected,
+        };
+        self.next = Some(pts + duration);
+        Some(pts)
+    }
+}
+
+/// The duration of an Opus packet, from its TOC byte, RFC 6716 3.1.
+fn opus_packet_duration(packet: &[u8]) -> Option<Duration> {
+    let toc = *packet.first()?;
+    let config = toc >> 3;
+    let frame_us = match config {
+        0..=11 => [10_000, 20_000, 40_000, 60_000][config as usize % 4],
+        12..=15 => [10_000, 20_000][config as usize % 2],
+        _ => [2_500, 5_000, 10_000, 20_000][config as usize % 4],
+    };
+    let frames = match toc & 0x3 {
+        0 => 1,
+        1 | 2 => 2,
+        _ => (*packet.get(1)? & 0x3f) as u64,
+    };
+    if frames == 0 {
+        return None;
+    }
+    Some(Duration::from_micros(frame_us * frames))
+}
+
+/// The codec private data of an Opus track, RFC 7845 5.1.
+fn opus_head() -> Vec<u8> {
+    let mut head = b"OpusHead".to_vec();
+    head.push(1); // version
+    head.push(OPUS_CHANNELS);
+    head.extend_from_slice(&0u16.to_le_bytes()); // pre-skip
+    head.extend_from_slice(&OPUS_SAMPLE_RATE.to_le_bytes());
+    head.extend_from_slice(&0i16.to_le_bytes()); // output gain
+    head.push(0); // channel mapping family
+    head
+}
+
+// What the webm muxer writes to, the file or its encryption.
+trait Output: Write + Seek {}
+
+impl<T: Write + Seek> Output for T {}
+
+fn add_opus_track(webm: &mut Segment<Writer<Box<dyn Output>>>) -> ResultType<AudioTrack> {
+    let at = webm.add_audio_track(
+        OPUS_SAMPLE_RATE as _,
+        OPUS_CHANNELS as _,
+        None,
+        mux::AudioCodecId::Opus,
+    );
+    if !webm.set_codec_private(at.track_number(), &opus_head()) {
+        bail!("Failed to set codec private of the audio track");
+    }
+    Ok(at)
+}
+
 struct WebmRecorder {
     vt: VideoTrack,
-    webm: Option<Segment<Writer<File>>>,
+    at: AudioTrack,
+    webm: Option<Segment<Writer<Box<dyn Output>>>>,
     ctx: RecorderContext,
     ctx2: RecorderContext2,
     key: bool,
@@ -282,15 +774,22 @@ struct WebmRecorder {
 
 impl RecorderApi for WebmRecorder {
     fn new(ctx: RecorderContext, ctx2: RecorderContext2) -> ResultType<Self> {
-        let out = match {
-            OpenOptions::new()
-                .write(true)
-                .create_new(true)
-                .open(&ctx2.filename)
-        } {
-            Ok(file) => file,
-            Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => File::create(&ctx2.filename)?,
-            Err(e) => return Err(e.into()),
+        let out: Box<dyn Output> = match &ctx2.public_key {
+            Some(pk) => Box::new(encrypt::EncryptedWriter::create(&ctx2.filename, pk)?),
+            None => Box::new(
+                match {
+                    OpenOptions::new()
+                        .write(true)
+                        .create_new(true)
+                        .open(&ctx2.filename)
+                } {
+                    Ok(file) => file,
+                    Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                        File::create(&ctx2.filename)?
+                    }
+                    Err(e) => return Err(e.into()),
+                },
+            ),
         };
         let mut webm = match mux::Segment::new(mux::Writer::new(out)) {
             Some(v) => v,
@@ -315,8 +814,10 @@ impl RecorderApi for WebmRecorder {
                 bail!("Failed to set codec private");
             }
         }
+        let at = add_opus_track(&mut webm)?;
         Ok(WebmRecorder {
             vt,
+            at,
             webm: Some(webm),
             ctx,
             ctx2,
@@ -342,6 +843,10 @@ impl RecorderApi for WebmRecorder {
             false
         }
     }
+
+    fn write_audio(&mut self, data: &[u8], pts: u64) -> bool {
+        self.key && self.at.add_frame(data, pts, true)
+    }
 }
 
 impl Drop for WebmRecorder {
@@ -356,6 +861,52 @@ impl Drop for WebmRecorder {
     }
 }
 
+/// The audio of a segment in its own WebM, for the MP4 muxer which has no audio track.
+/// The timestamps start at the first video frame of the segment.
+#[cfg(feature = "hwcodec")]
+struct AudioSidecar {
+    at: AudioTrack,
+    webm: Option<Segment<Writer<Box<dyn Output>>>>,
+    path: String,
+    written: bool,
+}
+
+#[cfg(feature = "hwcodec")]
+impl AudioSidecar {
+    fn new(segment: &str) -> ResultType<Self> {
+        let path = audio_sidecar_of(segment);
+        let out: Box<dyn Output> = Box::new(File::create(&path)?);
+        let Some(mut webm) = mux::Segment::new(mux::Writer::new(out)) else {
+            bail!("Failed to create webm mux");
+        };
+        let at = add_opus_track(&mut webm)?;
+        Ok(Self {
+            at,
+            webm: Some(webm),
+            path,
+            written: false,
+        })
+    }
+
+    fn write(&mut self, data: &[u8], pts: u64) -> bool {
+        let ok = self.at.add_frame(data, pts, true);
+        if ok {
+            self.written = true;
+        }
+        ok
+    }
+
+    // Finalize it, it is removed if empty or `keep` is false.
+    fn finish(&mut self, keep: bool) {
+        if let Some(webm) = self.webm.take() {
+            let _ = webm.finalize(None);
+        }
+        if !keep || !self.written {
+            std::fs::remove_file(&self.path).ok();
+        }
+    }
+}
+
 #[cfg(feature = "hwcodec")]
 struct HwRecorder {
     muxer: Option<Muxer>,
@@ -364,11 +915,19 @@ struct HwRecorder {
     written: bool,
     key: bool,
     start: Instant,
+    audio: Option<AudioSidecar>,
+    // The pts of the first video frame written, in nanoseconds
+    first_pts: Option<u64>,
 }
 
 #[cfg(feature = "hwcodec")]
 impl RecorderApi for HwRecorder {
     fn new(ctx: RecorderContext, ctx2: RecorderContext2) -> ResultType<Self> {
+        // The muxer writes the file itself, it can't be encrypted as it is written,
+        // and it is not to be kept in plain.
+        if ctx2.public_key.is_some() {
+            bail!("Encrypted recording is not supported with the hardware codecs");
+        }
         let muxer = Muxer::new(MuxContext {
             filename: ctx2.filename.clone(),
             width: ctx2.width,
@@ -377,6 +936,10 @@ impl RecorderApi for HwRecorder {
             framerate: crate::hwcodec::DEFAULT_FPS as _,
         })
         .map_err(|_| anyhow!("Failed to create hardware muxer"))?;
+        // The video is recorded without it if it fails.
+        let audio = AudioSidecar::new(&ctx2.filename)
+            .map_err(|e| log::error!("Failed to create the audio of the recording: {}", e))
+            .ok();
         Ok(HwRecorder {
             muxer: Some(muxer),
             ctx,
@@ -384,6 +947,8 @@ impl RecorderApi for HwRecorder {
             written: false,
             key: false,
             start: Instant::now(),
+            audio,
+            first_pts: None,
         })
     }
 
@@ -399,12 +964,21 @@ impl RecorderApi for HwRecorder {
                 .unwrap_or_default();
             if ok {
                 self.written = true;
+                self.first_pts
+                    .get_or_insert(frame.pts.max(0) as u64 * 1_000_000);
             }
             ok
         } else {
             false
         }
     }
+
+    fn write_audio(&mut self, data: &[u8], pts: u64) -> bool {
+        let (Some(audio), Some(first_pts)) = (self.audio.as_mut(), self.first_pts) else {
+            return false;
+        };
+        audio.write(data, pts.saturating_sub(first_pts))
+    }
 }
 
 #[cfg(feature = "hwcodec")]
@@ -412,12 +986,119 @@ impl Drop for HwRecorder {
     fn drop(&mut self) {
         self.muxer.as_mut().map(|m| m.write_tail().ok());
         let mut state = RecordState::WriteTail;
-        if !self.written || self.start.elapsed().as_secs() < MIN_SECS {
+        let keep = self.written && self.start.elapsed().as_secs() >= MIN_SECS;
+        if !keep {
             // The process cannot access the file because it is being used by another process
             self.muxer = None;
             std::fs::remove_file(&self.ctx2.filename).ok();
             state = RecordState::RemoveFile;
         }
+        if let Some(audio) = self.audio.as_mut() {
+            audio.finish(keep);
+        }
         self.ctx.tx.as_ref().map(|tx| tx.send(state));
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_opus_packet_duration() {
+        // CELT 20 ms, one frame
+        assert_eq!(
+            opus_packet_duration(&[31 << 3]),
+            Some(Duration::from_millis(20))
+        );
+        // SILK 60 ms, two frames
+        assert_eq!(
+            opus_packet_duration(&[3 << 3 | 1]),
+            Some(Duration::from_millis(120))
+        );
+        // CELT 10 ms, three frames signaled in the second byte
+        assert_eq!(
+            opus_packet_duration(&[18 << 3 | 3, 3]),
+            Some(Duration::from_millis(30))
+        );
+        assert_eq!(opus_packet_duration(&[]), None);
+        assert_eq!(opus_head().len(), 19);
+    }
+
+    #[test]
+    fn test_manifest_path() {
+        assert_eq!(
+            Manifest::path_of("/r/incoming_1_20261018120000123_display0_vp9.webm"),
+            PathBuf::from("/r/incoming_1_20261018120000123_display0.json")
+        );
+    }
+
+    #[test]
+    fn test_event_cue() {
+        assert_eq!(
+            event_cue(3_723_004, "Files received: /tmp/a"),
+            "01:02:03.004 --> 01:02:05.004\nFiles received: /tmp/a\n\n"
+        );
+        assert_eq!(
+            event_cue(-1, "a\r\n\nb --> c"),
+            "00:00:00.000 --> 00:00:02.000\na b -> c\n\n"
+        );
+    }
+
+    #[test]
+    fn test_audio_sidecar_path() {
+        assert_eq!(
+            audio_sidecar_of("incoming_1_20261018120000123_display0_h264.mp4"),
+            "incoming_1_20261018120000123_display0_h264.weba"
+        );
+    }
+
+    #[cfg(feature = "hwcodec")]
+    #[test]
+    fn test_audio_sidecar() {
+        let dir = std::env::temp_dir().join(format!("record_audio_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let segment = dir.join("incoming_1_1_display0_h264.mp4");
+        let segment = segment.to_string_lossy();
+        let path = audio_sidecar_of(&segment);
+        let packet = [31 << 3, 0, 0, 0];
+
+        let mut audio = AudioSidecar::new(&segment).unwrap();
+        assert!(audio.write(&packet, 0));
+        assert!(audio.write(&packet, 20_000_000));
+        audio.finish(true);
+        let data = std::fs::read(&path).unwrap();
+        // EBML header
+        assert!(data.starts_with(&[0x1a, 0x45, 0xdf, 0xa3]));
+
+        // Removed with its segment, or if no audio is written.
+        let mut audio = AudioSidecar::new(&segment).unwrap();
+        assert!(audio.write(&packet, 0));
+        audio.finish(false);
+        assert!(!Path::new(&path).exists());
+        let mut audio = AudioSidecar::new(&segment).unwrap();
+        audio.finish(true);
+        assert!(!Path::new(&path).exists());
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_audio_clock() {
+        let mut clock = AudioClock::default();
+        let start = Instant::now();
+        let d = Duration::from_millis(10);
+        assert_eq!(clock.timestamp(start, d), None);
+        clock.on_video(1000, start);
+        assert_eq!(clock.timestamp(start, d), Some(Duration::from_millis(1000)));
+        // Back to back although it arrives late.
+        let t = clock.timestamp(start + Duration::from_millis(50), d);
+        assert_eq!(t, Some(Duration::from_millis(1010)));
+        // Resynced after a gap.
+        let t = clock.timestamp(start + Duration::from_secs(2), d);
+        assert_eq!(t, Some(Duration::from_millis(3000)));
+        // Never back.
+        clock.on_video(1500, start + Duration::from_secs(2));
+        let t = clock.timestamp(start + Duration::from_secs(2), d);
+        assert_eq!(t, Some(Duration::from_millis(3010)));
+    }
+}
@@ -0,0 +1,385 @@
+//! Encryption of the recordings at rest.
+//!
+//! A recording is encrypted with a random data key, which is sealed to the public key configured
+//! by the admin, so only the holder of the secret key can decrypt it. The file is split into blocks
+//! of `BLOCK_SIZE`, each encrypted with XChaCha20-Poly1305 and a random nonce, so the muxer can seek
+//! back and rewrite what it has written. The index of a block and if it is the final one are
+//! authenticated with it, so blocks can't be reordered or dropped unnoticed.
+//!
+//! ```text
+//! MAGIC | block size: u32 LE | sealed data key | blocks: nonce | ciphertext | tag
+//! ```
+//!
+//! Only complete blocks are written until the recording is finished, so the file can be uploaded
+//! while it grows. The manifest and the events of a recording are encrypted the same way, as they
+//! are rewritten.
+
+use hbb_common::{
+    bail, base64,
+    config::Config,
+    sodiumoxide::crypto::{aead::xchacha20poly1305_ietf as aead, box_, sealedbox},
+    ResultType,
+};
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+pub use box_::{PublicKey, SecretKey};
+
+/// The base64 public key of the admin, recordings are encrypted if it is set.
+pub const OPTION_RECORDING_PUBLIC_KEY: &str = "recording-public-key";
+/// The extension added to the encrypted recordings.
+pub const EXTENSION: &str = "enc";
+const MAGIC: &[u8; 8] = b"RDRECENC";
+const BLOCK_SIZE: usize = 64 * 1024;
+const HEADER_LEN: usize = MAGIC.len() + 4 + sealedbox::SEALBYTES + aead::KEYBYTES;
+const FULL_BLOCK_LEN: usize = aead::NONCEBYTES + BLOCK_SIZE + aead::TAGBYTES;
+/// The length of the header and the first block, which are rewritten when a recording is finished.
+pub const HEAD_LEN: usize = HEADER_LEN + FULL_BLOCK_LEN;
+
+/// The configured public key, an invalid one is an error rather than recording in plain.
+pub fn configured_public_key() -> ResultType<Option<PublicKey>> {
+    let key = Config::get_option(OPTION_RECORDING_PUBLIC_KEY);
+    if key.trim().is_empty() {
+        return Ok(None);
+    }
+    match parse_public_key(&key) {
+        Some(key) => Ok(Some(key)),
+        None => bail!("Invalid {}", OPTION_RECORDING_PUBLIC_KEY),
+    }
+}
+
+pub fn parse_public_key(key: &str) -> Option<PublicKey> {
+    #[allow(deprecated)]
+    let key = base64::decode(key.trim()).ok()?;
+    PublicKey::from_slice(&key)
+}
+
+pub fn parse_secret_key(key: &str) -> Option<SecretKey> {
+    #[allow(deprecated)]
+    let key = base64::decode(key.trim()).ok()?;
+    SecretKey::from_slice(&key)
+}
+
+/// A new key pair in base64, the public key and the secret key.
+pub fn generate_key_pair() -> (String, String) {
+    let (pk, sk) = box_::gen_keypair();
+    #[allow(deprecated)]
+    (base64::encode(pk.0), base64::encode(sk.0))
+}
+
+fn additional_data(header: &[u8], index: u64, last: bool) -> Vec<u8> {
+    let mut ad = header.to_vec();
+    ad.extend_from_slice(&index.to_le_bytes());
+    ad.push(last as u8);
+    ad
+}
+
+/// Encrypts what is written to it, it must be finished to be decrypted.
+pub struct EncryptedWriter {
+    file: File,
+    header: Vec<u8>,
+    key: aead::Key,
+    // The plain length and position
+    len: u64,
+    pos: u64,
+    // The last block, which is incomplete
+    tail: Vec<u8>,
+    // A complete block which is being rewritten, and if it is changed
+    block: Option<(u64, Vec<u8>, bool)>,
+    finished: bool,
+}
+
+impl EncryptedWriter {
+    pub fn create(path: impl AsRef<Path>, pk: &PublicKey) -> io::Result<Self> {
+        // Read to rewrite the blocks.
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        let key = aead::gen_key();
+        let mut header = MAGIC.to_vec();
+        header.extend_from_slice(&(BLOCK_SIZE as u32).to_le_bytes());
+        header.extend_from_slice(&sealedbox::seal(&key.0, pk));
+        file.write_all(&header)?;
+        Ok(Self {
+            file,
+            header,
+            key,
+            len: 0,
+            pos: 0,
+            tail: Vec::with_capacity(BLOCK_SIZE),
+            block: None,
+            finished: false,
+        })
+    }
+
+    /// Write the last block, after which nothing can be written.
+    pub fn finish(&mut self) -> io::Result<()> {
+        if self.finished {
+            return Ok(());
+        }
+        self.flush_block()?;
+        let tail = std::mem::take(&mut self.tail);
+        self.write_block(self.len / BLOCK_SIZE as u64, &tail, true)?;
+        self.finished = true;
+        self.file.flush()
+    }
+
+    fn write_block(&mut self, index: u64, data: &[u8], last: bool) -> io::Result<()> {
+        let nonce = aead::gen_nonce();
+        let ad = additional_data(&self.header, index, last);
+        let data = aead::seal(data, Some(&ad), &nonce, &self.key);
+        self.file.seek(SeekFrom::Start(
+            HEADER_LEN as u64 + index * FULL_BLOCK_LEN as u64,
+        ))?;
+        self.file.write_all(&nonce.0)?;
+        self.file.write_all(&data)
+    }
+
+    fn flush_block(&mut self) -> io::Result<()> {
+        if let Some((index, data, true)) = self.block.take() {
+            self.write_block(index, &data, false)?;
+        }
+        Ok(())
+    }
+
+    fn load_block(&mut self, index: u64) -> io::Result<()> {
+        if self.block.as_ref().is_some_and(|b| b.0 == index) {
+            return Ok(());
+        }
+        self.flush_block()?;
+        let mut buf = vec![0; FULL_BLOCK_LEN];
+        self.file.seek(SeekFrom::Start(
+            HEADER_LEN as u64 + index * FULL_BLOCK_LEN as u64,
+        ))?;
+        self.file.read_exact(&mut buf)?;
+        let data = open_block(&buf, &self.header, &self.key, index, false)?;
+        self.block = Some((index, data, false));
+        Ok(())
+    }
+}
+
+fn open_block(
+    buf: &[u8],
+    header: &[u8],
+    key: &aead::Key,
+    index: u64,
+    last: bool,
+) -> io::Result<Vec<u8>> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "Corrupted recording");
+    if buf.len() < aead::NONCEBYTES + aead::TAGBYTES {
+        return Err(invalid());
+    }
+    let nonce = aead::Nonce::from_slice(&buf[..aead::NONCEBYTES]).ok_or_else(invalid)?;
+    let ad = additional_data(header, index, last);
+    aead::open(&buf[aead::NONCEBYTES..], Some(&ad), &nonce, key).map_err(|_| invalid())
+}
+
+impl Write for EncryptedWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.finished {
+            return Err(io::Error::other("The encrypted recording is finished"));
+        }
+        if self.pos > self.len {
+            // Fill the gap after seeking past the end.
+            let pos = self.pos;
+            self.pos = self.len;
+            while self.pos < pos {
+                let n = (pos - self.pos).min(BLOCK_SIZE as u64) as usize;
+                self.write_all(&vec![0; n])?;
+            }
+        }
+        let index = self.pos / BLOCK_SIZE as u64;
+        let offset = (self.pos % BLOCK_SIZE as u64) as usize;
+        let n = buf.len().min(BLOCK_SIZE - offset);
+        if index == self.len / BLOCK_SIZE as u64 {
+            if self.tail.len() < offset + n {
+                self.tail.resize(offset + n, 0);
+            }
+            self.tail[offset..offset + n].copy_from_slice(&buf[..n]);
+            self.len = self.len.max(self.pos + n as u64);
+            if self.tail.len() == BLOCK_SIZE {
+                let tail = std::mem::take(&mut self.tail);
+                self.write_block(index, &tail, false)?;
+                self.tail = tail;
+                self.tail.clear();
+            }
+        } else {
+            self.load_block(index)?;
+            if let Some((_, data, changed)) = self.block.as_mut() {
+                data[offset..offset + n].copy_from_slice(&buf[..n]);
+                *changed = true;
+            }
+        }
+        self.pos += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.flush_block()?;
+        self.file.flush()
+    }
+}
+
+impl Seek for EncryptedWriter {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let pos = match pos {
+            SeekFrom::Start(n) => Some(n),
+            SeekFrom::Current(n) => self.pos.checked_add_signed(n),
+            SeekFrom::End(n) => self.len.checked_add_signed(n),
+        };
+        match pos {
+            Some(pos) => {
+                self.pos = pos;
+                Ok(pos)
+            }
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Invalid seek to a negative position",
+            )),
+        }
+    }
+}
+
+impl Drop for EncryptedWriter {
+    fn drop(&mut self) {
+        if let Err(e) = self.finish() {
+            hbb_common::log::error!("Failed to finish the encrypted recording: {}", e);
+        }
+    }
+}
+
+/// The encrypted file of `path`, with `.enc` added.
+pub fn path_of(path: &Path) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(".");
+    path.push(EXTENSION);
+    PathBuf::from(path)
+}
+
+/// Write a whole file encrypted, e.g. the manifest of a recording.
+pub fn write_file(path: impl AsRef<Path>, data: &[u8], pk: &PublicKey) -> io::Result<()> {
+    let mut writer = EncryptedWriter::create(path, pk)?;
+    writer.write_all(data)?;
+    writer.finish()
+}
+
+/// Decrypt a recording. What can be decrypted is written if it was not finished, with an error.
+pub fn decrypt_file(
+    src: impl AsRef<Path>,
+    dst: impl AsRef<Path>,
+    sk: &SecretKey,
+) -> ResultType<()> {
+    let mut src = File::open(src)?;
+    let mut header = vec![0; HEADER_LEN];
+    if read_full(&mut src, &mut header)? < HEADER_LEN || !header.starts_with(MAGIC) {
+        bail!("Not an encrypted recording");
+    }
+    let block_size = u32::from_le_bytes([header[8], header[9], header[10], header[11]]) as usize;
+    if block_size == 0 || block_size > BLOCK_SIZE {
+        bail!(
+            "Invalid block size {} of the encrypted recording",
+            block_size
+        );
+    }
+    let key = sealedbox::open(&header[MAGIC.len() + 4..], &sk.public_key(), sk)
+        .ok()
+        .and_then(|key| aead::Key::from_slice(&key));
+    let Some(key) = key else {
+        bail!("The recording is not encrypted with this key");
+    };
+    let mut dst = File::create(dst)?;
+    let mut buf = vec![0; aead::NONCEBYTES + block_size + aead::TAGBYTES];
+    for index in 0.. {
+        let n = read_full(&mut src, &mut buf)?;
+        if n == 0 {
+            bail!("The recording was not finished, it is decrypted until where it was cut");
+        }
+        // Only the last block is incomplete, it may be empty.
+        let last = n < buf.len();
+        dst.write_all(&open_block(&buf[..n], &header, &key, index, last)?)?;
+        if last {
+            break;
+        }
+    }
+    Ok(())
+}
+
+fn read_full(r: &mut impl Read, buf: &mut [u8]) -> io::Result<usize> {
+    let mut n = 0;
+    while n < buf.len() {
+        match r.read(&mut buf[n..]) {
+            Ok(0) => break,
+            Ok(m) => n += m,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(n)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encrypted_writer() {
+        let dir = std::env::temp_dir().join(format!("record_encrypt_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (pk, sk) = box_::gen_keypair();
+        let path = dir.join("a.webm.enc");
+        let mut expected = vec![0u8; 3 * BLOCK_SIZE + 10];
+        for (i, b) in expected.iter_mut().enumerate() {
+            *b = i as u8;
+        }
+        let mut writer = EncryptedWriter::create(&path, &pk).unwrap();
+        writer.write_all(&expected).unwrap();
+        // Rewrite the header, and over a block boundary, as the muxer does.
+        writer.seek(SeekFrom::Start(4)).unwrap();
+        writer.write_all(&[1, 2, 3]).unwrap();
+        writer.seek(SeekFrom::Start(BLOCK_SIZE as u64 - 1)).unwrap();
+        writer.write_all(&[4, 5]).unwrap();
+        writer.seek(SeekFrom::End(0)).unwrap();
+        writer.write_all(&[6]).unwrap();
+        expected[4..7].copy_from_slice(&[1, 2, 3]);
+        expected[BLOCK_SIZE - 1..BLOCK_SIZE + 1].copy_from_slice(&[4, 5]);
+        expected.push(6);
+        // Only complete blocks are written before it is finished.
+        assert_eq!(
+            std::fs::metadata(&path).unwrap().len() as usize,
+            HEADER_LEN + 3 * FULL_BLOCK_LEN
+        );
+        drop(writer);
+
+        let out = dir.join("a.webm");
+        decrypt_file(&path, &out, &sk).unwrap();
+        assert_eq!(std::fs::read(&out).unwrap(), expected);
+        let (_, other) = box_::gen_keypair();
+        assert!(decrypt_file(&path, &out, &other).is_err());
+
+        // Cut off and tampered
+        let data = std::fs::read(&path).unwrap();
+        std::fs::write(&path, &data[..HEADER_LEN + 2 * FULL_BLOCK_LEN]).unwrap();
+        assert!(decrypt_file(&path, &out, &sk).is_err());
+        assert_eq!(std::fs::read(&out).unwrap(), expected[..2 * BLOCK_SIZE]);
+        let mut data = data;
+        data[HEADER_LEN + 100] ^= 1;
+        std::fs::write(&path, &data).unwrap();
+        assert!(decrypt_file(&path, &out, &sk).is_err());
+
+        // A whole file, and a header with a block size over the maximum
+        write_file(&path, b"WEBVTT", &pk).unwrap();
+        decrypt_file(&path, &out, &sk).unwrap();
+        assert_eq!(std::fs::read(&out).unwrap(), b"WEBVTT");
+        let mut data = std::fs::read(&path).unwrap();
+        data[8..12].copy_from_slice(&u32::MAX.to_le_bytes());
+        std::fs::write(&path, &data).unwrap();
+        assert!(decrypt_file(&path, &out, &sk).is_err());
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
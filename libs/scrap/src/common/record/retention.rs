@@ -0,0 +1,272 @@
+//! Retention of the recordings.
+//!
+//! A sweeper thread per recording directory removes the oldest segments when the recordings are
+//! older than the maximum age or bigger than the maximum total size, and then the manifests and
+//! the timelines without segments. The audio of a segment in its own file counts as a segment.
+//! Segments being written are never removed. The terminal
+//! recordings in the same directory are left to their own retention.
+//!
+//! A recording is protected from removal by a `.keep` file next to it, named after its manifest,
+//! e.g. `incoming_<id>_<time>_display0.keep`, or after one file, e.g. `<segment>.keep`.
+
+use super::{audio_sidecar_of, encrypt, Manifest, AUDIO_SIDECAR_EXTENSION, MANIFESTS};
+use hbb_common::{config::Config, log};
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+/// In MB
+pub const OPTION_RECORDING_MAX_TOTAL_SIZE: &str = "recording-max-total-size";
+/// In days
+pub const OPTION_RECORDING_MAX_AGE: &str = "recording-max-age";
+/// In minutes, a new segment is started at the next key frame after it.
+pub const OPTION_RECORDING_MAX_SEGMENT_LENGTH: &str = "recording-max-segment-length";
+const KEEP_EXTENSION: &str = "keep";
+const SWEEP_INTERVAL: Duration = Duration::from_secs(10 * 60);
+// Files modified within it may be written by another process.
+const ACTIVE_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+lazy_static::lazy_static! {
+    static ref SWEEPERS: std::sync::Mutex<HashSet<PathBuf>> = Default::default();
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Policy {
+    pub max_total_size: Option<u64>,
+    pub max_age: Option<Duration>,
+    pub max_segment_length: Option<Duration>,
+}
+
+impl Policy {
+    pub fn load() -> Self {
+        // Not limited if not set or 0.
+        let get = |name: &str| {
+            Config::get_option(name)
+                .trim()
+                .parse::<u64>()
+                .ok()
+                .filter(|v| *v > 0)
+        };
+        Self {
+            max_total_size: get(OPTION_RECORDING_MAX_TOTAL_SIZE).map(|v| v * 1024 * 1024),
+            max_age: get(OPTION_RECORDING_MAX_AGE).map(|v| Duration::from_secs(v * 24 * 3600)),
+            max_segment_length: get(OPTION_RECORDING_MAX_SEGMENT_LENGTH)
+                .map(|v| Duration::from_secs(v * 60)),
+        }
+    }
+}
+
+/// Start the sweeper of the directory if it is not running.
+pub fn start(dir: &str) {
+    let dir = PathBuf::from(dir);
+    if !SWEEPERS.lock().unwrap().insert(dir.clone()) {
+        return;
+    }
+    std::thread::spawn(move || loop {
+        let policy = Policy::load();
+        if policy.max_total_size.is_some() || policy.max_age.is_some() {
+            sweep(&dir, &policy, SystemTime::now(), &active_segments());
+        }
+        std::thread::sleep(SWEEP_INTERVAL);
+    });
+}
+
+// The segments being written by this process.
+fn active_segments() -> HashSet<PathBuf> {
+    let manifests = MANIFESTS.lock().unwrap();
+    let mut active = HashSet::new();
+    for state in manifests.values() {
+        let state = state.lock().unwrap();
+        for s in state.manifest.segments.iter().filter(|s| s.duration == 0) {
+            active.insert(state.path.with_file_name(&s.file));
+            active.insert(state.path.with_file_name(audio_sidecar_of(&s.file)));
+        }
+    }
+    active
+}
+
+struct Entry {
+    path: PathBuf,
+    // The file name, and the name of its manifest without the extension
+    file: String,
+    name: String,
+    size: u64,
+    modified: SystemTime,
+    segment: bool,
+}
+
+/// Remove the recordings out of the policy, the removed files are returned.
+fn sweep(dir: &Path, policy: &Policy, now: SystemTime, active: &HashSet<PathBuf>) -> Vec<PathBuf> {
+    let Ok(files) = std::fs::read_dir(dir) else {
+        return vec![];
+    };
+    let mut entries = vec![];
+    let mut keep = HashSet::new();
+    for file in files.flatten() {
+        let path = file.path();
+        let name = file.file_name().to_string_lossy().to_string();
+        if !name.starts_with("incoming_") && !name.starts_with("outgoing_") {
+            continue;
+        }
+        let Ok(meta) = file.metadata() else {
+            continue;
+        };
+        if !meta.is_file() {
+            continue;
+        }
+        // Of the plain file if encrypted, e.g. `json` and the manifest name of `<name>.json.enc`.
+        let plain = Path::new(
+            name.strip_suffix(&format!(".{}", encrypt::EXTENSION))
+                .unwrap_or(&name),
+        );
+        let ext = plain
+            .extension()
+            .map(|e| e.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        let stem = plain
+            .file_stem()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string();
+        if ext == KEEP_EXTENSION {
+            keep.insert(stem);
+            continue;
+        }
+        let segment = match ext.as_str() {
+            "webm" | "mp4" | AUDIO_SIDECAR_EXTENSION => true,
+            "json" | "vtt" => false,
+            // E.g. the terminal recordings, which have their own retention.
+            _ => continue,
+        };
+        entries.push(Entry {
+            name: if segment {
+                Manifest::path_of(&name)
+                    .with_extension("")
+                    .to_string_lossy()
+                    .to_string()
+            } else {
+                stem
+            },
+            file: name,
+            size: meta.len(),
+            modified: meta.modified().unwrap_or(now),
+            segment,
+            path,
+        });
+    }
+    let removable = |e: &Entry| {
+        !keep.contains(&e.name)
+            && !keep.contains(&e.file)
+            && !active.contains(&e.path)
+            && now.duration_since(e.modified).unwrap_or_default() >= ACTIVE_TIMEOUT
+    };
+    let mut total: u64 = entries.iter().map(|e| e.size).sum();
+    let mut segments: Vec<_> = entries.iter().filter(|e| e.segment).collect();
+    segments.sort_by_key(|e| e.modified);
+    let mut removed = vec![];
+    for e in segments {
+        let age = now.duration_since(e.modified).unwrap_or_default();
+        let reason = if policy.max_age.is_some_and(|max| age > max) {
+            "older than the maximum age"
+        } else if policy.max_total_size.is_some_and(|max| total > max) {
+            "over the maximum total size"
+        } else {
+            continue;
+        };
+        if !removable(e) {
+            continue;
+        }
+        match std::fs::remove_file(&e.path) {
+            Ok(_) => {
+                log::info!("Removed the recording {:?}, {}", e.path, reason);
+                total -= e.size;
+                removed.push(e.path.clone());
+            }
+            Err(err) => log::error!("Failed to remove the recording {:?}: {}", e.path, err),
+        }
+    }
+    let names: HashSet<_> = entries
+        .iter()
+        .filter(|e| e.segment && !removed.contains(&e.path))
+        .map(|e| e.name.as_str())
+        .collect();
+    for e in entries.iter().filter(|e| !e.segment) {
+        if names.contains(e.name.as_str()) || !removable(e) {
+            continue;
+        }
+        match std::fs::remove_file(&e.path) {
+            Ok(_) => {
+                log::info!("Removed {:?}, its recording is removed", e.path);
+                removed.push(e.path.clone());
+            }
+            Err(err) => log::error!("Failed to remove {:?}: {}", e.path, err),
+        }
+    }
+    removed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sweep() {
+        let dir = std::env::temp_dir().join(format!("record_retention_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let now = SystemTime::now();
+        let day = Duration::from_secs(24 * 3600);
+        let create = |name: &str, size: usize, age: Duration| {
+            let path = dir.join(name);
+            std::fs::write(&path, vec![0; size]).unwrap();
+            std::fs::File::options()
+                .write(true)
+                .open(&path)
+                .unwrap()
+                .set_modified(now - age)
+                .unwrap();
+            path
+        };
+        let old = create("incoming_1_1_display0_vp9.webm", 10, 10 * day);
+        let old_manifest = create("incoming_1_1_display0.json", 1, 10 * day);
+        let kept = create("incoming_1_2_display0_vp9.webm", 10, 10 * day);
+        create("incoming_1_2_display0.keep", 0, 10 * day);
+        let a = create("outgoing_2_3_display0_vp9.webm", 100, 3 * day);
+        let a_events = create("outgoing_2_3_display0.vtt", 1, 3 * day);
+        let a_manifest = create("outgoing_2_3_display0.json.enc", 1, 3 * day);
+        let b = create("outgoing_2_4_display0_av1.webm.enc", 100, 2 * day);
+        let b_events = create("outgoing_2_4_display0.vtt.enc", 1, 2 * day);
+        let c = create("outgoing_2_5_display1_h264.mp4", 10, 8 * day);
+        let c_audio = create("outgoing_2_5_display1_h264.weba", 1, 8 * day);
+        let c_manifest = create("outgoing_2_5_display1.json", 1, 8 * day);
+        let active = create("outgoing_2_5_display0_vp9.webm", 100, day);
+        let recent = create("outgoing_2_6_display0_vp9.webm", 100, Duration::ZERO);
+        let cast = create("incoming_1_7_terminal0.cast", 1000, 10 * day);
+        let other = create("other.webm", 1000, 10 * day);
+
+        let policy = Policy {
+            max_total_size: Some(350),
+            max_age: Some(7 * day),
+            ..Default::default()
+        };
+        let mut removed = sweep(&dir, &policy, now, &HashSet::from([active.clone()]));
+        removed.sort();
+        let mut expected = vec![
+            old,
+            old_manifest,
+            a,
+            a_events,
+            a_manifest,
+            c,
+            c_audio,
+            c_manifest,
+        ];
+        expected.sort();
+        assert_eq!(removed, expected);
+        for path in [kept, b, b_events, active, recent, cast, other] {
+            assert!(path.exists());
+        }
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
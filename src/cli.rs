@@ -1,9 +1,16 @@
 use crate::client::*;
+pub use crate::file_sync::{SyncDirection, SyncOptions};
+use crate::port_forward::{
+    profile::{ForwardProfile, ForwardProfiles},
+    ListenOptions, Stats, MAX_BACKOFF, MIN_BACKOFF,
+};
+use crate::ui_session_interface::{InvokeUiSession, Session as UiSession};
 use async_trait::async_trait;
 use hbb_common::{
     config::PeerConfig,
     config::READ_TIMEOUT,
-    futures::{SinkExt, StreamExt},
+    fs,
+    futures::StreamExt,
     log,
     message_proto::*,
     protobuf::Message as _,
@@ -11,7 +18,37 @@ use hbb_common::{
     tokio::{self, sync::mpsc},
     Stream,
 };
-use std::sync::{Arc, RwLock};
+use serde_json::json;
+use std::{
+    collections::HashMap,
+    io::{IsTerminal, Read, Write},
+    sync::{
+        atomic::{AtomicI32, AtomicUsize, Ordering},
+        mpsc::{channel, Receiver, RecvTimeoutError, Sender},
+        Arc, Mutex, RwLock,
+    },
+    time::{Duration, Instant},
+};
+
+/// Exit codes of the headless client, stable for scripting.
+pub const EXIT_OK: i32 = 0;
+pub const EXIT_ERROR: i32 = 1;
+pub const EXIT_USAGE: i32 = 2;
+pub const EXIT_CONNECT: i32 = 3;
+pub const EXIT_AUTH: i32 = 4;
+pub const EXIT_TRANSFER: i32 = 5;
+pub const EXIT_TIMEOUT: i32 = 6;
+pub const EXIT_UNSUPPORTED: i32 = 7;
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(60);
+const TERMINAL_ID: i32 = 0;
+const EXEC_ID: i32 = 1;
+// Extra time for the peer to report a command it killed on timeout.
+const EXEC_TIMEOUT_GRACE: Duration = Duration::from_secs(5);
+// Older peers don't answer a wake-on-lan request.
+const WAKE_RESPONSE_TIMEOUT: Duration = Duration::from_secs(10);
+const WAKE_TIMEOUT: Duration = Duration::from_secs(180);
+const ONLINE_POLL_INTERVAL: Duration = Duration::from_secs(5);
 
 #[derive(Clone)]
 pub struct Session {
@@ -19,6 +56,8 @@ pub struct Session {
     lc: Arc<RwLock<LoginConfigHandler>>,
     sender: mpsc::UnboundedSender<Data>,
     password: String,
+    // Ask for the password again on stdin if it is wrong.
+    interactive: bool,
 }
 
 impl Session {
@@ -27,10 +66,18 @@ impl Session {
         if PeerConfig::load(id).password.is_empty() {
             password = rpassword::prompt_password("Enter password: ").unwrap();
         }
+        let mut session = Self::with_password(id, sender, password);
+        session.interactive = true;
+        session
+    }
+
+    /// A session which never prompts, the saved password of the peer is used if `password` is empty.
+    pub fn with_password(id: &str, sender: mpsc::UnboundedSender<Data>, password: String) -> Self {
         let session = Self {
             id: id.to_owned(),
             sender,
             password,
+            interactive: false,
             lc: Default::default(),
         };
         session.lc.write().unwrap().initialize(
@@ -40,6 +87,7 @@ impl Session {
             false,
             None,
             None,
+            None,
         );
         session
     }
@@ -47,7 +95,7 @@ impl Session {
 
 #[async_trait]
 impl Interface for Session {
-    fn get_login_config_handler(&self) -> Arc<RwLock<LoginConfigHandler>> {
+    fn get_lch(&self) -> Arc<RwLock<LoginConfigHandler>> {
         return self.lc.clone();
     }
 
@@ -55,14 +103,23 @@ impl Interface for Session {
         match msgtype {
             "input-password" => {
                 self.sender
-                    .send(Data::Login((self.password.clone(), true)))
+                    .send(Data::Login((
+                        "".to_owned(),
+                        "".to_owned(),
+                        self.password.clone(),
+                        true,
+                    )))
                     .ok();
             }
             "re-input-password" => {
                 log::error!("{}: {}", title, text);
+                if !self.interactive {
+                    return;
+                }
                 match rpassword::prompt_password("Enter password: ") {
                     Ok(password) => {
-                        let login_data = Data::Login((password, true));
+                        let login_data =
+                            Data::Login(("".to_owned(), "".to_owned(), password, true));
                         self.sender.send(login_data).ok();
                     }
                     Err(e) => {
@@ -71,7 +128,7 @@ impl Interface for Session {
                 }
             }
             msg if msg.contains("error") => {
-                log::error!("{}: {}: {}", msgtype, title, text);
+                log::error!("{}: {}: {}: {}", msgtype, title, text, link);
             }
             _ => {
                 log::info!("{}: {}: {}", msgtype, title, text);
@@ -87,6 +144,8 @@ impl Interface for Session {
         self.lc.write().unwrap().handle_peer_info(&pi);
     }
 
+    fn set_multiple_windows_session(&self, _sessions: Vec<WindowsSession>) {}
+
     async fn handle_hash(&self, pass: &str, hash: Hash, peer: &mut Stream) {
         log::info!(
             "password={}",
@@ -123,15 +182,929 @@ impl Interface for Session {
     }
 }
 
+/// Options shared by the headless client subcommands.
+#[derive(Clone, Default)]
+pub struct CliOptions {
+    pub password: String,
+    pub json: bool,
+    pub force_relay: bool,
+    pub overwrite: bool,
+    pub include_hidden: bool,
+    pub timeout: Option<Duration>,
+}
+
+/// Events forwarded from the io loop to the command driving the session.
+enum CliEvent {
+    MsgBox(String, String, String),
+    Connected,
+    JobProgress(i32, i32, f64, f64),
+    JobDone(i32, i32),
+    JobError(i32, String, i32),
+    OverrideFileConfirm(i32, i32, String, bool, bool),
+    SyncPlan(i32, String),
+    Terminal(TerminalResponse),
+    WakeOnLan(WakeOnLanResponse),
+}
+
+/// The ui handler of the headless client. Everything related to rendering is ignored,
+/// the rest is forwarded to the command through a channel.
+#[derive(Clone, Default)]
+pub struct CliHandler {
+    sender: Arc<Mutex<Option<Sender<CliEvent>>>>,
+}
+
+impl CliHandler {
+    fn push(&self, event: CliEvent) {
+        if let Some(sender) = self.sender.lock().unwrap().as_ref() {
+            sender.send(event).ok();
+        }
+    }
+}
+
+impl InvokeUiSession for CliHandler {
+    fn set_cursor_data(&self, _cd: CursorData) {}
+
+    fn set_cursor_id(&self, _id: String) {}
+
+    fn set_cursor_position(&self, _cp: CursorPosition) {}
+
+    fn set_display(&self, _x: i32, _y: i32, _w: i32, _h: i32, _cursor_embedded: bool, _scale: f64) {
+    }
+
+    fn switch_display(&self, _display: &SwitchDisplay) {}
+
+    fn set_peer_info(&self, _peer_info: &PeerInfo) {}
+
+    fn set_displays(&self, _displays: &Vec<DisplayInfo>) {}
+
+    fn set_platform_additions(&self, _data: &str) {}
+
+    fn on_connected(&self, _conn_type: ConnType) {
+        self.push(CliEvent::Connected);
+    }
+
+    fn update_privacy_mode(&self) {}
+
+    fn set_permission(&self, _name: &str, _value: bool) {}
+
+    fn close_success(&self) {}
+
+    fn update_quality_status(&self, _qs: QualityStatus) {}
+
+    fn set_connection_type(&self, _is_secured: bool, _direct: bool, _stream_type: &str) {}
+
+    fn set_fingerprint(&self, _fingerprint: String) {}
+
+    fn job_error(&self, id: i32, err: String, file_num: i32) {
+        self.push(CliEvent::JobError(id, err, file_num));
+    }
+
+    fn job_done(&self, id: i32, file_num: i32) {
+        self.push(CliEvent::JobDone(id, file_num));
+    }
+
+    fn clear_all_jobs(&self) {}
+
+    fn new_message(&self, _msg: String) {}
+
+    fn update_transfer_list(&self) {}
+
+    fn load_last_job(&self, _cnt: i32, _job_json: &str, _auto_start: bool) {}
+
+    fn update_folder_files(
+        &self,
+        _id: i32,
+        _entries: &Vec<FileEntry>,
+        _path: String,
+        _is_local: bool,
+        _only_count: bool,
+    ) {
+    }
+
+    fn confirm_delete_files(&self, _id: i32, _i: i32, _name: String) {}
+
+    fn override_file_confirm(
+        &self,
+        id: i32,
+        file_num: i32,
+        to: String,
+        is_upload: bool,
+        is_identical: bool,
+    ) {
+        self.push(CliEvent::OverrideFileConfirm(
+            id,
+            file_num,
+            to,
+            is_upload,
+            is_identical,
+        ));
+    }
+
+    fn update_block_input_state(&self, _on: bool) {}
+
+    fn job_progress(&self, id: i32, file_num: i32, speed: f64, finished_size: f64) {
+        self.push(CliEvent::JobProgress(id, file_num, speed, finished_size));
+    }
+
+    fn adapt_size(&self) {}
+
+    fn on_rgba(&self, _display: usize, _rgba: &mut scrap::ImageRgb) {}
+
+    fn msgbox(&self, msgtype: &str, title: &str, text: &str, _link: &str, _retry: bool) {
+        self.push(CliEvent::MsgBox(
+            msgtype.to_owned(),
+            title.to_owned(),
+            text.to_owned(),
+        ));
+    }
+
+    fn cancel_msgbox(&self, _tag: &str) {}
+
+    fn switch_back(&self, _id: &str) {}
+
+    fn portable_service_running(&self, _running: bool) {}
+
+    fn on_voice_call_started(&self) {}
+
+    fn on_voice_call_closed(&self, _reason: &str) {}
+
+    fn on_voice_call_waiting(&self) {}
+
+    fn on_voice_call_incoming(&self) {}
+
+    fn get_rgba(&self, _display: usize) -> *const u8 {
+        std::ptr::null()
+    }
+
+    fn next_rgba(&self, _display: usize) {}
+
+    fn set_multiple_windows_session(&self, _sessions: Vec<WindowsSession>) {}
+
+    fn set_current_display(&self, _disp_idx: i32) {}
+
+    fn update_record_status(&self, _start: bool) {}
+
+    fn printer_request(&self, _id: i32, _path: String, _job: &PrintJobInfo) {}
+
+    fn handle_screenshot_resp(&self, _sid: String, _msg: String) {}
+
+    fn handle_terminal_response(&self, response: TerminalResponse) {
+        self.push(CliEvent::Terminal(response));
+    }
+
+    fn sync_dir_plan(&self, id: i32, plan: String) {
+        self.push(CliEvent::SyncPlan(id, plan));
+    }
+
+    fn handle_wake_on_lan_response(&self, response: WakeOnLanResponse) {
+        self.push(CliEvent::WakeOnLan(response));
+    }
+}
+
+/// Print one event. With `--json` every event is a single JSON object per line on stdout,
+/// otherwise a short human readable line goes to stderr so that stdout stays clean.
+fn emit(opts: &CliOptions, event: serde_json::Value) {
+    if opts.json {
+        println!("{}", event);
+        std::io::stdout().flush().ok();
+    } else {
+        let kind = event["event"].as_str().unwrap_or_default();
+        let message = event["message"].as_str().unwrap_or_default();
+        if message.is_empty() {
+            eprintln!("{}", kind);
+        } else {
+            eprintln!("{}: {}", kind, message);
+        }
+    }
+}
+
+fn fail(opts: &CliOptions, code: i32, message: &str) -> i32 {
+    emit(
+        opts,
+        json!({ "event": "error", "message": message, "exit_code": code }),
+    );
+    code
+}
+
+fn start_session(
+    id: &str,
+    conn_type: ConnType,
+    terminal_attach: Option<String>,
+    opts: &CliOptions,
+) -> (UiSession<CliHandler>, Receiver<CliEvent>) {
+    let (tx, rx) = channel();
+    let session: UiSession<CliHandler> = UiSession {
+        password: opts.password.clone(),
+        ui_handler: CliHandler {
+            sender: Arc::new(Mutex::new(Some(tx))),
+        },
+        server_keyboard_enabled: Arc::new(RwLock::new(true)),
+        server_file_transfer_enabled: Arc::new(RwLock::new(true)),
+        server_clipboard_enabled: Arc::new(RwLock::new(true)),
+        reconnect_count: Arc::new(AtomicUsize::new(0)),
+        ..Default::default()
+    };
+    session.lc.write().unwrap().initialize(
+        id.to_owned(),
+        conn_type,
+        None,
+        opts.force_relay,
+        None,
+        None,
+        None,
+    );
+    session.lc.write().unwrap().terminal_attach = terminal_attach;
+    let cloned = session.clone();
+    let round = session.connection_round_state.lock().unwrap().new_round();
+    *session.thread.lock().unwrap() = Some(std::thread::spawn(move || {
+        crate::ui_session_interface::io_loop(cloned, round);
+    }));
+    (session, rx)
+}
+
+/// Wait until the peer accepted the login, answering password and 2FA prompts on the way.
+fn wait_connected(
+    session: &UiSession<CliHandler>,
+    rx: &Receiver<CliEvent>,
+    opts: &CliOptions,
+) -> Result<(), i32> {
+    let interactive = std::io::stdin().is_terminal();
+    loop {
+        match rx.recv_timeout(CONNECT_TIMEOUT) {
+            Ok(CliEvent::Connected) => {
+                emit(
+                    opts,
+                    json!({ "event": "connected", "id": session.get_id() }),
+                );
+                return Ok(());
+            }
+            Ok(CliEvent::MsgBox(msgtype, title, text)) => match msgtype.as_str() {
+                "input-password" | "re-input-password" => {
+                    // Piped stdin is the input of the command, not the password.
+                    if !interactive {
+                        let message = if msgtype == "re-input-password" {
+                            &title
+                        } else {
+                            "Password required"
+                        };
+                        return Err(fail(opts, EXIT_AUTH, message));
+                    }
+                    match rpassword::prompt_password("Enter password: ") {
+                        Ok(password) => {
+                            session.login("".to_owned(), "".to_owned(), password, false)
+                        }
+                        Err(_) => return Err(fail(opts, EXIT_AUTH, "Password required")),
+                    }
+                }
+                "input-2fa" => {
+                    if !interactive {
+                        return Err(fail(opts, EXIT_AUTH, "2FA code required"));
+                    }
+                    eprint!("Enter 2FA code: ");
+                    let mut code = String::new();
+                    std::io::stdin().read_line(&mut code).ok();
+                    session.send2fa(code.trim().to_owned(), false);
+                }
+                t if t.contains("error") => {
+                    let code = if title.contains("Login") || title.contains("Password") {
+                        EXIT_AUTH
+                    } else {
+                        EXIT_CONNECT
+                    };
+                    return Err(fail(opts, code, &format!("{}: {}", title, text)));
+                }
+                _ => {
+                    log::info!("{}: {}: {}", msgtype, title, text);
+                }
+            },
+            Ok(_) => {}
+            Err(RecvTimeoutError::Timeout) => {
+                return Err(fail(opts, EXIT_TIMEOUT, "Timeout"));
+            }
+            Err(RecvTimeoutError::Disconnected) => {
+                return Err(fail(opts, EXIT_CONNECT, "Connection closed"));
+            }
+        }
+    }
+}
+
+fn close_session(session: &UiSession<CliHandler>) {
+    session.send(Data::Close);
+    if let Some(thread) = session.thread.lock().unwrap().take() {
+        thread.join().ok();
+    }
+}
+
+/// Join `name` of `from` to the destination `to`, like `cp` does when `to` ends with a separator.
+fn transfer_target(from: &str, to: &str, to_is_dir: bool) -> String {
+    let sep = if to.contains('\\') && !to.contains('/') {
+        '\\'
+    } else {
+        '/'
+    };
+    if to_is_dir || to.ends_with('/') || to.ends_with('\\') {
+        let name = from
+            .trim_end_matches(|c| c == '/' || c == '\\')
+            .rsplit(|c| c == '/' || c == '\\')
+            .next()
+            .unwrap_or_default();
+        format!("{}{}{}", to.trim_end_matches(sep), sep, name)
+    } else {
+        to.to_owned()
+    }
+}
+
+fn run_transfer(id: &str, path: String, to: String, is_remote: bool, opts: &CliOptions) -> i32 {
+    run_file_job(id, opts, |session, job_id| {
+        emit(
+            opts,
+            json!({ "event": "start", "job": job_id, "from": &path, "to": &to, "upload": !is_remote }),
+        );
+        session.send_files(
+            job_id,
+            fs::JobType::Generic as i32,
+            path,
+            to,
+            0,
+            opts.include_hidden,
+            is_remote,
+        );
+    })
+}
+
+/// Connect, start the job with `start` and report it until it is done.
+fn run_file_job(
+    id: &str,
+    opts: &CliOptions,
+    start: impl FnOnce(&UiSession<CliHandler>, i32),
+) -> i32 {
+    let (session, rx) = start_session(id, ConnType::FILE_TRANSFER, None, opts);
+    if let Err(code) = wait_connected(&session, &rx, opts) {
+        close_session(&session);
+        return code;
+    }
+    let job_id = fs::get_next_job_id();
+    start(&session, job_id);
+    let started = Instant::now();
+    let code = loop {
+        if let Some(timeout) = opts.timeout {
+            if started.elapsed() > timeout {
+                break fail(opts, EXIT_TIMEOUT, "Timeout");
+            }
+        }
+        match rx.recv_timeout(Duration::from_secs(1)) {
+            Ok(CliEvent::JobProgress(id, file_num, speed, finished_size)) if id == job_id => {
+                emit(
+                    opts,
+                    json!({
+                        "event": "progress",
+                        "job": id,
+                        "file_num": file_num,
+                        "speed": speed,
+                        "finished_size": finished_size,
+                    }),
+                );
+            }
+            Ok(CliEvent::JobDone(id, _)) if id == job_id => {
+                emit(opts, json!({ "event": "done", "job": id }));
+                break EXIT_OK;
+            }
+            Ok(CliEvent::JobError(id, err, file_num)) if id == job_id => {
+                emit(
+                    opts,
+                    json!({ "event": "error", "job": id, "file_num": file_num, "message": &err, "exit_code": EXIT_TRANSFER }),
+                );
+                break EXIT_TRANSFER;
+            }
+            Ok(CliEvent::OverrideFileConfirm(id, file_num, to, is_upload, is_identical)) => {
+                let overwrite = opts.overwrite && !is_identical;
+                emit(
+                    opts,
+                    json!({
+                        "event": if overwrite { "overwrite" } else { "skip" },
+                        "job": id,
+                        "file_num": file_num,
+                        "path": to,
+                        "identical": is_identical,
+                    }),
+                );
+                session.set_confirm_override_file(id, file_num, overwrite, true, is_upload);
+            }
+            Ok(CliEvent::SyncPlan(id, plan)) if id == job_id => {
+                let items: Vec<crate::file_sync::SyncItem> =
+                    serde_json::from_str(&plan).unwrap_or_default();
+                for item in items {
+                    let action = serde_json::to_value(item.action).unwrap_or_default();
+                    emit(
+                        opts,
+                        json!({
+                            "event": "plan",
+                            "job": id,
+                            "action": &action,
+                            "path": &item.path,
+                            "size": item.size,
+                            "message": format!("{} {}", action.as_str().unwrap_or_default(), item.path),
+                        }),
+                    );
+                }
+            }
+            Ok(CliEvent::MsgBox(msgtype, title, text)) if msgtype.contains("error") => {
+                break fail(opts, EXIT_CONNECT, &format!("{}: {}", title, text));
+            }
+            Ok(_) | Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => {
+                break fail(opts, EXIT_CONNECT, "Connection closed");
+            }
+        }
+    };
+    close_session(&session);
+    code
+}
+
+/// Download `remote` from the peer to `local`.
+pub fn files_get(id: &str, remote: &str, local: &str, opts: &CliOptions) -> i32 {
+    let to = transfer_target(remote, local, std::path::Path::new(local).is_dir());
+    run_transfer(id, remote.to_owned(), to, true, opts)
+}
+
+/// Mirror `local` and `remote` on the peer, only the plan is printed with `dry_run`.
+pub fn files_sync(
+    id: &str,
+    local: &str,
+    remote: &str,
+    mut options: SyncOptions,
+    opts: &CliOptions,
+) -> i32 {
+    options.include_hidden = opts.include_hidden;
+    run_file_job(id, opts, |session, job_id| {
+        emit(
+            opts,
+            json!({ "event": "start", "job": job_id, "local": local, "remote": remote, "sync": &options }),
+        );
+        session.sync_dir(job_id, local.to_owned(), remote.to_owned(), options);
+    })
+}
+
+/// Upload `local` to `remote` on the peer.
+pub fn files_put(id: &str, local: &str, remote: &str, opts: &CliOptions) -> i32 {
+    if !std::path::Path::new(local).exists() {
+        return fail(opts, EXIT_USAGE, &format!("{} does not exist", local));
+    }
+    let to = transfer_target(local, remote, false);
+    run_transfer(id, local.to_owned(), to, false, opts)
+}
+
+#[cfg(target_os = "linux")]
+struct RawMode(Option<termios::Termios>);
+
+#[cfg(target_os = "linux")]
+impl RawMode {
+    fn enable() -> Self {
+        use std::os::unix::io::AsRawFd;
+        let fd = std::io::stdin().as_raw_fd();
+        let Ok(old) = termios::Termios::from_fd(fd) else {
+            return Self(None);
+        };
+        let mut raw = old;
+        termios::cfmakeraw(&mut raw);
+        if termios::tcsetattr(fd, termios::TCSANOW, &raw).is_err() {
+            return Self(None);
+        }
+        Self(Some(old))
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        use std::os::unix::io::AsRawFd;
+        if let Some(old) = self.0.as_ref() {
+            termios::tcsetattr(std::io::stdin().as_raw_fd(), termios::TCSANOW, old).ok();
+        }
+    }
+}
+
+// The size of the terminal of stdout, else `LINES` and `COLUMNS`, else 24x80.
+fn terminal_size() -> (u32, u32) {
+    if let Some(size) = tty_size().filter(|(rows, cols)| *rows > 0 && *cols > 0) {
+        return size;
+    }
+    let get = |name: &str, default: u32| {
+        std::env::var(name)
+            .ok()
+            .and_then(|v| v.parse::<u32>().ok())
+            .filter(|v| *v > 0)
+            .unwrap_or(default)
+    };
+    (get("LINES", 24), get("COLUMNS", 80))
+}
+
+#[cfg(unix)]
+fn tty_size() -> Option<(u32, u32)> {
+    use hbb_common::libc;
+    let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+    if unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ as _, &mut size) } != 0 {
+        return None;
+    }
+    Some((size.ws_row as _, size.ws_col as _))
+}
+
+#[cfg(windows)]
+fn tty_size() -> Option<(u32, u32)> {
+    use windows::Win32::System::Console::{
+        GetConsoleScreenBufferInfo, GetStdHandle, CONSOLE_SCREEN_BUFFER_INFO, STD_OUTPUT_HANDLE,
+    };
+    let mut info = CONSOLE_SCREEN_BUFFER_INFO::default();
+    unsafe { GetConsoleScreenBufferInfo(GetStdHandle(STD_OUTPUT_HANDLE).ok()?, &mut info).ok()? };
+    let window = info.srWindow;
+    Some((
+        (window.Bottom - window.Top + 1).max(0) as _,
+        (window.Right - window.Left + 1).max(0) as _,
+    ))
+}
+
+fn spawn_stdin_forwarder(session: UiSession<CliHandler>, terminal_id: Arc<AtomicI32>) {
+    std::thread::spawn(move || {
+        let mut stdin = std::io::stdin();
+        let mut buf = [0u8; 4096];
+        loop {
+            match stdin.read(&mut buf) {
+                Ok(0) | Err(_) => break,
+                Ok(n) => {
+                    session.send_terminal_input(
+                        terminal_id.load(Ordering::SeqCst),
+                        String::from_utf8_lossy(&buf[..n]).to_string(),
+                    );
+                }
+            }
+        }
+    });
+}
+
+fn emit_viewers(opts: &CliOptions, viewers: &TerminalViewers) {
+    let list: Vec<serde_json::Value> = viewers
+        .viewers
+        .iter()
+        .map(|v| json!({ "conn_id": v.conn_id, "id": &v.peer_id, "name": &v.name }))
+        .collect();
+    let message = viewers
+        .viewers
+        .iter()
+        .map(|v| {
+            let mut tags = vec![v.peer_id.as_str()];
+            if v.conn_id == viewers.driver {
+                tags.push("driver");
+            }
+            if v.conn_id == viewers.conn_id {
+                tags.push("you");
+            }
+            format!("{} ({})", v.name, tags.join(", "))
+        })
+        .collect::<Vec<_>>()
+        .join(", ");
+    emit(
+        opts,
+        json!({
+            "event": "viewers",
+            "message": message,
+            "viewers": list,
+            "driver": viewers.driver,
+            "conn_id": viewers.conn_id,
+        }),
+    );
+}
+
+/// Drive one remote terminal until it is closed, returns the exit code of the remote shell.
+/// `shell` and `cwd` are empty for the defaults of the peer.
+fn run_terminal(
+    id: &str,
+    attach: Option<String>,
+    shell: &str,
+    cwd: &str,
+    opts: &CliOptions,
+) -> i32 {
+    let (session, rx) = start_session(id, ConnType::TERMINAL, attach, opts);
+    if let Err(code) = wait_connected(&session, &rx, opts) {
+        close_session(&session);
+        return code;
+    }
+    #[cfg(target_os = "linux")]
+    let mut _raw_mode = None;
+    // The terminal the input goes to. An attached viewer follows the terminal of the output.
+    let terminal_id = Arc::new(AtomicI32::new(TERMINAL_ID));
+    let attached = session.lc.read().unwrap().terminal_attach.is_some();
+    if attached {
+        #[cfg(target_os = "linux")]
+        if std::io::stdin().is_terminal() && !opts.json {
+            _raw_mode = Some(RawMode::enable());
+        }
+        spawn_stdin_forwarder(session.clone(), terminal_id.clone());
+    } else {
+        let (rows, cols) = terminal_size();
+        session.open_terminal_with_shell(TERMINAL_ID, rows, cols, shell.to_owned(), cwd.to_owned());
+    }
+    let started = Instant::now();
+    let mut stdout = std::io::stdout();
+    let code = loop {
+        if let Some(timeout) = opts.timeout {
+            if started.elapsed() > timeout {
+                if !attached {
+                    session.close_terminal(TERMINAL_ID);
+                }
+                break fail(opts, EXIT_TIMEOUT, "Timeout");
+            }
+        }
+        let response = match rx.recv_timeout(Duration::from_secs(1)) {
+            Ok(CliEvent::Terminal(response)) => response,
+            Ok(CliEvent::MsgBox(msgtype, title, text)) if msgtype.contains("error") => {
+                break fail(opts, EXIT_CONNECT, &format!("{}: {}", title, text));
+            }
+            Ok(_) | Err(RecvTimeoutError::Timeout) => continue,
+            Err(RecvTimeoutError::Disconnected) => {
+                break fail(opts, EXIT_CONNECT, "Connection closed");
+            }
+        };
+        match response.union {
+            Some(terminal_response::Union::Opened(opened)) => {
+                if !opened.success {
+                    break fail(opts, EXIT_ERROR, &opened.message);
+                }
+                emit(opts, json!({ "event": "opened", "pid": opened.pid }));
+                #[cfg(target_os = "linux")]
+                if std::io::stdin().is_terminal() && !opts.json {
+                    _raw_mode = Some(RawMode::enable());
+                }
+                spawn_stdin_forwarder(session.clone(), terminal_id.clone());
+            }
+            Some(terminal_response::Union::Data(data)) => {
+                terminal_id.store(data.terminal_id, Ordering::SeqCst);
+                let output = if data.compressed {
+                    hbb_common::compress::decompress(&data.data)
+                } else {
+                    data.data.to_vec()
+                };
+                if opts.json {
+                    emit(
+                        opts,
+                        json!({ "event": "output", "data": String::from_utf8_lossy(&output) }),
+                    );
+                } else {
+                    stdout.write_all(&output).ok();
+                    stdout.flush().ok();
+                }
+            }
+            Some(terminal_response::Union::Closed(closed)) => {
+                if closed.terminal_id != terminal_id.load(Ordering::SeqCst) {
+                    continue;
+                }
+                emit(
+                    opts,
+                    json!({ "event": "closed", "exit_code": closed.exit_code }),
+                );
+                break closed.exit_code;
+            }
+            Some(terminal_response::Union::Error(err)) => {
+                break fail(opts, EXIT_ERROR, &err.message);
+            }
+            Some(terminal_response::Union::Viewers(viewers)) => {
+                emit_viewers(opts, &viewers);
+            }
+            _ => {}
+        }
+    };
+    #[cfg(target_os = "linux")]
+    drop(_raw_mode);
+    close_session(&session);
+    code
+}
+
+/// Open an interactive shell on the peer, stdin and stdout are wired to the remote pty.
+///
+/// With `attach`, watch the shell of a running terminal service instead.
+/// The input is only accepted after the driver of the service hands the control over.
+/// `shell` and `cwd` choose among the shells and start directories allowed by the peer.
+pub fn terminal(
+    id: &str,
+    attach: Option<String>,
+    shell: String,
+    cwd: String,
+    opts: &CliOptions,
+) -> i32 {
+    run_terminal(id, attach, &shell, &cwd, opts)
+}
+
+/// Run `argv` on the peer without a shell and return its exit code.
+///
+/// The remote stdout and stderr are written to ours, stdin is sent along unless it is a terminal.
+/// Peers without the exec action fail with `EXIT_UNSUPPORTED`, as a shell would lose the quoting
+/// of the arguments, the exit code and the separation of stdout and stderr.
+pub fn exec(
+    id: &str,
+    argv: Vec<String>,
+    env: HashMap<String, String>,
+    cwd: String,
+    opts: &CliOptions,
+) -> i32 {
+    let (session, rx) = start_session(id, ConnType::TERMINAL, None, opts);
+    if let Err(code) = wait_connected(&session, &rx, opts) {
+        close_session(&session);
+        return code;
+    }
+    if !crate::common::is_support_remote_exec_num(session.lc.read().unwrap().version) {
+        close_session(&session);
+        return fail(
+            opts,
+            EXIT_UNSUPPORTED,
+            "The peer does not support exec, update it or use terminal",
+        );
+    }
+    let mut stdin = Vec::new();
+    if !std::io::stdin().is_terminal() {
+        std::io::stdin().read_to_end(&mut stdin).ok();
+    }
+    let timeout_ms = opts.timeout.map_or(0, |t| t.as_millis() as u32);
+    session.exec_command(EXEC_ID, argv, env, cwd, stdin, timeout_ms);
+    let started = Instant::now();
+    let mut stdout = std::io::stdout();
+    let mut stderr = std::io::stderr();
+    let code = loop {
+        if let Some(timeout) = opts.timeout {
+            if started.elapsed() > timeout + EXEC_TIMEOUT_GRACE {
+                break fail(opts, EXIT_TIMEOUT, "Timeout");
+            }
+        }
+        let response = match rx.recv_timeout(Duration::from_secs(1)) {
+            Ok(CliEvent::Terminal(response)) => response,
+            Ok(CliEvent::MsgBox(msgtype, title, text)) if msgtype.contains("error") => {
+                break fail(opts, EXIT_CONNECT, &format!("{}: {}", title, text));
+            }
+            Ok(_) | Err(RecvTimeoutError::Timeout) => continue,
+            Err(RecvTimeoutError::Disconnected) => {
+                break fail(opts, EXIT_CONNECT, "Connection closed");
+            }
+        };
+        match response.union {
+            Some(terminal_response::Union::ExecOutput(output)) => {
+                if opts.json {
+                    for (name, data) in [("stdout", &output.stdout), ("stderr", &output.stderr)] {
+                        if !data.is_empty() {
+                            emit(
+                                opts,
+                                json!({ "event": name, "data": String::from_utf8_lossy(data) }),
+                            );
+                        }
+                    }
+                } else {
+                    stdout.write_all(&output.stdout).ok();
+                    stdout.flush().ok();
+                    stderr.write_all(&output.stderr).ok();
+                }
+            }
+            Some(terminal_response::Union::ExecResult(result)) => {
+                if !result.error.is_empty() {
+                    break fail(opts, EXIT_ERROR, &result.error);
+                }
+                if result.timed_out {
+                    break fail(opts, EXIT_TIMEOUT, "Timeout");
+                }
+                emit(
+                    opts,
+                    json!({ "event": "exited", "exit_code": result.exit_code }),
+                );
+                break result.exit_code;
+            }
+            Some(terminal_response::Union::Error(err)) => {
+                break fail(opts, EXIT_ERROR, &err.message);
+            }
+            _ => {}
+        }
+    };
+    close_session(&session);
+    code
+}
+
+/// Wake `id` through an online peer on its subnet, then wait until it is online.
+///
+/// The LAN interfaces of the peer are remembered from the last connection to it. The relay is
+/// `via`, or any online peer which reported an interface on the same subnet.
+pub fn wake(id: &str, via: Option<String>, opts: &CliOptions) -> i32 {
+    let targets = crate::lan::load_peer_interfaces(&PeerConfig::load(id).options);
+    if targets.is_empty() {
+        return fail(
+            opts,
+            EXIT_ERROR,
+            "The LAN interfaces of the peer are unknown, connect to it once first",
+        );
+    }
+    if is_online(id) {
+        emit(opts, json!({ "event": "online", "id": id }));
+        return EXIT_OK;
+    }
+    // It may be on our own LAN as well.
+    crate::lan::send_wol(id.to_owned());
+    let relays = match via {
+        Some(via) => vec![via],
+        None => {
+            let candidates: Vec<String> = PeerConfig::peers(None)
+                .into_iter()
+                .filter(|(peer, _, config)| {
+                    peer != id
+                        && crate::lan::load_peer_interfaces(&config.options)
+                            .iter()
+                            .any(|i| targets.iter().any(|t| crate::lan::is_on_subnet(i, t)))
+                })
+                .map(|(peer, _, _)| peer)
+                .collect();
+            let online = query_online(candidates.clone());
+            candidates
+                .into_iter()
+                .filter(|c| online.contains(c))
+                .collect()
+        }
+    };
+    if relays.is_empty() {
+        return fail(
+            opts,
+            EXIT_CONNECT,
+            "No peer on the subnet of the peer is online",
+        );
+    }
+    let relayed = relays.iter().any(|relay| {
+        emit(opts, json!({ "event": "relay", "id": relay }));
+        match relay_wake(relay, &targets, opts) {
+            Ok(()) => true,
+            Err(e) => {
+                emit(
+                    opts,
+                    json!({ "event": "relay_failed", "id": relay, "message": e }),
+                );
+                false
+            }
+        }
+    });
+    if !relayed {
+        return fail(opts, EXIT_ERROR, "No peer could send the magic packet");
+    }
+    let timeout = opts.timeout.unwrap_or(WAKE_TIMEOUT);
+    let started = Instant::now();
+    while started.elapsed() < timeout {
+        std::thread::sleep(ONLINE_POLL_INTERVAL);
+        if is_online(id) {
+            emit(opts, json!({ "event": "online", "id": id }));
+            return EXIT_OK;
+        }
+    }
+    fail(opts, EXIT_TIMEOUT, "Timeout")
+}
+
+/// Connect to `relay` and ask it to send the magic packets.
+fn relay_wake(relay: &str, targets: &[LanInterface], opts: &CliOptions) -> Result<(), String> {
+    let (session, rx) = start_session(relay, ConnType::DEFAULT_CONN, None, opts);
+    if wait_connected(&session, &rx, opts).is_err() {
+        close_session(&session);
+        return Err("Failed to connect".to_owned());
+    }
+    session.wake_on_lan(targets.to_vec());
+    let started = Instant::now();
+    let res = loop {
+        let Some(timeout) = WAKE_RESPONSE_TIMEOUT.checked_sub(started.elapsed()) else {
+            break Err("The peer does not support relaying wake-on-lan".to_owned());
+        };
+        match rx.recv_timeout(timeout) {
+            Ok(CliEvent::WakeOnLan(response)) if response.error.is_empty() => break Ok(()),
+            Ok(CliEvent::WakeOnLan(response)) => break Err(response.error),
+            Ok(_) => {}
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => break Err("Connection closed".to_owned()),
+        }
+    };
+    close_session(&session);
+    res
+}
+
+fn is_online(id: &str) -> bool {
+    query_online(vec![id.to_owned()]).iter().any(|x| x == id)
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn query_online(ids: Vec<String>) -> Vec<String> {
+    if ids.is_empty() {
+        return Vec::new();
+    }
+    let mut online = Vec::new();
+    crate::client::peer_online::query_online_states(ids, |onlines, _| online = onlines).await;
+    online
+}
+
 #[tokio::main(flavor = "current_thread")]
 pub async fn connect_test(id: &str, key: String, token: String) {
-    let (sender, mut receiver) = mpsc::unbounded_channel::<Data>();
+    let (sender, mut _receiver) = mpsc::unbounded_channel::<Data>();
     let handler = Session::new(&id, sender);
     match crate::client::Client::start(id, &key, &token, ConnType::PORT_FORWARD, handler).await {
         Err(err) => {
             log::error!("Failed to connect {}: {}", &id, err);
         }
-        Ok((mut stream, direct)) => {
+        Ok(((mut stream, direct, ..), _)) => {
             log::info!("direct: {}", direct);
             // rpassword::prompt_password("Input anything to exit").ok();
             loop {
@@ -144,7 +1117,7 @@ pub async fn connect_test(id: &str, key: String, token: String) {
                         Ok(Some(Ok(bytes))) => {
                             if let Ok(msg_in) = Message::parse_from_bytes(&bytes) {
                                 match msg_in.union {
-                                    Some(message::Union::Hash(hash)) => {
+                                    Some(message::Union::Hash(_hash)) => {
                                         log::info!("Got hash");
                                         break;
                                     }
@@ -166,28 +1139,245 @@ pub async fn start_one_port_forward(
     port: i32,
     remote_host: String,
     remote_port: i32,
+    udp: bool,
+    bind: String,
+    allow: String,
+    key: String,
+    token: String,
+) {
+    let opts = match crate::port_forward::parse_allow_list(&allow) {
+        Ok(allow) => ListenOptions {
+            bind,
+            allow,
+            ..Default::default()
+        },
+        Err(err) => {
+            log::error!("{}", err);
+            return;
+        }
+    };
+    crate::common::test_rendezvous_server();
+    crate::common::test_nat_type();
+    let (sender, receiver) = mpsc::unbounded_channel::<Data>();
+    let handler = Session::new(&id, sender);
+    let res = if udp {
+        crate::port_forward::listen_udp(
+            handler.id.clone(),
+            handler.password.clone(),
+            port,
+            handler.clone(),
+            receiver,
+            &key,
+            &token,
+            handler.lc.clone(),
+            remote_host,
+            remote_port,
+            opts,
+        )
+        .await
+    } else {
+        crate::port_forward::listen(
+            handler.id.clone(),
+            handler.password.clone(),
+            port,
+            handler.clone(),
+            receiver,
+            &key,
+            &token,
+            handler.lc.clone(),
+            remote_host,
+            remote_port,
+            opts,
+        )
+        .await
+    };
+    if let Err(err) = res {
+        log::error!("Failed to listen on {}: {}", port, err);
+    }
+    log::info!("port forward (:{}) exit", port);
+}
+
+#[tokio::main(flavor = "current_thread")]
+pub async fn start_one_reverse_forward(
+    id: String,
+    bind: String,
+    remote_port: i32,
+    local_host: String,
+    local_port: i32,
     key: String,
     token: String,
 ) {
     crate::common::test_rendezvous_server();
     crate::common::test_nat_type();
-    let (sender, mut receiver) = mpsc::unbounded_channel::<Data>();
+    let (sender, receiver) = mpsc::unbounded_channel::<Data>();
     let handler = Session::new(&id, sender);
-    if let Err(err) = crate::port_forward::listen(
+    if let Err(err) = crate::port_forward::listen_reverse(
         handler.id.clone(),
         handler.password.clone(),
-        port,
         handler.clone(),
         receiver,
         &key,
         &token,
         handler.lc.clone(),
-        remote_host,
+        bind,
         remote_port,
+        local_host,
+        local_port,
     )
     .await
     {
-        log::error!("Failed to listen on {}: {}", port, err);
+        log::error!("Failed to forward remote port {}: {}", remote_port, err);
+    }
+    log::info!("reverse port forward (:{}) exit", remote_port);
+}
+
+/// Bring up all the enabled profiles of [`ForwardProfiles::path`] and keep them up until killed.
+#[tokio::main]
+pub async fn run_forward_profiles(key: String, token: String) -> i32 {
+    let path = ForwardProfiles::path();
+    let profiles: Vec<ForwardProfile> = ForwardProfiles::load()
+        .profiles
+        .into_iter()
+        .filter(|p| !p.disabled)
+        .collect();
+    if profiles.is_empty() {
+        log::error!("No port forward profile in {}", path.display());
+        return EXIT_USAGE;
+    }
+    let mut tunnels = Vec::new();
+    for profile in profiles {
+        if let Err(err) = profile.check() {
+            log::error!("{}: {}", path.display(), err);
+            return EXIT_USAGE;
+        }
+        let stats = Arc::new(Stats::default());
+        tunnels.push((profile.clone(), stats.clone()));
+        tokio::spawn(run_forward_profile(
+            profile,
+            stats,
+            key.clone(),
+            token.clone(),
+        ));
+    }
+    if let Err(err) = crate::port_forward::profile::serve_status(tunnels).await {
+        log::error!("Failed to serve port forward status: {}", err);
+    }
+    std::future::pending::<()>().await;
+    EXIT_OK
+}
+
+async fn run_forward_profile(
+    profile: ForwardProfile,
+    stats: Arc<Stats>,
+    key: String,
+    token: String,
+) {
+    let mut backoff = MIN_BACKOFF;
+    loop {
+        let (sender, receiver) = mpsc::unbounded_channel::<Data>();
+        let handler = Session::with_password(&profile.peer, sender, "".to_owned());
+        let opts = ListenOptions {
+            bind: profile.bind.clone(),
+            // Checked before the profile is started.
+            allow: profile.allow_list().unwrap_or_default(),
+            keep_alive: true,
+            stats: stats.clone(),
+        };
+        log::info!("port forward {} to {} started", profile.name, profile.peer);
+        // Errors of the session are retried inside, only listener errors end up here.
+        let res = if profile.udp {
+            crate::port_forward::listen_udp(
+                handler.id.clone(),
+                handler.password.clone(),
+                profile.local_port,
+                handler.clone(),
+                receiver,
+                &key,
+                &token,
+                handler.lc.clone(),
+                profile.remote_host(),
+                profile.remote_port,
+                opts,
+            )
+            .await
+        } else {
+            crate::port_forward::listen(
+                handler.id.clone(),
+                handler.password.clone(),
+                profile.local_port,
+                handler.clone(),
+                receiver,
+                &key,
+                &token,
+                handler.lc.clone(),
+                profile.remote_host(),
+                profile.remote_port,
+                opts,
+            )
+            .await
+        };
+        let err = res.err().map(|e| e.to_string()).unwrap_or_default();
+        log::error!(
+            "port forward {} stopped, retry in {:?}: {}",
+            profile.name,
+            backoff,
+            err
+        );
+        stats.on_disconnected(Some(err));
+        tokio::time::sleep(backoff).await;
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+}
+
+/// Print the status of the profiles brought up by [`run_forward_profiles`].
+#[tokio::main(flavor = "current_thread")]
+pub async fn forward_status(json: bool) -> i32 {
+    let status = match crate::port_forward::profile::get_status().await {
+        Ok(status) => status,
+        Err(err) => {
+            eprintln!("No port forward profiles running: {}", err);
+            return EXIT_CONNECT;
+        }
+    };
+    if json {
+        println!("{}", serde_json::to_string(&status).unwrap_or_default());
+        return EXIT_OK;
+    }
+    for s in status {
+        println!(
+            "{}\t{}\t:{}\t{}\tsent {}\treceived {}\t{}",
+            s.name,
+            s.peer,
+            s.local_port,
+            if s.connected { "up" } else { "down" },
+            s.sent,
+            s.received,
+            if s.last_error.is_empty() {
+                "".to_owned()
+            } else {
+                format!("retries {}: {}", s.retries, s.last_error)
+            }
+        );
+    }
+    EXIT_OK
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_transfer_target() {
+        assert_eq!(
+            transfer_target("/a/b.txt", "/tmp/c.txt", false),
+            "/tmp/c.txt"
+        );
+        assert_eq!(transfer_target("/a/b.txt", "/tmp/", false), "/tmp/b.txt");
+        assert_eq!(transfer_target("/a/b.txt", "/tmp", true), "/tmp/b.txt");
+        assert_eq!(transfer_target("/a/dir/", "/tmp", true), "/tmp/dir");
+        assert_eq!(
+            transfer_target("/a/b.txt", "C:\\Users\\", false),
+            "C:\\Users\\b.txt"
+        );
     }
-    log::info!("port forward (:{}) exit", port);
 }
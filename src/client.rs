@@ -1647,6 +1647,23 @@ impl VideoHandler {
         self.first_frame = true;
     }
 
+    /// Write the audio of the session to the recording.
+    pub fn record_audio(&mut self, frame: &AudioFrame) {
+        if self.record {
+            if let Some(r) = self.recorder.lock().unwrap().as_mut() {
+                r.write_audio(frame);
+            }
+        }
+    }
+
+    pub fn record_event(&mut self, text: &str) {
+        if self.record {
+            if let Some(r) = self.recorder.lock().unwrap().as_mut() {
+                r.write_event(text);
+            }
+        }
+    }
+
     /// Start or stop screen record.
     pub fn record_screen(&mut self, start: bool, id: String, display_idx: usize, camera: bool) {
         self.record = false;
@@ -1728,6 +1745,8 @@ pub struct LoginConfigHandler {
     id: String,
     pub conn_type: ConnType,
     pub is_terminal_admin: bool,
+    /// Attach to the running terminal service with this id.
+    pub terminal_attach: Option<String>,
     hash: Hash,
     password: Vec<u8>, // remember password for reconnect
     pub remember: bool,
@@ -2548,6 +2567,10 @@ impl LoginConfigHandler {
                 .options
                 .insert("force-always-relay".to_owned(), "Y".to_owned());
         }
+        // To wake the peer through another one on its subnet later.
+        if !pi.lan_interfaces.is_empty() {
+            crate::lan::store_peer_interfaces(&mut config.options, &pi.lan_interfaces);
+        }
         #[cfg(feature = "flutter")]
         {
             // sync connected password to personal ab automatically if it is not shared password
@@ -2683,6 +2706,13 @@ impl LoginConfigHandler {
             hwid,
             ..Default::default()
         };
+        #[cfg(all(target_os = "windows", feature = "flutter"))]
+        {
+            lr.print_formats = crate::print_job::accepted_formats()
+                .into_iter()
+                .map(|f| f.into())
+                .collect();
+        }
         match self.conn_type {
             ConnType::FILE_TRANSFER => lr.set_file_transfer(FileTransfer {
                 dir: self.get_remote_dir(),
@@ -2697,7 +2727,12 @@ impl LoginConfigHandler {
             }),
             ConnType::TERMINAL => {
                 let mut terminal = Terminal::new();
-                terminal.service_id = self.get_option(self.get_key_terminal_service_id());
+                if let Some(service_id) = self.terminal_attach.as_ref() {
+                    terminal.service_id = service_id.clone();
+                    terminal.attach = true;
+                } else {
+                    terminal.service_id = self.get_option(self.get_key_terminal_service_id());
+                }
                 lr.set_terminal(terminal);
             }
             _ => {}
@@ -2766,6 +2801,7 @@ pub enum MediaData {
     AudioFormat(AudioFormat),
     Reset,
     RecordScreen(bool),
+    RecordEvent(String),
 }
 
 pub type MediaSender = mpsc::Sender<MediaData>;
@@ -2917,6 +2953,16 @@ pub fn start_video_thread<F, T>(
                             handler.record_screen(start, id, display, is_view_camera);
                         }
                     }
+                    MediaData::AudioFrame(af) => {
+                        if let Some(handler) = video_handler.as_mut() {
+                            handler.record_audio(&af);
+                        }
+                    }
+                    MediaData::RecordEvent(text) => {
+                        if let Some(handler) = video_handler.as_mut() {
+                            handler.record_event(&text);
+                        }
+                    }
                     _ => {}
                 }
             } else {
@@ -3684,6 +3730,9 @@ pub enum Data {
     SetConfirmOverrideFile((i32, i32, bool, bool, bool)),
     AddJob((i32, JobType, String, String, i32, bool, bool)),
     ResumeJob((i32, bool)),
+    LocalChecksum((i32, i32, Result<String, String>)),
+    DeltaSignature((i32, i32, String, Result<crate::file_delta::Signature, String>)),
+    SyncDir((i32, String, String, crate::file_sync::SyncOptions)),
     RecordScreen(bool),
     ElevateDirect,
     ElevateWithLogon(String, String),
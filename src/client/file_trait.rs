@@ -161,6 +161,17 @@ pub trait FileManager: Interface {
         )));
     }
 
+    /// Mirror `local` and `remote`, see [`crate::file_sync`].
+    fn sync_dir(
+        &self,
+        id: i32,
+        local: String,
+        remote: String,
+        options: crate::file_sync::SyncOptions,
+    ) {
+        self.send(Data::SyncDir((id, local, remote, options)));
+    }
+
     fn resume_job(&self, id: i32, is_remote: bool) {
         self.send(Data::ResumeJob((id, is_remote)));
     }
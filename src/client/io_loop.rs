@@ -8,6 +8,9 @@ use crate::{
         QualityStatus, MILLI1, SEC30,
     },
     common::get_default_sound_input,
+    file_delta::{self, Decoder, Signature},
+    file_journal::{Journals, Verification},
+    file_sync::{SyncAction, SyncJob, SyncJobs, SyncOptions, SyncStage},
     ui_session_interface::{InvokeUiSession, Session},
 };
 #[cfg(feature = "unix-file-copy-paste")]
@@ -64,6 +67,13 @@ pub struct Remote<T: InvokeUiSession> {
     read_jobs: Vec<fs::TransferJob>,
     write_jobs: Vec<fs::TransferJob>,
     remove_jobs: HashMap<i32, RemoveJob>,
+    journals: Journals,
+    // The local and remote paths of the files of the upload jobs, by job id.
+    upload_files: HashMap<i32, Vec<(i32, String, String)>>,
+    verifications: HashMap<i32, Verification>,
+    // The decoders of the files sent as deltas against our copies, by job id and file num.
+    deltas: HashMap<(i32, i32), Decoder>,
+    syncs: SyncJobs,
     timer: crate::RustDeskInterval,
     last_update_jobs_status: (Instant, HashMap<i32, u64>),
     is_connected: bool,
@@ -111,6 +121,11 @@ impl<T: InvokeUiSession> Remote<T> {
             read_jobs: Vec::new(),
             write_jobs: Vec::new(),
             remove_jobs: Default::default(),
+            journals: Journals::new(false),
+            upload_files: Default::default(),
+            verifications: Default::default(),
+            deltas: Default::default(),
+            syncs: Default::default(),
             timer: crate::rustdesk_interval(time::interval(SEC30)),
             last_update_jobs_status: (Instant::now(), Default::default()),
             is_connected: false,
@@ -408,6 +423,10 @@ impl<T: InvokeUiSession> Remote<T> {
     }
 
     fn handle_job_status(&mut self, id: i32, file_num: i32, err: Option<String>) {
+        if self.syncs.contains_key(&id) {
+            self.on_sync_status(id, err);
+            return;
+        }
         if let Some(job) = self.remove_jobs.get_mut(&id) {
             if job.no_confirm {
                 let file_num = (file_num + 1) as usize;
@@ -434,6 +453,321 @@ impl<T: InvokeUiSession> Remote<T> {
         }
     }
 
+    fn is_support_file_checksum(&self) -> bool {
+        crate::is_support_file_checksum_num(self.handler.lc.read().unwrap().version)
+    }
+
+    fn is_file_delta_enabled(&self) -> bool {
+        config::option2bool(
+            file_delta::OPTION_FILE_TRANSFER_DELTA,
+            &LocalConfig::get_option(file_delta::OPTION_FILE_TRANSFER_DELTA),
+        ) && crate::is_support_file_delta_num(self.handler.lc.read().unwrap().version)
+    }
+
+    // Rebuild the data of a block sent as a delta, the decoders of the previous files are dropped.
+    fn decode_delta(
+        &mut self,
+        mut block: FileTransferBlock,
+    ) -> hbb_common::ResultType<FileTransferBlock> {
+        let key = (block.id, block.file_num);
+        self.deltas.retain(|k, _| k.0 != key.0 || k.1 >= key.1);
+        if let Some(decoder) = self.deltas.get(&key) {
+            let data = if block.compressed {
+                hbb_common::compress::decompress(&block.data)
+            } else {
+                block.data.to_vec()
+            };
+            block.data = decoder.decode(&data)?.into();
+            block.compressed = false;
+        }
+        Ok(block)
+    }
+
+    fn remote_path(&self, dir: &str, name: &str) -> String {
+        if name.is_empty() {
+            return dir.to_owned();
+        }
+        let sep = self.handler.get_path_sep(true);
+        let name = if sep == "/" {
+            name.replace('\\', "/")
+        } else {
+            name.to_owned()
+        };
+        format!("{}{}{}", dir.trim_end_matches(sep), sep, name)
+    }
+
+    fn add_upload_files(&mut self, job: &fs::TransferJob, path: &str, to: &str) {
+        let files = job
+            .files()
+            .iter()
+            .enumerate()
+            .map(|(i, f)| {
+                (
+                    i as i32,
+                    get_string(&fs::TransferJob::join(&PathBuf::from(path), &f.name)),
+                    self.remote_path(to, &f.name),
+                )
+            })
+            .collect();
+        self.upload_files.insert(job.id(), files);
+    }
+
+    // Ask the peer for the sha256 of every file of a done job, which is reported done once they match.
+    async fn start_verification(
+        &mut self,
+        id: i32,
+        verification: Verification,
+        requests: Vec<(i32, String)>,
+        is_upload: bool,
+        peer: &mut Stream,
+    ) {
+        for (file_num, path) in requests {
+            let mut file_action = FileAction::new();
+            file_action.set_checksum(FileChecksumRequest {
+                id,
+                file_num,
+                path,
+                is_upload,
+                ..Default::default()
+            });
+            let mut msg_out = Message::new();
+            msg_out.set_file_action(file_action);
+            allow_err!(peer.send(&msg_out).await);
+        }
+        self.verifications.insert(id, verification);
+    }
+
+    fn check_verification(&mut self, id: i32) {
+        if self.verifications.get(&id).map(|v| v.is_done()) == Some(true) {
+            if let Some(v) = self.verifications.remove(&id) {
+                self.handle_job_status(id, v.file_num, v.error());
+            }
+        }
+    }
+
+    fn start_sync(&mut self, id: i32, local: String, remote: String, options: SyncOptions) {
+        if !can_enable_overwrite_detection(self.handler.lc.read().unwrap().version) {
+            self.handler
+                .job_error(id, "Not supported by the remote side".to_owned(), -1);
+            return;
+        }
+        let include_hidden = options.include_hidden;
+        let local_files = if PathBuf::from(&local).exists() {
+            match fs::get_recursive_files(&local, include_hidden) {
+                Ok(files) => files,
+                Err(err) => {
+                    self.handler.job_error(id, err.to_string(), -1);
+                    return;
+                }
+            }
+        } else {
+            Vec::new()
+        };
+        let local_dirs = fs::get_empty_dirs_recursive(&local, include_hidden).unwrap_or_default();
+        let sync = SyncJob::new(local, remote.clone(), options, &local_files, &local_dirs);
+        self.syncs.insert(id, sync);
+        let mut file_action = FileAction::new();
+        file_action.set_all_files(ReadAllFiles {
+            id,
+            path: remote.clone(),
+            include_hidden,
+            ..Default::default()
+        });
+        let mut msg_out = Message::new();
+        msg_out.set_file_action(file_action);
+        self.sender.send(Data::Message(msg_out)).ok();
+        let mut file_action = FileAction::new();
+        file_action.set_read_empty_dirs(ReadEmptyDirs {
+            path: remote,
+            include_hidden,
+            ..Default::default()
+        });
+        let mut msg_out = Message::new();
+        msg_out.set_file_action(file_action);
+        self.sender.send(Data::Message(msg_out)).ok();
+    }
+
+    // Hash the candidates with `checksum` once both trees are listed, then make the plan.
+    fn check_sync_listed(&mut self, id: i32) {
+        let Some(sync) = self.syncs.get_mut(&id) else {
+            return;
+        };
+        if !sync.is_listed() || sync.stage != SyncStage::Listing {
+            return;
+        }
+        let requests = sync.start_hashing();
+        if requests.is_empty() {
+            self.start_sync_plan(id);
+            return;
+        }
+        let (local, remote) = (PathBuf::from(&sync.local), sync.remote.clone());
+        for (num, path) in requests {
+            let mut file_action = FileAction::new();
+            file_action.set_checksum(FileChecksumRequest {
+                id,
+                file_num: num,
+                path: self.remote_path(&remote, &path),
+                is_upload: false,
+                ..Default::default()
+            });
+            let mut msg_out = Message::new();
+            msg_out.set_file_action(file_action);
+            self.sender.send(Data::Message(msg_out)).ok();
+            let path = get_string(&local.join(&path));
+            let sender = self.sender.clone();
+            tokio::task::spawn_blocking(move || {
+                let res = crate::file_journal::file_sha256(&path).map_err(|e| e.to_string());
+                sender.send(Data::LocalChecksum((id, num, res))).ok();
+            });
+        }
+    }
+
+    // Report the plan, and carry it out unless it is a dry run.
+    fn start_sync_plan(&mut self, id: i32) {
+        let Some(sync) = self.syncs.get_mut(&id) else {
+            return;
+        };
+        sync.make_plan();
+        self.handler.sync_dir_plan(id, sync.plan_json());
+        if sync.options.dry_run {
+            self.syncs.remove(&id);
+            self.handler.job_done(id, -1);
+            return;
+        }
+        sync.stage = SyncStage::RemoteOps;
+        let local = PathBuf::from(&sync.local);
+        let removals: Vec<_> = sync.items(SyncAction::DeleteLocal).cloned().collect();
+        for item in removals {
+            let path = local.join(&item.path);
+            if item.is_dir {
+                // Not empty if it holds files which are not synced.
+                if let Err(err) = std::fs::remove_dir(&path) {
+                    log::warn!("Failed to remove {:?} of sync: {}", path, err);
+                }
+            } else if let Err(err) = std::fs::remove_file(&path) {
+                log::error!("Failed to remove {:?} of sync: {}", path, err);
+                sync.error.get_or_insert(err.to_string());
+            }
+        }
+        for item in sync.items(SyncAction::CreateLocalDir) {
+            allow_err!(std::fs::create_dir_all(local.join(&item.path)));
+        }
+        let remote_ops: Vec<_> = sync
+            .plan
+            .iter()
+            .filter(|i| {
+                matches!(
+                    i.action,
+                    SyncAction::DeleteRemote | SyncAction::CreateRemoteDir
+                )
+            })
+            .cloned()
+            .collect();
+        sync.pending = remote_ops.len();
+        let remote = sync.remote.clone();
+        for item in remote_ops {
+            let path = self.remote_path(&remote, &item.path);
+            let mut file_action = FileAction::new();
+            match (item.action, item.is_dir) {
+                (SyncAction::CreateRemoteDir, _) => file_action.set_create(FileDirCreate {
+                    id,
+                    path,
+                    ..Default::default()
+                }),
+                (_, true) => file_action.set_remove_dir(FileRemoveDir {
+                    id,
+                    path,
+                    recursive: false,
+                    ..Default::default()
+                }),
+                (_, false) => file_action.set_remove_file(FileRemoveFile {
+                    id,
+                    path,
+                    ..Default::default()
+                }),
+            }
+            let mut msg_out = Message::new();
+            msg_out.set_file_action(file_action);
+            self.sender.send(Data::Message(msg_out)).ok();
+        }
+        if self.syncs.get(&id).map(|s| s.pending) == Some(0) {
+            self.next_sync_stage(id);
+        }
+    }
+
+    // The download job, then the upload job, reuse the id of the sync job.
+    fn next_sync_stage(&mut self, id: i32) {
+        let Some(sync) = self.syncs.get_mut(&id) else {
+            return;
+        };
+        let include_hidden = sync.options.include_hidden;
+        let (local, remote) = (sync.local.clone(), sync.remote.clone());
+        if sync.stage < SyncStage::Download && sync.items(SyncAction::Download).next().is_some() {
+            sync.stage = SyncStage::Download;
+            self.sender
+                .send(Data::SendFiles((
+                    id,
+                    fs::JobType::Generic,
+                    remote,
+                    local,
+                    0,
+                    include_hidden,
+                    true,
+                )))
+                .ok();
+        } else if sync.stage < SyncStage::Upload && sync.items(SyncAction::Upload).next().is_some()
+        {
+            sync.stage = SyncStage::Upload;
+            self.sender
+                .send(Data::SendFiles((
+                    id,
+                    fs::JobType::Generic,
+                    local,
+                    remote,
+                    0,
+                    include_hidden,
+                    false,
+                )))
+                .ok();
+        } else if let Some(sync) = self.syncs.remove(&id) {
+            match sync.error {
+                Some(err) => self.handler.job_error(id, err, -1),
+                None => self.handler.job_done(id, -1),
+            }
+        }
+    }
+
+    fn on_sync_status(&mut self, id: i32, err: Option<String>) {
+        let Some(sync) = self.syncs.get_mut(&id) else {
+            return;
+        };
+        match (sync.stage, err) {
+            (SyncStage::Listing, Some(err)) => {
+                if sync.set_remote_missing() {
+                    log::info!("Sync to the missing {}: {}", sync.remote, err);
+                    self.check_sync_listed(id);
+                } else {
+                    self.syncs.remove(&id);
+                    self.handler.job_error(id, err, -1);
+                }
+            }
+            (SyncStage::RemoteOps, err) => {
+                if let Some(err) = err {
+                    sync.error.get_or_insert(err);
+                }
+                sync.pending = sync.pending.saturating_sub(1);
+                if sync.pending == 0 {
+                    self.next_sync_stage(id);
+                }
+            }
+            (SyncStage::Download | SyncStage::Upload, None) => self.next_sync_stage(id),
+            (_, err) => {
+                self.syncs.remove(&id);
+                self.handler.job_error(id, err.unwrap_or_default(), -1);
+            }
+        }
+    }
+
     fn stop_voice_call(&mut self) {
         let voice_call_sender = std::mem::replace(&mut self.stop_voice_call_sender, None);
         if let Some(stopper) = voice_call_sender {
@@ -565,13 +899,18 @@ impl<T: InvokeUiSession> Remote<T> {
                     },
                     _ => {}
                 }
+                if let Some(event) = crate::common::get_record_event(&msg) {
+                    self.record_event(event);
+                }
                 allow_err!(peer.send(&msg).await);
             }
             Data::SendFiles((id, r#type, path, to, file_num, include_hidden, is_remote)) => {
                 log::info!("send files, is remote {}", is_remote);
+                self.record_event(crate::common::get_file_record_event(!is_remote, &path));
                 let od = can_enable_overwrite_detection(self.handler.lc.read().unwrap().version);
                 if is_remote {
                     log::debug!("New job {}, write to {} from remote {}", id, to, path);
+                    let delta = self.is_file_delta_enabled();
                     let to = match r#type {
                         fs::JobType::Generic => fs::DataSource::FilePath(PathBuf::from(&to)),
                         fs::JobType::Printer => {
@@ -590,8 +929,15 @@ impl<T: InvokeUiSession> Remote<T> {
                         od,
                     ));
                     allow_err!(
-                        peer.send(&fs::new_send(id, r#type, path, file_num, include_hidden))
-                            .await
+                        peer.send(&file_delta::new_send(
+                            id,
+                            r#type,
+                            path,
+                            file_num,
+                            include_hidden,
+                            delta
+                        ))
+                        .await
                     );
                 } else {
                     match fs::TransferJob::new_read(
@@ -607,7 +953,11 @@ impl<T: InvokeUiSession> Remote<T> {
                         Err(err) => {
                             self.handle_job_status(id, -1, Some(err.to_string()));
                         }
-                        Ok(job) => {
+                        Ok(mut job) => {
+                            if let Some(sync) = self.syncs.get(&id) {
+                                job.set_files(sync.upload_files(job.files()));
+                                job.set_overwrite_strategy(Some(true));
+                            }
                             log::debug!(
                                 "New job {}, read {} to remote {}, {} files",
                                 id,
@@ -631,7 +981,8 @@ impl<T: InvokeUiSession> Remote<T> {
                                 // peer is not windows, need transform \ to /
                                 fs::transform_windows_path(&mut files);
                             }
-                            let total_size = job.total_size();
+                            let total_size = files.iter().map(|f| f.size).sum();
+                            self.add_upload_files(&job, &path, &to);
                             self.read_jobs.push(job);
                             self.timer = crate::rustdesk_interval(time::interval(MILLI1));
                             allow_err!(
@@ -694,6 +1045,7 @@ impl<T: InvokeUiSession> Remote<T> {
                                 true,
                             );
                             job.is_last_job = true;
+                            self.add_upload_files(&job, &path, &to);
                             self.read_jobs.push(job);
                             self.timer = crate::rustdesk_interval(time::interval(MILLI1));
                         }
@@ -702,16 +1054,20 @@ impl<T: InvokeUiSession> Remote<T> {
             }
             Data::ResumeJob((id, is_remote)) => {
                 if is_remote {
+                    self.journals.suspend(id);
+                    self.deltas.retain(|k, _| k.0 != id);
+                    let delta = self.is_file_delta_enabled();
                     if let Some(job) = get_job(id, &mut self.write_jobs) {
                         job.is_last_job = false;
                         job.is_resume = true;
                         allow_err!(
-                            peer.send(&fs::new_send(
+                            peer.send(&file_delta::new_send(
                                 id,
                                 fs::JobType::Generic,
                                 job.remote.clone(),
                                 job.file_num,
-                                job.show_hidden
+                                job.show_hidden,
+                                delta
                             ))
                             .await
                         );
@@ -787,10 +1143,20 @@ impl<T: InvokeUiSession> Remote<T> {
                         .await;
                     }
                 } else {
+                    let delta = need_override && self.is_file_delta_enabled();
                     if let Some(job) = fs::get_job(id, &mut self.write_jobs) {
                         if remember {
                             job.set_overwrite_strategy(Some(need_override));
                         }
+                        if delta {
+                            if let (fs::DataSource::FilePath(p), Some(file)) =
+                                (&job.data_source, job.files().get(file_num as usize))
+                            {
+                                let path = get_string(&fs::TransferJob::join(p, &file.name));
+                                start_delta(&self.sender, id, file_num, path);
+                                return true;
+                            }
+                        }
                         let mut msg = Message::new();
                         let mut file_action = FileAction::new();
                         let req = FileTransferSendConfirmRequest {
@@ -844,6 +1210,59 @@ impl<T: InvokeUiSession> Remote<T> {
                     }
                 }
             }
+            Data::DeltaSignature((id, file_num, path, res)) => {
+                if let Some(job) = fs::get_job(id, &mut self.write_jobs) {
+                    match res.and_then(|s| {
+                        Decoder::new(&path, s.block_size)
+                            .map(|d| (s, d))
+                            .map_err(|e| e.to_string())
+                    }) {
+                        Ok((signature, decoder)) => {
+                            self.deltas.insert((id, file_num), decoder);
+                            let mut file_action = FileAction::new();
+                            file_action.set_delta_signature(FileDeltaSignature {
+                                id,
+                                file_num,
+                                signature: signature.encode().into(),
+                                ..Default::default()
+                            });
+                            let mut msg_out = Message::new();
+                            msg_out.set_file_action(file_action);
+                            allow_err!(peer.send(&msg_out).await);
+                        }
+                        Err(err) => {
+                            // Fall back to the whole file.
+                            log::error!("Failed to get the delta signature of {}: {}", path, err);
+                        }
+                    }
+                    let req = FileTransferSendConfirmRequest {
+                        id,
+                        file_num,
+                        union: Some(file_transfer_send_confirm_request::Union::OffsetBlk(0)),
+                        ..Default::default()
+                    };
+                    job.confirm(&req).await;
+                    allow_err!(peer.send(&new_send_confirm(req)).await);
+                }
+            }
+            Data::SyncDir((id, local, remote, options)) => {
+                self.start_sync(id, local, remote, options);
+            }
+            Data::LocalChecksum((id, file_num, res)) => {
+                if let Some(sync) = self
+                    .syncs
+                    .get_mut(&id)
+                    .filter(|s| s.stage == SyncStage::Hashing)
+                {
+                    sync.on_hash(file_num, true, res);
+                    if sync.is_hashed() {
+                        self.start_sync_plan(id);
+                    }
+                } else if let Some(v) = self.verifications.get_mut(&id) {
+                    v.on_local(file_num, res);
+                }
+                self.check_verification(id);
+            }
             Data::CancelJob(id) => {
                 let mut msg_out = Message::new();
                 let mut file_action = FileAction::new();
@@ -858,6 +1277,11 @@ impl<T: InvokeUiSession> Remote<T> {
                 }
                 let _ = fs::remove_job(id, &mut self.read_jobs);
                 self.remove_jobs.remove(&id);
+                self.journals.cancel(id);
+                self.upload_files.remove(&id);
+                self.verifications.remove(&id);
+                self.deltas.retain(|k, _| k.0 != id);
+                self.syncs.remove(&id);
             }
             Data::RemoveDir((id, path)) => {
                 let mut msg_out = Message::new();
@@ -1425,29 +1849,41 @@ impl<T: InvokeUiSession> Remote<T> {
                 }
                 Some(message::Union::Clipboard(cb)) => {
                     if !self.handler.lc.read().unwrap().disable_clipboard.v {
-                        #[cfg(not(any(target_os = "android", target_os = "ios")))]
-                        update_clipboard(vec![cb], ClipboardSide::Client);
-                        #[cfg(target_os = "ios")]
-                        {
-                            let content = if cb.compress {
-                                hbb_common::compress::decompress(&cb.content)
-                            } else {
-                                cb.content.into()
-                            };
-                            if let Ok(content) = String::from_utf8(content) {
-                                self.handler.clipboard(content);
+                        self.record_event("Clipboard received".to_owned());
+                        crate::clipboard::history::record(
+                            &self.handler.get_id(),
+                            std::slice::from_ref(&cb),
+                        );
+                        // Only pasted from the history if the clipboard isn't synced automatically.
+                        if !self.handler.is_manual_clipboard_sync() {
+                            #[cfg(not(any(target_os = "android", target_os = "ios")))]
+                            update_clipboard(vec![cb], ClipboardSide::Client);
+                            #[cfg(target_os = "ios")]
+                            {
+                                let content = if cb.compress {
+                                    hbb_common::compress::decompress(&cb.content)
+                                } else {
+                                    cb.content.into()
+                                };
+                                if let Ok(content) = String::from_utf8(content) {
+                                    self.handler.clipboard(content);
+                                }
                             }
+                            #[cfg(target_os = "android")]
+                            crate::clipboard::handle_msg_clipboard(cb);
                         }
-                        #[cfg(target_os = "android")]
-                        crate::clipboard::handle_msg_clipboard(cb);
                     }
                 }
                 Some(message::Union::MultiClipboards(_mcb)) => {
                     if !self.handler.lc.read().unwrap().disable_clipboard.v {
-                        #[cfg(not(any(target_os = "android", target_os = "ios")))]
-                        update_clipboard(_mcb.clipboards, ClipboardSide::Client);
-                        #[cfg(target_os = "android")]
-                        crate::clipboard::handle_msg_multi_clipboards(_mcb);
+                        self.record_event("Clipboard received".to_owned());
+                        crate::clipboard::history::record(&self.handler.get_id(), &_mcb.clipboards);
+                        if !self.handler.is_manual_clipboard_sync() {
+                            #[cfg(not(any(target_os = "android", target_os = "ios")))]
+                            update_clipboard(_mcb.clipboards, ClipboardSide::Client);
+                            #[cfg(target_os = "android")]
+                            crate::clipboard::handle_msg_multi_clipboards(_mcb);
+                        }
                     }
                 }
                 #[cfg(any(target_os = "windows", feature = "unix-file-copy-paste"))]
@@ -1457,7 +1893,16 @@ impl<T: InvokeUiSession> Remote<T> {
                 Some(message::Union::FileResponse(fr)) => {
                     match fr.union {
                         Some(file_response::Union::EmptyDirs(res)) => {
-                            self.handler.update_empty_dirs(res);
+                            let sync = self.syncs.iter_mut().find(|(_, s)| {
+                                s.stage == SyncStage::Listing && s.remote == res.path
+                            });
+                            if let Some((id, sync)) = sync {
+                                let id = *id;
+                                sync.set_remote_dirs(&res.empty_dirs);
+                                self.check_sync_listed(id);
+                            } else {
+                                self.handler.update_empty_dirs(res);
+                            }
                         }
                         Some(file_response::Union::Dir(fd)) => {
                             #[cfg(windows)]
@@ -1470,6 +1915,15 @@ impl<T: InvokeUiSession> Remote<T> {
                                     fs::transform_windows_path(&mut entries);
                                 }
                             }
+                            if let Some(sync) = self
+                                .syncs
+                                .get_mut(&fd.id)
+                                .filter(|s| s.stage == SyncStage::Listing)
+                            {
+                                sync.set_remote_files(entries);
+                                self.check_sync_listed(fd.id);
+                                return true;
+                            }
                             self.handler
                                 .update_folder_files(fd.id, &entries, fd.path, false, false);
                             if let Some(job) = fs::get_job(fd.id, &mut self.write_jobs) {
@@ -1525,12 +1979,24 @@ impl<T: InvokeUiSession> Remote<T> {
                                     }
                                 }
                             } else {
+                                let delta = self.is_file_delta_enabled();
                                 if let Some(job) = fs::get_job(digest.id, &mut self.write_jobs) {
                                     if let Some(file) = job.files().get(digest.file_num as usize) {
                                         if let fs::DataSource::FilePath(p) = &job.data_source {
                                             let write_path =
                                                 get_string(&fs::TransferJob::join(p, &file.name));
+                                            // Sync jobs only download the planned files.
+                                            let sync_download = self
+                                                .syncs
+                                                .get(&digest.id)
+                                                .map(|s| s.is_download(&file.name));
                                             job.set_digest(digest.file_size, digest.last_modified);
+                                            self.journals.on_digest(
+                                                digest.id,
+                                                digest.file_num,
+                                                digest.file_size,
+                                                digest.last_modified,
+                                            );
                                             let peer_ver = self.handler.lc.read().unwrap().version;
                                             let is_support_resume =
                                                 crate::is_support_file_transfer_resume_num(
@@ -1562,9 +2028,30 @@ impl<T: InvokeUiSession> Remote<T> {
                                                             && digest.transferred_size > 0
                                                         {
                                                             overwrite_strategy = Some(true);
-                                                            offset = digest.transferred_size as _;
+                                                            offset = self.journals.resume(
+                                                                job,
+                                                                digest.file_num,
+                                                                digest.file_size,
+                                                                digest.last_modified,
+                                                                digest.transferred_size,
+                                                            )
+                                                                as _;
+                                                        }
+                                                        if sync_download.is_some() {
+                                                            overwrite_strategy = sync_download;
                                                         }
-                                                        if let Some(overwrite) = overwrite_strategy
+                                                        if overwrite_strategy == Some(true)
+                                                            && offset == 0
+                                                            && delta
+                                                        {
+                                                            start_delta(
+                                                                &self.sender,
+                                                                digest.id,
+                                                                digest.file_num,
+                                                                write_path,
+                                                            );
+                                                        } else if let Some(overwrite) =
+                                                            overwrite_strategy
                                                         {
                                                             let req =
                                                                 FileTransferSendConfirmRequest {
@@ -1592,11 +2079,17 @@ impl<T: InvokeUiSession> Remote<T> {
                                                     }
                                                     DigestCheckResult::NoSuchFile => {
                                                         let req = FileTransferSendConfirmRequest {
-                                                        id: digest.id,
-                                                        file_num: digest.file_num,
-                                                        union: Some(file_transfer_send_confirm_request::Union::OffsetBlk(0)),
-                                                        ..Default::default()
-                                                    };
+                                                            id: digest.id,
+                                                            file_num: digest.file_num,
+                                                            union: Some(
+                                                                if sync_download == Some(false) {
+                                                                    file_transfer_send_confirm_request::Union::Skip(true)
+                                                                } else {
+                                                                    file_transfer_send_confirm_request::Union::OffsetBlk(0)
+                                                                },
+                                                            ),
+                                                            ..Default::default()
+                                                        };
                                                         job.confirm(&req).await;
                                                         let msg = new_send_confirm(req);
                                                         allow_err!(peer.send(&msg).await);
@@ -1612,9 +2105,38 @@ impl<T: InvokeUiSession> Remote<T> {
                             }
                         }
                         Some(file_response::Union::Block(block)) => {
+                            let (id, file_num) = (block.id, block.file_num);
+                            let block = match self.decode_delta(block) {
+                                Ok(block) => block,
+                                Err(err) => {
+                                    log::error!(
+                                        "Failed to decode the delta of job {}: {}",
+                                        id,
+                                        err
+                                    );
+                                    let mut file_action = FileAction::new();
+                                    file_action.set_cancel(FileTransferCancel {
+                                        id,
+                                        ..Default::default()
+                                    });
+                                    let mut msg_out = Message::new();
+                                    msg_out.set_file_action(file_action);
+                                    allow_err!(peer.send(&msg_out).await);
+                                    if let Some(job) = fs::remove_job(id, &mut self.write_jobs) {
+                                        job.remove_download_file();
+                                    }
+                                    self.journals.cancel(id);
+                                    self.deltas.retain(|k, _| k.0 != id);
+                                    self.handle_job_status(id, file_num, Some(err.to_string()));
+                                    return true;
+                                }
+                            };
                             if let Some(job) = fs::get_job(block.id, &mut self.write_jobs) {
-                                if let Err(_err) = job.write(block).await {
-                                    // to-do: add "skip" for writing job
+                                match job.write(block.clone()).await {
+                                    Ok(()) => self.journals.record(job, &block),
+                                    Err(_err) => {
+                                        // to-do: add "skip" for writing job
+                                    }
                                 }
                                 if job.r#type == fs::JobType::Generic {
                                     self.update_jobs_status();
@@ -1625,7 +2147,12 @@ impl<T: InvokeUiSession> Remote<T> {
                             let mut err: Option<String> = None;
                             let mut job_type = fs::JobType::Generic;
                             let mut printer_data = None;
+                            let mut verification = Verification::new(d.file_num);
+                            let mut requests = Vec::new();
+                            let is_upload;
+                            self.deltas.retain(|k, _| k.0 != d.id);
                             if let Some(job) = fs::remove_job(d.id, &mut self.write_jobs) {
+                                is_upload = false;
                                 job.modify_time();
                                 err = job.job_error();
                                 job_type = job.r#type;
@@ -1636,14 +2163,51 @@ impl<T: InvokeUiSession> Remote<T> {
                                         None
                                     }
                                 };
+                                for (file_num, journal) in self.journals.finish(d.id) {
+                                    if let Some(file) = job.files().get(file_num as usize) {
+                                        requests.push((
+                                            file_num,
+                                            self.remote_path(&job.remote, &file.name),
+                                        ));
+                                        verification.add(
+                                            file_num,
+                                            journal.path,
+                                            Some(journal.sha256),
+                                        );
+                                    }
+                                }
+                            } else {
+                                is_upload = true;
+                                for (file_num, local, remote) in
+                                    self.upload_files.remove(&d.id).unwrap_or_default()
+                                {
+                                    requests.push((file_num, remote));
+                                    verification.add(file_num, local, None);
+                                }
                             }
                             match job_type {
                                 fs::JobType::Generic => {
-                                    self.handle_job_status(d.id, d.file_num, err);
+                                    if err.is_none()
+                                        && !requests.is_empty()
+                                        && self.is_support_file_checksum()
+                                    {
+                                        self.start_verification(
+                                            d.id,
+                                            verification,
+                                            requests,
+                                            is_upload,
+                                            peer,
+                                        )
+                                        .await;
+                                    } else {
+                                        self.handle_job_status(d.id, d.file_num, err);
+                                    }
                                 }
                                 fs::JobType::Printer => {
                                     if let Some(err) = err {
                                         log::error!("Receive print job failed, error {err}");
+                                        self.handler.printer_names.write().unwrap().remove(&d.id);
+                                        self.handler.print_copies.write().unwrap().remove(&d.id);
                                     } else {
                                         log::info!(
                                             "Receive print job done, data len: {:?}",
@@ -1657,16 +2221,27 @@ impl<T: InvokeUiSession> Remote<T> {
                                                 .write()
                                                 .unwrap()
                                                 .remove(&d.id);
+                                            let copies = self
+                                                .handler
+                                                .print_copies
+                                                .write()
+                                                .unwrap()
+                                                .remove(&d.id)
+                                                .unwrap_or(1);
                                             // Spawn a new thread to handle the print job.
                                             // Or print job will block the ui thread.
                                             std::thread::spawn(move || {
-                                                if let Err(e) =
-                                                    crate::platform::send_raw_data_to_printer(
-                                                        printer_name,
-                                                        data,
-                                                    )
-                                                {
-                                                    log::error!("Print job error: {}", e);
+                                                // The peer doesn't make the copies.
+                                                for _ in 0..copies.max(1) {
+                                                    if let Err(e) =
+                                                        crate::platform::send_raw_data_to_printer(
+                                                            printer_name.clone(),
+                                                            data.clone(),
+                                                        )
+                                                    {
+                                                        log::error!("Print job error: {}", e);
+                                                        break;
+                                                    }
                                                 }
                                             });
                                         }
@@ -1674,7 +2249,38 @@ impl<T: InvokeUiSession> Remote<T> {
                                 }
                             }
                         }
+                        Some(file_response::Union::Checksum(c)) => {
+                            let res = if c.error.is_empty() {
+                                Ok(c.sha256)
+                            } else {
+                                Err(c.error)
+                            };
+                            if let Some(sync) = self
+                                .syncs
+                                .get_mut(&c.id)
+                                .filter(|s| s.stage == SyncStage::Hashing)
+                            {
+                                sync.on_hash(c.file_num, false, res);
+                                if sync.is_hashed() {
+                                    self.start_sync_plan(c.id);
+                                }
+                            } else if let Some(v) = self.verifications.get_mut(&c.id) {
+                                if let Some(path) = v.on_remote(c.file_num, res) {
+                                    let sender = self.sender.clone();
+                                    let (id, file_num) = (c.id, c.file_num);
+                                    tokio::task::spawn_blocking(move || {
+                                        let res = crate::file_journal::file_sha256(&path)
+                                            .map_err(|e| e.to_string());
+                                        sender.send(Data::LocalChecksum((id, file_num, res))).ok();
+                                    });
+                                }
+                            }
+                            self.check_verification(c.id);
+                        }
                         Some(file_response::Union::Error(e)) => {
+                            self.journals.suspend(e.id);
+                            self.upload_files.remove(&e.id);
+                            self.deltas.retain(|k, _| k.0 != e.id);
                             let job_type = fs::remove_job(e.id, &mut self.write_jobs)
                                 .or_else(|| fs::remove_job(e.id, &mut self.read_jobs))
                                 .map(|j| j.r#type)
@@ -1910,6 +2516,13 @@ impl<T: InvokeUiSession> Remote<T> {
                 }
                 Some(message::Union::AudioFrame(frame)) => {
                     if !self.handler.lc.read().unwrap().disable_audio.v {
+                        if self.last_record_state {
+                            for (_, v) in self.video_threads.iter() {
+                                v.video_sender
+                                    .send(MediaData::AudioFrame(Box::new(frame.clone())))
+                                    .ok();
+                            }
+                        }
                         self.audio_sender
                             .send(MediaData::AudioFrame(Box::new(frame)))
                             .ok();
@@ -1929,10 +2542,29 @@ impl<T: InvokeUiSession> Remote<T> {
                                 // Just ignore the incoming print job.
                             } else {
                                 let id = fs::get_next_job_id();
+                                let job = _s.print_job.clone().unwrap_or_default();
+                                self.handler
+                                    .print_copies
+                                    .write()
+                                    .unwrap()
+                                    .insert(
+                                        id,
+                                        job.copies.clamp(1, crate::print_job::MAX_COPIES),
+                                    );
                                 #[cfg(feature = "flutter")]
-                                let allow_auto_print = LocalConfig::get_bool_option(
-                                    config::keys::OPTION_PRINTER_ALLOW_AUTO_PRINT,
-                                );
+                                let allow_auto_print = match self
+                                    .handler
+                                    .get_option(
+                                        crate::print_job::OPTION_PRINTER_AUTO_ACCEPT.to_owned(),
+                                    )
+                                    .as_str()
+                                {
+                                    "Y" => true,
+                                    "N" => false,
+                                    _ => LocalConfig::get_bool_option(
+                                        config::keys::OPTION_PRINTER_ALLOW_AUTO_PRINT,
+                                    ),
+                                };
                                 #[cfg(not(feature = "flutter"))]
                                 let allow_auto_print = false;
                                 if allow_auto_print {
@@ -1945,7 +2577,7 @@ impl<T: InvokeUiSession> Remote<T> {
                                     };
                                     self.handler.printer_response(id, _s.path, printer_name);
                                 } else {
-                                    self.handler.printer_request(id, _s.path);
+                                    self.handler.printer_request(id, _s.path, &job);
                                 }
                             }
                         }
@@ -2001,6 +2633,9 @@ impl<T: InvokeUiSession> Remote<T> {
                     self.handler.set_displays(&pi.displays);
                     self.handler.set_platform_additions(&pi.platform_additions);
                 }
+                Some(message::Union::WakeOnLanResponse(response)) => {
+                    self.handler.handle_wake_on_lan_response(response);
+                }
                 Some(message::Union::ScreenshotResponse(response)) => {
                     crate::client::screenshot::set_screenshot(response.data);
                     self.handler
@@ -2009,7 +2644,11 @@ impl<T: InvokeUiSession> Remote<T> {
                 Some(message::Union::TerminalResponse(response)) => {
                     use hbb_common::message_proto::terminal_response::Union;
                     if let Some(Union::Opened(opened)) = &response.union {
-                        if opened.success && !opened.service_id.is_empty() {
+                        // The service of others is not remembered as ours when attached.
+                        if opened.success
+                            && !opened.service_id.is_empty()
+                            && self.handler.lc.read().unwrap().terminal_attach.is_none()
+                        {
                             let mut lc = self.handler.lc.write().unwrap();
                             let key = lc.get_key_terminal_service_id().to_owned();
                             lc.set_option(key, opened.service_id.clone());
@@ -2384,6 +3023,29 @@ impl<T: InvokeUiSession> Remote<T> {
         msg.set_misc(misc);
         self.sender.send(Data::Message(msg)).ok();
     }
+
+    // Add an event to the timelines of the recordings of all displays.
+    fn record_event(&self, text: String) {
+        if !self.last_record_state {
+            return;
+        }
+        for (_, v) in self.video_threads.iter() {
+            v.video_sender
+                .send(MediaData::RecordEvent(text.clone()))
+                .ok();
+        }
+    }
+}
+
+// The signature of our copy of a file is sent before confirming to overwrite it, see `file_delta`.
+fn start_delta(sender: &mpsc::UnboundedSender<Data>, id: i32, file_num: i32, path: String) {
+    let sender = sender.clone();
+    tokio::task::spawn_blocking(move || {
+        let res = Signature::of_file(&path).map_err(|e| e.to_string());
+        sender
+            .send(Data::DeltaSignature((id, file_num, path, res)))
+            .ok();
+    });
 }
 
 struct RemoveJob {
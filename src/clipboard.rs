@@ -747,6 +747,262 @@ pub fn get_clipboards_msg(client: bool) -> Option<Message> {
     Some(msg)
 }
 
+/// The clipboard history of the controlling side, the clipboard contents received from the peers.
+/// An entry can be pasted to a peer later, which is the only way the clipboard is sent
+/// if the session option [`history::OPTION_MANUAL_CLIPBOARD_SYNC`] is set.
+pub mod history {
+    use hbb_common::{
+        compress::decompress,
+        get_time,
+        message_proto::{Clipboard, ClipboardFormat, Message, MultiClipboards},
+    };
+    use serde_derive::Serialize;
+    use std::{
+        collections::VecDeque,
+        sync::{Arc, Mutex},
+    };
+
+    /// Session option, the clipboard is not synced automatically, only pasted from the history.
+    pub const OPTION_MANUAL_CLIPBOARD_SYNC: &str = "manual-clipboard-sync";
+
+    const MAX_ENTRIES: usize = 50;
+    const MAX_SIZE: usize = 64 * 1024 * 1024;
+    const PREVIEW_CHARS: usize = 200;
+
+    lazy_static::lazy_static! {
+        static ref HISTORY: Arc<Mutex<History>> = Default::default();
+    }
+
+    struct Entry {
+        id: i32,
+        peer_id: String,
+        time: i64,
+        // As received, the contents may be compressed.
+        clipboards: Vec<Clipboard>,
+        size: usize,
+    }
+
+    #[derive(Default)]
+    struct History {
+        next_id: i32,
+        // The latest first.
+        entries: VecDeque<Entry>,
+        size: usize,
+    }
+
+    impl History {
+        fn push(&mut self, peer_id: &str, clipboards: Vec<Clipboard>) -> Option<i32> {
+            let size = clipboards.iter().map(|c| c.content.len()).sum::<usize>();
+            if clipboards.is_empty() || size > MAX_SIZE {
+                return None;
+            }
+            // Copying the same contents again moves the entry to the top.
+            if let Some(i) = self
+                .entries
+                .iter()
+                .position(|e| e.peer_id == peer_id && e.clipboards == clipboards)
+            {
+                self.remove_at(i);
+            }
+            self.next_id = self.next_id.wrapping_add(1).max(1);
+            let id = self.next_id;
+            self.entries.push_front(Entry {
+                id,
+                peer_id: peer_id.to_owned(),
+                time: get_time(),
+                clipboards,
+                size,
+            });
+            self.size += size;
+            while self.entries.len() > MAX_ENTRIES || self.size > MAX_SIZE {
+                self.remove_at(self.entries.len() - 1);
+            }
+            Some(id)
+        }
+
+        fn remove_at(&mut self, i: usize) {
+            if let Some(e) = self.entries.remove(i) {
+                self.size -= e.size;
+            }
+        }
+    }
+
+    #[derive(Debug, Serialize)]
+    pub struct EntrySummary {
+        pub id: i32,
+        pub peer_id: String,
+        pub time: i64,
+        // "text", "html", "rtf" or "image"
+        pub formats: Vec<String>,
+        // The beginning of the text, or the size of the image.
+        pub preview: String,
+        pub size: usize,
+    }
+
+    fn content(c: &Clipboard) -> Vec<u8> {
+        if c.compress {
+            decompress(&c.content)
+        } else {
+            c.content.to_vec()
+        }
+    }
+
+    fn format_name(format: ClipboardFormat) -> Option<&'static str> {
+        match format {
+            ClipboardFormat::Text => Some("text"),
+            ClipboardFormat::Html => Some("html"),
+            ClipboardFormat::Rtf => Some("rtf"),
+            ClipboardFormat::ImageRgba | ClipboardFormat::ImagePng | ClipboardFormat::ImageSvg => {
+                Some("image")
+            }
+            _ => None,
+        }
+    }
+
+    fn summary(e: &Entry) -> EntrySummary {
+        let mut formats: Vec<String> = vec![];
+        for c in e.clipboards.iter() {
+            if let Some(name) = c.format.enum_value().ok().and_then(format_name) {
+                if !formats.iter().any(|f| f == name) {
+                    formats.push(name.to_owned());
+                }
+            }
+        }
+        let text = e
+            .clipboards
+            .iter()
+            .find(|c| c.format.enum_value() == Ok(ClipboardFormat::Text));
+        let preview = if let Some(text) = text {
+            String::from_utf8_lossy(&content(text))
+                .chars()
+                .take(PREVIEW_CHARS)
+                .collect()
+        } else if let Some(image) = e.clipboards.iter().find(|c| c.width > 0 && c.height > 0) {
+            format!("{}x{}", image.width, image.height)
+        } else {
+            String::new()
+        };
+        EntrySummary {
+            id: e.id,
+            peer_id: e.peer_id.clone(),
+            time: e.time,
+            formats,
+            preview,
+            size: e.size,
+        }
+    }
+
+    /// Record the clipboard contents received from a peer.
+    /// Only text, HTML, RTF and images are kept, not the formats used by RustDesk itself.
+    pub fn record(peer_id: &str, clipboards: &[Clipboard]) -> Option<i32> {
+        let clipboards: Vec<_> = clipboards
+            .iter()
+            .filter(|c| c.format.enum_value().ok().and_then(format_name).is_some())
+            .cloned()
+            .collect();
+        HISTORY.lock().unwrap().push(peer_id, clipboards)
+    }
+
+    /// The entries of all peers, the latest first.
+    pub fn list() -> Vec<EntrySummary> {
+        HISTORY
+            .lock()
+            .unwrap()
+            .entries
+            .iter()
+            .map(summary)
+            .collect()
+    }
+
+    pub fn get(id: i32) -> Option<Vec<Clipboard>> {
+        HISTORY
+            .lock()
+            .unwrap()
+            .entries
+            .iter()
+            .find(|e| e.id == id)
+            .map(|e| e.clipboards.clone())
+    }
+
+    pub fn remove(id: i32) {
+        let mut history = HISTORY.lock().unwrap();
+        if let Some(i) = history.entries.iter().position(|e| e.id == id) {
+            history.remove_at(i);
+        }
+    }
+
+    pub fn clear() {
+        *HISTORY.lock().unwrap() = Default::default();
+    }
+
+    /// The message to paste an entry to a peer, the text only if the peer doesn't support multiple formats.
+    pub fn get_msg(id: i32, peer_version: &str, peer_platform: &str) -> Option<Message> {
+        let multi_clipboards = MultiClipboards {
+            clipboards: get(id)?,
+            ..Default::default()
+        };
+        if !super::is_support_multi_clipboard(peer_version, peer_platform) {
+            return super::get_msg_if_not_support_multi_clip(
+                peer_version,
+                peer_platform,
+                &multi_clipboards,
+            );
+        }
+        let mut msg = Message::new();
+        msg.set_multi_clipboards(multi_clipboards);
+        Some(msg)
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        fn clipboard(format: ClipboardFormat, content: &str) -> Clipboard {
+            Clipboard {
+                content: content.as_bytes().to_vec().into(),
+                format: format.into(),
+                ..Default::default()
+            }
+        }
+
+        #[test]
+        fn test_history() {
+            let mut history = History::default();
+            let text = clipboard(ClipboardFormat::Text, "hello");
+            let id = history.push("1", vec![text.clone()]).unwrap();
+            history.push("2", vec![text.clone()]);
+            assert_eq!(history.entries.len(), 2);
+            // The same contents of a peer are moved to the top.
+            let id2 = history.push("1", vec![text.clone()]).unwrap();
+            assert_ne!(id, id2);
+            assert_eq!(history.entries.len(), 2);
+            assert_eq!(history.entries[0].peer_id, "1");
+            assert!(history.push("1", vec![]).is_none());
+            for i in 0..MAX_ENTRIES {
+                history.push("1", vec![clipboard(ClipboardFormat::Html, &i.to_string())]);
+            }
+            assert_eq!(history.entries.len(), MAX_ENTRIES);
+            assert_eq!(
+                history.size,
+                history.entries.iter().map(|e| e.size).sum::<usize>()
+            );
+            let s = summary(&Entry {
+                id: 1,
+                peer_id: "1".to_owned(),
+                time: 0,
+                clipboards: vec![
+                    clipboard(ClipboardFormat::Html, "<b>hello</b>"),
+                    text,
+                    clipboard(ClipboardFormat::Special, "x"),
+                ],
+                size: 0,
+            });
+            assert_eq!(s.formats, vec!["html", "text"]);
+            assert_eq!(s.preview, "hello");
+        }
+    }
+}
+
 // We need this mod to notify multiple subscribers when the clipboard changes.
 // Because only one clipboard master(listener) can trigger the clipboard change event multiple listeners are created on Linux(x11).
 // https://github.com/rustdesk-org/clipboard-master/blob/4fb62e5b62fb6350d82b571ec7ba94b3cd466695/src/master/x11.rs#L226
@@ -188,6 +188,21 @@ pub fn is_support_file_transfer_resume_num(ver: i64) -> bool {
     ver >= hbb_common::get_version_number("1.4.2")
 }
 
+#[inline]
+pub fn is_support_file_checksum_num(ver: i64) -> bool {
+    ver >= hbb_common::get_version_number("1.4.6")
+}
+
+#[inline]
+pub fn is_support_file_delta_num(ver: i64) -> bool {
+    ver >= hbb_common::get_version_number("1.4.6")
+}
+
+#[inline]
+pub fn is_support_remote_exec_num(ver: i64) -> bool {
+    ver >= hbb_common::get_version_number("1.4.6")
+}
+
 /// Minimum server version required for relative mouse mode support.
 /// This constant must mirror Flutter's `kMinVersionForRelativeMouseMode` in `consts.dart`.
 const MIN_VERSION_RELATIVE_MOUSE_MODE: &str = "1.4.5";
@@ -1418,6 +1433,43 @@ pub fn make_privacy_mode_msg(
     make_privacy_mode_msg_with_details(state, "".to_owned(), impl_key)
 }
 
+/// The event of a message from the controlling side, for the timeline of the recordings.
+/// Only that there was keyboard input is recorded, not the keys.
+pub fn get_record_event(msg: &Message) -> Option<String> {
+    match &msg.union {
+        Some(message::Union::KeyEvent(e)) if e.down || e.press => Some("Keyboard input".to_owned()),
+        Some(message::Union::MouseEvent(e))
+            if e.mask & input::MOUSE_TYPE_MASK == input::MOUSE_TYPE_DOWN =>
+        {
+            Some("Mouse click".to_owned())
+        }
+        Some(message::Union::Clipboard(_)) | Some(message::Union::MultiClipboards(_)) => {
+            Some("Clipboard sent".to_owned())
+        }
+        Some(message::Union::Misc(misc)) => match &misc.union {
+            Some(misc::Union::SwitchDisplay(s)) => Some(format!("Switch to display {}", s.display)),
+            Some(misc::Union::CaptureDisplays(d)) => Some(format!(
+                "Capture displays, add {:?}, remove {:?}, set {:?}",
+                d.add, d.sub, d.set
+            )),
+            Some(misc::Union::TogglePrivacyMode(t)) => {
+                Some(format!("Privacy mode {}", if t.on { "on" } else { "off" }))
+            }
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// The event of a file transfer, for the timeline of the recordings.
+pub fn get_file_record_event(upload: bool, path: &str) -> String {
+    format!(
+        "Files {}: {}",
+        if upload { "uploaded" } else { "downloaded" },
+        path
+    )
+}
+
 pub fn is_keyboard_mode_supported(
     keyboard_mode: &KeyboardMode,
     version_number: i64,
@@ -2521,4 +2573,34 @@ mod tests {
         assert_eq!(combined_mask & MOUSE_TYPE_MASK, MOUSE_TYPE_DOWN);
         assert_eq!(combined_mask >> 3, MOUSE_BUTTON_LEFT | MOUSE_BUTTON_RIGHT);
     }
+
+    #[test]
+    fn test_get_record_event() {
+        let mut msg = Message::new();
+        msg.set_key_event(KeyEvent {
+            down: true,
+            union: Some(key_event::Union::Chr(65)),
+            ..Default::default()
+        });
+        assert_eq!(get_record_event(&msg).as_deref(), Some("Keyboard input"));
+        msg.set_key_event(KeyEvent::new());
+        assert_eq!(get_record_event(&msg), None);
+        msg.set_mouse_event(MouseEvent {
+            mask: input::MOUSE_TYPE_DOWN | input::MOUSE_BUTTON_LEFT << 3,
+            ..Default::default()
+        });
+        assert_eq!(get_record_event(&msg).as_deref(), Some("Mouse click"));
+        msg.set_mouse_event(MouseEvent {
+            mask: input::MOUSE_TYPE_MOVE,
+            ..Default::default()
+        });
+        assert_eq!(get_record_event(&msg), None);
+        let mut misc = Misc::new();
+        misc.set_toggle_privacy_mode(TogglePrivacyMode {
+            on: true,
+            ..Default::default()
+        });
+        msg.set_misc(misc);
+        assert_eq!(get_record_event(&msg).as_deref(), Some("Privacy mode on"));
+    }
 }
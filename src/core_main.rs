@@ -291,6 +291,19 @@ pub fn core_main() -> Option<Vec<String>> {
                 } else {
                     log::error!("Win10 or greater required!");
                 }
+                #[cfg(target_os = "linux")]
+                if crate::platform::is_root() {
+                    match remote_printer::install_update_printer(&crate::get_app_name()) {
+                        Ok(_) => {
+                            log::info!("Remote printer installed/updated successfully");
+                        }
+                        Err(e) => {
+                            log::error!("Failed to install/update the remote printer: {}", e);
+                        }
+                    }
+                } else {
+                    log::error!("Root is required to install the remote printer!");
+                }
                 return None;
             } else if args[0] == "--uninstall-remote-printer" {
                 #[cfg(windows)]
@@ -298,6 +311,11 @@ pub fn core_main() -> Option<Vec<String>> {
                     remote_printer::uninstall_printer(&crate::get_app_name());
                     log::info!("Remote printer uninstalled");
                 }
+                #[cfg(target_os = "linux")]
+                if crate::platform::is_root() {
+                    remote_printer::uninstall_printer(&crate::get_app_name());
+                    log::info!("Remote printer uninstalled");
+                }
                 return None;
             }
         }
@@ -611,6 +629,43 @@ pub fn core_main() -> Option<Vec<String>> {
                 println!("Installation and administrative privileges required!");
             }
             return None;
+        } else if args[0] == "--merge-recording" {
+            if args.len() >= 2 {
+                match crate::recording::merge(&args[1], args.get(2).map(|s| s.as_str())) {
+                    Ok(output) => println!("{}", output.display()),
+                    Err(e) => {
+                        log::error!("Failed to merge the recording {}: {}", args[1], e);
+                        eprintln!("{}", e);
+                    }
+                }
+            } else {
+                println!("Usage: --merge-recording <manifest.json> [output]");
+            }
+            return None;
+        } else if args[0] == "--generate-recording-key" {
+            if args.len() == 2 {
+                match crate::recording::generate_key(&args[1]) {
+                    Ok(pk) => println!("{}", pk),
+                    Err(e) => eprintln!("{}", e),
+                }
+            } else {
+                println!("Usage: --generate-recording-key <secret key file>");
+            }
+            return None;
+        } else if args[0] == "--decrypt-recording" {
+            if args.len() >= 3 {
+                match crate::recording::decrypt(&args[1], &args[2], args.get(3).map(|s| s.as_str()))
+                {
+                    Ok(output) => println!("{}", output.display()),
+                    Err(e) => {
+                        log::error!("Failed to decrypt the recording {}: {}", args[1], e);
+                        eprintln!("{}", e);
+                    }
+                }
+            } else {
+                println!("Usage: --decrypt-recording <recording.enc> <secret key file> [output]");
+            }
+            return None;
         } else if args[0] == "--check-hwcodec-config" {
             #[cfg(feature = "hwcodec")]
             crate::ipc::hwcodec_process();
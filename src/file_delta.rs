@@ -0,0 +1,399 @@
+//! rsync style delta transfer of files the receiver already has an older copy of.
+//!
+//! The receiver sends the [`Signature`] of its copy before confirming the overwrite. The sender
+//! encodes the blocks read from the source with an [`Encoder`], referring to the blocks of the
+//! receiver wherever the rolling checksum and sha256 match, and the receiver rebuilds the file
+//! from its copy with a [`Decoder`].
+//!
+//! The encoded data is a sequence of ops, every `FileTransferBlock` holds complete ops:
+//! - `[0][first block: u32 BE][count: u32 BE]`, copy `count` blocks of the receiver's copy.
+//! - `[1][len: u32 BE][data]`, literal data.
+
+use bytes::{Buf, BufMut, BytesMut};
+use hbb_common::{
+    bail,
+    fs::{self, JobType},
+    message_proto::*,
+    ResultType,
+};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{Read, Seek, SeekFrom},
+};
+
+pub const OPTION_FILE_TRANSFER_DELTA: &str = "enable-file-transfer-delta";
+
+const MIN_BLOCK_SIZE: u64 = 4 << 10;
+const MAX_BLOCK_SIZE: u64 = 128 << 10;
+// Pending literal data is sent once this long.
+const MAX_LITERAL: usize = 128 << 10;
+// Limits the output of a single copy op.
+const MAX_COPY_LEN: u64 = 64 << 20;
+const OP_COPY: u8 = 0;
+const OP_DATA: u8 = 1;
+const STRONG_LEN: usize = 16;
+
+type Strong = [u8; STRONG_LEN];
+
+fn strong(data: &[u8]) -> Strong {
+    let mut s = [0u8; STRONG_LEN];
+    s.copy_from_slice(&Sha256::digest(data)[..STRONG_LEN]);
+    s
+}
+
+fn block_size(file_size: u64) -> u64 {
+    let size = (file_size as f64).sqrt() as u64;
+    (size.div_ceil(1024) * 1024).clamp(MIN_BLOCK_SIZE, MAX_BLOCK_SIZE)
+}
+
+fn read_full(file: &mut File, buf: &mut [u8]) -> std::io::Result<usize> {
+    let mut n = 0;
+    while n < buf.len() {
+        match file.read(&mut buf[n..])? {
+            0 => break,
+            m => n += m,
+        }
+    }
+    Ok(n)
+}
+
+/// The rolling checksum of rsync.
+#[derive(Default)]
+struct Rolling {
+    a: u32,
+    b: u32,
+    len: u32,
+}
+
+impl Rolling {
+    fn new(data: &[u8]) -> Self {
+        let mut r = Self {
+            len: data.len() as u32,
+            ..Default::default()
+        };
+        for (i, x) in data.iter().enumerate() {
+            r.a = r.a.wrapping_add(*x as u32);
+            r.b = r.b.wrapping_add((data.len() - i) as u32 * (*x as u32));
+        }
+        r
+    }
+
+    fn roll(&mut self, out: u8, incoming: u8) {
+        self.a = self
+            .a
+            .wrapping_sub(out as u32)
+            .wrapping_add(incoming as u32);
+        self.b = self
+            .b
+            .wrapping_sub(self.len.wrapping_mul(out as u32))
+            .wrapping_add(self.a);
+    }
+
+    fn digest(&self) -> u32 {
+        (self.a & 0xffff) | (self.b << 16)
+    }
+}
+
+/// Checksums of the complete blocks of the receiver's copy.
+#[derive(Debug, Clone)]
+pub struct Signature {
+    pub block_size: u32,
+    blocks: Vec<(u32, Strong)>,
+}
+
+impl Signature {
+    pub fn of_file(path: &str) -> ResultType<Self> {
+        let mut file = File::open(path)?;
+        let block_size = block_size(file.metadata()?.len());
+        let mut buf = vec![0u8; block_size as usize];
+        let mut blocks = Vec::new();
+        while read_full(&mut file, &mut buf)? == buf.len() {
+            blocks.push((Rolling::new(&buf).digest(), strong(&buf)));
+        }
+        Ok(Self {
+            block_size: block_size as _,
+            blocks,
+        })
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = BytesMut::with_capacity(4 + self.blocks.len() * (4 + STRONG_LEN));
+        buf.put_u32(self.block_size);
+        for (weak, strong) in self.blocks.iter() {
+            buf.put_u32(*weak);
+            buf.put_slice(strong);
+        }
+        buf.to_vec()
+    }
+
+    pub fn decode(mut buf: &[u8]) -> ResultType<Self> {
+        let count = buf.len().saturating_sub(4) / (4 + STRONG_LEN);
+        if buf.len() < 4 || buf.len() != 4 + count * (4 + STRONG_LEN) {
+            bail!("Invalid delta signature length {}", buf.len());
+        }
+        let block_size = buf.get_u32();
+        if !(MIN_BLOCK_SIZE..=MAX_BLOCK_SIZE).contains(&(block_size as u64)) {
+            bail!("Invalid delta block size {}", block_size);
+        }
+        let mut blocks = Vec::with_capacity(count);
+        while !buf.is_empty() {
+            let weak = buf.get_u32();
+            let mut strong = [0u8; STRONG_LEN];
+            buf.copy_to_slice(&mut strong);
+            blocks.push((weak, strong));
+        }
+        Ok(Self { block_size, blocks })
+    }
+}
+
+/// Encodes the source of a file, as it is read, against the [`Signature`] of the receiver.
+pub struct Encoder {
+    block_size: usize,
+    weak: HashMap<u32, Vec<u32>>,
+    strong: Vec<Strong>,
+    buf: Vec<u8>,
+    // The start of the window in `buf`.
+    pos: usize,
+    // The start of the pending literal data in `buf`.
+    lit: usize,
+    rolling: Option<Rolling>,
+    // The first block and count of the pending copy.
+    copy: Option<(u32, u32)>,
+    out: BytesMut,
+}
+
+impl Encoder {
+    pub fn new(signature: Signature) -> Self {
+        let mut weak: HashMap<u32, Vec<u32>> = HashMap::new();
+        let mut strong = Vec::with_capacity(signature.blocks.len());
+        for (i, (w, s)) in signature.blocks.into_iter().enumerate() {
+            weak.entry(w).or_default().push(i as u32);
+            strong.push(s);
+        }
+        Self {
+            block_size: signature.block_size as _,
+            weak,
+            strong,
+            buf: Vec::new(),
+            pos: 0,
+            lit: 0,
+            rolling: None,
+            copy: None,
+            out: BytesMut::new(),
+        }
+    }
+
+    /// Encode the next data of the source, returns the ops to send, which may be empty.
+    pub fn push(&mut self, data: &[u8]) -> Vec<u8> {
+        self.buf.extend_from_slice(data);
+        self.scan();
+        self.flush_copy();
+        if self.lit > 0 {
+            self.buf.drain(..self.lit);
+            self.pos -= self.lit;
+            self.lit = 0;
+        }
+        self.out.split().to_vec()
+    }
+
+    /// The remaining ops once the whole source is pushed.
+    pub fn finish(&mut self) -> Vec<u8> {
+        self.flush_literal(self.buf.len());
+        self.flush_copy();
+        self.buf.clear();
+        self.out.split().to_vec()
+    }
+
+    fn scan(&mut self) {
+        let bs = self.block_size;
+        while self.buf.len() - self.pos >= bs {
+            let window = &self.buf[self.pos..self.pos + bs];
+            let weak = match &self.rolling {
+                Some(r) => r.digest(),
+                None => {
+                    let r = Rolling::new(window);
+                    let digest = r.digest();
+                    self.rolling = Some(r);
+                    digest
+                }
+            };
+            let found = self.weak.get(&weak).and_then(|blocks| {
+                let s = strong(window);
+                blocks
+                    .iter()
+                    .find(|i| self.strong[**i as usize] == s)
+                    .copied()
+            });
+            if let Some(index) = found {
+                self.flush_literal(self.pos);
+                self.add_copy(index);
+                self.pos += bs;
+                self.lit = self.pos;
+                self.rolling = None;
+                continue;
+            }
+            match self.buf.get(self.pos + bs).copied() {
+                Some(incoming) => {
+                    let out = self.buf[self.pos];
+                    if let Some(r) = self.rolling.as_mut() {
+                        r.roll(out, incoming);
+                    }
+                }
+                // The next byte is not read yet.
+                None => self.rolling = None,
+            }
+            self.pos += 1;
+            if self.pos - self.lit >= MAX_LITERAL {
+                self.flush_literal(self.pos);
+            }
+        }
+    }
+
+    fn flush_literal(&mut self, end: usize) {
+        if end > self.lit {
+            self.flush_copy();
+            self.out.put_u8(OP_DATA);
+            self.out.put_u32((end - self.lit) as _);
+            self.out.put_slice(&self.buf[self.lit..end]);
+            self.lit = end;
+        }
+    }
+
+    fn add_copy(&mut self, index: u32) {
+        match self.copy.as_mut() {
+            Some((first, count))
+                if *first + *count == index
+                    && (*count as u64 + 1) * (self.block_size as u64) <= MAX_COPY_LEN =>
+            {
+                *count += 1
+            }
+            _ => {
+                self.flush_copy();
+                self.copy = Some((index, 1));
+            }
+        }
+    }
+
+    fn flush_copy(&mut self) {
+        if let Some((first, count)) = self.copy.take() {
+            self.out.put_u8(OP_COPY);
+            self.out.put_u32(first);
+            self.out.put_u32(count);
+        }
+    }
+}
+
+/// Rebuilds a file from the ops of an [`Encoder`] and the receiver's copy.
+pub struct Decoder {
+    base: String,
+    block_size: u64,
+}
+
+impl Decoder {
+    pub fn new(base: &str, block_size: u32) -> ResultType<Self> {
+        File::open(base)?;
+        Ok(Self {
+            base: base.to_owned(),
+            block_size: block_size as _,
+        })
+    }
+
+    pub fn decode(&self, mut data: &[u8]) -> ResultType<Vec<u8>> {
+        // The copy is opened for every block, it is replaced once the file is written.
+        let mut base = File::open(&self.base)?;
+        let mut out = Vec::new();
+        while data.has_remaining() {
+            match data.get_u8() {
+                OP_COPY if data.remaining() >= 8 => {
+                    let first = data.get_u32() as u64;
+                    let len = data.get_u32() as u64 * self.block_size;
+                    if len > MAX_COPY_LEN {
+                        bail!("Invalid delta copy of {} bytes", len);
+                    }
+                    base.seek(SeekFrom::Start(first * self.block_size))?;
+                    let start = out.len();
+                    out.resize(start + len as usize, 0);
+                    base.read_exact(&mut out[start..])?;
+                }
+                OP_DATA if data.remaining() >= 4 => {
+                    let len = data.get_u32() as usize;
+                    if data.remaining() < len {
+                        bail!("Truncated delta data");
+                    }
+                    out.extend_from_slice(&data[..len]);
+                    data.advance(len);
+                }
+                _ => bail!("Invalid delta data"),
+            }
+        }
+        Ok(out)
+    }
+}
+
+/// `fs::new_send`, asking the peer to encode the files against our signatures if `delta`.
+pub fn new_send(
+    id: i32,
+    r#type: JobType,
+    path: String,
+    file_num: i32,
+    include_hidden: bool,
+    delta: bool,
+) -> Message {
+    let mut msg = fs::new_send(id, r#type, path, file_num, include_hidden);
+    if delta && r#type == JobType::Generic {
+        if let Some(message::Union::FileAction(fa)) = msg.union.as_mut() {
+            if let Some(file_action::Union::Send(s)) = fa.union.as_mut() {
+                s.delta = true;
+            }
+        }
+    }
+    msg
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(base: &[u8], source: &[u8], chunk: usize) -> usize {
+        let path =
+            std::env::temp_dir().join(format!("rustdesk-delta-{}-{}", std::process::id(), chunk));
+        std::fs::write(&path, base).unwrap();
+        let path = path.to_string_lossy().to_string();
+        let signature = Signature::decode(&Signature::of_file(&path).unwrap().encode()).unwrap();
+        let decoder = Decoder::new(&path, signature.block_size).unwrap();
+        let mut encoder = Encoder::new(signature);
+        let mut sent = 0;
+        let mut out = Vec::new();
+        for data in source.chunks(chunk) {
+            let ops = encoder.push(data);
+            sent += ops.len();
+            out.extend(decoder.decode(&ops).unwrap());
+        }
+        let ops = encoder.finish();
+        sent += ops.len();
+        out.extend(decoder.decode(&ops).unwrap());
+        std::fs::remove_file(&path).ok();
+        assert_eq!(out, source);
+        sent
+    }
+
+    #[test]
+    fn test_delta() {
+        let base: Vec<u8> = (0..1_000_000u32)
+            .map(|i| (i.wrapping_mul(2654435761) >> 13) as u8)
+            .collect();
+        let mut source = base[..300_000].to_vec();
+        source.extend_from_slice(b"inserted");
+        source.extend_from_slice(&base[300_000..990_000]);
+        source[700_000] ^= 0xff;
+        let sent = round_trip(&base, &source, 128 << 10);
+        assert!(sent < 40_000, "{} bytes sent", sent);
+        round_trip(&base, &source, 1000);
+        // Nothing in common
+        assert!(round_trip(b"", &source, 128 << 10) > source.len());
+        assert!(Decoder::new("", 4096).is_err());
+        assert!(Signature::decode(&[0, 0, 0, 1]).is_err());
+    }
+}
@@ -0,0 +1,542 @@
+//! Journals of the files being received, so an interrupted transfer resumes from the last
+//! verified block and is checked end to end once done.
+//!
+//! The receiving side records the sha256 of every [`BLOCK_SIZE`] block written to the partial file,
+//! in the `journal` directory of the config path. On resume, the partial file is verified against
+//! the journal and cut to the last matching block. When the job is done, the controlling side asks
+//! the peer for the sha256 of every transferred file, see [`Verification`].
+
+use hbb_common::{
+    compress::decompress,
+    config::{self, Config},
+    fs::{self, get_string, TransferJob},
+    log,
+    message_proto::FileTransferBlock,
+    ResultType,
+};
+use serde_derive::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    io::Read,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+pub const BLOCK_SIZE: u64 = 1 << 20;
+// The journal is stored every this many blocks.
+const STORE_INTERVAL: usize = 16;
+// Journals not touched for this long are of transfers never resumed or checked.
+const MAX_AGE: Duration = Duration::from_secs(7 * 24 * 3600);
+// Mirrors the partial file of `fs::TransferJob`.
+const PARTIAL_SUFFIX: &str = ".download";
+
+#[derive(Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Journal {
+    pub path: String,
+    pub source_size: u64,
+    pub source_modified: u64,
+    /// Sha256 of the complete blocks written so far.
+    pub blocks: Vec<String>,
+    /// Sha256 of the whole file, set once finished.
+    pub sha256: String,
+    #[serde(skip)]
+    len: u64,
+    #[serde(skip)]
+    block: Sha256,
+    #[serde(skip)]
+    file: Sha256,
+}
+
+impl Journal {
+    fn new(path: String, source_size: u64, source_modified: u64) -> Self {
+        Self {
+            path,
+            source_size,
+            source_modified,
+            ..Default::default()
+        }
+    }
+
+    fn dir() -> PathBuf {
+        Config::path("journal")
+    }
+
+    fn store_path(path: &str) -> PathBuf {
+        let hash = Sha256::digest(path.as_bytes());
+        Self::dir().join(format!("{}.toml", hex::encode(&hash[..16])))
+    }
+
+    pub fn load(path: &str) -> Option<Self> {
+        let store_path = Self::store_path(path);
+        if !store_path.exists() {
+            return None;
+        }
+        let journal: Self = config::load_path(store_path);
+        if journal.path == path {
+            Some(journal)
+        } else {
+            None
+        }
+    }
+
+    fn store(&self) {
+        if let Err(err) = std::fs::create_dir_all(Self::dir()) {
+            log::error!("Failed to create the journal directory: {}", err);
+            return;
+        }
+        if let Err(err) = config::store_path(Self::store_path(&self.path), self) {
+            log::error!("Failed to store the journal of {}: {}", self.path, err);
+        }
+    }
+
+    fn remove(path: &str) {
+        let store_path = Self::store_path(path);
+        if store_path.exists() {
+            if let Err(err) = std::fs::remove_file(&store_path) {
+                log::error!("Failed to remove the journal of {}: {}", path, err);
+            }
+        }
+    }
+
+    /// Returns true if a block is completed.
+    fn update(&mut self, mut data: &[u8]) -> bool {
+        let mut completed = false;
+        self.file.update(data);
+        while !data.is_empty() {
+            let n = ((BLOCK_SIZE - self.len % BLOCK_SIZE) as usize).min(data.len());
+            self.block.update(&data[..n]);
+            self.len += n as u64;
+            data = &data[n..];
+            if self.len % BLOCK_SIZE == 0 {
+                self.blocks.push(hex::encode(self.block.finalize_reset()));
+                completed = true;
+            }
+        }
+        completed
+    }
+
+    fn finish(&mut self) {
+        if self.sha256.is_empty() {
+            self.sha256 = hex::encode(self.file.finalize_reset());
+        }
+    }
+
+    /// Verify `partial` against the recorded blocks, anything after them is kept up to `trusted` bytes,
+    /// eg. written before there was a journal. `partial` is cut to the returned length.
+    fn resume(&mut self, partial: &Path, trusted: u64) -> ResultType<u64> {
+        let recorded = std::mem::take(&mut self.blocks);
+        self.len = 0;
+        self.block = Default::default();
+        self.file = Default::default();
+        let mut file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(partial)?;
+        let limit = (recorded.len() as u64 * BLOCK_SIZE)
+            .max(trusted)
+            .min(file.metadata()?.len());
+        let mut buf = vec![0u8; BLOCK_SIZE as usize];
+        while self.len < limit {
+            let n = (BLOCK_SIZE.min(limit - self.len)) as usize;
+            file.read_exact(&mut buf[..n])?;
+            if let Some(expected) = recorded.get(self.blocks.len()) {
+                if n as u64 != BLOCK_SIZE || hex::encode(Sha256::digest(&buf[..n])) != *expected {
+                    break;
+                }
+            }
+            self.update(&buf[..n]);
+        }
+        file.set_len(self.len)?;
+        Ok(self.len)
+    }
+}
+
+/// The journals of the files being received, by job id and file number.
+#[derive(Default)]
+pub struct Journals {
+    journals: HashMap<(i32, i32), Journal>,
+    // Size and modified time of the source files from their digests.
+    sources: HashMap<(i32, i32), (u64, u64)>,
+    // The controlled side keeps the finished journals for the checksum request of the peer.
+    keep_finished: bool,
+}
+
+impl Journals {
+    pub fn new(keep_finished: bool) -> Self {
+        prune();
+        Self {
+            journals: Default::default(),
+            sources: Default::default(),
+            keep_finished,
+        }
+    }
+
+    /// Remember the digest of a source file, which identifies it on resume.
+    pub fn on_digest(&mut self, id: i32, file_num: i32, source_size: u64, source_modified: u64) {
+        self.sources
+            .insert((id, file_num), (source_size, source_modified));
+    }
+
+    /// The offset to resume `file_num` of `job` from, `transferred` is the length of the partial file.
+    pub fn resume(
+        &mut self,
+        job: &TransferJob,
+        file_num: i32,
+        source_size: u64,
+        source_modified: u64,
+        transferred: u64,
+    ) -> u64 {
+        let Some(path) = dest_path(job, file_num) else {
+            return transferred;
+        };
+        self.finish_before(job.id(), file_num);
+        let (mut journal, trusted) = match Journal::load(&path) {
+            Some(journal)
+                if journal.source_size == source_size
+                    && journal.source_modified == source_modified
+                    && journal.sha256.is_empty() =>
+            {
+                (journal, 0)
+            }
+            // The source has changed since.
+            Some(_) => (Journal::new(path, source_size, source_modified), 0),
+            None => (
+                Journal::new(path, source_size, source_modified),
+                transferred,
+            ),
+        };
+        let partial = PathBuf::from(format!("{}{}", journal.path, PARTIAL_SUFFIX));
+        match journal.resume(&partial, trusted) {
+            Ok(offset) => {
+                log::info!(
+                    "Resume {} from {} of {} bytes",
+                    journal.path,
+                    offset,
+                    transferred
+                );
+                journal.store();
+                self.journals.insert((job.id(), file_num), journal);
+                offset
+            }
+            Err(err) => {
+                log::error!(
+                    "Failed to verify the partial file of {}: {}",
+                    journal.path,
+                    err
+                );
+                Journal::remove(&journal.path);
+                0
+            }
+        }
+    }
+
+    /// Record a block written by `job`.
+    pub fn record(&mut self, job: &TransferJob, block: &FileTransferBlock) {
+        let key = (block.id, block.file_num);
+        if !self.journals.contains_key(&key) {
+            self.finish_before(block.id, block.file_num);
+            let Some(path) = dest_path(job, block.file_num) else {
+                return;
+            };
+            let (size, modified) = self.sources.remove(&key).unwrap_or_else(|| {
+                job.files()
+                    .get(block.file_num as usize)
+                    .map(|f| (f.size, f.modified_time))
+                    .unwrap_or_default()
+            });
+            self.journals
+                .insert(key, Journal::new(path, size, modified));
+        }
+        let Some(journal) = self.journals.get_mut(&key) else {
+            return;
+        };
+        let data = if block.compressed {
+            Cow::Owned(decompress(&block.data))
+        } else {
+            Cow::Borrowed(&block.data[..])
+        };
+        if journal.update(&data) && journal.blocks.len() % STORE_INTERVAL == 0 {
+            journal.store();
+        }
+    }
+
+    // Files are written one by one, a new one means the previous ones are done.
+    fn finish_before(&mut self, id: i32, file_num: i32) {
+        for ((job_id, n), journal) in self.journals.iter_mut() {
+            if *job_id == id && *n < file_num && journal.sha256.is_empty() {
+                journal.finish();
+                if self.keep_finished {
+                    journal.store();
+                }
+            }
+        }
+    }
+
+    /// Finish the journals of job `id`, returns the file numbers and journals of the written files.
+    pub fn finish(&mut self, id: i32) -> Vec<(i32, Journal)> {
+        self.sources.retain(|(job_id, _), _| *job_id != id);
+        let keys: Vec<_> = self
+            .journals
+            .keys()
+            .filter(|k| k.0 == id)
+            .cloned()
+            .collect();
+        let mut finished = Vec::new();
+        for key in keys {
+            if let Some(mut journal) = self.journals.remove(&key) {
+                journal.finish();
+                if self.keep_finished {
+                    journal.store();
+                } else {
+                    Journal::remove(&journal.path);
+                }
+                finished.push((key.1, journal));
+            }
+        }
+        finished.sort_by_key(|(n, _)| *n);
+        finished
+    }
+
+    /// Store the journals of the failed job `id` for a later resume.
+    pub fn suspend(&mut self, id: i32) {
+        self.journals.retain(|(job_id, _), journal| {
+            if *job_id == id && journal.sha256.is_empty() {
+                journal.store();
+            }
+            *job_id != id
+        });
+    }
+
+    /// Forget the journals of the cancelled job `id`, whose partial files are removed.
+    pub fn cancel(&mut self, id: i32) {
+        self.sources.retain(|(job_id, _), _| *job_id != id);
+        self.journals.retain(|(job_id, _), journal| {
+            if *job_id == id {
+                Journal::remove(&journal.path);
+            }
+            *job_id != id
+        });
+    }
+}
+
+impl Drop for Journals {
+    fn drop(&mut self) {
+        for journal in self.journals.values() {
+            if journal.sha256.is_empty() || self.keep_finished {
+                journal.store();
+            }
+        }
+    }
+}
+
+fn dest_path(job: &TransferJob, file_num: i32) -> Option<String> {
+    let file = job.files().get(file_num as usize)?;
+    match &job.data_source {
+        fs::DataSource::FilePath(p) => Some(get_string(&TransferJob::join(p, &file.name))),
+        _ => None,
+    }
+}
+
+fn prune() {
+    let Ok(entries) = std::fs::read_dir(Journal::dir()) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let expired = entry
+            .metadata()
+            .and_then(|m| m.modified())
+            .map(|t| t.elapsed().unwrap_or_default() > MAX_AGE)
+            .unwrap_or(false);
+        if expired {
+            std::fs::remove_file(entry.path()).ok();
+        }
+    }
+}
+
+/// The sha256 of a file finished by the controlled side, taken out of its journal.
+/// Empty if nothing was written to `path` by the last transfer.
+pub fn take_finished(path: &str) -> String {
+    match Journal::load(path) {
+        Some(journal) if !journal.sha256.is_empty() => {
+            Journal::remove(path);
+            journal.sha256
+        }
+        _ => "".to_owned(),
+    }
+}
+
+pub fn file_sha256(path: &str) -> ResultType<String> {
+    let mut file = std::fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; BLOCK_SIZE as usize];
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(hex::encode(hasher.finalize()))
+}
+
+struct Check {
+    path: String,
+    local: Option<String>,
+    remote: Option<String>,
+}
+
+/// The end-to-end check of a done job, which is complete once the sha256 of both sides match for every file.
+pub struct Verification {
+    pub file_num: i32,
+    checks: HashMap<i32, Check>,
+    error: Option<String>,
+}
+
+impl Verification {
+    pub fn new(file_num: i32) -> Self {
+        Self {
+            file_num,
+            checks: Default::default(),
+            error: None,
+        }
+    }
+
+    /// `local` is `None` if the local file is to be hashed once the peer confirms it was written.
+    pub fn add(&mut self, file_num: i32, path: String, local: Option<String>) {
+        self.checks.insert(
+            file_num,
+            Check {
+                path,
+                local,
+                remote: None,
+            },
+        );
+    }
+
+    /// Returns the path of the local file to hash, if needed.
+    pub fn on_remote(&mut self, file_num: i32, res: Result<String, String>) -> Option<String> {
+        let check = self.checks.get_mut(&file_num)?;
+        match res {
+            // Nothing was written by the peer, eg. skipped.
+            Ok(sha256) if sha256.is_empty() => {
+                self.checks.remove(&file_num);
+                None
+            }
+            Ok(sha256) => {
+                check.remote = Some(sha256);
+                let path = check.local.is_none().then(|| check.path.clone());
+                self.check(file_num);
+                path
+            }
+            Err(err) => {
+                self.fail(file_num, err);
+                None
+            }
+        }
+    }
+
+    pub fn on_local(&mut self, file_num: i32, res: Result<String, String>) {
+        let Some(check) = self.checks.get_mut(&file_num) else {
+            return;
+        };
+        match res {
+            Ok(sha256) => {
+                check.local = Some(sha256);
+                self.check(file_num);
+            }
+            Err(err) => self.fail(file_num, err),
+        }
+    }
+
+    fn check(&mut self, file_num: i32) {
+        let Some(Check {
+            path,
+            local: Some(local),
+            remote: Some(remote),
+        }) = self.checks.get(&file_num)
+        else {
+            return;
+        };
+        if local != remote {
+            let err = format!("Checksum mismatch of {}", path);
+            self.fail(file_num, err);
+        } else {
+            self.checks.remove(&file_num);
+        }
+    }
+
+    fn fail(&mut self, file_num: i32, err: String) {
+        if let Some(check) = self.checks.remove(&file_num) {
+            log::error!("Failed to verify {}: {}", check.path, err);
+        }
+        self.error.get_or_insert(err);
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.checks.is_empty()
+    }
+
+    pub fn error(&self) -> Option<String> {
+        self.error.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_journal_resume() {
+        let dir = std::env::temp_dir().join(format!("rustdesk-journal-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let partial = dir.join("a.bin.download");
+        let data: Vec<u8> = (0..BLOCK_SIZE * 2 + 100).map(|i| i as u8).collect();
+        std::fs::write(&partial, &data).unwrap();
+
+        let mut journal = Journal::new("a.bin".to_owned(), data.len() as _, 0);
+        assert!(journal.update(&data[..BLOCK_SIZE as usize + 1]));
+        assert_eq!(journal.blocks.len(), 1);
+        assert!(journal.update(&data[BLOCK_SIZE as usize + 1..]));
+        assert_eq!(journal.blocks.len(), 2);
+        journal.finish();
+        assert_eq!(journal.sha256, hex::encode(Sha256::digest(&data)));
+
+        journal.sha256.clear();
+        // The tail after the recorded blocks is dropped.
+        assert_eq!(journal.resume(&partial, 0).unwrap(), BLOCK_SIZE * 2);
+        assert_eq!(std::fs::metadata(&partial).unwrap().len(), BLOCK_SIZE * 2);
+
+        // A corrupted second block.
+        let mut corrupted = data[..(BLOCK_SIZE * 2) as usize].to_vec();
+        corrupted[BLOCK_SIZE as usize + 7] ^= 0xff;
+        std::fs::write(&partial, &corrupted).unwrap();
+        assert_eq!(journal.resume(&partial, 0).unwrap(), BLOCK_SIZE);
+        journal.update(&data[BLOCK_SIZE as usize..]);
+        journal.finish();
+        assert_eq!(journal.sha256, hex::encode(Sha256::digest(&data)));
+
+        // Without a journal, the partial file is trusted as before.
+        std::fs::write(&partial, &data[..100]).unwrap();
+        let mut journal = Journal::new("a.bin".to_owned(), data.len() as _, 0);
+        assert_eq!(journal.resume(&partial, 100).unwrap(), 100);
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_verification() {
+        let mut v = Verification::new(2);
+        v.add(0, "a".to_owned(), Some("00".to_owned()));
+        v.add(1, "b".to_owned(), None);
+        v.add(2, "c".to_owned(), None);
+        assert_eq!(v.on_remote(0, Ok("00".to_owned())), None);
+        assert_eq!(v.on_remote(1, Ok("11".to_owned())), Some("b".to_owned()));
+        assert_eq!(v.on_remote(2, Ok("".to_owned())), None);
+        assert!(!v.is_done());
+        v.on_local(1, Ok("12".to_owned()));
+        assert!(v.is_done());
+        assert_eq!(v.error(), Some("Checksum mismatch of b".to_owned()));
+    }
+}
@@ -0,0 +1,546 @@
+//! Mirror a local and a remote directory, transferring only the files which differ.
+//!
+//! A sync job lists both trees (`ReadAllFiles` and `ReadEmptyDirs` on the remote side), optionally
+//! hashes the files whose size matches but modification time doesn't, and makes a [`SyncItem`]
+//! plan. The plan is shown to the user and, unless it is a dry run, carried out with the remote
+//! removals and directory creations first, then a download job and an upload job restricted to
+//! the planned files. All of them use the id of the sync job.
+
+use hbb_common::{
+    log,
+    message_proto::{FileDirectory, FileEntry},
+};
+use serde_derive::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SyncDirection {
+    /// Make the remote directory a copy of the local one.
+    #[default]
+    Upload,
+    /// Make the local directory a copy of the remote one.
+    Download,
+    /// Copy the missing files both ways, the newer file wins if both exist.
+    Both,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SyncOptions {
+    pub direction: SyncDirection,
+    /// Glob patterns of the paths to sync, everything if empty.
+    pub include: Vec<String>,
+    /// Glob patterns of the paths to leave alone, they win over `include`.
+    pub exclude: Vec<String>,
+    /// Remove the files and directories missing on the source side, not with `Both`.
+    pub delete: bool,
+    /// Only report the plan.
+    pub dry_run: bool,
+    /// Compare the sha256 of the files of the same size but different modification time.
+    pub checksum: bool,
+    pub include_hidden: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SyncAction {
+    DeleteRemote,
+    DeleteLocal,
+    CreateRemoteDir,
+    CreateLocalDir,
+    Download,
+    Upload,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SyncItem {
+    pub action: SyncAction,
+    /// Relative to the synced directories, with `/` separators.
+    pub path: String,
+    pub is_dir: bool,
+    pub size: u64,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SyncStage {
+    #[default]
+    Listing,
+    Hashing,
+    RemoteOps,
+    Download,
+    Upload,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Meta {
+    size: u64,
+    modified: u64,
+}
+
+#[derive(Default)]
+struct Tree {
+    files: BTreeMap<String, Meta>,
+    // Every directory, including the parents of the files.
+    dirs: BTreeSet<String>,
+    // The parents of the files left out, which are never removed.
+    kept: BTreeSet<String>,
+}
+
+impl Tree {
+    // Only the selected files and directories, the parents of the others are left out too.
+    fn new(files: &[FileEntry], empty_dirs: &[String], options: &SyncOptions) -> Self {
+        let mut tree = Self::default();
+        for f in files.iter() {
+            let path = normalize(&f.name);
+            if path.is_empty() {
+                continue;
+            }
+            if !is_selected(options, &path) {
+                add_parents(&mut tree.kept, &path);
+                continue;
+            }
+            add_parents(&mut tree.dirs, &path);
+            tree.files.insert(
+                path,
+                Meta {
+                    size: f.size,
+                    modified: f.modified_time,
+                },
+            );
+        }
+        for d in empty_dirs.iter() {
+            if !d.is_empty() && is_selected(options, d) {
+                add_parents(&mut tree.dirs, d);
+                tree.dirs.insert(d.clone());
+            }
+        }
+        tree
+    }
+}
+
+fn add_parents(dirs: &mut BTreeSet<String>, path: &str) {
+    let mut end = 0;
+    while let Some(i) = path[end..].find('/') {
+        end += i;
+        dirs.insert(path[..end].to_owned());
+        end += 1;
+    }
+}
+
+fn is_selected(options: &SyncOptions, path: &str) -> bool {
+    let matches = |patterns: &Vec<String>| patterns.iter().any(|p| glob_matches(p, path));
+    (options.include.is_empty() || matches(&options.include)) && !matches(&options.exclude)
+}
+
+pub struct SyncJob {
+    pub local: String,
+    pub remote: String,
+    pub options: SyncOptions,
+    pub stage: SyncStage,
+    local_tree: Option<Tree>,
+    remote_files: Option<Vec<FileEntry>>,
+    remote_dirs: Option<Vec<String>>,
+    // The paths to hash and their local and remote sha256, by request number.
+    hashes: Vec<(String, Option<String>, Option<String>)>,
+    pub plan: Vec<SyncItem>,
+    // The remote operations not answered yet.
+    pub pending: usize,
+    pub error: Option<String>,
+}
+
+impl SyncJob {
+    pub fn new(
+        local: String,
+        remote: String,
+        options: SyncOptions,
+        local_files: &[FileEntry],
+        local_dirs: &[FileDirectory],
+    ) -> Self {
+        let dirs: Vec<_> = local_dirs
+            .iter()
+            .filter_map(|d| relative(&local, &d.path))
+            .collect();
+        Self {
+            local_tree: Some(Tree::new(local_files, &dirs, &options)),
+            local,
+            remote,
+            options,
+            stage: SyncStage::Listing,
+            remote_files: None,
+            remote_dirs: None,
+            hashes: Vec::new(),
+            plan: Vec::new(),
+            pending: 0,
+            error: None,
+        }
+    }
+
+    pub fn set_remote_files(&mut self, files: Vec<FileEntry>) {
+        self.remote_files = Some(files);
+    }
+
+    pub fn set_remote_dirs(&mut self, dirs: &[FileDirectory]) {
+        let dirs = dirs
+            .iter()
+            .filter_map(|d| relative(&self.remote, &d.path))
+            .collect();
+        self.remote_dirs = Some(dirs);
+    }
+
+    /// The remote directory does not exist yet, which is fine when uploading.
+    pub fn set_remote_missing(&mut self) -> bool {
+        if self.options.direction != SyncDirection::Upload {
+            return false;
+        }
+        self.remote_files = Some(Vec::new());
+        self.remote_dirs = Some(Vec::new());
+        true
+    }
+
+    pub fn is_listed(&self) -> bool {
+        self.remote_files.is_some() && self.remote_dirs.is_some()
+    }
+
+    fn remote_tree(&self) -> Tree {
+        Tree::new(
+            self.remote_files.as_deref().unwrap_or_default(),
+            self.remote_dirs.as_deref().unwrap_or_default(),
+            &self.options,
+        )
+    }
+
+    /// Start hashing with `checksum`, returns the request number and relative path of the files to hash.
+    pub fn start_hashing(&mut self) -> Vec<(i32, String)> {
+        if !self.options.checksum {
+            return Vec::new();
+        }
+        let remote = self.remote_tree();
+        let Some(local) = self.local_tree.as_ref() else {
+            return Vec::new();
+        };
+        self.hashes = local
+            .files
+            .iter()
+            .filter(|(path, l)| {
+                remote
+                    .files
+                    .get(*path)
+                    .map(|r| r.size == l.size && r.modified != l.modified)
+                    == Some(true)
+            })
+            .map(|(path, _)| (path.clone(), None, None))
+            .collect();
+        if !self.hashes.is_empty() {
+            self.stage = SyncStage::Hashing;
+        }
+        self.hashes
+            .iter()
+            .enumerate()
+            .map(|(i, (path, _, _))| (i as i32, path.clone()))
+            .collect()
+    }
+
+    /// A failed hash makes the file differ.
+    pub fn on_hash(&mut self, num: i32, is_local: bool, res: Result<String, String>) {
+        if let Some((path, local, remote)) = self.hashes.get_mut(num as usize) {
+            let sha256 = res.unwrap_or_else(|e| {
+                log::warn!("Failed to hash {} of sync: {}", path, e);
+                format!("error:{}", if is_local { "local" } else { "remote" })
+            });
+            if is_local {
+                *local = Some(sha256);
+            } else {
+                *remote = Some(sha256);
+            }
+        }
+    }
+
+    pub fn is_hashed(&self) -> bool {
+        self.hashes
+            .iter()
+            .all(|(_, local, remote)| local.is_some() && remote.is_some())
+    }
+
+    pub fn make_plan(&mut self) -> &Vec<SyncItem> {
+        let remote = self.remote_tree();
+        let local = self.local_tree.take().unwrap_or_default();
+        let identical: HashSet<&String> = self
+            .hashes
+            .iter()
+            .filter(|(_, l, r)| l.is_some() && l == r)
+            .map(|(path, _, _)| path)
+            .collect();
+        let direction = self.options.direction;
+        let delete = self.options.delete && direction != SyncDirection::Both;
+        let upload = direction != SyncDirection::Download;
+        let download = direction != SyncDirection::Upload;
+        let mut plan = Vec::new();
+        let mut item = |action, path: &String, is_dir, size| {
+            plan.push(SyncItem {
+                action,
+                path: path.clone(),
+                is_dir,
+                size,
+            })
+        };
+        for (path, l) in local.files.iter() {
+            match remote.files.get(path) {
+                None if upload => item(SyncAction::Upload, path, false, l.size),
+                None if delete => item(SyncAction::DeleteLocal, path, false, l.size),
+                None => {}
+                Some(r) => {
+                    if (r.size == l.size && r.modified == l.modified) || identical.contains(path) {
+                        continue;
+                    }
+                    match direction {
+                        SyncDirection::Upload => item(SyncAction::Upload, path, false, l.size),
+                        SyncDirection::Download => item(SyncAction::Download, path, false, r.size),
+                        SyncDirection::Both if l.modified > r.modified => {
+                            item(SyncAction::Upload, path, false, l.size)
+                        }
+                        SyncDirection::Both if l.modified < r.modified => {
+                            item(SyncAction::Download, path, false, r.size)
+                        }
+                        SyncDirection::Both => {
+                            log::warn!("Sync conflict of {}, left alone", path);
+                        }
+                    }
+                }
+            }
+        }
+        for (path, r) in remote.files.iter() {
+            if !local.files.contains_key(path) {
+                if download {
+                    item(SyncAction::Download, path, false, r.size);
+                } else if delete {
+                    item(SyncAction::DeleteRemote, path, false, r.size);
+                }
+            }
+        }
+        // The directories are deleted once empty, the deepest first.
+        for path in local.dirs.iter().rev() {
+            if !remote.dirs.contains(path) {
+                if upload {
+                    item(SyncAction::CreateRemoteDir, path, true, 0);
+                } else if delete && !local.kept.contains(path) {
+                    item(SyncAction::DeleteLocal, path, true, 0);
+                }
+            }
+        }
+        for path in remote.dirs.iter().rev() {
+            if !local.dirs.contains(path) {
+                if download {
+                    item(SyncAction::CreateLocalDir, path, true, 0);
+                } else if delete && !remote.kept.contains(path) {
+                    item(SyncAction::DeleteRemote, path, true, 0);
+                }
+            }
+        }
+        // Files before directories, so that the emptied directories can be removed, and the
+        // parents before their children for the created ones.
+        plan.sort_by(|a, b| {
+            a.action
+                .cmp(&b.action)
+                .then(a.is_dir.cmp(&b.is_dir))
+                .then_with(|| match a.action {
+                    SyncAction::CreateLocalDir | SyncAction::CreateRemoteDir => a.path.cmp(&b.path),
+                    _ => std::cmp::Ordering::Equal,
+                })
+        });
+        self.plan = plan;
+        &self.plan
+    }
+
+    pub fn items(&self, action: SyncAction) -> impl Iterator<Item = &SyncItem> {
+        self.plan.iter().filter(move |i| i.action == action)
+    }
+
+    pub fn paths(&self, action: SyncAction) -> HashSet<String> {
+        self.items(action).map(|i| i.path.clone()).collect()
+    }
+
+    pub fn is_download(&self, name: &str) -> bool {
+        self.stage == SyncStage::Download
+            && self
+                .items(SyncAction::Download)
+                .any(|i| i.path == normalize(name))
+    }
+
+    /// Keep the planned uploads of the files of the upload job.
+    pub fn upload_files(&self, files: &[FileEntry]) -> Vec<FileEntry> {
+        let paths = self.paths(SyncAction::Upload);
+        files
+            .iter()
+            .filter(|f| paths.contains(&normalize(&f.name)))
+            .cloned()
+            .collect()
+    }
+
+    pub fn plan_json(&self) -> String {
+        serde_json::to_string(&self.plan).unwrap_or_default()
+    }
+}
+
+/// The sync jobs of a session, by job id.
+pub type SyncJobs = HashMap<i32, SyncJob>;
+
+fn normalize(path: &str) -> String {
+    path.replace('\\', "/").trim_matches('/').to_owned()
+}
+
+fn relative(root: &str, path: &str) -> Option<String> {
+    let root = normalize(root);
+    let path = normalize(path);
+    let rest = path.strip_prefix(&root)?;
+    if rest.is_empty() {
+        return None;
+    }
+    rest.strip_prefix('/').map(|s| s.to_owned())
+}
+
+/// Match a glob pattern against a relative path, `*` and `?` don't match `/` but `**` does.
+/// A pattern without `/` matches the name of any file or directory in the tree, like in
+/// `.gitignore`, and a directory matches everything below it.
+pub fn glob_matches(pattern: &str, path: &str) -> bool {
+    let pattern = normalize(pattern);
+    if pattern.is_empty() {
+        return false;
+    }
+    let components: Vec<&str> = path.split('/').collect();
+    (1..=components.len()).any(|n| {
+        if pattern.contains('/') {
+            wildcard(pattern.as_bytes(), components[..n].join("/").as_bytes())
+        } else {
+            wildcard(pattern.as_bytes(), components[n - 1].as_bytes())
+        }
+    })
+}
+
+fn wildcard(pattern: &[u8], text: &[u8]) -> bool {
+    match pattern.first() {
+        None => text.is_empty(),
+        Some(b'*') if pattern.get(1) == Some(&b'*') => {
+            let rest = pattern[2..].strip_prefix(b"/").unwrap_or(&pattern[2..]);
+            (0..=text.len()).any(|i| {
+                (i == 0 || text[i - 1] == b'/') && wildcard(rest, &text[i..])
+                    || wildcard(&pattern[2..], &text[i..])
+            })
+        }
+        Some(b'*') => (0..=text.len())
+            .take_while(|i| *i == 0 || text[i - 1] != b'/')
+            .any(|i| wildcard(&pattern[1..], &text[i..])),
+        Some(b'?') => !text.is_empty() && text[0] != b'/' && wildcard(&pattern[1..], &text[1..]),
+        Some(c) => text.first() == Some(c) && wildcard(&pattern[1..], &text[1..]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(name: &str, size: u64, modified_time: u64) -> FileEntry {
+        FileEntry {
+            name: name.to_owned(),
+            size,
+            modified_time,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_glob() {
+        assert!(glob_matches("*.log", "a/b/c.log"));
+        assert!(!glob_matches("*.log", "a/b/c.log.gz"));
+        assert!(glob_matches("build", "build/out/x.o"));
+        assert!(glob_matches("src/*.rs", "src/main.rs"));
+        assert!(!glob_matches("src/*.rs", "src/a/main.rs"));
+        assert!(glob_matches("src/**/*.rs", "src/a/b/main.rs"));
+        assert!(glob_matches("src/**/*.rs", "src/main.rs"));
+        assert!(glob_matches("?.txt", "x/a.txt"));
+        assert!(!glob_matches("?.txt", "ab.txt"));
+    }
+
+    #[test]
+    fn test_plan() {
+        let local = vec![
+            entry("same.txt", 1, 10),
+            entry("changed.txt", 2, 20),
+            entry("new.txt", 3, 30),
+            entry("dir\\skip.log", 4, 40),
+            entry("touched.txt", 5, 50),
+        ];
+        let remote = vec![
+            entry("same.txt", 1, 10),
+            entry("changed.txt", 3, 10),
+            entry("old/gone.txt", 6, 60),
+            entry("touched.txt", 5, 55),
+            entry("logs/kept.log", 7, 70),
+            entry("logs/gone.txt", 8, 80),
+        ];
+        let options = SyncOptions {
+            exclude: vec!["*.log".to_owned()],
+            delete: true,
+            checksum: true,
+            ..Default::default()
+        };
+        let mut job = SyncJob::new("/l".to_owned(), "/r".to_owned(), options, &local, &[]);
+        job.set_remote_files(remote.clone());
+        job.set_remote_dirs(&[FileDirectory {
+            path: "/r/empty".to_owned(),
+            ..Default::default()
+        }]);
+        assert!(job.is_listed());
+        assert_eq!(job.start_hashing(), vec![(0, "touched.txt".to_owned())]);
+        job.on_hash(0, true, Ok("x".to_owned()));
+        assert!(!job.is_hashed());
+        job.on_hash(0, false, Ok("x".to_owned()));
+        assert!(job.is_hashed());
+        let plan: Vec<_> = job
+            .make_plan()
+            .iter()
+            .map(|i| (i.action, i.path.as_str()))
+            .collect();
+        assert_eq!(
+            plan,
+            vec![
+                (SyncAction::DeleteRemote, "logs/gone.txt"),
+                (SyncAction::DeleteRemote, "old/gone.txt"),
+                (SyncAction::DeleteRemote, "old"),
+                (SyncAction::DeleteRemote, "empty"),
+                (SyncAction::Upload, "changed.txt"),
+                (SyncAction::Upload, "new.txt"),
+            ]
+        );
+        assert_eq!(job.upload_files(&local).len(), 2);
+
+        let options = SyncOptions {
+            direction: SyncDirection::Both,
+            delete: true,
+            ..Default::default()
+        };
+        let mut job = SyncJob::new("/l".to_owned(), "/r".to_owned(), options, &local, &[]);
+        job.set_remote_files(remote);
+        job.set_remote_dirs(&[]);
+        let plan: Vec<_> = job
+            .make_plan()
+            .iter()
+            .map(|i| (i.action, i.path.as_str()))
+            .collect();
+        assert_eq!(
+            plan,
+            vec![
+                (SyncAction::CreateRemoteDir, "dir"),
+                (SyncAction::CreateLocalDir, "logs"),
+                (SyncAction::CreateLocalDir, "old"),
+                (SyncAction::Download, "touched.txt"),
+                (SyncAction::Download, "logs/gone.txt"),
+                (SyncAction::Download, "logs/kept.log"),
+                (SyncAction::Download, "old/gone.txt"),
+                (SyncAction::Upload, "changed.txt"),
+                (SyncAction::Upload, "dir/skip.log"),
+                (SyncAction::Upload, "new.txt"),
+            ]
+        );
+    }
+}
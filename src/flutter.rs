@@ -816,6 +816,14 @@ impl InvokeUiSession for FlutterHandler {
         );
     }
 
+    fn sync_dir_plan(&self, id: i32, plan: String) {
+        self.push_event(
+            "sync_dir_plan",
+            &[("id", &id.to_string()), ("value", &plan)],
+            &[],
+        );
+    }
+
     // unused in flutter
     fn update_transfer_list(&self) {}
 
@@ -1105,10 +1113,24 @@ impl InvokeUiSession for FlutterHandler {
         self.push_event("record_status", &[("start", &start.to_string())], &[]);
     }
 
-    fn printer_request(&self, id: i32, path: String) {
+    fn printer_request(&self, id: i32, path: String, job: &PrintJobInfo) {
+        let format = match job.format.enum_value() {
+            Ok(PrintFormat::Pdf) => "PDF",
+            _ => "XPS",
+        };
         self.push_event(
             "printer_request",
-            &[("id", json!(id)), ("path", json!(path))],
+            &[
+                ("id", json!(id)),
+                ("path", json!(path)),
+                ("format", json!(format)),
+                ("title", json!(job.title)),
+                ("pages", json!(job.pages)),
+                ("paper_size", json!(job.paper_size)),
+                ("color_mode", json!(job.color_mode)),
+                ("copies", json!(job.copies)),
+                ("size", json!(job.size)),
+            ],
             &[],
         );
     }
@@ -1139,6 +1161,9 @@ impl InvokeUiSession for FlutterHandler {
                 if !opened.persistent_sessions.is_empty() {
                     event_data.push(("persistent_sessions", json!(opened.persistent_sessions)));
                 }
+                if !opened.shells.is_empty() {
+                    event_data.push(("shells", json!(opened.shells)));
+                }
                 self.push_event_("terminal_response", &event_data, &[], &[]);
             }
             Some(Union::Data(data)) => {
@@ -1173,6 +1198,61 @@ impl InvokeUiSession for FlutterHandler {
                 ];
                 self.push_event_("terminal_response", &event_data, &[], &[]);
             }
+            Some(Union::ExecOutput(output)) => {
+                let event_data: Vec<(&str, serde_json::Value)> = vec![
+                    ("type", json!("exec_output")),
+                    ("exec_id", json!(output.exec_id)),
+                    ("stdout", json!(crate::encode64(&output.stdout))),
+                    ("stderr", json!(crate::encode64(&output.stderr))),
+                ];
+                self.push_event_("terminal_response", &event_data, &[], &[]);
+            }
+            Some(Union::ExecResult(result)) => {
+                let event_data: Vec<(&str, serde_json::Value)> = vec![
+                    ("type", json!("exec_result")),
+                    ("exec_id", json!(result.exec_id)),
+                    ("exit_code", json!(result.exit_code)),
+                    ("timed_out", json!(result.timed_out)),
+                    ("error", json!(&result.error)),
+                ];
+                self.push_event_("terminal_response", &event_data, &[], &[]);
+            }
+            Some(Union::Viewers(viewers)) => {
+                let list: Vec<serde_json::Value> = viewers
+                    .viewers
+                    .iter()
+                    .map(|v| {
+                        json!({
+                            "conn_id": v.conn_id,
+                            "peer_id": &v.peer_id,
+                            "name": &v.name,
+                        })
+                    })
+                    .collect();
+                let event_data: Vec<(&str, serde_json::Value)> = vec![
+                    ("type", json!("viewers")),
+                    ("conn_id", json!(viewers.conn_id)),
+                    ("driver", json!(viewers.driver)),
+                    ("viewers", json!(list)),
+                ];
+                self.push_event_("terminal_response", &event_data, &[], &[]);
+            }
+            Some(Union::Scrollback(scrollback)) => {
+                let lines: Vec<serde_json::Value> = scrollback
+                    .lines
+                    .iter()
+                    .map(|l| json!({ "line": l.line, "data": crate::encode64(&l.data) }))
+                    .collect();
+                let event_data: Vec<(&str, serde_json::Value)> = vec![
+                    ("type", json!("scrollback")),
+                    ("terminal_id", json!(scrollback.terminal_id)),
+                    ("first_line", json!(scrollback.first_line)),
+                    ("end_line", json!(scrollback.end_line)),
+                    ("query", json!(&scrollback.query)),
+                    ("lines", json!(lines)),
+                ];
+                self.push_event_("terminal_response", &event_data, &[], &[]);
+            }
             None => {}
             Some(_) => {
                 log::warn!("Unhandled terminal response type");
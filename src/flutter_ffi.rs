@@ -326,7 +326,10 @@ pub fn session_toggle_option(session_id: SessionID, value: String) {
         try_sync_peer_option(&session, &session_id, &value, None);
     }
     #[cfg(not(target_os = "ios"))]
-    if sessions::get_session_by_session_id(&session_id).is_some() && value == "disable-clipboard" {
+    if sessions::get_session_by_session_id(&session_id).is_some()
+        && (value == "disable-clipboard"
+            || value == crate::clipboard::history::OPTION_MANUAL_CLIPBOARD_SYNC)
+    {
         crate::flutter::update_text_clipboard_required();
     }
     #[cfg(feature = "unix-file-copy-paste")]
@@ -337,6 +340,37 @@ pub fn session_toggle_option(session_id: SessionID, value: String) {
     }
 }
 
+pub fn session_get_clipboard_history(session_id: SessionID) -> SyncReturn<String> {
+    if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        SyncReturn(session.get_clipboard_history())
+    } else {
+        SyncReturn("".to_owned())
+    }
+}
+
+pub fn session_paste_clipboard_history(session_id: SessionID, id: i32) {
+    if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        session.paste_clipboard_history(id);
+    }
+}
+
+pub fn session_copy_clipboard_history(session_id: SessionID, id: i32) {
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        session.copy_clipboard_history(id);
+    }
+    #[cfg(any(target_os = "android", target_os = "ios"))]
+    let _ = (session_id, id);
+}
+
+pub fn main_remove_clipboard_history(id: i32) {
+    crate::clipboard::history::remove(id);
+}
+
+pub fn main_clear_clipboard_history() {
+    crate::clipboard::history::clear();
+}
+
 pub fn session_toggle_privacy_mode(session_id: SessionID, impl_key: String, on: bool) {
     if let Some(session) = sessions::get_session_by_session_id(&session_id) {
         session.toggle_privacy_mode(impl_key, on);
@@ -667,6 +701,19 @@ pub fn session_open_terminal(session_id: SessionID, terminal_id: i32, rows: u32,
     }
 }
 
+pub fn session_open_terminal_with_shell(
+    session_id: SessionID,
+    terminal_id: i32,
+    rows: u32,
+    cols: u32,
+    shell: String,
+    cwd: String,
+) {
+    if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        session.open_terminal_with_shell(terminal_id, rows, cols, shell, cwd);
+    }
+}
+
 pub fn session_send_terminal_input(session_id: SessionID, terminal_id: i32, data: String) {
     if let Some(session) = sessions::get_session_by_session_id(&session_id) {
         session.send_terminal_input(terminal_id, data);
@@ -685,6 +732,41 @@ pub fn session_close_terminal(session_id: SessionID, terminal_id: i32) {
     }
 }
 
+/// `env` is a JSON object of the environment variables added to the command.
+pub fn session_exec_command(
+    session_id: SessionID,
+    exec_id: i32,
+    argv: Vec<String>,
+    env: String,
+    cwd: String,
+    stdin: String,
+    timeout_ms: u32,
+) {
+    if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        let env = serde_json::from_str(&env).unwrap_or_default();
+        session.exec_command(exec_id, argv, env, cwd, stdin.into_bytes(), timeout_ms);
+    }
+}
+
+pub fn session_hand_over_terminal(session_id: SessionID, conn_id: i32) {
+    if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        session.hand_over_terminal(conn_id);
+    }
+}
+
+pub fn session_terminal_scrollback(
+    session_id: SessionID,
+    terminal_id: i32,
+    from_line: u64,
+    count: u32,
+    query: String,
+    max_matches: u32,
+) {
+    if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        session.terminal_scrollback(terminal_id, from_line, count, query, max_matches);
+    }
+}
+
 pub fn session_peer_option(session_id: SessionID, name: String, value: String) {
     if let Some(session) = sessions::get_session_by_session_id(&session_id) {
         session.set_option(name, value);
@@ -734,6 +816,22 @@ pub fn session_send_files(
     }
 }
 
+/// `options` is the json of `file_sync::SyncOptions`, the plan comes back in the `sync_dir_plan` event.
+pub fn session_sync_dir(
+    session_id: SessionID,
+    act_id: i32,
+    local: String,
+    remote: String,
+    options: String,
+) {
+    if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        match serde_json::from_str(&options) {
+            Ok(options) => session.sync_dir(act_id, local, remote, options),
+            Err(e) => log::error!("Invalid sync options {}: {}", options, e),
+        }
+    }
+}
+
 pub fn session_set_confirm_override_file(
     session_id: SessionID,
     act_id: i32,
@@ -2029,6 +2127,12 @@ pub fn session_printer_response(
     }
 }
 
+pub fn session_printer_dismiss(session_id: SessionID, id: i32) {
+    if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        session.printer_dismiss(id);
+    }
+}
+
 pub fn main_set_home_dir(_home: String) {
     #[cfg(any(target_os = "android", target_os = "ios"))]
     {
@@ -2736,19 +2840,24 @@ pub fn main_get_printer_names() -> SyncReturn<String> {
 
 pub fn main_get_common(key: String) -> String {
     if key == "is-printer-installed" {
-        #[cfg(target_os = "windows")]
+        #[cfg(any(target_os = "windows", target_os = "linux"))]
         {
             return match remote_printer::is_rd_printer_installed(&get_app_name()) {
                 Ok(r) => r.to_string(),
                 Err(e) => e.to_string(),
             };
         }
-        #[cfg(not(target_os = "windows"))]
+        #[cfg(not(any(target_os = "windows", target_os = "linux")))]
         return false.to_string();
     } else if key == "is-support-printer-driver" {
         #[cfg(target_os = "windows")]
         return crate::platform::is_win_10_or_greater().to_string();
-        #[cfg(not(target_os = "windows"))]
+        // The CUPS printer on Linux
+        #[cfg(target_os = "linux")]
+        return std::path::Path::new("/usr/sbin/lpadmin")
+            .exists()
+            .to_string();
+        #[cfg(not(any(target_os = "windows", target_os = "linux")))]
         return false.to_string();
     } else if key == "transfer-job-id" {
         return hbb_common::fs::get_next_job_id().to_string();
@@ -2836,6 +2945,38 @@ pub fn main_set_common(_key: String, _value: String) {
             );
         });
     }
+    #[cfg(target_os = "linux")]
+    if _key == "install-printer" {
+        std::thread::spawn(move || {
+            let (success, msg) = match std::env::current_exe() {
+                Ok(exe) => {
+                    let cmd = format!(
+                        "{} --install-remote-printer",
+                        crate::platform::shell_quote(&exe.to_string_lossy())
+                    );
+                    if !crate::platform::run_cmds_privileged(&cmd) {
+                        (false, "Failed to run the installation as root".to_owned())
+                    } else {
+                        match remote_printer::is_rd_printer_installed(&get_app_name()) {
+                            Ok(true) => (true, "".to_owned()),
+                            Ok(false) => (false, "Failed to install the CUPS printer".to_owned()),
+                            Err(e) => (false, e.to_string()),
+                        }
+                    }
+                }
+                Err(e) => (false, e.to_string()),
+            };
+            let data = HashMap::from([
+                ("name", serde_json::json!("install-printer-res")),
+                ("success", serde_json::json!(success)),
+                ("msg", serde_json::json!(msg)),
+            ]);
+            let _res = flutter::push_global_event(
+                flutter::APP_TYPE_MAIN,
+                serde_json::ser::to_string(&data).unwrap_or("".to_owned()),
+            );
+        });
+    }
     #[cfg(any(target_os = "windows", target_os = "macos"))]
     {
         use crate::updater::get_download_file_from_url;
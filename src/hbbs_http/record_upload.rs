@@ -2,7 +2,7 @@ use crate::hbbs_http::create_http_client_with_url;
 use bytes::Bytes;
 use hbb_common::{bail, config::Config, lazy_static, log, ResultType};
 use reqwest::blocking::{Body, Client};
-use scrap::record::RecordState;
+use scrap::record::{encrypt, RecordState};
 use serde::Serialize;
 use serde_json::Map;
 use std::{
@@ -170,6 +170,8 @@ impl RecordUploader {
                 }
                 Err(e) => bail!(e.to_string()),
             },
+            // The hardware codec recordings are encrypted when they are finished.
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound && !flush => Ok(()),
             Err(e) => bail!(e.to_string()),
         }
     }
@@ -178,10 +180,15 @@ impl RecordUploader {
         self.handle_frame(true)?;
         match File::open(&self.filepath) {
             Ok(mut file) => {
-                let mut buf = vec![0u8; MAX_HEADER_LEN];
-                match file.read(&mut buf) {
+                // The encrypted blocks of the header are rewritten as a whole, it is uploaded as it is.
+                let len = if self.filename.ends_with(&format!(".{}", encrypt::EXTENSION)) {
+                    encrypt::HEAD_LEN
+                } else {
+                    MAX_HEADER_LEN
+                };
+                let mut buf = Vec::with_capacity(len);
+                match file.take(len as u64).read_to_end(&mut buf) {
                     Ok(length) => {
-                        buf.truncate(length);
                         self.send(
                             &[
                                 ("type", "tail"),
@@ -110,6 +110,13 @@ pub enum FS {
         is_upload: bool,
         is_resume: bool,
     },
+    // The sha256 of `path`, from the journal of the written file if `is_upload`.
+    Checksum {
+        id: i32,
+        file_num: i32,
+        path: String,
+        is_upload: bool,
+    },
     SendConfirm(Vec<u8>),
     Rename {
         id: i32,
@@ -373,8 +380,8 @@ pub enum Data {
     HwCodecConfig(Option<String>),
     RemoveTrustedDevices(Vec<Bytes>),
     ClearTrustedDevices,
-    #[cfg(all(target_os = "windows", feature = "flutter"))]
-    PrinterData(Vec<u8>),
+    #[cfg(all(any(target_os = "windows", target_os = "linux"), feature = "flutter"))]
+    PrinterData(Vec<u8>, crate::print_job::JobOptions),
     InstallOption(Option<(String, String)>),
     #[cfg(all(
         feature = "flutter",
@@ -389,6 +396,8 @@ pub enum Data {
     #[cfg(not(any(target_os = "android", target_os = "ios")))]
     Whiteboard((String, crate::whiteboard::CustomEvent)),
     ControlPermissionsRemoteModify(Option<bool>),
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    PortForwardStatus(Option<Vec<crate::port_forward::profile::TunnelStatus>>),
     #[cfg(target_os = "windows")]
     FileTransferEnabledState(Option<bool>),
 }
@@ -6,6 +6,7 @@ use hbb_common::{
     config::Config,
     config::{self, RENDEZVOUS_PORT},
     log,
+    message_proto::LanInterface,
     protobuf::Message as _,
     rendezvous_proto::*,
     tokio::{
@@ -15,14 +16,38 @@ use hbb_common::{
     ResultType,
 };
 
+use mdns_sd::{ServiceDaemon, ServiceEvent, ServiceInfo};
 use std::{
     collections::{HashMap, HashSet},
     net::{IpAddr, Ipv4Addr, SocketAddr, ToSocketAddrs, UdpSocket},
-    time::Instant,
+    time::{Duration, Instant},
 };
 
 type Message = RendezvousMessage;
 
+// Peers are also announced and browsed as a DNS-SD service over mDNS.
+const SERVICE_TYPE: &str = "_rustdesk._tcp.local.";
+/// Subnets pinged one host at a time, where broadcast and multicast are blocked,
+/// e.g. `10.0.1.0/24; 10.0.2.7`.
+pub const OPTION_LAN_DISCOVERY_SUBNETS: &str = "lan-discovery-subnets";
+const MAX_UNICAST_HOSTS: usize = 4096; // All subnets together
+/// Send magic packets for authorized controlling sides to the peers on the subnets of this device,
+/// and tell them the LAN interfaces of this device. Off by default.
+pub const OPTION_ALLOW_WOL_RELAY: &str = "allow-wol-relay";
+/// Peer option of the controlling side, the LAN interfaces the peer reported, in JSON.
+pub const OPTION_PEER_LAN_INTERFACES: &str = "lan-interfaces";
+#[cfg(not(target_os = "ios"))]
+const ANNOUNCE_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+const BROWSE_TIMEOUT: Duration = Duration::from_secs(3);
+
+#[cfg(not(target_os = "ios"))]
+fn is_lan_discovery_enabled() -> bool {
+    config::option2bool(
+        "enable-lan-discovery",
+        &Config::get_option("enable-lan-discovery"),
+    )
+}
+
 #[cfg(not(target_os = "ios"))]
 pub(super) fn start_listening() -> ResultType<()> {
     let addr = SocketAddr::from(([0, 0, 0, 0], get_broadcast_port()));
@@ -35,12 +60,7 @@ pub(super) fn start_listening() -> ResultType<()> {
             if let Ok(msg_in) = Message::parse_from_bytes(&buf[0..len]) {
                 match msg_in.union {
                     Some(rendezvous_message::Union::PeerDiscovery(p)) => {
-                        if p.cmd == "ping"
-                            && config::option2bool(
-                                "enable-lan-discovery",
-                                &Config::get_option("enable-lan-discovery"),
-                            )
-                        {
+                        if p.cmd == "ping" && is_lan_discovery_enabled() {
                             let id = Config::get_id();
                             if p.id == id {
                                 continue;
@@ -73,10 +93,97 @@ pub(super) fn start_listening() -> ResultType<()> {
     }
 }
 
+// What is announced over mDNS, re-announced when it changes.
+#[cfg(not(target_os = "ios"))]
+#[derive(Debug, PartialEq)]
+struct Announcement {
+    id: String,
+    hostname: String,
+    platform: String,
+    direct_port: Option<u16>,
+}
+
+#[cfg(not(target_os = "ios"))]
+impl Announcement {
+    fn current() -> Option<Self> {
+        if !is_lan_discovery_enabled() {
+            return None;
+        }
+        let id = Config::get_id();
+        if id.is_empty() {
+            return None;
+        }
+        let direct_port = config::option2bool(
+            config::keys::OPTION_DIRECT_SERVER,
+            &Config::get_option(config::keys::OPTION_DIRECT_SERVER),
+        )
+        .then(|| crate::rendezvous_mediator::get_direct_port() as u16);
+        Some(Self {
+            id,
+            hostname: crate::whoami_hostname(),
+            platform: whoami::platform().to_string(),
+            direct_port,
+        })
+    }
+
+    fn service_info(&self) -> ResultType<ServiceInfo> {
+        let mut properties = HashMap::from([
+            ("id".to_owned(), self.id.clone()),
+            ("hostname".to_owned(), self.hostname.clone()),
+            ("platform".to_owned(), self.platform.clone()),
+        ]);
+        if let Some(port) = self.direct_port {
+            properties.insert("port".to_owned(), port.to_string());
+        }
+        // A host name of letters, digits and hyphens only.
+        let label: String = self
+            .hostname
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+            .collect();
+        let host = format!("{}.local.", label.trim_matches('-'));
+        let port = self.direct_port.unwrap_or(get_broadcast_port());
+        let info = ServiceInfo::new(SERVICE_TYPE, &self.id, &host, "", port, properties)?;
+        Ok(info.enable_addr_auto())
+    }
+}
+
+/// Announce this device as a `_rustdesk._tcp` service while LAN discovery is enabled.
+#[cfg(not(target_os = "ios"))]
+pub(super) fn start_announcing() -> ResultType<()> {
+    let daemon = ServiceDaemon::new()?;
+    let mut announced: Option<(Announcement, String)> = None;
+    log::info!("lan discovery announcement started");
+    loop {
+        let current = Announcement::current();
+        if current.as_ref() != announced.as_ref().map(|(a, _)| a) {
+            if let Some((_, fullname)) = announced.take() {
+                allow_err!(daemon.unregister(&fullname));
+            }
+            if let Some(announcement) = current {
+                match announcement.service_info().and_then(|info| {
+                    let fullname = info.get_fullname().to_owned();
+                    daemon.register(info)?;
+                    Ok(fullname)
+                }) {
+                    Ok(fullname) => {
+                        log::info!("Announced {} over mDNS", fullname);
+                        announced = Some((announcement, fullname));
+                    }
+                    Err(e) => log::error!("Failed to announce over mDNS: {}", e),
+                }
+            }
+        }
+        std::thread::sleep(ANNOUNCE_CHECK_INTERVAL);
+    }
+}
+
 #[tokio::main(flavor = "current_thread")]
 pub async fn discover() -> ResultType<()> {
     let sockets = send_query()?;
-    let rx = spawn_wait_responses(sockets);
+    let (tx, rx) = unbounded_channel::<_>();
+    spawn_wait_responses(sockets, tx.clone());
+    spawn_browse(tx);
     handle_received_peers(rx).await?;
 
     log::info!("discover ping done");
@@ -104,6 +211,100 @@ pub fn send_wol(id: String) {
     }
 }
 
+/// The IPv4 interfaces of this device with a mac address.
+#[cfg(not(target_os = "ios"))]
+pub fn get_lan_interfaces() -> Vec<LanInterface> {
+    let mut interfaces = Vec::new();
+    for interface in default_net::get_interfaces() {
+        let Some(mac) = interface.mac_addr.as_ref() else {
+            continue;
+        };
+        for ipv4 in &interface.ipv4 {
+            if ipv4.addr.is_loopback() {
+                continue;
+            }
+            interfaces.push(LanInterface {
+                ip: ipv4.addr.to_string(),
+                mac: mac.address(),
+                prefix_len: ipv4.prefix_len as _,
+                ..Default::default()
+            });
+        }
+    }
+    interfaces
+}
+
+pub fn store_peer_interfaces(options: &mut HashMap<String, String>, interfaces: &[LanInterface]) {
+    let interfaces: Vec<_> = interfaces
+        .iter()
+        .map(|i| serde_json::json!({ "ip": i.ip, "mac": i.mac, "prefix_len": i.prefix_len }))
+        .collect();
+    options.insert(
+        OPTION_PEER_LAN_INTERFACES.to_owned(),
+        serde_json::Value::from(interfaces).to_string(),
+    );
+}
+
+pub fn load_peer_interfaces(options: &HashMap<String, String>) -> Vec<LanInterface> {
+    let Some(Ok(serde_json::Value::Array(interfaces))) = options
+        .get(OPTION_PEER_LAN_INTERFACES)
+        .map(|s| serde_json::from_str::<serde_json::Value>(s))
+    else {
+        return Vec::new();
+    };
+    interfaces
+        .iter()
+        .map(|i| LanInterface {
+            ip: i["ip"].as_str().unwrap_or_default().to_owned(),
+            mac: i["mac"].as_str().unwrap_or_default().to_owned(),
+            prefix_len: i["prefix_len"].as_u64().unwrap_or_default() as _,
+            ..Default::default()
+        })
+        .collect()
+}
+
+fn prefix_mask(prefix_len: u32) -> u32 {
+    u32::MAX.checked_shl(32 - prefix_len.min(32)).unwrap_or(0)
+}
+
+/// Whether `target` is on the subnet of `interface`.
+pub fn is_on_subnet(interface: &LanInterface, target: &LanInterface) -> bool {
+    match (
+        interface.ip.parse::<Ipv4Addr>(),
+        target.ip.parse::<Ipv4Addr>(),
+    ) {
+        (Ok(a), Ok(b)) => {
+            let mask = prefix_mask(interface.prefix_len);
+            a != b && u32::from(a) & mask == u32::from(b) & mask
+        }
+        _ => false,
+    }
+}
+
+/// Send magic packets to the targets on the subnets of this device, from the interface of the
+/// subnet to its broadcast address. Returns the number of packets sent.
+#[cfg(not(target_os = "ios"))]
+pub fn relay_wol(targets: &[LanInterface]) -> ResultType<usize> {
+    let mut sent = 0;
+    for interface in get_lan_interfaces() {
+        for target in targets.iter().filter(|t| is_on_subnet(&interface, t)) {
+            let (Ok(mac), Ok(addr)) = (target.mac.parse(), interface.ip.parse::<Ipv4Addr>()) else {
+                continue;
+            };
+            let broadcast = Ipv4Addr::from(u32::from(addr) | !prefix_mask(interface.prefix_len));
+            log::info!("Relay wol to {} via {}", target.mac, broadcast);
+            match wol::send_wol(mac, Some(IpAddr::V4(broadcast)), Some(IpAddr::V4(addr))) {
+                Ok(_) => sent += 1,
+                Err(e) => log::warn!("Failed to send wol to {}: {}", target.mac, e),
+            }
+        }
+    }
+    if sent == 0 {
+        bail!("The peer to wake is not on the subnets of this device");
+    }
+    Ok(sent)
+}
+
 #[inline]
 fn get_broadcast_port() -> u16 {
     (RENDEZVOUS_PORT + 3) as _
@@ -214,10 +415,60 @@ fn send_query() -> ResultType<Vec<UdpSocket>> {
     for socket in &sockets {
         allow_err!(socket.send_to(&out, maddr));
     }
+    // The last socket is bound to the unspecified address, routed to any subnet.
+    let hosts = unicast_hosts(&Config::get_option(OPTION_LAN_DISCOVERY_SUBNETS));
+    if let (false, Some(socket)) = (hosts.is_empty(), sockets.last()) {
+        for host in &hosts {
+            socket
+                .send_to(&out, SocketAddr::from((*host, get_broadcast_port())))
+                .ok();
+        }
+        log::info!("discover ping sent to {} hosts of the subnets", hosts.len());
+    }
     log::info!("discover ping sent");
     Ok(sockets)
 }
 
+/// The hosts of the subnets to ping, separated by new lines, `;` or `,`.
+fn unicast_hosts(subnets: &str) -> Vec<Ipv4Addr> {
+    let mut hosts = Vec::new();
+    for subnet in subnets
+        .split(|c| c == '\n' || c == ';' || c == ',')
+        .map(|s| s.trim())
+        .filter(|s| !s.is_empty())
+    {
+        let (ip, prefix) = match subnet.split_once('/') {
+            Some((ip, prefix)) => (ip, prefix.trim().parse::<u32>().ok()),
+            None => (subnet, Some(32)),
+        };
+        let (Ok(ip), Some(prefix)) = (ip.trim().parse::<Ipv4Addr>(), prefix.filter(|p| *p <= 32))
+        else {
+            log::warn!("Invalid lan discovery subnet: {}", subnet);
+            continue;
+        };
+        let mask = prefix_mask(prefix);
+        let network = u32::from(ip) & mask;
+        let broadcast = network | !mask;
+        // The network and the broadcast addresses are not hosts, except in /31 and /32.
+        let (first, last) = if prefix >= 31 {
+            (network, broadcast)
+        } else {
+            (network + 1, broadcast - 1)
+        };
+        for host in first..=last {
+            if hosts.len() >= MAX_UNICAST_HOSTS {
+                log::warn!(
+                    "At most {} hosts of the subnets are pinged",
+                    MAX_UNICAST_HOSTS
+                );
+                return hosts;
+            }
+            hosts.push(Ipv4Addr::from(host));
+        }
+    }
+    hosts
+}
+
 fn wait_response(
     socket: UdpSocket,
     timeout: Option<std::time::Duration>,
@@ -287,8 +538,7 @@ fn wait_response(
     Ok(())
 }
 
-fn spawn_wait_responses(sockets: Vec<UdpSocket>) -> UnboundedReceiver<config::DiscoveryPeer> {
-    let (tx, rx) = unbounded_channel::<_>();
+fn spawn_wait_responses(sockets: Vec<UdpSocket>, tx: UnboundedSender<config::DiscoveryPeer>) {
     for socket in sockets {
         let tx_clone = tx.clone();
         std::thread::spawn(move || {
@@ -299,7 +549,69 @@ fn spawn_wait_responses(sockets: Vec<UdpSocket>) -> UnboundedReceiver<config::Di
             ));
         });
     }
-    rx
+}
+
+fn spawn_browse(tx: UnboundedSender<config::DiscoveryPeer>) {
+    std::thread::spawn(move || {
+        allow_err!(browse(tx));
+    });
+}
+
+/// Find the peers announced over mDNS, their mac addresses are unknown.
+fn browse(tx: UnboundedSender<config::DiscoveryPeer>) -> ResultType<()> {
+    let daemon = ServiceDaemon::new()?;
+    let receiver = daemon.browse(SERVICE_TYPE)?;
+    let local_ips = get_local_ips();
+    let deadline = Instant::now() + BROWSE_TIMEOUT;
+    while let Some(timeout) = deadline.checked_duration_since(Instant::now()) {
+        let Ok(event) = receiver.recv_timeout(timeout) else {
+            break;
+        };
+        let ServiceEvent::ServiceResolved(info) = event else {
+            continue;
+        };
+        let id = info.get_property_val_str("id").unwrap_or_default();
+        let ips: Vec<_> = info
+            .get_addresses()
+            .iter()
+            .filter(|ip| ip.is_ipv4())
+            .cloned()
+            .collect();
+        // Ourselves
+        if id.is_empty() || ips.is_empty() || ips.iter().any(|ip| local_ips.contains(ip)) {
+            continue;
+        }
+        allow_err!(tx.send(config::DiscoveryPeer {
+            id: id.to_owned(),
+            ip_mac: ips
+                .iter()
+                .map(|ip| (ip.to_string(), "".to_owned()))
+                .collect(),
+            username: "".to_owned(),
+            hostname: info
+                .get_property_val_str("hostname")
+                .unwrap_or_default()
+                .to_owned(),
+            platform: info
+                .get_property_val_str("platform")
+                .unwrap_or_default()
+                .to_owned(),
+            online: true,
+        }));
+    }
+    allow_err!(daemon.shutdown());
+    log::info!("discover mdns browse done");
+    Ok(())
+}
+
+fn get_local_ips() -> Vec<IpAddr> {
+    #[cfg(not(target_os = "ios"))]
+    return default_net::get_interfaces()
+        .iter()
+        .flat_map(|i| i.ipv4.iter().map(|x| IpAddr::V4(x.addr)))
+        .collect();
+    #[cfg(target_os = "ios")]
+    Vec::new()
 }
 
 async fn handle_received_peers(mut rx: UnboundedReceiver<config::DiscoveryPeer>) -> ResultType<()> {
@@ -317,8 +629,20 @@ async fn handle_received_peers(mut rx: UnboundedReceiver<config::DiscoveryPeer>)
                     let in_response_set = !response_set.insert(peer.id.clone());
                     if let Some(pos) = peers.iter().position(|x| x.is_same_peer(&peer) ) {
                         let peer1 = peers.remove(pos);
+                        // The mac addresses are unknown to mDNS, the known ones are kept.
+                        for (ip, mac) in peer1.ip_mac {
+                            if in_response_set {
+                                let known = peer.ip_mac.entry(ip).or_default();
+                                if known.is_empty() {
+                                    *known = mac;
+                                }
+                            } else if let Some(known) = peer.ip_mac.get_mut(&ip) {
+                                if known.is_empty() {
+                                    *known = mac;
+                                }
+                            }
+                        }
                         if in_response_set {
-                            peer.ip_mac.extend(peer1.ip_mac);
                             peer.online = true;
                         }
                     }
@@ -342,3 +666,44 @@ async fn handle_received_peers(mut rx: UnboundedReceiver<config::DiscoveryPeer>)
     crate::flutter_ffi::main_load_lan_peers();
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unicast_hosts() {
+        let hosts = unicast_hosts("10.0.1.5/30; 10.0.2.7,\n192.168.0.0/31; bad/24");
+        assert_eq!(
+            hosts,
+            [
+                "10.0.1.5",
+                "10.0.1.6",
+                "10.0.2.7",
+                "192.168.0.0",
+                "192.168.0.1"
+            ]
+            .iter()
+            .map(|s| s.parse::<Ipv4Addr>().unwrap())
+            .collect::<Vec<_>>()
+        );
+        assert_eq!(unicast_hosts("10.0.0.0/8").len(), MAX_UNICAST_HOSTS);
+    }
+
+    #[test]
+    fn test_peer_interfaces() {
+        let interface = |ip: &str, prefix_len| LanInterface {
+            ip: ip.to_owned(),
+            mac: "00:11:22:33:44:55".to_owned(),
+            prefix_len,
+            ..Default::default()
+        };
+        let mut options = HashMap::new();
+        store_peer_interfaces(&mut options, &[interface("10.0.1.5", 24)]);
+        let loaded = load_peer_interfaces(&options);
+        assert_eq!(loaded, vec![interface("10.0.1.5", 24)]);
+        assert!(is_on_subnet(&loaded[0], &interface("10.0.1.9", 16)));
+        assert!(!is_on_subnet(&loaded[0], &interface("10.0.2.9", 24)));
+        assert!(!is_on_subnet(&loaded[0], &loaded[0]));
+    }
+}
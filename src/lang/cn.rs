@@ -739,5 +739,26 @@ pub static ref T: std::collections::HashMap<&'static str, &'static str> =
         ("Changelog", "更新日志"),
         ("keep-awake-during-outgoing-sessions-label", ""),
         ("keep-awake-during-incoming-sessions-label", ""),
+        ("Enable dynamic TCP tunneling (SOCKS5)", "允许建立动态 TCP 隧道 (SOCKS5)"),
+        ("Enable reverse TCP tunneling", "允许建立反向 TCP 隧道"),
+        ("Allow reverse TCP tunneling on any address", "允许反向 TCP 隧道监听所有地址"),
+        ("Enable delta file transfer", "启用增量文件传输"),
+        ("Record incoming terminal sessions", "录制被控终端会话"),
+        ("Include terminal input in recordings", "录制中包含终端输入"),
+        ("Enable remote command execution", "允许远程执行命令"),
+        ("Log commands entered in terminals", "记录在终端中输入的命令"),
+        ("Pages", "页数"),
+        ("Paper size", "纸张大小"),
+        ("Color mode", "颜色模式"),
+        ("Color", "彩色"),
+        ("Monochrome", "黑白"),
+        ("Copies", "份数"),
+        ("Format", "格式"),
+        ("print-auto-accept-peer-tip", "打印来自此设备的任务时不再询问"),
+        ("Clipboard history", "剪贴板历史"),
+        ("Manual clipboard sync", "手动同步剪贴板"),
+        ("Copy to local", "复制到本地"),
+        ("Paste to remote", "粘贴到远程"),
+        ("Enable Wake-on-LAN relay", "允许中继网络唤醒"),
     ].iter().cloned().collect();
 }
@@ -739,5 +739,26 @@ pub static ref T: std::collections::HashMap<&'static str, &'static str> =
         ("Changelog", ""),
         ("keep-awake-during-outgoing-sessions-label", ""),
         ("keep-awake-during-incoming-sessions-label", ""),
+        ("Enable dynamic TCP tunneling (SOCKS5)", ""),
+        ("Enable reverse TCP tunneling", ""),
+        ("Allow reverse TCP tunneling on any address", ""),
+        ("Enable delta file transfer", ""),
+        ("Record incoming terminal sessions", ""),
+        ("Include terminal input in recordings", ""),
+        ("Enable remote command execution", ""),
+        ("Log commands entered in terminals", ""),
+        ("Pages", ""),
+        ("Paper size", ""),
+        ("Color mode", ""),
+        ("Color", ""),
+        ("Monochrome", ""),
+        ("Copies", ""),
+        ("Format", ""),
+        ("print-auto-accept-peer-tip", ""),
+        ("Clipboard history", ""),
+        ("Manual clipboard sync", ""),
+        ("Copy to local", ""),
+        ("Paste to remote", ""),
+        ("Enable Wake-on-LAN relay", ""),
     ].iter().cloned().collect();
 }
@@ -45,9 +45,16 @@ mod clipboard;
 #[cfg(not(any(target_os = "android", target_os = "ios", feature = "cli")))]
 pub mod core_main;
 mod custom_server;
+mod file_delta;
+mod file_journal;
+mod file_sync;
 mod lang;
 #[cfg(not(any(target_os = "android", target_os = "ios")))]
 mod port_forward;
+#[cfg(all(any(target_os = "windows", target_os = "linux"), feature = "flutter"))]
+mod print_job;
+#[cfg(not(any(target_os = "android", target_os = "ios", feature = "cli")))]
+mod recording;
 
 #[cfg(all(feature = "flutter", feature = "plugin_framework"))]
 #[cfg(not(any(target_os = "android", target_os = "ios")))]
@@ -38,23 +38,349 @@ fn main() {
     if !common::global_init() {
         return;
     }
-    use clap::App;
+    use clap::{Arg, ArgAction, ArgMatches, Command};
     use hbb_common::log;
-    let args = format!(
-        "-p, --port-forward=[PORT-FORWARD-OPTIONS] 'Format: remote-id:local-port:remote-port[:remote-host]'
-        -c, --connect=[REMOTE_ID] 'test only'
-        -k, --key=[KEY] ''
-       -s, --server=[] 'Start server'",
-    );
-    let matches = App::new("rustdesk")
+    let peer_args = || {
+        [
+            Arg::new("id").required(true).help("Remote id"),
+            Arg::new("password")
+                .long("password")
+                .help("Password of the remote peer, RUSTDESK_PASSWORD or a prompt if not given"),
+            Arg::new("json")
+                .long("json")
+                .action(ArgAction::SetTrue)
+                .help("Print progress as JSON lines on stdout"),
+            Arg::new("relay")
+                .long("relay")
+                .action(ArgAction::SetTrue)
+                .help("Force relay"),
+            Arg::new("timeout")
+                .long("timeout")
+                .value_parser(clap::value_parser!(u64))
+                .help("Timeout in seconds"),
+        ]
+    };
+    let matches = Command::new("rustdesk")
         .version(crate::VERSION)
         .author("Purslane Ltd<info@rustdesk.com>")
         .about("RustDesk command line tool")
-        .args_from_usage(&args)
+        .arg(
+            Arg::new("port-forward")
+                .short('p')
+                .long("port-forward")
+                .help(
+                    "Format: remote-id:local-port:remote-port[:remote-host], remote-port 0 starts a SOCKS5 proxy",
+                ),
+        )
+        .arg(
+            Arg::new("bind")
+                .long("bind")
+                .value_name("ADDR")
+                .help("Listen on ADDR with --port-forward, 127.0.0.1 by default"),
+        )
+        .arg(
+            Arg::new("allow")
+                .long("allow")
+                .value_name("CIDRS")
+                .help("Only accept clients from the comma separated addresses or CIDRs with --port-forward"),
+        )
+        .arg(
+            Arg::new("udp")
+                .long("udp")
+                .action(ArgAction::SetTrue)
+                .help("Forward UDP instead of TCP with --port-forward"),
+        )
+        .arg(
+            Arg::new("reverse-forward")
+                .short('R')
+                .long("reverse-forward")
+                .help(
+                    "Format: remote-id:remote-port:local-port[:local-host[:remote-bind]], the remote side listens on remote-port",
+                ),
+        )
+        .arg(
+            Arg::new("connect")
+                .short('c')
+                .long("connect")
+                .value_name("REMOTE_ID")
+                .help("test only"),
+        )
+        .arg(Arg::new("key").short('k').long("key"))
+        .arg(
+            Arg::new("server")
+                .short('s')
+                .long("server")
+                .action(ArgAction::SetTrue)
+                .help("Start server"),
+        )
+        .subcommand(
+            Command::new("files")
+                .about("Transfer files")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("get")
+                        .about("Download from the remote peer")
+                        .args(peer_args())
+                        .arg(Arg::new("remote").required(true))
+                        .arg(Arg::new("local").required(true)),
+                )
+                .subcommand(
+                    Command::new("put")
+                        .about("Upload to the remote peer")
+                        .args(peer_args())
+                        .arg(Arg::new("local").required(true))
+                        .arg(Arg::new("remote").required(true)),
+                )
+                .subcommand(
+                    Command::new("sync")
+                        .about("Mirror a local and a remote directory")
+                        .args(peer_args())
+                        .arg(Arg::new("local").required(true))
+                        .arg(Arg::new("remote").required(true))
+                        .arg(
+                            Arg::new("direction")
+                                .long("direction")
+                                .value_parser(["upload", "download", "both"])
+                                .default_value("upload")
+                                .help("Which side is the source, the newer file wins with both"),
+                        )
+                        .arg(
+                            Arg::new("include")
+                                .long("include")
+                                .value_name("GLOB")
+                                .action(ArgAction::Append)
+                                .help("Only sync the matching paths"),
+                        )
+                        .arg(
+                            Arg::new("exclude")
+                                .long("exclude")
+                                .value_name("GLOB")
+                                .action(ArgAction::Append)
+                                .help("Leave the matching paths alone"),
+                        )
+                        .arg(
+                            Arg::new("delete")
+                                .long("delete")
+                                .action(ArgAction::SetTrue)
+                                .help("Remove the files missing on the source side"),
+                        )
+                        .arg(
+                            Arg::new("dry-run")
+                                .long("dry-run")
+                                .action(ArgAction::SetTrue)
+                                .help("Only print the plan"),
+                        )
+                        .arg(
+                            Arg::new("checksum")
+                                .long("checksum")
+                                .action(ArgAction::SetTrue)
+                                .help("Compare the sha256 of the files which only differ in time"),
+                        ),
+                )
+                .args([
+                    Arg::new("overwrite")
+                        .long("overwrite")
+                        .global(true)
+                        .action(ArgAction::SetTrue)
+                        .help("Overwrite existing files, they are skipped by default"),
+                    Arg::new("hidden")
+                        .long("hidden")
+                        .global(true)
+                        .action(ArgAction::SetTrue)
+                        .help("Include hidden files"),
+                ]),
+        )
+        .subcommand(
+            Command::new("terminal")
+                .about("Open a remote terminal")
+                .args(peer_args())
+                .arg(
+                    Arg::new("attach")
+                        .long("attach")
+                        .value_name("SERVICE_ID")
+                        .help("Watch the shell of a running terminal session"),
+                )
+                .arg(
+                    Arg::new("shell")
+                        .long("shell")
+                        .value_name("SHELL")
+                        .help("Shell to start, one of those allowed by the peer, e.g. zsh or /bin/zsh"),
+                )
+                .arg(
+                    Arg::new("cwd")
+                        .long("cwd")
+                        .value_name("DIR")
+                        .help("Start directory, one of those allowed by the peer"),
+                ),
+        )
+        .subcommand(
+            Command::new("exec")
+                .about("Run a command on the remote peer")
+                .args(peer_args())
+                .arg(
+                    Arg::new("env")
+                        .long("env")
+                        .value_name("KEY=VALUE")
+                        .action(ArgAction::Append)
+                        .help("Set an environment variable of the command, can be repeated"),
+                )
+                .arg(
+                    Arg::new("cwd")
+                        .long("cwd")
+                        .value_name("DIR")
+                        .help("Working directory of the command, the home directory by default"),
+                )
+                .arg(
+                    Arg::new("command")
+                        .required(true)
+                        .num_args(1..)
+                        .trailing_var_arg(true)
+                        .allow_hyphen_values(true),
+                ),
+        )
+        .subcommand(
+            Command::new("wake")
+                .about("Wake a peer through an online peer on its subnet and wait until it is online")
+                .args(peer_args())
+                .arg(
+                    Arg::new("via")
+                        .long("via")
+                        .value_name("RELAY_ID")
+                        .help("Peer to send the magic packet, any online peer on the same subnet if not given"),
+                ),
+        )
+        .subcommand(
+            Command::new("forwards")
+                .about("Saved port forward profiles")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("up")
+                        .about("Bring up all the profiles and reconnect them until killed"),
+                )
+                .subcommand(
+                    Command::new("status")
+                        .about("Show the status of the running profiles")
+                        .arg(
+                            Arg::new("json")
+                                .long("json")
+                                .action(ArgAction::SetTrue)
+                                .help("Print JSON"),
+                        ),
+                ),
+        )
         .get_matches();
     use hbb_common::{config::LocalConfig, env_logger::*};
     init_from_env(Env::default().filter_or(DEFAULT_FILTER_ENV, "info"));
-    if let Some(p) = matches.value_of("port-forward") {
+    let cli_options = |m: &ArgMatches| cli::CliOptions {
+        password: m
+            .get_one::<String>("password")
+            .cloned()
+            .or_else(|| std::env::var("RUSTDESK_PASSWORD").ok())
+            .unwrap_or_default(),
+        json: m.get_flag("json"),
+        force_relay: m.get_flag("relay"),
+        overwrite: m.try_get_one::<bool>("overwrite").ok().flatten() == Some(&true),
+        include_hidden: m.try_get_one::<bool>("hidden").ok().flatten() == Some(&true),
+        timeout: m
+            .get_one::<u64>("timeout")
+            .map(|t| std::time::Duration::from_secs(*t)),
+    };
+    let id = |m: &ArgMatches| m.get_one::<String>("id").cloned().unwrap_or_default();
+    let arg = |m: &ArgMatches, name: &str| m.get_one::<String>(name).cloned().unwrap_or_default();
+    let mut code = cli::EXIT_OK;
+    let is_status = matches
+        .subcommand_matches("forwards")
+        .and_then(|m| m.subcommand_name())
+        == Some("status");
+    if matches.subcommand().is_some() && !is_status {
+        common::test_rendezvous_server();
+        common::test_nat_type();
+    }
+    match matches.subcommand() {
+        Some(("files", m)) => match m.subcommand() {
+            Some(("get", m)) => {
+                code = cli::files_get(&id(m), &arg(m, "remote"), &arg(m, "local"), &cli_options(m));
+            }
+            Some(("put", m)) => {
+                code = cli::files_put(&id(m), &arg(m, "local"), &arg(m, "remote"), &cli_options(m));
+            }
+            Some(("sync", m)) => {
+                let globs = |name: &str| {
+                    m.get_many::<String>(name)
+                        .map(|v| v.cloned().collect())
+                        .unwrap_or_default()
+                };
+                let options = cli::SyncOptions {
+                    direction: match arg(m, "direction").as_str() {
+                        "download" => cli::SyncDirection::Download,
+                        "both" => cli::SyncDirection::Both,
+                        _ => cli::SyncDirection::Upload,
+                    },
+                    include: globs("include"),
+                    exclude: globs("exclude"),
+                    delete: m.get_flag("delete"),
+                    dry_run: m.get_flag("dry-run"),
+                    checksum: m.get_flag("checksum"),
+                    ..Default::default()
+                };
+                code = cli::files_sync(
+                    &id(m),
+                    &arg(m, "local"),
+                    &arg(m, "remote"),
+                    options,
+                    &cli_options(m),
+                );
+            }
+            _ => {}
+        },
+        Some(("terminal", m)) => {
+            code = cli::terminal(
+                &id(m),
+                m.get_one::<String>("attach").cloned(),
+                arg(m, "shell"),
+                arg(m, "cwd"),
+                &cli_options(m),
+            );
+        }
+        Some(("exec", m)) => {
+            let argv = m
+                .get_many::<String>("command")
+                .map(|v| v.cloned().collect::<Vec<_>>())
+                .unwrap_or_default();
+            let env = m
+                .get_many::<String>("env")
+                .map(|v| {
+                    v.filter_map(|kv| kv.split_once('='))
+                        .map(|(k, v)| (k.to_owned(), v.to_owned()))
+                        .collect()
+                })
+                .unwrap_or_default();
+            code = cli::exec(&id(m), argv, env, arg(m, "cwd"), &cli_options(m));
+        }
+        Some(("wake", m)) => {
+            code = cli::wake(&id(m), m.get_one::<String>("via").cloned(), &cli_options(m));
+        }
+        Some(("forwards", m)) => match m.subcommand() {
+            Some(("up", _)) => {
+                let key = matches
+                    .get_one::<String>("key")
+                    .cloned()
+                    .unwrap_or_default();
+                let token = LocalConfig::get_option("access_token");
+                code = cli::run_forward_profiles(key, token);
+            }
+            Some(("status", m)) => {
+                code = cli::forward_status(m.get_flag("json"));
+            }
+            _ => {}
+        },
+        _ => {}
+    }
+    if matches.subcommand().is_some() {
+        common::global_clean();
+        std::process::exit(code);
+    }
+    if let Some(p) = matches.get_one::<String>("port-forward") {
         let options: Vec<String> = p.split(":").map(|x| x.to_owned()).collect();
         if options.len() < 3 {
             log::error!("Wrong port-forward options");
@@ -74,29 +400,75 @@ fn main() {
             log::error!("Wrong remote-port");
             return;
         }
+        if matches.get_flag("udp") && remote_port <= 0 {
+            log::error!("Wrong remote-port, dynamic forwarding is TCP only");
+            return;
+        }
         let mut remote_host = "localhost".to_owned();
         if options.len() > 3 {
             remote_host = options[3].clone();
         }
         common::test_rendezvous_server();
         common::test_nat_type();
-        let key = matches.value_of("key").unwrap_or("").to_owned();
+        let key = matches
+            .get_one::<String>("key")
+            .cloned()
+            .unwrap_or_default();
         let token = LocalConfig::get_option("access_token");
         cli::start_one_port_forward(
             options[0].clone(),
             port,
             remote_host,
             remote_port,
+            matches.get_flag("udp"),
+            arg(&matches, "bind"),
+            arg(&matches, "allow"),
+            key,
+            token,
+        );
+    } else if let Some(p) = matches.get_one::<String>("reverse-forward") {
+        let options: Vec<String> = p.split(":").map(|x| x.to_owned()).collect();
+        if options.len() < 3 {
+            log::error!("Wrong reverse-forward options");
+            return;
+        }
+        let Ok(remote_port) = options[1].parse::<i32>() else {
+            log::error!("Wrong remote-port");
+            return;
+        };
+        let Ok(local_port) = options[2].parse::<i32>() else {
+            log::error!("Wrong local-port");
+            return;
+        };
+        let local_host = options
+            .get(3)
+            .cloned()
+            .unwrap_or_else(|| "localhost".to_owned());
+        let bind = options.get(4).cloned().unwrap_or_default();
+        let key = matches
+            .get_one::<String>("key")
+            .cloned()
+            .unwrap_or_default();
+        let token = LocalConfig::get_option("access_token");
+        cli::start_one_reverse_forward(
+            options[0].clone(),
+            bind,
+            remote_port,
+            local_host,
+            local_port,
             key,
             token,
         );
-    } else if let Some(p) = matches.value_of("connect") {
+    } else if let Some(p) = matches.get_one::<String>("connect") {
         common::test_rendezvous_server();
         common::test_nat_type();
-        let key = matches.value_of("key").unwrap_or("").to_owned();
+        let key = matches
+            .get_one::<String>("key")
+            .cloned()
+            .unwrap_or_default();
         let token = LocalConfig::get_option("access_token");
         cli::connect_test(p, key, token);
-    } else if let Some(p) = matches.value_of("server") {
+    } else if matches.get_flag("server") {
         log::info!("id={}", hbb_common::config::Config::get_id());
         crate::start_server(true, false);
     }
@@ -554,6 +554,14 @@ fn try_start_server_(desktop: Option<&Desktop>) -> ResultType<Option<Child>> {
                 "TERM",
                 get_cur_term(&desktop.uid).unwrap_or_else(|| suggest_best_term()),
             ));
+            // The printer service of the server takes the jobs out of the spool.
+            if let Err(e) = remote_printer::grant_spool(&desktop.username) {
+                log::warn!(
+                    "Failed to grant the printer spool to {}: {}",
+                    desktop.username,
+                    e
+                );
+            }
             run_as_user(
                 vec!["--server"],
                 Some((desktop.uid.clone(), desktop.username.clone())),
@@ -3447,6 +3447,10 @@ pub fn send_raw_data_to_printer(printer_name: Option<String>, data: Vec<u8>) ->
         return Err(anyhow!("Failed to get printer name"));
     }
 
+    if data.starts_with(b"%PDF-") {
+        return print_pdf(&printer_name, data);
+    }
+
     log::info!("Sending data to printer: {}", &printer_name);
     let printer_name = wide_string(&printer_name);
     unsafe {
@@ -3465,6 +3469,54 @@ pub fn send_raw_data_to_printer(printer_name: Option<String>, data: Vec<u8>) ->
     Ok(())
 }
 
+/// Print a PDF job, sent by Linux peers, with the application registered for PDF files.
+fn print_pdf(printer_name: &str, data: Vec<u8>) -> ResultType<()> {
+    const PREFIX: &str = "rustdesk_print_";
+    let dir = std::env::temp_dir();
+    // The printing application reads the file asynchronously, so the files are removed later.
+    if let Ok(entries) = std::fs::read_dir(&dir) {
+        for entry in entries.flatten() {
+            let old = entry.file_name().to_string_lossy().starts_with(PREFIX)
+                && entry
+                    .metadata()
+                    .and_then(|m| m.modified())
+                    .map(|t| t.elapsed().unwrap_or_default() > std::time::Duration::from_secs(3600))
+                    .unwrap_or(false);
+            if old {
+                std::fs::remove_file(entry.path()).ok();
+            }
+        }
+    }
+    // Created exclusively with a random name, kept for the printing application.
+    let mut file = tempfile::Builder::new()
+        .prefix(PREFIX)
+        .suffix(".pdf")
+        .tempfile_in(&dir)?;
+    file.write_all(&data)?;
+    let (_, path) = file.keep()?;
+    log::info!("Printing PDF {:?} to printer: {}", path, printer_name);
+    let wop = wide_string("printto");
+    let wfile = wide_string(&path.to_string_lossy());
+    let warg = wide_string(&format!("\"{}\"", printer_name));
+    let ret = unsafe {
+        ShellExecuteW(
+            NULL as _,
+            wop.as_ptr() as _,
+            wfile.as_ptr() as _,
+            warg.as_ptr() as _,
+            NULL as _,
+            SW_HIDE,
+        )
+    };
+    if ret as i32 <= 32 {
+        bail!(
+            "Failed to print the PDF job, no application can print PDF files, error {}",
+            ret as i32
+        );
+    }
+    Ok(())
+}
+
 fn get_pids<S: AsRef<str>>(name: S) -> ResultType<Vec<u32>> {
     let name = name.as_ref().to_lowercase();
     let mut pids = Vec::new();
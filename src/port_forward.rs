@@ -1,19 +1,53 @@
-use std::sync::{Arc, RwLock};
+use cidr_utils::cidr::IpCidr;
+use std::{
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering},
+        Arc, Mutex, RwLock,
+    },
+    time::Duration,
+};
 
 use crate::client::*;
 use hbb_common::{
     allow_err, bail,
-    config::READ_TIMEOUT,
+    config::{LocalConfig, READ_TIMEOUT},
     futures::{SinkExt, StreamExt},
     log,
     message_proto::*,
     protobuf::Message as _,
     rendezvous_proto::ConnType,
     tcp, timeout,
-    tokio::{self, net::TcpStream, sync::mpsc},
+    tokio::{
+        self,
+        net::{TcpStream, UdpSocket},
+        sync::mpsc,
+        time::Instant,
+    },
     tokio_util::codec::{BytesCodec, Framed},
     ResultType, Stream,
 };
+use mux::{Frame, Mux, MUX_DYNAMIC_HOST, MUX_HOST_PREFIX, MUX_NOT_SUPPORTED, MUX_REVERSE_PREFIX};
+
+pub mod mux;
+pub mod profile;
+pub mod socks5;
+pub mod udp;
+
+/// Allow the controlling side to open forwarded connections to any host, see [`is_dynamic`].
+pub const OPTION_ALLOW_DYNAMIC_TUNNEL: &str = "allow-dynamic-tunnel";
+/// Allow the controlling side to make this device listen on a port, see [`listen_reverse`].
+pub const OPTION_ALLOW_REVERSE_TUNNEL: &str = "allow-reverse-tunnel";
+/// Allow the port of reverse forwarding to be bound to other addresses than loopback.
+pub const OPTION_ALLOW_REVERSE_TUNNEL_ANY_ADDRESS: &str = "allow-reverse-tunnel-any-address";
+
+/// A remote port of 0 asks for dynamic forwarding: the local listener speaks SOCKS5
+/// and every connection is tunnelled to the host requested by the SOCKS5 client, like `ssh -D`.
+#[inline]
+pub fn is_dynamic(remote_port: i32) -> bool {
+    remote_port == 0
+}
 
 fn run_rdp(port: u16) {
     std::process::Command::new("cmdkey")
@@ -42,6 +76,132 @@ fn run_rdp(port: u16) {
         .ok();
 }
 
+/// Local options of the listeners of forwards added ad hoc, see [`ListenOptions::from_local_config`].
+pub const OPTION_PORT_FORWARD_BIND: &str = "port-forward-bind";
+pub const OPTION_PORT_FORWARD_ALLOW: &str = "port-forward-allow";
+
+/// Settings of a local listener, the defaults match a forward added ad hoc.
+#[derive(Clone, Default)]
+pub struct ListenOptions {
+    /// The address to listen on, loopback if empty.
+    pub bind: String,
+    /// Clients allowed to connect, anyone who can reach the listener if empty.
+    pub allow: Vec<IpCidr>,
+    /// Keep the session up without any forwarded connection and set it up again
+    /// with backoff when it drops, instead of connecting on demand.
+    pub keep_alive: bool,
+    pub stats: Arc<Stats>,
+}
+
+impl ListenOptions {
+    pub fn from_local_config() -> ResultType<Self> {
+        Ok(Self {
+            bind: LocalConfig::get_option(OPTION_PORT_FORWARD_BIND),
+            allow: parse_allow_list(&LocalConfig::get_option(OPTION_PORT_FORWARD_ALLOW))?,
+            ..Default::default()
+        })
+    }
+
+    fn listen_addr(&self, port: i32) -> String {
+        let bind = if self.bind.is_empty() {
+            "127.0.0.1"
+        } else {
+            &self.bind
+        };
+        if bind.contains(':') && !bind.starts_with('[') {
+            format!("[{}]:{}", bind, port)
+        } else {
+            format!("{}:{}", bind, port)
+        }
+    }
+
+    fn is_allowed(&self, addr: &SocketAddr) -> bool {
+        if self.allow.is_empty() {
+            return true;
+        }
+        // Clients of a dual stack listener show up as mapped addresses.
+        let ip = match addr.ip() {
+            IpAddr::V6(ip) => ip.to_ipv4_mapped().map_or(IpAddr::V6(ip), IpAddr::V4),
+            ip => ip,
+        };
+        self.allow.iter().any(|cidr| cidr.contains(ip))
+    }
+}
+
+/// The address to listen on for reverse forwarding, the bind address requested by the peer
+/// must be loopback unless `allow_any`. The reason is returned if it is refused.
+pub fn reverse_listen_addr(bind: &str, port: i32, allow_any: bool) -> Result<String, String> {
+    if port <= 0 || port > u16::MAX as i32 {
+        return Err(format!("Invalid port {}", port));
+    }
+    let bind = if bind.is_empty() { "127.0.0.1" } else { bind };
+    let ip = bind.trim_start_matches('[').trim_end_matches(']');
+    let is_loopback = ip.eq_ignore_ascii_case("localhost")
+        || IpAddr::from_str(ip).map_or(false, |ip| ip.is_loopback());
+    if !is_loopback && !allow_any {
+        return Err(format!("No permission to listen on {}", bind));
+    }
+    Ok(if ip.contains(':') {
+        format!("[{}]:{}", ip, port)
+    } else {
+        format!("{}:{}", ip, port)
+    })
+}
+
+/// Parse a comma separated list of addresses or CIDRs, eg. `192.168.1.0/24,10.0.0.8`.
+pub fn parse_allow_list(s: &str) -> ResultType<Vec<IpCidr>> {
+    let mut allow = Vec::new();
+    for x in s.split(',').map(|x| x.trim()).filter(|x| !x.is_empty()) {
+        match IpCidr::from_str(x) {
+            Ok(cidr) => allow.push(cidr),
+            Err(_) => bail!("Invalid address or CIDR {}", x),
+        }
+    }
+    Ok(allow)
+}
+
+/// Counters of a forward, shared with whoever reports its status.
+#[derive(Debug, Default)]
+pub struct Stats {
+    pub connected: AtomicBool,
+    /// Payload bytes sent to the peer.
+    pub sent: AtomicU64,
+    /// Payload bytes received from the peer.
+    pub received: AtomicU64,
+    /// Failed attempts to set the session up since the last success.
+    pub retries: AtomicU32,
+    pub last_error: Mutex<String>,
+}
+
+impl Stats {
+    fn on_connected(&self) {
+        self.connected.store(true, Ordering::Relaxed);
+        self.retries.store(0, Ordering::Relaxed);
+    }
+
+    pub fn on_disconnected(&self, err: Option<String>) {
+        self.connected.store(false, Ordering::Relaxed);
+        if let Some(err) = err {
+            self.retries.fetch_add(1, Ordering::Relaxed);
+            *self.last_error.lock().unwrap() = err;
+        }
+    }
+
+    fn on_frame(&self, frame: &Frame, sent: bool) {
+        if let Frame::Data { data, .. } = frame {
+            self.add(data.len(), sent);
+        }
+    }
+
+    fn add(&self, n: usize, sent: bool) {
+        let counter = if sent { &self.sent } else { &self.received };
+        counter.fetch_add(n as _, Ordering::Relaxed);
+    }
+}
+
+pub const MIN_BACKOFF: Duration = Duration::from_secs(1);
+pub const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
 pub async fn listen(
     id: String,
     password: String,
@@ -53,39 +213,203 @@ pub async fn listen(
     lc: Arc<RwLock<LoginConfigHandler>>,
     remote_host: String,
     remote_port: i32,
+    opts: ListenOptions,
 ) -> ResultType<()> {
-    let listener = tcp::new_listener(format!("0.0.0.0:{}", port), true).await?;
+    let listener = tcp::new_listener(opts.listen_addr(port), true).await?;
     let addr = listener.local_addr()?;
     log::info!("listening on port {:?}", addr);
     let is_rdp = port == 0;
     if is_rdp {
         run_rdp(addr.port());
     }
+    let stats = opts.stats.clone();
     let mut ui_receiver = ui_receiver;
+    let dynamic = !is_rdp && is_dynamic(remote_port);
+    // All forwarded connections share one session if the peer supports it,
+    // the version of the peer is known after the first session.
+    let mut use_mux = !is_rdp && mux::is_support_mux(lc.read().unwrap().version);
+    let mux_target = if dynamic {
+        (MUX_DYNAMIC_HOST.to_owned(), 0)
+    } else {
+        (format!("{}{}", MUX_HOST_PREFIX, remote_host), remote_port)
+    };
+    let mut mux = Mux::new(true);
+    let mut mux_stream: Option<Stream> = None;
+    let (socks_tx, mut socks_rx) = mpsc::unbounded_channel::<(TcpStream, String)>();
+    let mut backoff = MIN_BACKOFF;
+    let mut retry_at = if opts.keep_alive && use_mux {
+        Some(Instant::now())
+    } else {
+        None
+    };
     loop {
         tokio::select! {
             Ok((forward, addr)) = listener.accept() => {
+                if !opts.is_allowed(&addr) {
+                    log::warn!("connection from {:?} is not allowed", addr);
+                    continue;
+                }
                 log::info!("new connection from {:?}", addr);
+                if dynamic {
+                    let socks_tx = socks_tx.clone();
+                    tokio::spawn(async move {
+                        let mut forward = forward;
+                        match timeout(READ_TIMEOUT, socks5::accept(&mut forward)).await {
+                            Ok(Ok(target)) => {
+                                socks_tx.send((forward, target)).ok();
+                            }
+                            Ok(Err(err)) => log::info!("socks5 handshake with {:?} failed: {}", addr, err),
+                            Err(_) => log::info!("socks5 handshake with {:?} timeout", addr),
+                        }
+                    });
+                    continue;
+                }
+                if use_mux && mux_stream.is_none() {
+                    lc.write().unwrap().port_forward = mux_target.clone();
+                    match connect_and_login(&id, &password, &mut ui_receiver, interface.clone(), None, key, token, is_rdp).await {
+                        Ok(Some(stream)) => {
+                            log::info!("multiplexed port forwarding session started");
+                            stats.on_connected();
+                            mux_stream = Some(stream);
+                        }
+                        Err(err) if err.to_string() == MUX_NOT_SUPPORTED => {
+                            log::info!("{}, fall back to one session per connection", err);
+                            use_mux = false;
+                            retry_at = None;
+                        }
+                        Err(err) => {
+                            stats.on_disconnected(Some(err.to_string()));
+                            interface.on_establish_connection_error(err.to_string());
+                            continue;
+                        }
+                        Ok(None) => continue,
+                    }
+                }
+                if let Some(stream) = mux_stream.as_mut() {
+                    let frame = mux.open(forward, "".to_owned());
+                    if let Err(err) = stream.send_bytes(frame.encode()).await {
+                        log::error!("Failed to open multiplexed stream: {}", err);
+                        mux_stream = None;
+                        mux = Mux::new(true);
+                        stats.on_disconnected(Some(err.to_string()));
+                    }
+                    continue;
+                }
                 lc.write().unwrap().port_forward = (remote_host.clone(), remote_port);
                 let id = id.clone();
                 let password = password.clone();
                 let mut forward = Framed::new(forward, BytesCodec::new());
-                match connect_and_login(&id, &password, &mut ui_receiver, interface.clone(), &mut forward, key, token, is_rdp).await {
+                match connect_and_login(&id, &password, &mut ui_receiver, interface.clone(), Some(&mut forward), key, token, is_rdp).await {
                     Ok(Some(stream)) => {
+                        if !is_rdp && !use_mux && mux::is_support_mux(lc.read().unwrap().version) {
+                            log::info!("the peer supports multiplexing, share one session from now on");
+                            use_mux = true;
+                            if opts.keep_alive {
+                                retry_at = Some(Instant::now());
+                            }
+                        }
                         let interface = interface.clone();
+                        let stats = stats.clone();
                         tokio::spawn(async move {
-                            if let Err(err) = run_forward(forward, stream).await {
+                            if let Err(err) = run_forward(forward, stream, stats).await {
                                 interface.msgbox("error", "Error", &err.to_string(), "");
                             }
                             log::info!("connection from {:?} closed", addr);
                        });
                     }
                     Err(err) => {
+                        stats.on_disconnected(Some(err.to_string()));
                         interface.on_establish_connection_error(err.to_string());
                     }
                     _ => {}
                 }
             }
+            Some((mut forward, target)) = socks_rx.recv() => {
+                if mux_stream.is_none() {
+                    lc.write().unwrap().port_forward = mux_target.clone();
+                    match connect_and_login(&id, &password, &mut ui_receiver, interface.clone(), None, key, token, is_rdp).await {
+                        Ok(Some(stream)) => {
+                            log::info!("dynamic port forwarding session started");
+                            stats.on_connected();
+                            mux_stream = Some(stream);
+                        }
+                        res => {
+                            allow_err!(socks5::reply(&mut forward, socks5::REP_GENERAL_FAILURE).await);
+                            if let Err(err) = res {
+                                stats.on_disconnected(Some(err.to_string()));
+                                interface.on_establish_connection_error(err.to_string());
+                            }
+                            continue;
+                        }
+                    }
+                }
+                if let Some(stream) = mux_stream.as_mut() {
+                    log::info!("dynamic port forwarding to {}", target);
+                    let frame = mux.open_socks5(forward, target);
+                    if let Err(err) = stream.send_bytes(frame.encode()).await {
+                        log::error!("Failed to open multiplexed stream: {}", err);
+                        mux_stream = None;
+                        mux = Mux::new(true);
+                        stats.on_disconnected(Some(err.to_string()));
+                    }
+                }
+            }
+            _ = async { match retry_at {
+                Some(at) => tokio::time::sleep_until(at).await,
+                None => std::future::pending().await,
+            } } => {
+                retry_at = None;
+                if mux_stream.is_some() || !use_mux {
+                    continue;
+                }
+                lc.write().unwrap().port_forward = mux_target.clone();
+                match connect_and_login(&id, &password, &mut ui_receiver, interface.clone(), None, key, token, is_rdp).await {
+                    Ok(Some(stream)) => {
+                        log::info!("multiplexed port forwarding session started");
+                        stats.on_connected();
+                        backoff = MIN_BACKOFF;
+                        mux_stream = Some(stream);
+                    }
+                    Err(err) if err.to_string() == MUX_NOT_SUPPORTED => {
+                        log::info!("{}, fall back to one session per connection", err);
+                        use_mux = false;
+                    }
+                    res => {
+                        let err = res.err().map(|e| e.to_string()).unwrap_or_else(|| "Login failed".to_owned());
+                        log::info!("port forwarding session failed, retry in {:?}: {}", backoff, err);
+                        stats.on_disconnected(Some(err));
+                        retry_at = Some(Instant::now() + backoff);
+                        backoff = (backoff * 2).min(MAX_BACKOFF);
+                    }
+                }
+            }
+            frame = mux.next_frame() => {
+                if let Some(stream) = mux_stream.as_mut() {
+                    stats.on_frame(&frame, true);
+                    allow_err!(stream.send_bytes(frame.encode()).await);
+                }
+            }
+            res = async { match mux_stream.as_mut() {
+                Some(stream) => stream.next().await,
+                None => std::future::pending().await,
+            } } => {
+                match res.map(|r| r.map(|bytes| Frame::decode(&bytes))) {
+                    Some(Ok(Ok(frame))) => {
+                        stats.on_frame(&frame, false);
+                        mux.handle_frame(frame, |_| Err("Unexpected stream from the peer".to_owned()));
+                    }
+                    res => {
+                        log::info!("multiplexed port forwarding session closed: {:?}", res.map(|r| r.err()));
+                        // Drop all the streams, a new session is created for the next connection.
+                        mux_stream = None;
+                        mux = Mux::new(true);
+                        stats.on_disconnected(None);
+                        if opts.keep_alive {
+                            retry_at = Some(Instant::now() + backoff);
+                        }
+                    }
+                }
+            }
             d = ui_receiver.recv() => {
                 match d {
                     Some(Data::Close) => {
@@ -103,12 +427,244 @@ pub async fn listen(
     Ok(())
 }
 
+/// Reverse forwarding, like `ssh -R`: the peer listens on `bind:remote_port` as long as the session lasts,
+/// and every connection accepted there is tunnelled back to `local_host:local_port`.
+///
+/// The session is set up again if it breaks, until [`Data::Close`] is received.
+pub async fn listen_reverse(
+    id: String,
+    password: String,
+    interface: impl Interface,
+    ui_receiver: mpsc::UnboundedReceiver<Data>,
+    key: &str,
+    token: &str,
+    lc: Arc<RwLock<LoginConfigHandler>>,
+    bind: String,
+    remote_port: i32,
+    local_host: String,
+    local_port: i32,
+) -> ResultType<()> {
+    let mut ui_receiver = ui_receiver;
+    let local_addr = format!("{}:{}", local_host, local_port);
+    lc.write().unwrap().port_forward = (format!("{}{}", MUX_REVERSE_PREFIX, bind), remote_port);
+    loop {
+        let mut stream = match connect_and_login(
+            &id,
+            &password,
+            &mut ui_receiver,
+            interface.clone(),
+            None,
+            key,
+            token,
+            false,
+        )
+        .await
+        {
+            Ok(Some(stream)) => stream,
+            Ok(None) => return Ok(()),
+            Err(err) if err.to_string() == MUX_NOT_SUPPORTED => {
+                bail!("Reverse port forwarding is not supported by the remote side");
+            }
+            Err(err) => {
+                interface.on_establish_connection_error(err.to_string());
+                return Ok(());
+            }
+        };
+        log::info!(
+            "reverse port forwarding from remote port {} to {} started",
+            remote_port,
+            local_addr
+        );
+        let mut mux = Mux::new(true);
+        loop {
+            tokio::select! {
+                frame = mux.next_frame() => {
+                    allow_err!(stream.send_bytes(frame.encode()).await);
+                }
+                res = stream.next() => {
+                    match res.map(|r| r.map(|bytes| Frame::decode(&bytes))) {
+                        Some(Ok(Ok(frame))) => {
+                            mux.handle_frame(frame, |target| {
+                                if target.is_empty() {
+                                    Ok(local_addr.clone())
+                                } else {
+                                    Err("Unexpected target from the peer".to_owned())
+                                }
+                            });
+                        }
+                        res => {
+                            log::info!("reverse port forwarding session closed: {:?}", res.map(|r| r.err()));
+                            break;
+                        }
+                    }
+                }
+                d = ui_receiver.recv() => {
+                    if matches!(d, Some(Data::Close) | None) {
+                        return Ok(());
+                    }
+                }
+            }
+        }
+        // The remote listener is gone with the session.
+        tokio::select! {
+            _ = hbb_common::sleep(3.) => {}
+            d = ui_receiver.recv() => {
+                if matches!(d, Some(Data::Close) | None) {
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+/// Forward the datagrams received on the local UDP `port` to `remote_host:remote_port` through the peer.
+pub async fn listen_udp(
+    id: String,
+    password: String,
+    port: i32,
+    interface: impl Interface,
+    ui_receiver: mpsc::UnboundedReceiver<Data>,
+    key: &str,
+    token: &str,
+    lc: Arc<RwLock<LoginConfigHandler>>,
+    remote_host: String,
+    remote_port: i32,
+    opts: ListenOptions,
+) -> ResultType<()> {
+    let socket = UdpSocket::bind(opts.listen_addr(port)).await?;
+    let stats = opts.stats.clone();
+    log::info!("listening on udp port {:?}", socket.local_addr()?);
+    let mut ui_receiver = ui_receiver;
+    lc.write().unwrap().port_forward = (
+        format!("{}{}", udp::UDP_HOST_PREFIX, remote_host),
+        remote_port,
+    );
+    let mut stream: Option<Stream> = None;
+    let mut assocs = udp::Associations::default();
+    let mut buf = vec![0u8; udp::MAX_DATAGRAM_LEN];
+    let mut timer = crate::rustdesk_interval(tokio::time::interval(udp::IDLE_TIMEOUT / 2));
+    let mut backoff = MIN_BACKOFF;
+    let mut retry_at = if opts.keep_alive {
+        Some(Instant::now())
+    } else {
+        None
+    };
+    loop {
+        tokio::select! {
+            res = socket.recv_from(&mut buf) => {
+                let (n, from) = match res {
+                    Ok(res) => res,
+                    Err(err) => {
+                        log::debug!("failed to receive udp datagram: {}", err);
+                        continue;
+                    }
+                };
+                if !opts.is_allowed(&from) {
+                    log::debug!("udp datagram from {:?} is not allowed", from);
+                    continue;
+                }
+                if stream.is_none() {
+                    match connect_and_login(&id, &password, &mut ui_receiver, interface.clone(), None, key, token, false).await {
+                        Ok(Some(s)) => {
+                            log::info!("udp forwarding session started");
+                            stats.on_connected();
+                            backoff = MIN_BACKOFF;
+                            stream = Some(s);
+                        }
+                        Err(err) if err.to_string() == MUX_NOT_SUPPORTED => {
+                            bail!(udp::UDP_NOT_SUPPORTED);
+                        }
+                        Err(err) => {
+                            stats.on_disconnected(Some(err.to_string()));
+                            interface.on_establish_connection_error(err.to_string());
+                            continue;
+                        }
+                        Ok(None) => continue,
+                    }
+                }
+                if let Some(s) = stream.as_mut() {
+                    let id = assocs.id_of(from);
+                    stats.add(n, true);
+                    if let Err(err) = s.send_bytes(udp::encode(id, &buf[..n])).await {
+                        log::error!("Failed to forward udp datagram: {}", err);
+                        stream = None;
+                        assocs.clear();
+                        stats.on_disconnected(Some(err.to_string()));
+                        if opts.keep_alive {
+                            retry_at = Some(Instant::now() + backoff);
+                        }
+                    }
+                }
+            }
+            _ = async { match retry_at {
+                Some(at) => tokio::time::sleep_until(at).await,
+                None => std::future::pending().await,
+            } } => {
+                retry_at = None;
+                if stream.is_some() {
+                    continue;
+                }
+                match connect_and_login(&id, &password, &mut ui_receiver, interface.clone(), None, key, token, false).await {
+                    Ok(Some(s)) => {
+                        log::info!("udp forwarding session started");
+                        stats.on_connected();
+                        backoff = MIN_BACKOFF;
+                        stream = Some(s);
+                    }
+                    Err(err) if err.to_string() == MUX_NOT_SUPPORTED => {
+                        bail!(udp::UDP_NOT_SUPPORTED);
+                    }
+                    res => {
+                        let err = res.err().map(|e| e.to_string()).unwrap_or_else(|| "Login failed".to_owned());
+                        log::info!("udp forwarding session failed, retry in {:?}: {}", backoff, err);
+                        stats.on_disconnected(Some(err));
+                        retry_at = Some(Instant::now() + backoff);
+                        backoff = (backoff * 2).min(MAX_BACKOFF);
+                    }
+                }
+            }
+            res = async { match stream.as_mut() {
+                Some(stream) => stream.next().await,
+                None => std::future::pending().await,
+            } } => {
+                match res.map(|r| r.map(|bytes| udp::decode(&bytes))) {
+                    Some(Ok(Ok((id, payload)))) => {
+                        stats.add(payload.len(), false);
+                        if let Some(addr) = assocs.addr_of(id) {
+                            allow_err!(socket.send_to(&payload, addr).await);
+                        }
+                    }
+                    res => {
+                        log::info!("udp forwarding session closed: {:?}", res.map(|r| r.err()));
+                        // A new session is created for the next datagram.
+                        stream = None;
+                        assocs.clear();
+                        stats.on_disconnected(None);
+                        if opts.keep_alive {
+                            retry_at = Some(Instant::now() + backoff);
+                        }
+                    }
+                }
+            }
+            _ = timer.tick() => {
+                assocs.expire();
+            }
+            d = ui_receiver.recv() => {
+                if matches!(d, Some(Data::Close) | None) {
+                    break;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
 async fn connect_and_login(
     id: &str,
     password: &str,
     ui_receiver: &mut mpsc::UnboundedReceiver<Data>,
     interface: impl Interface,
-    forward: &mut Framed<TcpStream, BytesCodec>,
+    mut forward: Option<&mut Framed<TcpStream, BytesCodec>>,
     key: &str,
     token: &str,
     is_rdp: bool,
@@ -118,6 +674,8 @@ async fn connect_and_login(
     } else {
         ConnType::PORT_FORWARD
     };
+    let host = interface.get_lch().read().unwrap().port_forward.0.clone();
+    let is_mux = mux::is_mux_host(&host) || udp::is_udp_host(&host);
     let ((mut stream, direct, _pk, _kcp, _stream_type), (feedback, rendezvous_server)) =
         Client::start(id, key, token, conn_type, interface.clone()).await?;
     interface.update_direct(Some(direct));
@@ -149,7 +707,11 @@ async fn connect_and_login(
                                 }
                             }
                             Some(login_response::Union::PeerInfo(pi)) => {
+                                let supported = mux::is_support_mux(hbb_common::get_version_number(&pi.version));
                                 interface.handle_peer_info(pi);
+                                if is_mux && !supported {
+                                    bail!(MUX_NOT_SUPPORTED);
+                                }
                                 break;
                             }
                             _ => {}
@@ -178,7 +740,10 @@ async fn connect_and_login(
                     _ => {}
                 }
             },
-            res = forward.next() => {
+            res = async { match forward.as_mut() {
+                Some(forward) => forward.next().await,
+                None => std::future::pending().await,
+            } } => {
                 if let Some(Ok(bytes)) = res {
                     buffer.extend(bytes);
                 } else {
@@ -194,7 +759,11 @@ async fn connect_and_login(
     Ok(Some(stream))
 }
 
-async fn run_forward(forward: Framed<TcpStream, BytesCodec>, stream: Stream) -> ResultType<()> {
+async fn run_forward(
+    forward: Framed<TcpStream, BytesCodec>,
+    stream: Stream,
+    stats: Arc<Stats>,
+) -> ResultType<()> {
     log::info!("new port forwarding connection started");
     let mut forward = forward;
     let mut stream = stream;
@@ -202,6 +771,7 @@ async fn run_forward(forward: Framed<TcpStream, BytesCodec>, stream: Stream) ->
         tokio::select! {
             res = forward.next() => {
                 if let Some(Ok(bytes)) = res {
+                    stats.add(bytes.len(), true);
                     allow_err!(stream.send_bytes(bytes.into()).await);
                 } else {
                     break;
@@ -209,6 +779,7 @@ async fn run_forward(forward: Framed<TcpStream, BytesCodec>, stream: Stream) ->
             },
             res = stream.next() => {
                 if let Some(Ok(bytes)) = res {
+                    stats.add(bytes.len(), false);
                     allow_err!(forward.send(bytes).await);
                 } else {
                     break;
@@ -218,3 +789,43 @@ async fn run_forward(forward: Framed<TcpStream, BytesCodec>, stream: Stream) ->
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_allow_list() {
+        let mut opts = ListenOptions::default();
+        assert_eq!(opts.listen_addr(80), "127.0.0.1:80");
+        assert!(opts.is_allowed(&"8.8.8.8:1".parse().unwrap()));
+        opts.bind = "::".to_owned();
+        assert_eq!(opts.listen_addr(80), "[::]:80");
+        opts.allow = parse_allow_list("192.168.1.0/24, 10.0.0.8").unwrap();
+        assert!(opts.is_allowed(&"192.168.1.20:1".parse().unwrap()));
+        assert!(opts.is_allowed(&"[::ffff:10.0.0.8]:1".parse().unwrap()));
+        assert!(!opts.is_allowed(&"10.0.0.9:1".parse().unwrap()));
+        assert!(parse_allow_list("").unwrap().is_empty());
+        assert!(parse_allow_list("10.0.0.300").is_err());
+    }
+
+    #[test]
+    fn test_reverse_listen_addr() {
+        assert_eq!(reverse_listen_addr("", 80, false).unwrap(), "127.0.0.1:80");
+        assert_eq!(
+            reverse_listen_addr("localhost", 80, false).unwrap(),
+            "localhost:80"
+        );
+        assert_eq!(reverse_listen_addr("[::1]", 80, false).unwrap(), "[::1]:80");
+        assert!(reverse_listen_addr("0.0.0.0", 80, false).is_err());
+        assert!(reverse_listen_addr("::", 80, false).is_err());
+        assert!(reverse_listen_addr("192.168.1.2", 80, false).is_err());
+        assert_eq!(
+            reverse_listen_addr("0.0.0.0", 80, true).unwrap(),
+            "0.0.0.0:80"
+        );
+        assert_eq!(reverse_listen_addr("::", 80, true).unwrap(), "[::]:80");
+        assert!(reverse_listen_addr("", 0, true).is_err());
+        assert!(reverse_listen_addr("", 65536, true).is_err());
+    }
+}
@@ -0,0 +1,467 @@
+//! Multiplexing of many forwarded connections over one logged-in session.
+//!
+//! After login the session stream is switched to raw mode, every raw message is one frame:
+//! `[type: u8][stream id: u32 BE][payload]`.
+//! Stream ids allocated by the controlling side are odd, the ones allocated by the controlled side are even.
+//! Each side may only send `Data` up to the credit granted by the peer with `Window` frames.
+//! `Fin` ends one direction of a stream like a TCP half-close, the stream is gone after `Close`
+//! or once `Fin` went both ways.
+
+use bytes::{BufMut, Bytes, BytesMut};
+use hbb_common::{
+    bail, log,
+    tokio::{
+        self,
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpStream,
+        sync::mpsc,
+    },
+    ResultType,
+};
+use std::collections::HashMap;
+
+/// Prefix of `PortForward.host` in the login request to ask for a multiplexed session,
+/// the forwarded host follows it, eg. `@mux/localhost`.
+pub const MUX_HOST_PREFIX: &str = "@mux/";
+/// `PortForward.host` in the login request to ask for a multiplexed session
+/// whose streams may connect to any target, see [`super::socks5`].
+pub const MUX_DYNAMIC_HOST: &str = "@mux-dynamic";
+/// Prefix of `PortForward.host` in the login request to ask the peer to listen on `PortForward.port`
+/// and open a stream towards the controlling side for every accepted connection.
+/// The bind address follows it and defaults to loopback, eg. `@mux-reverse/0.0.0.0`,
+/// other addresses than loopback are refused unless allowed by the controlled side.
+pub const MUX_REVERSE_PREFIX: &str = "@mux-reverse/";
+#[inline]
+pub fn is_mux_host(host: &str) -> bool {
+    host.starts_with(MUX_HOST_PREFIX)
+        || host.starts_with(MUX_REVERSE_PREFIX)
+        || host == MUX_DYNAMIC_HOST
+}
+
+/// The error of a session whose peer is too old to multiplex.
+pub const MUX_NOT_SUPPORTED: &str = "Port forward multiplexing is not supported by the remote side";
+/// Peers before it take the multiplexed host as a plain host name.
+const MIN_VER_MUX: &str = "1.4.6";
+
+#[inline]
+pub fn is_support_mux(version: i64) -> bool {
+    version >= hbb_common::get_version_number(MIN_VER_MUX)
+}
+
+/// Bytes a stream may send before it has to wait for the peer to grant more.
+pub const INITIAL_WINDOW: u32 = 256 * 1024;
+const MAX_DATA_LEN: usize = 32 * 1024;
+/// Streams of one session, `Open` requests of the peer beyond it are refused.
+const MAX_STREAMS: usize = 1024;
+
+const FRAME_OPEN: u8 = 1;
+const FRAME_OPEN_OK: u8 = 2;
+const FRAME_OPEN_ERR: u8 = 3;
+const FRAME_DATA: u8 = 4;
+const FRAME_CLOSE: u8 = 5;
+const FRAME_WINDOW: u8 = 6;
+const FRAME_FIN: u8 = 7;
+const HEADER_LEN: usize = 5;
+
+/// An empty `Open` target means the one given at login.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Frame {
+    Open { id: u32, target: String },
+    OpenOk { id: u32 },
+    OpenErr { id: u32, reason: String },
+    Data { id: u32, data: Bytes },
+    Close { id: u32 },
+    Window { id: u32, credit: u32 },
+    Fin { id: u32 },
+}
+
+impl Frame {
+    pub fn id(&self) -> u32 {
+        match self {
+            Frame::Open { id, .. }
+            | Frame::OpenOk { id }
+            | Frame::OpenErr { id, .. }
+            | Frame::Data { id, .. }
+            | Frame::Close { id }
+            | Frame::Window { id, .. }
+            | Frame::Fin { id } => *id,
+        }
+    }
+
+    pub fn encode(&self) -> Bytes {
+        let (t, payload): (u8, &[u8]) = match self {
+            Frame::Open { target, .. } => (FRAME_OPEN, target.as_bytes()),
+            Frame::OpenOk { .. } => (FRAME_OPEN_OK, &[]),
+            Frame::OpenErr { reason, .. } => (FRAME_OPEN_ERR, reason.as_bytes()),
+            Frame::Data { data, .. } => (FRAME_DATA, data),
+            Frame::Close { .. } => (FRAME_CLOSE, &[]),
+            Frame::Window { .. } => (FRAME_WINDOW, &[]),
+            Frame::Fin { .. } => (FRAME_FIN, &[]),
+        };
+        let mut buf = BytesMut::with_capacity(HEADER_LEN + payload.len() + 4);
+        buf.put_u8(t);
+        buf.put_u32(self.id());
+        if let Frame::Window { credit, .. } = self {
+            buf.put_u32(*credit);
+        } else {
+            buf.put_slice(payload);
+        }
+        buf.freeze()
+    }
+
+    pub fn decode(buf: &[u8]) -> ResultType<Self> {
+        if buf.len() < HEADER_LEN {
+            bail!("Invalid mux frame length {}", buf.len());
+        }
+        let id = u32::from_be_bytes([buf[1], buf[2], buf[3], buf[4]]);
+        let payload = &buf[HEADER_LEN..];
+        let text = || String::from_utf8_lossy(payload).to_string();
+        Ok(match buf[0] {
+            FRAME_OPEN => Frame::Open { id, target: text() },
+            FRAME_OPEN_OK => Frame::OpenOk { id },
+            FRAME_OPEN_ERR => Frame::OpenErr { id, reason: text() },
+            FRAME_DATA => Frame::Data {
+                id,
+                data: Bytes::copy_from_slice(payload),
+            },
+            FRAME_CLOSE => Frame::Close { id },
+            FRAME_WINDOW => {
+                if payload.len() != 4 {
+                    bail!("Invalid mux window frame");
+                }
+                Frame::Window {
+                    id,
+                    credit: u32::from_be_bytes([payload[0], payload[1], payload[2], payload[3]]),
+                }
+            }
+            FRAME_FIN => Frame::Fin { id },
+            t => bail!("Unknown mux frame type {}", t),
+        })
+    }
+}
+
+enum Event {
+    Opened,
+    Refused,
+    Data(Bytes),
+    Window(u32),
+    Fin,
+    Close,
+}
+
+/// Book-keeping of the streams of one session. Frames produced by the stream tasks are
+/// collected in one queue, the owner of the session stream writes them out with [`Mux::next_frame`].
+pub struct Mux {
+    next_id: u32,
+    streams: HashMap<u32, mpsc::UnboundedSender<Event>>,
+    tx: mpsc::UnboundedSender<Frame>,
+    rx: mpsc::UnboundedReceiver<Frame>,
+}
+
+impl Mux {
+    pub fn new(is_controlling: bool) -> Self {
+        let (tx, rx) = mpsc::unbounded_channel();
+        Self {
+            next_id: if is_controlling { 1 } else { 2 },
+            streams: Default::default(),
+            tx,
+            rx,
+        }
+    }
+
+    /// Register a locally accepted socket, the returned `Open` frame must be sent to the peer.
+    /// No data is read from the socket until the peer confirms the stream.
+    pub fn open(&mut self, socket: TcpStream, target: String) -> Frame {
+        self.open_stream(socket, target, false)
+    }
+
+    /// Like [`Mux::open`] for a socket which finished the SOCKS5 handshake,
+    /// the SOCKS5 reply is sent when the peer confirms or refuses the stream.
+    pub fn open_socks5(&mut self, socket: TcpStream, target: String) -> Frame {
+        self.open_stream(socket, target, true)
+    }
+
+    fn open_stream(&mut self, socket: TcpStream, target: String, socks5: bool) -> Frame {
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(2);
+        let (tx, rx) = mpsc::unbounded_channel();
+        self.streams.insert(id, tx);
+        tokio::spawn(run_stream(id, socket, rx, self.tx.clone(), false, socks5));
+        Frame::Open { id, target }
+    }
+
+    /// The next frame to be sent to the peer.
+    pub async fn next_frame(&mut self) -> Frame {
+        // `self.tx` is never dropped, so `recv` never returns `None`.
+        let frame = self.rx.recv().await.unwrap_or(Frame::Close { id: 0 });
+        match &frame {
+            Frame::Close { id } => {
+                self.streams.remove(id);
+            }
+            // Only streams opened by the peer are refused here, an `Open` of the peer
+            // with an id of ours must not drop the stream of ours.
+            Frame::OpenErr { id, .. } if !self.is_local_id(*id) => {
+                self.streams.remove(id);
+            }
+            _ => {}
+        }
+        frame
+    }
+
+    #[inline]
+    fn is_local_id(&self, id: u32) -> bool {
+        id & 1 == self.next_id & 1
+    }
+
+    /// Handle a frame received from the peer.
+    ///
+    /// `resolve` maps the target of an `Open` request to the address to connect to,
+    /// or returns the reason why it is refused.
+    pub fn handle_frame(
+        &mut self,
+        frame: Frame,
+        resolve: impl FnOnce(&str) -> Result<String, String>,
+    ) {
+        let id = frame.id();
+        match frame {
+            Frame::Open { target, .. } => {
+                if self.is_local_id(id) {
+                    log::warn!("Invalid mux stream id {} of the peer", id);
+                    self.refuse(id, "Invalid stream id");
+                    return;
+                }
+                if self.streams.contains_key(&id) {
+                    log::warn!("Duplicated mux stream {}", id);
+                    return;
+                }
+                if self.streams.len() >= MAX_STREAMS {
+                    log::warn!("Too many mux streams, refuse {}", id);
+                    self.refuse(id, "Too many streams");
+                    return;
+                }
+                let addr = match resolve(&target) {
+                    Ok(addr) => addr,
+                    Err(reason) => {
+                        self.refuse(id, &reason);
+                        return;
+                    }
+                };
+                let (tx, rx) = mpsc::unbounded_channel();
+                self.streams.insert(id, tx);
+                let frames = self.tx.clone();
+                tokio::spawn(async move {
+                    match hbb_common::timeout(3000, TcpStream::connect(&addr)).await {
+                        Ok(Ok(socket)) => {
+                            frames.send(Frame::OpenOk { id }).ok();
+                            run_stream(id, socket, rx, frames, true, false).await;
+                        }
+                        _ => {
+                            log::info!("mux stream {} failed to connect {}", id, addr);
+                            frames
+                                .send(Frame::OpenErr {
+                                    id,
+                                    reason: format!("Failed to access remote {}", addr),
+                                })
+                                .ok();
+                        }
+                    }
+                });
+            }
+            Frame::OpenOk { .. } => self.notify(id, Event::Opened),
+            Frame::OpenErr { reason, .. } => {
+                log::info!("mux stream {} refused: {}", id, reason);
+                self.notify(id, Event::Refused);
+                self.streams.remove(&id);
+            }
+            Frame::Data { data, .. } => self.notify(id, Event::Data(data)),
+            Frame::Window { credit, .. } => self.notify(id, Event::Window(credit)),
+            Frame::Fin { .. } => self.notify(id, Event::Fin),
+            Frame::Close { .. } => {
+                self.notify(id, Event::Close);
+                self.streams.remove(&id);
+            }
+        }
+    }
+
+    fn refuse(&self, id: u32, reason: &str) {
+        self.tx
+            .send(Frame::OpenErr {
+                id,
+                reason: reason.to_owned(),
+            })
+            .ok();
+    }
+
+    fn notify(&self, id: u32, event: Event) {
+        if let Some(tx) = self.streams.get(&id) {
+            tx.send(event).ok();
+        }
+    }
+}
+
+async fn run_stream(
+    id: u32,
+    socket: TcpStream,
+    mut rx: mpsc::UnboundedReceiver<Event>,
+    frames: mpsc::UnboundedSender<Frame>,
+    opened: bool,
+    socks5: bool,
+) {
+    use super::socks5::{reply_bytes, REP_GENERAL_FAILURE, REP_NOT_ALLOWED, REP_SUCCEEDED};
+    let (mut reader, mut writer) = socket.into_split();
+    let mut established = opened;
+    let mut credit = if opened { INITIAL_WINDOW as usize } else { 0 };
+    let mut buf = vec![0u8; MAX_DATA_LEN];
+    let mut closed_by_peer = false;
+    // Fin sent and received
+    let mut read_closed = false;
+    let mut write_closed = false;
+    loop {
+        tokio::select! {
+            res = reader.read(&mut buf[..credit.min(MAX_DATA_LEN)]), if credit > 0 && !read_closed => {
+                match res {
+                    Ok(n) if n > 0 => {
+                        credit -= n;
+                        frames.send(Frame::Data { id, data: Bytes::copy_from_slice(&buf[..n]) }).ok();
+                    }
+                    Ok(_) => {
+                        frames.send(Frame::Fin { id }).ok();
+                        read_closed = true;
+                        if write_closed {
+                            break;
+                        }
+                    }
+                    Err(_) => break,
+                }
+            }
+            event = rx.recv() => {
+                match event {
+                    Some(Event::Opened) => {
+                        if socks5 && writer.write_all(&reply_bytes(REP_SUCCEEDED)).await.is_err() {
+                            break;
+                        }
+                        established = true;
+                        credit = INITIAL_WINDOW as usize;
+                    }
+                    Some(Event::Refused) => {
+                        if socks5 {
+                            writer.write_all(&reply_bytes(REP_NOT_ALLOWED)).await.ok();
+                        }
+                        closed_by_peer = true;
+                        break;
+                    }
+                    Some(Event::Data(data)) => {
+                        if write_closed {
+                            continue;
+                        }
+                        if writer.write_all(&data).await.is_err() {
+                            break;
+                        }
+                        frames.send(Frame::Window { id, credit: data.len() as _ }).ok();
+                    }
+                    Some(Event::Window(n)) => credit += n as usize,
+                    Some(Event::Fin) => {
+                        writer.shutdown().await.ok();
+                        write_closed = true;
+                        if read_closed {
+                            break;
+                        }
+                    }
+                    Some(Event::Close) | None => {
+                        if socks5 && !established {
+                            writer.write_all(&reply_bytes(REP_GENERAL_FAILURE)).await.ok();
+                        }
+                        closed_by_peer = true;
+                        break;
+                    }
+                }
+            }
+        }
+    }
+    writer.shutdown().await.ok();
+    if !closed_by_peer {
+        frames.send(Frame::Close { id }).ok();
+    }
+    log::debug!("mux stream {} closed", id);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frame_codec() {
+        let frames = vec![
+            Frame::Open {
+                id: 1,
+                target: "localhost:22".to_owned(),
+            },
+            Frame::OpenOk { id: 3 },
+            Frame::OpenErr {
+                id: 5,
+                reason: "refused".to_owned(),
+            },
+            Frame::Data {
+                id: 7,
+                data: Bytes::from_static(b"hello"),
+            },
+            Frame::Close { id: u32::MAX },
+            Frame::Fin { id: 9 },
+            Frame::Window {
+                id: 2,
+                credit: INITIAL_WINDOW,
+            },
+        ];
+        for frame in frames {
+            assert_eq!(Frame::decode(&frame.encode()).unwrap(), frame);
+        }
+        assert!(Frame::decode(&[FRAME_DATA, 0, 0]).is_err());
+        assert!(Frame::decode(&[FRAME_WINDOW, 0, 0, 0, 1, 0]).is_err());
+        assert!(Frame::decode(&[0xff, 0, 0, 0, 1]).is_err());
+    }
+
+    fn open(mux: &mut Mux, id: u32) -> Option<Frame> {
+        mux.handle_frame(
+            Frame::Open {
+                id,
+                target: String::new(),
+            },
+            |_| Err("refused".to_owned()),
+        );
+        mux.rx.try_recv().ok()
+    }
+
+    fn is_refused(frame: Option<Frame>, id: u32, reason: &str) -> bool {
+        matches!(frame, Some(Frame::OpenErr { id: i, reason: r }) if i == id && r == reason)
+    }
+
+    #[test]
+    fn test_open_local_id() {
+        let mut controlling = Mux::new(true);
+        assert!(is_refused(
+            open(&mut controlling, 3),
+            3,
+            "Invalid stream id"
+        ));
+        assert!(is_refused(open(&mut controlling, 4), 4, "refused"));
+        let mut controlled = Mux::new(false);
+        assert!(is_refused(open(&mut controlled, 4), 4, "Invalid stream id"));
+        assert!(is_refused(open(&mut controlled, 3), 3, "refused"));
+    }
+
+    #[test]
+    fn test_open_limit() {
+        let mut mux = Mux::new(false);
+        let mut receivers = vec![];
+        for i in 0..MAX_STREAMS as u32 {
+            let (tx, rx) = mpsc::unbounded_channel();
+            mux.streams.insert(i * 2 + 1, tx);
+            receivers.push(rx);
+        }
+        let id = MAX_STREAMS as u32 * 2 + 1;
+        assert!(is_refused(open(&mut mux, id), id, "Too many streams"));
+        assert!(!mux.streams.contains_key(&id));
+        mux.streams.remove(&1);
+        assert!(is_refused(open(&mut mux, id), id, "refused"));
+    }
+}
@@ -0,0 +1,189 @@
+//! Named port forwards kept in `port_forward.toml` of the config directory, eg.
+//!
+//! ```toml
+//! [[profiles]]
+//! name = "license"
+//! peer = "123456789"
+//! local_port = 27000
+//! remote_host = "localhost"
+//! remote_port = 27000
+//! bind = "127.0.0.1"
+//! ```
+//!
+//! No password is kept here, the one saved for the peer is used, eg. remembered by connecting
+//! once with `--port-forward`.
+//!
+//! The headless client brings them all up and reports their status over ipc, see [`IPC_POSTFIX`].
+
+use super::Stats;
+use crate::ipc::{self, Data};
+use cidr_utils::cidr::IpCidr;
+use hbb_common::{
+    allow_err, bail,
+    config::{self, Config},
+    futures::StreamExt,
+    log, tokio, ResultType,
+};
+use serde_derive::{Deserialize, Serialize};
+use std::{
+    path::PathBuf,
+    sync::{atomic::Ordering, Arc},
+};
+
+pub const IPC_POSTFIX: &str = "_port_forward";
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ForwardProfile {
+    pub name: String,
+    pub peer: String,
+    pub local_port: i32,
+    pub remote_host: String,
+    pub remote_port: i32,
+    /// The address of the local listener, loopback if empty.
+    pub bind: String,
+    /// Addresses or CIDRs of the clients allowed to connect, anyone if empty.
+    pub allow: Vec<String>,
+    pub udp: bool,
+    pub disabled: bool,
+}
+
+impl ForwardProfile {
+    pub fn check(&self) -> ResultType<()> {
+        if self.name.is_empty() || self.peer.is_empty() {
+            bail!("Port forward profile without name or peer");
+        }
+        if self.local_port <= 0 || self.local_port > u16::MAX as i32 {
+            bail!("Invalid local port {} of {}", self.local_port, self.name);
+        }
+        if self.remote_port < 0 || self.remote_port > u16::MAX as i32 {
+            bail!("Invalid remote port {} of {}", self.remote_port, self.name);
+        }
+        if self.udp && super::is_dynamic(self.remote_port) {
+            bail!("Dynamic forwarding of {} is TCP only", self.name);
+        }
+        self.allow_list()?;
+        Ok(())
+    }
+
+    pub fn allow_list(&self) -> ResultType<Vec<IpCidr>> {
+        super::parse_allow_list(&self.allow.join(","))
+    }
+
+    pub fn remote_host(&self) -> String {
+        if self.remote_host.is_empty() {
+            "localhost".to_owned()
+        } else {
+            self.remote_host.clone()
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct ForwardProfiles {
+    #[serde(default)]
+    pub profiles: Vec<ForwardProfile>,
+}
+
+impl ForwardProfiles {
+    pub fn path() -> PathBuf {
+        Config::path("port_forward.toml")
+    }
+
+    pub fn load() -> Self {
+        config::load_path(Self::path())
+    }
+}
+
+/// What is reported for every profile over ipc.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct TunnelStatus {
+    pub name: String,
+    pub peer: String,
+    pub local_port: i32,
+    pub connected: bool,
+    pub sent: u64,
+    pub received: u64,
+    pub retries: u32,
+    pub last_error: String,
+}
+
+impl TunnelStatus {
+    pub fn new(profile: &ForwardProfile, stats: &Stats) -> Self {
+        Self {
+            name: profile.name.clone(),
+            peer: profile.peer.clone(),
+            local_port: profile.local_port,
+            connected: stats.connected.load(Ordering::Relaxed),
+            sent: stats.sent.load(Ordering::Relaxed),
+            received: stats.received.load(Ordering::Relaxed),
+            retries: stats.retries.load(Ordering::Relaxed),
+            last_error: stats.last_error.lock().unwrap().clone(),
+        }
+    }
+}
+
+/// Answer `Data::PortForwardStatus(None)` requests with the status of `tunnels`.
+pub async fn serve_status(tunnels: Vec<(ForwardProfile, Arc<Stats>)>) -> ResultType<()> {
+    let mut incoming = ipc::new_listener(IPC_POSTFIX).await?;
+    let tunnels = Arc::new(tunnels);
+    while let Some(result) = incoming.next().await {
+        match result {
+            Ok(stream) => {
+                let tunnels = tunnels.clone();
+                tokio::spawn(async move {
+                    let mut stream = ipc::Connection::new(stream);
+                    while let Ok(Some(data)) = stream.next().await {
+                        if let Data::PortForwardStatus(None) = data {
+                            let status = tunnels
+                                .iter()
+                                .map(|(profile, stats)| TunnelStatus::new(profile, stats))
+                                .collect();
+                            allow_err!(stream.send(&Data::PortForwardStatus(Some(status))).await);
+                        }
+                    }
+                });
+            }
+            Err(err) => {
+                log::error!("Couldn't get port forward status client: {:?}", err);
+            }
+        }
+    }
+    Ok(())
+}
+
+pub async fn get_status() -> ResultType<Vec<TunnelStatus>> {
+    let mut c = ipc::connect(1000, IPC_POSTFIX).await?;
+    c.send(&Data::PortForwardStatus(None)).await?;
+    match c.next_timeout(1000).await? {
+        Some(Data::PortForwardStatus(Some(status))) => Ok(status),
+        _ => bail!("Unexpected response of port forward status"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_profile_check() {
+        let mut profile = ForwardProfile {
+            name: "ssh".to_owned(),
+            peer: "123456789".to_owned(),
+            local_port: 2222,
+            remote_port: 22,
+            ..Default::default()
+        };
+        assert!(profile.check().is_ok());
+        assert_eq!(profile.remote_host(), "localhost");
+        profile.local_port = 0;
+        assert!(profile.check().is_err());
+        profile.local_port = 2222;
+        profile.udp = true;
+        profile.remote_port = 0;
+        assert!(profile.check().is_err());
+        profile.udp = false;
+        profile.allow = vec!["10.0.0.0/8".to_owned(), "localhost".to_owned()];
+        assert!(profile.check().is_err());
+    }
+}
@@ -0,0 +1,88 @@
+//! The server part of SOCKS5 (RFC 1928) used by dynamic port forwarding.
+//! Only the `CONNECT` command without authentication is supported.
+
+use hbb_common::{
+    bail,
+    tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpStream,
+    },
+    ResultType,
+};
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+const VERSION: u8 = 5;
+const NO_AUTH: u8 = 0;
+const NO_ACCEPTABLE_METHODS: u8 = 0xff;
+const CMD_CONNECT: u8 = 1;
+const ATYP_IPV4: u8 = 1;
+const ATYP_DOMAIN: u8 = 3;
+const ATYP_IPV6: u8 = 4;
+
+pub const REP_SUCCEEDED: u8 = 0;
+pub const REP_GENERAL_FAILURE: u8 = 1;
+pub const REP_NOT_ALLOWED: u8 = 2;
+const REP_COMMAND_NOT_SUPPORTED: u8 = 7;
+const REP_ADDRESS_TYPE_NOT_SUPPORTED: u8 = 8;
+
+/// Run the handshake on an accepted socket, returns the requested `host:port`.
+///
+/// The caller must answer with [`reply`] once the connection to the target is established or failed.
+pub async fn accept(socket: &mut TcpStream) -> ResultType<String> {
+    let mut header = [0u8; 2];
+    socket.read_exact(&mut header).await?;
+    if header[0] != VERSION {
+        bail!("Unsupported socks version {}", header[0]);
+    }
+    let mut methods = vec![0u8; header[1] as usize];
+    socket.read_exact(&mut methods).await?;
+    if !methods.contains(&NO_AUTH) {
+        socket.write_all(&[VERSION, NO_ACCEPTABLE_METHODS]).await?;
+        bail!("No acceptable socks authentication method");
+    }
+    socket.write_all(&[VERSION, NO_AUTH]).await?;
+
+    let mut request = [0u8; 4];
+    socket.read_exact(&mut request).await?;
+    if request[0] != VERSION {
+        bail!("Unsupported socks version {}", request[0]);
+    }
+    if request[1] != CMD_CONNECT {
+        reply(socket, REP_COMMAND_NOT_SUPPORTED).await.ok();
+        bail!("Unsupported socks command {}", request[1]);
+    }
+    let host = match request[3] {
+        ATYP_IPV4 => {
+            let mut addr = [0u8; 4];
+            socket.read_exact(&mut addr).await?;
+            Ipv4Addr::from(addr).to_string()
+        }
+        ATYP_DOMAIN => {
+            let len = socket.read_u8().await? as usize;
+            let mut addr = vec![0u8; len];
+            socket.read_exact(&mut addr).await?;
+            String::from_utf8_lossy(&addr).to_string()
+        }
+        ATYP_IPV6 => {
+            let mut addr = [0u8; 16];
+            socket.read_exact(&mut addr).await?;
+            format!("[{}]", Ipv6Addr::from(addr))
+        }
+        t => {
+            reply(socket, REP_ADDRESS_TYPE_NOT_SUPPORTED).await.ok();
+            bail!("Unsupported socks address type {}", t);
+        }
+    };
+    let port = socket.read_u16().await?;
+    Ok(format!("{}:{}", host, port))
+}
+
+/// The bound address is not known on this side of the tunnel, zeros are sent as many clients do.
+pub fn reply_bytes(rep: u8) -> [u8; 10] {
+    [VERSION, rep, 0, ATYP_IPV4, 0, 0, 0, 0, 0, 0]
+}
+
+pub async fn reply(socket: &mut TcpStream, rep: u8) -> ResultType<()> {
+    socket.write_all(&reply_bytes(rep)).await?;
+    Ok(())
+}
@@ -0,0 +1,245 @@
+//! UDP forwarding over one logged-in session.
+//!
+//! After login the session stream is switched to raw mode, every raw message is one datagram:
+//! `[association id: u32 BE][payload]`.
+//! The controlling side allocates an association for every source address of its listener,
+//! the peer relays each association through a socket of its own, which is dropped after [`IDLE_TIMEOUT`].
+
+use bytes::{BufMut, Bytes, BytesMut};
+use hbb_common::{
+    bail, log,
+    tokio::{
+        self,
+        net::{lookup_host, UdpSocket},
+        sync::mpsc,
+        time::{Duration, Instant},
+    },
+    ResultType,
+};
+use std::{collections::HashMap, net::SocketAddr};
+
+/// Prefix of `PortForward.host` in the login request to ask for UDP forwarding,
+/// the forwarded host follows it, eg. `@udp/localhost`.
+pub const UDP_HOST_PREFIX: &str = "@udp/";
+#[inline]
+pub fn is_udp_host(host: &str) -> bool {
+    host.starts_with(UDP_HOST_PREFIX)
+}
+
+pub const UDP_NOT_SUPPORTED: &str = "UDP forwarding is not supported by the remote side";
+
+/// An association without any datagram in either direction for this long is dropped.
+pub const IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+pub const MAX_DATAGRAM_LEN: usize = 65535;
+const MAX_ASSOCIATIONS: usize = 256;
+/// Datagrams queued per association towards the target, and in total towards the peer.
+/// Further ones are dropped like on a congested link.
+const QUEUE_LEN: usize = 64;
+const PEER_QUEUE_LEN: usize = 1024;
+const HEADER_LEN: usize = 4;
+
+pub fn encode(id: u32, payload: &[u8]) -> Bytes {
+    let mut buf = BytesMut::with_capacity(HEADER_LEN + payload.len());
+    buf.put_u32(id);
+    buf.put_slice(payload);
+    buf.freeze()
+}
+
+pub fn decode(buf: &[u8]) -> ResultType<(u32, Bytes)> {
+    if buf.len() < HEADER_LEN {
+        bail!("Invalid udp datagram length {}", buf.len());
+    }
+    let id = u32::from_be_bytes([buf[0], buf[1], buf[2], buf[3]]);
+    Ok((id, Bytes::copy_from_slice(&buf[HEADER_LEN..])))
+}
+
+/// Source addresses of the local listener and their association ids, used by the controlling side.
+#[derive(Default)]
+pub struct Associations {
+    next_id: u32,
+    ids: HashMap<SocketAddr, u32>,
+    addrs: HashMap<u32, (SocketAddr, Instant)>,
+}
+
+impl Associations {
+    pub fn id_of(&mut self, addr: SocketAddr) -> u32 {
+        let id = match self.ids.get(&addr) {
+            Some(id) => *id,
+            None => {
+                let id = self.next_id;
+                self.next_id = self.next_id.wrapping_add(1);
+                self.ids.insert(addr, id);
+                id
+            }
+        };
+        self.addrs.insert(id, (addr, Instant::now()));
+        id
+    }
+
+    pub fn addr_of(&mut self, id: u32) -> Option<SocketAddr> {
+        let (addr, last) = self.addrs.get_mut(&id)?;
+        *last = Instant::now();
+        Some(*addr)
+    }
+
+    pub fn expire(&mut self) {
+        let ids = &mut self.ids;
+        self.addrs.retain(|_, (addr, last)| {
+            let alive = last.elapsed() < IDLE_TIMEOUT;
+            if !alive {
+                ids.remove(addr);
+            }
+            alive
+        });
+    }
+
+    pub fn clear(&mut self) {
+        self.ids.clear();
+        self.addrs.clear();
+    }
+}
+
+/// The controlled side of a UDP forwarding session, datagrams of every association
+/// are sent to `target` from a socket of its own.
+pub struct Relay {
+    target: String,
+    assocs: HashMap<u32, mpsc::Sender<Bytes>>,
+    tx: mpsc::Sender<(u32, Bytes)>,
+    rx: mpsc::Receiver<(u32, Bytes)>,
+}
+
+impl Relay {
+    pub fn new(target: String) -> Self {
+        let (tx, rx) = mpsc::channel(PEER_QUEUE_LEN);
+        Self {
+            target,
+            assocs: Default::default(),
+            tx,
+            rx,
+        }
+    }
+
+    /// Handle a datagram received from the peer.
+    pub fn handle(&mut self, buf: &[u8]) -> ResultType<()> {
+        let (id, payload) = decode(buf)?;
+        let payload = match self.assocs.get(&id) {
+            Some(tx) => match tx.try_send(payload) {
+                Ok(()) | Err(mpsc::error::TrySendError::Full(_)) => return Ok(()),
+                // The association timed out, set it up again.
+                Err(mpsc::error::TrySendError::Closed(payload)) => payload,
+            },
+            None => payload,
+        };
+        if self.assocs.len() >= MAX_ASSOCIATIONS {
+            self.assocs.retain(|_, tx| !tx.is_closed());
+            if self.assocs.len() >= MAX_ASSOCIATIONS {
+                log::warn!("Too many udp associations, datagram dropped");
+                return Ok(());
+            }
+        }
+        let (tx, rx) = mpsc::channel(QUEUE_LEN);
+        tx.try_send(payload).ok();
+        self.assocs.insert(id, tx);
+        tokio::spawn(run_association(
+            id,
+            self.target.clone(),
+            rx,
+            self.tx.clone(),
+        ));
+        Ok(())
+    }
+
+    /// The next datagram to be sent to the peer.
+    pub async fn next_datagram(&mut self) -> Bytes {
+        // `self.tx` is never dropped, so `recv` never returns `None`.
+        let (id, data) = self.rx.recv().await.unwrap_or_default();
+        encode(id, &data)
+    }
+}
+
+async fn run_association(
+    id: u32,
+    target: String,
+    mut rx: mpsc::Receiver<Bytes>,
+    tx: mpsc::Sender<(u32, Bytes)>,
+) {
+    let socket = match connect(&target).await {
+        Ok(socket) => socket,
+        Err(err) => {
+            log::info!(
+                "udp association {} failed to connect {}: {}",
+                id,
+                target,
+                err
+            );
+            return;
+        }
+    };
+    let mut buf = vec![0u8; MAX_DATAGRAM_LEN];
+    loop {
+        tokio::select! {
+            data = rx.recv() => {
+                match data {
+                    Some(data) => {
+                        if let Err(err) = socket.send(&data).await {
+                            log::debug!("udp association {} failed to send: {}", id, err);
+                        }
+                    }
+                    None => break,
+                }
+            }
+            res = socket.recv(&mut buf) => {
+                match res {
+                    Ok(n) => {
+                        // Dropped if the session can not keep up.
+                        tx.try_send((id, Bytes::copy_from_slice(&buf[..n]))).ok();
+                    }
+                    // eg. ICMP port unreachable of the previous datagram
+                    Err(err) => log::debug!("udp association {} failed to receive: {}", id, err),
+                }
+            }
+            _ = tokio::time::sleep(IDLE_TIMEOUT) => {
+                break;
+            }
+        }
+    }
+    log::debug!("udp association {} closed", id);
+}
+
+async fn connect(target: &str) -> ResultType<UdpSocket> {
+    let Some(addr) = lookup_host(target).await?.next() else {
+        bail!("Failed to resolve {}", target);
+    };
+    let local = if addr.is_ipv4() {
+        "0.0.0.0:0"
+    } else {
+        "[::]:0"
+    };
+    let socket = UdpSocket::bind(local).await?;
+    socket.connect(addr).await?;
+    Ok(socket)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_associations() {
+        let a: SocketAddr = "127.0.0.1:1000".parse().unwrap();
+        let b: SocketAddr = "127.0.0.1:1001".parse().unwrap();
+        let mut assocs = Associations::default();
+        let id_a = assocs.id_of(a);
+        let id_b = assocs.id_of(b);
+        assert_ne!(id_a, id_b);
+        assert_eq!(assocs.id_of(a), id_a);
+        assert_eq!(assocs.addr_of(id_b), Some(b));
+        assert_eq!(assocs.addr_of(id_b + 1), None);
+        assocs.expire();
+        assert_eq!(assocs.addr_of(id_a), Some(a));
+
+        let (id, payload) = decode(&encode(id_b, b"ping")).unwrap();
+        assert_eq!((id, &payload[..]), (id_b, &b"ping"[..]));
+        assert!(decode(&[0, 0, 1]).is_err());
+    }
+}
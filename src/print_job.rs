@@ -0,0 +1,269 @@
+//! Print jobs of the remote printer, the document format and the job options sent with them.
+//!
+//! The controlling side tells the formats it can print in the login request, in preference
+//! order. A job is only sent to a controlling side which accepts its format.
+
+use hbb_common::message_proto::{LoginRequest, PrintFormat, PrintJobInfo};
+use serde_derive::{Deserialize, Serialize};
+
+// (name, width, height) in points
+const PAPER_SIZES: [(&str, f64, f64); 5] = [
+    ("A3", 842., 1191.),
+    ("A4", 595., 842.),
+    ("A5", 420., 595.),
+    ("Letter", 612., 792.),
+    ("Legal", 612., 1008.),
+];
+const PAPER_SIZE_TOLERANCE: f64 = 3.;
+pub(crate) const MAX_COPIES: u32 = 99;
+
+/// Peer option of the controlling side, "Y" prints the jobs of the peer without asking,
+/// "N" always asks, empty follows the local printer options.
+#[cfg(target_os = "windows")]
+pub const OPTION_PRINTER_AUTO_ACCEPT: &str = "printer-auto-accept";
+
+/// Options of a job, given by the printing system or read from the document.
+/// Empty or 0 if unknown.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct JobOptions {
+    pub title: String,
+    pub pages: u32,
+    pub paper_size: String,
+    // "color" or "monochrome"
+    pub color_mode: String,
+    pub copies: u32,
+}
+
+impl JobOptions {
+    /// The options CUPS passes to a backend, `options` is like `media=A4 ColorModel=Gray`.
+    #[cfg(target_os = "linux")]
+    pub fn from_cups(title: &str, copies: &str, options: &str) -> Self {
+        let mut job = Self {
+            title: title.to_owned(),
+            copies: copies.trim().parse().unwrap_or(0),
+            ..Default::default()
+        };
+        for (k, v) in options.split_whitespace().filter_map(|o| o.split_once('=')) {
+            match k {
+                "ColorModel" | "print-color-mode" | "output-mode" => {
+                    job.color_mode = match v.to_lowercase().as_str() {
+                        "gray" | "grey" | "grayscale" | "monochrome" | "black" => "monochrome",
+                        _ => "color",
+                    }
+                    .to_owned();
+                }
+                "media" | "PageSize" => {
+                    // e.g. "iso_a4_210x297mm" or "A4,tray1"
+                    let media = v.split(',').next().unwrap_or_default();
+                    job.paper_size = PAPER_SIZES
+                        .iter()
+                        .find(|(name, _, _)| {
+                            media.eq_ignore_ascii_case(name)
+                                || media.split('_').any(|p| p.eq_ignore_ascii_case(name))
+                        })
+                        .map(|(name, _, _)| name.to_string())
+                        .unwrap_or_else(|| media.to_owned());
+                }
+                _ => {}
+            }
+        }
+        job
+    }
+
+    /// Fill the options unknown to the printing system from the document.
+    pub fn with_document(mut self, data: &[u8], format: PrintFormat) -> Self {
+        match format {
+            PrintFormat::Pdf => {
+                if self.pages == 0 {
+                    self.pages = pdf_pages(data);
+                }
+                if self.paper_size.is_empty() {
+                    self.paper_size = pdf_paper_size(data).unwrap_or_default();
+                }
+            }
+            PrintFormat::Xps => {
+                if self.pages == 0 {
+                    self.pages = xps_pages(data);
+                }
+            }
+        }
+        self
+    }
+}
+
+/// The format of a document, by its signature.
+pub fn detect_format(data: &[u8]) -> PrintFormat {
+    if data.starts_with(b"%PDF-") {
+        PrintFormat::Pdf
+    } else {
+        // The documents of the Windows printer driver, a zip package.
+        PrintFormat::Xps
+    }
+}
+
+pub fn job_info(data: &[u8], options: &JobOptions) -> PrintJobInfo {
+    PrintJobInfo {
+        format: detect_format(data).into(),
+        pages: options.pages,
+        paper_size: options.paper_size.clone(),
+        color_mode: options.color_mode.clone(),
+        copies: options.copies.clamp(1, MAX_COPIES),
+        title: options.title.clone(),
+        size: data.len() as _,
+        ..Default::default()
+    }
+}
+
+/// The formats the controlling side can print, in preference order.
+#[cfg(target_os = "windows")]
+pub fn accepted_formats() -> Vec<PrintFormat> {
+    // PDF is printed by the application registered for it.
+    vec![PrintFormat::Xps, PrintFormat::Pdf]
+}
+
+/// The formats a controlling side accepts, older versions only print XPS.
+pub fn formats_of(lr: &LoginRequest) -> Vec<PrintFormat> {
+    let formats: Vec<_> = lr
+        .print_formats
+        .iter()
+        .filter_map(|f| f.enum_value().ok())
+        .collect();
+    if formats.is_empty() {
+        vec![PrintFormat::Xps]
+    } else {
+        formats
+    }
+}
+
+/// Whether the controlling side can print a job of `format`.
+pub fn is_accepted(format: PrintFormat, accepted: &[PrintFormat]) -> bool {
+    accepted.contains(&format)
+}
+
+fn find_all<'a>(data: &'a [u8], pat: &'a [u8]) -> impl Iterator<Item = usize> + 'a {
+    data.windows(pat.len())
+        .enumerate()
+        .filter(move |(_, w)| *w == pat)
+        .map(|(i, _)| i)
+}
+
+fn skip_whitespace(data: &[u8]) -> &[u8] {
+    let n = data.iter().take_while(|c| c.is_ascii_whitespace()).count();
+    &data[n..]
+}
+
+fn parse_number(data: &[u8]) -> Option<(f64, &[u8])> {
+    let data = skip_whitespace(data);
+    let n = data
+        .iter()
+        .take_while(|c| c.is_ascii_digit() || **c == b'.' || **c == b'-' || **c == b'+')
+        .count();
+    let v = std::str::from_utf8(&data[..n]).ok()?.parse().ok()?;
+    Some((v, &data[n..]))
+}
+
+fn pdf_pages(data: &[u8]) -> u32 {
+    // The root of the page tree has the largest count.
+    let count = find_all(data, b"/Count")
+        .filter_map(|i| parse_number(&data[i + 6..]))
+        .map(|(v, _)| v as u32)
+        .max()
+        .unwrap_or(0);
+    if count > 0 {
+        return count;
+    }
+    // The page objects, if the page tree isn't readable.
+    find_all(data, b"/Type")
+        .filter(|i| {
+            let rest = skip_whitespace(&data[i + 5..]);
+            rest.starts_with(b"/Page") && !rest.get(5).is_some_and(|c| c.is_ascii_alphanumeric())
+        })
+        .count() as _
+}
+
+fn pdf_paper_size(data: &[u8]) -> Option<String> {
+    let i = find_all(data, b"/MediaBox").next()?;
+    let rest = skip_whitespace(&data[i + 9..]);
+    let mut rest = rest.strip_prefix(b"[")?;
+    let mut v = [0f64; 4];
+    for v in v.iter_mut() {
+        (*v, rest) = parse_number(rest)?;
+    }
+    Some(paper_size_name(v[2] - v[0], v[3] - v[1]))
+}
+
+fn paper_size_name(width: f64, height: f64) -> String {
+    let (short, long) = if width < height {
+        (width, height)
+    } else {
+        (height, width)
+    };
+    PAPER_SIZES
+        .iter()
+        .find(|(_, w, h)| {
+            (short - w).abs() <= PAPER_SIZE_TOLERANCE && (long - h).abs() <= PAPER_SIZE_TOLERANCE
+        })
+        .map(|(name, _, _)| name.to_string())
+        .unwrap_or_else(|| {
+            let mm = |pt: f64| (pt * 25.4 / 72.).round() as u32;
+            format!("{}x{} mm", mm(width), mm(height))
+        })
+}
+
+/// The number of pages of an XPS package, one `.fpage` part each.
+fn xps_pages(data: &[u8]) -> u32 {
+    // File headers of the central directory, or the local ones if it is missing.
+    for (sig, len_offset, name_offset) in [(b"PK\x01\x02", 28, 46), (b"PK\x03\x04", 26, 30)] {
+        let mut found = false;
+        let mut pages = 0;
+        for i in find_all(data, &sig[..]) {
+            let Some(len) = data.get(i + len_offset..i + len_offset + 2) else {
+                continue;
+            };
+            let len = u16::from_le_bytes([len[0], len[1]]) as usize;
+            let Some(name) = data.get(i + name_offset..i + name_offset + len) else {
+                continue;
+            };
+            found = true;
+            if String::from_utf8_lossy(name)
+                .to_lowercase()
+                .ends_with(".fpage")
+            {
+                pages += 1;
+            }
+        }
+        if found {
+            return pages;
+        }
+    }
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pdf_info() {
+        let pdf = b"%PDF-1.4\n1 0 obj << /Type /Pages /Kids [3 0 R 4 0 R] /Count 2 >> endobj\n\
+            3 0 obj << /Type /Page /MediaBox [0 0 595.28 841.89] >> endobj\n\
+            4 0 obj << /Type/Page >> endobj\n";
+        let job = JobOptions::default().with_document(pdf, detect_format(pdf));
+        assert_eq!(job.pages, 2);
+        assert_eq!(job.paper_size, "A4");
+        let pdf = b"%PDF-1.4\n<</Type /Page/MediaBox[0 0 612 792]>>\n<</Type /Page>>";
+        assert_eq!(pdf_pages(pdf), 2);
+        assert_eq!(pdf_paper_size(pdf).unwrap(), "Letter");
+        assert_eq!(paper_size_name(283.5, 425.2), "100x150 mm");
+    }
+
+    #[test]
+    fn test_accepted() {
+        use PrintFormat::*;
+        assert!(is_accepted(Xps, &[Xps, Pdf]));
+        assert!(is_accepted(Pdf, &[Xps, Pdf]));
+        assert!(!is_accepted(Pdf, &formats_of(&LoginRequest::default())));
+        assert_eq!(detect_format(b"%PDF-1.7"), Pdf);
+        assert_eq!(detect_format(b"PK\x03\x04"), Xps);
+    }
+}
@@ -0,0 +1,309 @@
+//! Tools for the screen recordings.
+
+use hbb_common::{bail, log, ResultType};
+use scrap::record::{encrypt, Manifest, ManifestSegment};
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+/// Generate a key pair to encrypt the recordings, the secret key is written to `key_file`.
+/// The public key is returned, to be set as the `recording-public-key` option.
+pub fn generate_key(key_file: &str) -> ResultType<String> {
+    let (pk, sk) = encrypt::generate_key_pair();
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options.open(key_file)?.write_all(sk.as_bytes())?;
+    Ok(pk)
+}
+
+/// Decrypt an encrypted recording with the secret key in `key_file`.
+/// `output` is the recording without `.enc` if not given.
+pub fn decrypt(file: &str, key_file: &str, output: Option<&str>) -> ResultType<PathBuf> {
+    let Some(sk) = encrypt::parse_secret_key(&std::fs::read_to_string(key_file)?) else {
+        bail!("Invalid secret key in {}", key_file);
+    };
+    let output = match output {
+        Some(output) => PathBuf::from(output),
+        None => {
+            let path = Path::new(file);
+            if !path
+                .extension()
+                .is_some_and(|e| e.eq_ignore_ascii_case(encrypt::EXTENSION))
+            {
+                bail!("{} is not an encrypted recording", file);
+            }
+            path.with_extension("")
+        }
+    };
+    encrypt::decrypt_file(file, &output, &sk)?;
+    Ok(output)
+}
+
+/// Join the segments of a recording into one file with ffmpeg.
+///
+/// Segments of the same codec and resolution are concatenated as they are. Otherwise, or if the audio
+/// of a segment is in its own file, they are scaled to the largest resolution and re-encoded,
+/// to VP9 and Opus for `.webm`, H.264 and AAC else.
+/// `output` is next to the manifest if not given.
+pub fn merge(manifest: &str, output: Option<&str>) -> ResultType<PathBuf> {
+    let path = Path::new(manifest);
+    let dir = path.parent().unwrap_or(Path::new("."));
+    let encrypted = format!(".{}", encrypt::EXTENSION);
+    if manifest.ends_with(&encrypted) {
+        bail!("{} is encrypted, decrypt it first", manifest);
+    }
+    let segments: Vec<_> = Manifest::load(path)?
+        .segments
+        .into_iter()
+        .filter_map(|mut s| {
+            // Merged from the decrypted segment.
+            if let Some(file) = s.file.strip_suffix(&encrypted) {
+                s.file = file.to_owned();
+            }
+            if !dir.join(&s.file).exists() {
+                log::warn!(
+                    "Segment {} of {} is missing, skipped. Encrypted segments are to be decrypted first.",
+                    s.file,
+                    manifest
+                );
+                return None;
+            }
+            Some(s)
+        })
+        .collect();
+    let Some(first) = segments.first() else {
+        bail!("No segments to merge in {}", manifest);
+    };
+    let output = match output {
+        Some(output) => PathBuf::from(output),
+        None => path.with_extension(
+            Path::new(&first.file)
+                .extension()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .to_string(),
+        ),
+    };
+    let copy = segments.iter().all(|s| {
+        s.format == first.format
+            && s.width == first.width
+            && s.height == first.height
+            && s.audio == first.audio
+            && s.audio_file.is_empty()
+    });
+    let mut list = None;
+    let args = if copy {
+        let file = output.with_extension("txt");
+        let entries: Vec<_> = segments
+            .iter()
+            .map(|s| {
+                let path = dir.join(&s.file).to_string_lossy().replace('\'', "'\\''");
+                format!("file '{}'\n", path)
+            })
+            .collect();
+        std::fs::write(&file, entries.concat())?;
+        let args = concat_args(&file, &output);
+        list = Some(file);
+        args
+    } else {
+        reencode_args(dir, &segments, &output)
+    };
+    log::info!(
+        "Merging {} segments of {}: ffmpeg {:?}",
+        segments.len(),
+        manifest,
+        args
+    );
+    let res = Command::new("ffmpeg").args(&args).output();
+    if let Some(list) = list {
+        std::fs::remove_file(list).ok();
+    }
+    match res {
+        Ok(o) if o.status.success() => Ok(output),
+        Ok(o) => bail!(
+            "ffmpeg failed: {}",
+            String::from_utf8_lossy(&o.stderr)
+                .lines()
+                .last()
+                .unwrap_or_default()
+        ),
+        Err(e) => bail!("Failed to run ffmpeg, is it installed? {}", e),
+    }
+}
+
+fn concat_args(list: &Path, output: &Path) -> Vec<String> {
+    [
+        "-y",
+        "-f",
+        "concat",
+        "-safe",
+        "0",
+        "-i",
+        &list.to_string_lossy(),
+        "-c",
+        "copy",
+        &output.to_string_lossy(),
+    ]
+    .iter()
+    .map(|s| s.to_string())
+    .collect()
+}
+
+fn reencode_args(dir: &Path, segments: &[ManifestSegment], output: &Path) -> Vec<String> {
+    // Even, as required by the encoders.
+    let width = segments.iter().map(|s| s.width).max().unwrap_or_default() / 2 * 2;
+    let height = segments.iter().map(|s| s.height).max().unwrap_or_default() / 2 * 2;
+    let mut args: Vec<String> = vec!["-y".to_owned()];
+    let mut filter = String::new();
+    let mut inputs = 0;
+    for (i, s) in segments.iter().enumerate() {
+        let secs = s.duration as f64 / 1000.;
+        args.extend([
+            "-i".to_owned(),
+            dir.join(&s.file).to_string_lossy().to_string(),
+        ]);
+        let video = inputs;
+        inputs += 1;
+        let audio = if s.audio_file.is_empty() {
+            video
+        } else {
+            args.extend([
+                "-i".to_owned(),
+                dir.join(&s.audio_file).to_string_lossy().to_string(),
+            ]);
+            inputs += 1;
+            inputs - 1
+        };
+        filter += &format!(
+            "[{video}:v:0]scale={width}:{height}:force_original_aspect_ratio=decrease,\
+             pad={width}:{height}:(ow-iw)/2:(oh-ih)/2,setsar=1[v{i}];"
+        );
+        if s.audio {
+            // Fill the gaps, and the end which is muted. The duration is unknown if the
+            // recording was not finished.
+            let end = if s.duration > 0 {
+                format!(",apad,atrim=end={secs}")
+            } else {
+                String::new()
+            };
+            filter += &format!("[{audio}:a:0]aresample=48000:async=1{end}[a{i}];");
+        } else {
+            args.extend(
+                [
+                    "-f",
+                    "lavfi",
+                    "-t",
+                    &secs.to_string(),
+                    "-i",
+                    "anullsrc=r=48000:cl=stereo",
+                ]
+                .iter()
+                .map(|s| s.to_string()),
+            );
+            filter += &format!("[{inputs}:a:0]anull[a{i}];");
+            inputs += 1;
+        }
+    }
+    for i in 0..segments.len() {
+        filter += &format!("[v{i}][a{i}]");
+    }
+    filter += &format!("concat=n={}:v=1:a=1[v][a]", segments.len());
+    args.extend(["-filter_complex".to_owned(), filter]);
+    args.extend(["-map", "[v]", "-map", "[a]"].iter().map(|s| s.to_string()));
+    let webm = output
+        .extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case("webm"));
+    let codecs: &[&str] = if webm {
+        &[
+            "-c:v",
+            "libvpx-vp9",
+            "-crf",
+            "32",
+            "-b:v",
+            "0",
+            "-c:a",
+            "libopus",
+        ]
+    } else {
+        &["-c:v", "libx264", "-pix_fmt", "yuv420p", "-c:a", "aac"]
+    };
+    args.extend(codecs.iter().map(|s| s.to_string()));
+    args.push(output.to_string_lossy().to_string());
+    args
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reencode_args() {
+        let segments = vec![
+            ManifestSegment {
+                file: "a_vp9.webm".to_owned(),
+                duration: 2000,
+                width: 1280,
+                height: 720,
+                format: "VP9".to_owned(),
+                audio: true,
+                ..Default::default()
+            },
+            ManifestSegment {
+                file: "a_h264.mp4".to_owned(),
+                start: 2500,
+                duration: 1500,
+                width: 1281,
+                height: 1200,
+                format: "H264".to_owned(),
+                ..Default::default()
+            },
+        ];
+        let args = reencode_args(Path::new("/r"), &segments, Path::new("/r/a.webm"));
+        let filter = &args[args.iter().position(|a| a == "-filter_complex").unwrap() + 1];
+        assert!(filter.starts_with("[0:v:0]scale=1280:1200:"));
+        assert!(filter.contains("[0:a:0]aresample=48000:async=1,apad,atrim=end=2[a0];"));
+        // The second segment has no audio, silence is the third input.
+        assert!(filter.contains("[1:v:0]scale"));
+        assert!(filter.contains("[2:a:0]anull[a1];"));
+        assert!(filter.ends_with("[v0][a0][v1][a1]concat=n=2:v=1:a=1[v][a]"));
+        assert!(args.contains(&"anullsrc=r=48000:cl=stereo".to_owned()));
+        assert!(args.contains(&"libvpx-vp9".to_owned()));
+        assert_eq!(args.last().unwrap(), "/r/a.webm");
+    }
+
+    #[test]
+    fn test_reencode_args_audio_file() {
+        let segments = vec![ManifestSegment {
+            file: "a_h264.mp4".to_owned(),
+            duration: 2000,
+            width: 1280,
+            height: 720,
+            format: "H264".to_owned(),
+            audio: true,
+            audio_file: "a_h264.weba".to_owned(),
+            ..Default::default()
+        }];
+        let args = reencode_args(Path::new("/r"), &segments, Path::new("/r/a.mp4"));
+        let inputs: Vec<_> = args
+            .iter()
+            .enumerate()
+            .filter(|(_, a)| *a == "-i")
+            .map(|(i, _)| args[i + 1].as_str())
+            .collect();
+        assert_eq!(
+            inputs,
+            [Path::new("/r/a_h264.mp4"), Path::new("/r/a_h264.weba")].map(|p| p.to_str().unwrap())
+        );
+        let filter = &args[args.iter().position(|a| a == "-filter_complex").unwrap() + 1];
+        // The audio is the second input.
+        assert!(filter.contains("[1:a:0]aresample=48000:async=1,apad,atrim=end=2[a0];"));
+        assert!(filter.ends_with("[v0][a0]concat=n=1:v=1:a=1[v][a]"));
+    }
+}
@@ -85,6 +85,9 @@ impl RendezvousMediator {
             std::thread::spawn(move || {
                 allow_err!(super::lan::start_listening());
             });
+            std::thread::spawn(move || {
+                allow_err!(super::lan::start_announcing());
+            });
         }
         // It is ok to run xdesktop manager when the headless function is not allowed.
         #[cfg(target_os = "linux")]
@@ -749,7 +752,7 @@ impl RendezvousMediator {
     }
 }
 
-fn get_direct_port() -> i32 {
+pub(crate) fn get_direct_port() -> i32 {
     let mut port = Config::get_option("direct-access-port")
         .parse::<i32>()
         .unwrap_or(0);
@@ -36,6 +36,14 @@ pub mod audio_service;
 #[cfg(target_os = "windows")]
 pub mod terminal_helper;
 #[cfg(not(any(target_os = "android", target_os = "ios")))]
+pub mod remote_exec;
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+pub mod terminal_policy;
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+mod terminal_recorder;
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+mod terminal_scrollback;
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
 pub mod terminal_service;
 cfg_if::cfg_if! {
 if #[cfg(not(target_os = "ios"))] {
@@ -74,7 +82,7 @@ mod service;
 mod video_qos;
 pub mod video_service;
 
-#[cfg(all(target_os = "windows", feature = "flutter"))]
+#[cfg(all(any(target_os = "windows", target_os = "linux"), feature = "flutter"))]
 pub mod printer_service;
 
 pub type Childs = Arc<Mutex<Vec<std::process::Child>>>;
@@ -136,7 +144,7 @@ pub fn new() -> ServerPtr {
             server.add_service(Box::new(input_service::new_window_focus()));
         }
     }
-    #[cfg(all(target_os = "windows", feature = "flutter"))]
+    #[cfg(all(any(target_os = "windows", target_os = "linux"), feature = "flutter"))]
     {
         match printer_service::init(&crate::get_app_name()) {
             Ok(()) => {
@@ -595,6 +603,12 @@ pub async fn start_server(is_server: bool, no_server: bool) {
             }
         });
         input_service::fix_key_down_timeout_loop();
+        // Not only when something is recorded, the recordings are kept within the limits anyway.
+        #[cfg(windows)]
+        let root = crate::platform::is_root();
+        #[cfg(not(windows))]
+        let root = false;
+        scrap::record::retention::start(&crate::ui_interface::video_save_directory(root));
         #[cfg(target_os = "linux")]
         if input_service::wayland_use_uinput() {
             allow_err!(input_service::setup_uinput(0, 1920, 0, 1080).await);
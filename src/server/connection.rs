@@ -217,6 +217,18 @@ pub struct Connection {
     view_camera: bool,
     terminal: bool,
     port_forward_socket: Option<Framed<TcpStream, BytesCodec>>,
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    port_forward_mux: Option<crate::port_forward::mux::Mux>,
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    port_forward_dynamic: bool,
+    // The address of reverse port forwarding, it is bound only after the login is authorized.
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    port_forward_reverse: Option<String>,
+    // The listener of reverse port forwarding, closed with the connection.
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    port_forward_listener: Option<hbb_common::tokio::net::TcpListener>,
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    port_forward_udp: Option<crate::port_forward::udp::Relay>,
     port_forward_address: String,
     tx_to_cm: mpsc::UnboundedSender<ipc::Data>,
     authorized: bool,
@@ -294,13 +306,20 @@ pub struct Connection {
     // Used to filter stale responses (FileBlockFromCM, FileReadDone, etc.) for
     // cancelled or unknown jobs.
     cm_read_job_ids: HashSet<i32>,
+    // The encoders of the files sent as deltas, by job id and file num, see `file_delta`.
+    delta_encoders: HashMap<(i32, i32), crate::file_delta::Encoder>,
     terminal_service_id: String,
     terminal_persistent: bool,
+    // Watch the terminals of a running service together with the connections already on it.
+    terminal_attach: bool,
     // The user token must be set when terminal is enabled.
     // 0 indicates SYSTEM user
     // other values indicate current user
     #[cfg(not(any(target_os = "android", target_os = "ios")))]
     terminal_user_token: Option<TerminalUserToken>,
+    // The user of the terminals, only the services of the same one can be attached to.
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    terminal_owner: String,
     terminal_generic_service: Option<Box<GenericService>>,
 }
 
@@ -407,6 +426,16 @@ impl Connection {
             view_camera: false,
             terminal: false,
             port_forward_socket: None,
+            #[cfg(not(any(target_os = "android", target_os = "ios")))]
+            port_forward_mux: None,
+            #[cfg(not(any(target_os = "android", target_os = "ios")))]
+            port_forward_dynamic: false,
+            #[cfg(not(any(target_os = "android", target_os = "ios")))]
+            port_forward_reverse: None,
+            #[cfg(not(any(target_os = "android", target_os = "ios")))]
+            port_forward_listener: None,
+            #[cfg(not(any(target_os = "android", target_os = "ios")))]
+            port_forward_udp: None,
             port_forward_address: "".to_owned(),
             tx_to_cm,
             authorized: false,
@@ -474,10 +503,14 @@ impl Connection {
             printer_data: Vec::new(),
             tx_post_seq,
             cm_read_job_ids: HashSet::new(),
+            delta_encoders: HashMap::new(),
             terminal_service_id: "".to_owned(),
             terminal_persistent: false,
+            terminal_attach: false,
             #[cfg(not(any(target_os = "android", target_os = "ios")))]
             terminal_user_token: None,
+            #[cfg(not(any(target_os = "android", target_os = "ios")))]
+            terminal_owner: "".to_owned(),
             terminal_generic_service: None,
         };
         let addr = hbb_common::try_into_v4(addr);
@@ -519,7 +552,7 @@ impl Connection {
         let mut last_recv_time = Instant::now();
 
         conn.stream.set_send_timeout(
-            if conn.file_transfer.is_some() || conn.port_forward_socket.is_some() || conn.terminal {
+            if conn.file_transfer.is_some() || conn.is_port_forward() || conn.terminal {
                 SEND_TIMEOUT_OTHER
             } else {
                 SEND_TIMEOUT_VIDEO
@@ -561,7 +594,7 @@ impl Connection {
                         ipc::Data::Authorize => {
                             conn.require_2fa.take();
                             conn.send_logon_response().await;
-                            if conn.port_forward_socket.is_some() {
+                            if conn.is_port_forward() {
                                 break;
                             }
                         }
@@ -779,7 +812,7 @@ impl Connection {
                                     if !conn.on_message(msg_in).await {
                                         break;
                                     }
-                                    if conn.port_forward_socket.is_some() && conn.authorized {
+                                    if conn.is_port_forward() && conn.authorized {
                                         log::info!("Port forward, last_test_delay is none: {}", conn.last_test_delay.is_none());
                                         // Avoid TestDelay reply injection into rdp data stream
                                         if conn.last_test_delay.is_none() {
@@ -872,7 +905,11 @@ impl Connection {
                                 msg = Arc::new(new_msg);
                             }
                         }
+                        Some(message::Union::Clipboard(_)) => {
+                            conn.record_event("Clipboard received");
+                        }
                         Some(message::Union::MultiClipboards(_multi_clipboards)) => {
+                            conn.record_event("Clipboard received");
                             #[cfg(not(target_os = "ios"))]
                             if let Some(msg_out) = crate::clipboard::get_msg_if_not_support_multi_clip(&conn.lr.version, &conn.lr.my_platform, _multi_clipboards) {
                                 if let Err(err) = conn.stream.send(&msg_out).await {
@@ -893,10 +930,10 @@ impl Connection {
                 },
                 Some(data) = rx_from_authed.recv() => {
                     match data {
-                        #[cfg(all(target_os = "windows", feature = "flutter"))]
-                        ipc::Data::PrinterData(data) => {
+                        #[cfg(all(any(target_os = "windows", target_os = "linux"), feature = "flutter"))]
+                        ipc::Data::PrinterData(data, options) => {
                             if Self::permission(keys::OPTION_ENABLE_REMOTE_PRINTER, &conn.control_permissions) {
-                                conn.send_printer_request(data).await;
+                                conn.send_printer_request(data, options).await;
                             } else {
                                 conn.send_remote_printing_disallowed().await;
                             }
@@ -925,7 +962,7 @@ impl Connection {
                         break;
                     }
                     // The control end will jump out of the loop after receiving LoginResponse and will not reply to the TestDelay
-                    if conn.last_test_delay.is_none() && !(conn.port_forward_socket.is_some() && conn.authorized) {
+                    if conn.last_test_delay.is_none() && !(conn.is_port_forward() && conn.authorized) {
                         conn.last_test_delay = Some(Instant::now());
                         let mut msg_out = Message::new();
                         msg_out.set_test_delay(TestDelay{
@@ -984,6 +1021,7 @@ impl Connection {
         conn.post_conn_audit(json!({
             "action": "close",
         }));
+        conn.record_event("Disconnected");
         if let Some(s) = conn.server.upgrade() {
             let mut s = s.write().unwrap();
             s.remove_connection(&conn.inner);
@@ -1159,6 +1197,136 @@ impl Connection {
                 }
             }
         }
+        #[cfg(not(any(target_os = "android", target_os = "ios")))]
+        if let Some(mut mux) = self.port_forward_mux.take() {
+            use crate::port_forward::mux::Frame;
+            log::info!("Running multiplexed port forwarding loop");
+            self.stream.set_raw();
+            let mut hbbs_rx = crate::hbbs_http::sync::signal_receiver();
+            let addr = self.port_forward_address.clone();
+            let dynamic = self.port_forward_dynamic;
+            let listener = self.port_forward_listener.take();
+            let reverse = listener.is_some();
+            loop {
+                tokio::select! {
+                    Some(data) = rx_from_cm.recv() => {
+                        match data {
+                            ipc::Data::Close => {
+                                bail!("Close requested from connection manager");
+                            }
+                            ipc::Data::CmErr(e) => {
+                                log::error!("Connection manager error: {e}");
+                                bail!("{e}");
+                            }
+                            _ => {}
+                        }
+                    }
+                    frame = mux.next_frame() => {
+                        self.stream.send_bytes(frame.encode()).await?;
+                    }
+                    res = async { match listener.as_ref() {
+                        Some(listener) => listener.accept().await,
+                        None => std::future::pending().await,
+                    } } => {
+                        match res {
+                            Ok((socket, from)) => {
+                                log::info!("Reverse port forwarding connection from {:?}", from);
+                                last_recv_time = Instant::now();
+                                let frame = mux.open(socket, "".to_owned());
+                                self.stream.send_bytes(frame.encode()).await?;
+                            }
+                            Err(err) => log::error!("Failed to accept reverse port forwarding connection: {}", err),
+                        }
+                    }
+                    res = self.stream.next() => {
+                        if let Some(res) = res {
+                            last_recv_time = Instant::now();
+                            let frame = Frame::decode(&res?)?;
+                            let control_permissions = &self.control_permissions;
+                            mux.handle_frame(frame, |target| {
+                                if reverse {
+                                    return Err("Unexpected stream from the peer".to_owned());
+                                }
+                                if !dynamic {
+                                    return if target.is_empty() {
+                                        Ok(addr.clone())
+                                    } else {
+                                        Err("No permission of dynamic IP tunneling".to_owned())
+                                    };
+                                }
+                                // The option may be turned off during the session.
+                                if target.is_empty()
+                                    || !Self::opt_in_permission(
+                                        crate::port_forward::OPTION_ALLOW_DYNAMIC_TUNNEL,
+                                        control_permissions,
+                                    )
+                                {
+                                    return Err("No permission of dynamic IP tunneling".to_owned());
+                                }
+                                log::info!("Dynamic port forwarding to {}", target);
+                                Ok(target.to_owned())
+                            });
+                        } else {
+                            bail!("Stream reset by the peer");
+                        }
+                    },
+                    _ = self.timer.tick() => {
+                        if last_recv_time.elapsed() >= H1 {
+                            bail!("Timeout");
+                        }
+                    }
+                    Ok(conns) = hbbs_rx.recv() => {
+                        if conns.contains(&self.inner.id) {
+                            bail!("Closed manually by the web console");
+                        }
+                    }
+                }
+            }
+        }
+        #[cfg(not(any(target_os = "android", target_os = "ios")))]
+        if let Some(mut relay) = self.port_forward_udp.take() {
+            log::info!("Running udp forwarding loop");
+            self.stream.set_raw();
+            let mut hbbs_rx = crate::hbbs_http::sync::signal_receiver();
+            loop {
+                tokio::select! {
+                    Some(data) = rx_from_cm.recv() => {
+                        match data {
+                            ipc::Data::Close => {
+                                bail!("Close requested from connection manager");
+                            }
+                            ipc::Data::CmErr(e) => {
+                                log::error!("Connection manager error: {e}");
+                                bail!("{e}");
+                            }
+                            _ => {}
+                        }
+                    }
+                    datagram = relay.next_datagram() => {
+                        last_recv_time = Instant::now();
+                        self.stream.send_bytes(datagram).await?;
+                    }
+                    res = self.stream.next() => {
+                        if let Some(res) = res {
+                            last_recv_time = Instant::now();
+                            relay.handle(&res?)?;
+                        } else {
+                            bail!("Stream reset by the peer");
+                        }
+                    },
+                    _ = self.timer.tick() => {
+                        if last_recv_time.elapsed() >= H1 {
+                            bail!("Timeout");
+                        }
+                    }
+                    Ok(conns) = hbbs_rx.recv() => {
+                        if conns.contains(&self.inner.id) {
+                            bail!("Closed manually by the web console");
+                        }
+                    }
+                }
+            }
+        }
         Ok(())
     }
 
@@ -1286,6 +1454,14 @@ impl Connection {
         files: Vec<(String, i64)>,
         info: Value,
     ) {
+        self.record_event(&crate::common::get_file_record_event(
+            matches!(r#type, FileAuditType::RemoteReceive),
+            if path.is_empty() {
+                files.first().map(|f| f.0.as_str()).unwrap_or_default()
+            } else {
+                path
+            },
+        ));
         if self.server_audit_file.is_empty() {
             return;
         }
@@ -1314,6 +1490,17 @@ impl Connection {
         });
     }
 
+    // The recordings are of the displays, which may be shared by several connections.
+    fn record_event(&self, text: &str) {
+        video_service::record_event(format!("{}: {}", self.lr.my_id, text));
+    }
+
+    fn record_msg_event(&self, event: &Option<String>) {
+        if let Some(event) = event {
+            self.record_event(event);
+        }
+    }
+
     pub fn post_alarm_audit(typ: AlarmAuditType, info: Value) {
         let url = crate::get_audit_server(
             Config::get_option("api-server"),
@@ -1373,10 +1560,27 @@ impl Connection {
             self.send_login_error(crate::client::REQUIRE_2FA).await;
             return;
         }
+        // The port of reverse forwarding is bound only once the login is authorized.
+        #[cfg(not(any(target_os = "android", target_os = "ios")))]
+        if let Some(addr) = self.port_forward_reverse.take() {
+            match hbb_common::tcp::new_listener(&addr, true).await {
+                Ok(listener) => {
+                    log::info!("Reverse port forwarding listening on {}", addr);
+                    self.port_forward_listener = Some(listener);
+                }
+                Err(err) => {
+                    self.send_login_error(format!("Failed to listen on {}: {}", addr, err))
+                        .await;
+                    // Not a port forwarding connection any more, it is closed by the peer.
+                    self.port_forward_mux.take();
+                    return;
+                }
+            }
+        }
         self.authorized = true;
         let (conn_type, auth_conn_type) = if self.file_transfer.is_some() {
             (1, AuthConnType::FileTransfer)
-        } else if self.port_forward_socket.is_some() {
+        } else if self.is_port_forward() {
             (2, AuthConnType::PortForward)
         } else if self.view_camera {
             (3, AuthConnType::ViewCamera)
@@ -1400,6 +1604,7 @@ impl Connection {
         self.post_conn_audit(
             json!({"peer": ((&self.lr.my_id, &self.lr.my_name)), "type": conn_type}),
         );
+        self.record_event("Connected");
         #[allow(unused_mut)]
         let mut username = crate::platform::get_active_username();
         let mut res = LoginResponse::new();
@@ -1419,6 +1624,16 @@ impl Connection {
             pi.hostname = DEVICE_NAME.lock().unwrap().clone();
             pi.platform = "Android".into();
         }
+        // Lets the controlling side wake this device through another peer on its subnet.
+        // The MAC and IP addresses are only given to the peers allowed to control this device.
+        if self.peer_keyboard_enabled()
+            && config::option2bool(
+                crate::lan::OPTION_ALLOW_WOL_RELAY,
+                &Config::get_option(crate::lan::OPTION_ALLOW_WOL_RELAY),
+            )
+        {
+            pi.lan_interfaces = crate::lan::get_lan_interfaces();
+        }
         #[cfg(all(target_os = "macos", not(feature = "unix-file-copy-paste")))]
         let platform_additions = serde_json::Map::new();
         #[cfg(any(
@@ -1482,7 +1697,7 @@ impl Connection {
             pi.platform_additions = serde_json::to_string(&platform_additions).unwrap_or("".into());
         }
 
-        if self.port_forward_socket.is_some() {
+        if self.is_port_forward() {
             let mut msg_out = Message::new();
             res.set_peer_info(pi);
             msg_out.set_login_response(res);
@@ -1675,10 +1890,19 @@ impl Connection {
         }
     }
 
+    #[inline]
+    fn is_port_forward(&self) -> bool {
+        #[cfg(not(any(target_os = "android", target_os = "ios")))]
+        if self.port_forward_mux.is_some() || self.port_forward_udp.is_some() {
+            return true;
+        }
+        self.port_forward_socket.is_some()
+    }
+
     #[inline]
     fn is_remote(&self) -> bool {
         self.file_transfer.is_none()
-            && self.port_forward_socket.is_none()
+            && !self.is_port_forward()
             && !self.view_camera
             && !self.terminal
     }
@@ -1971,10 +2195,36 @@ impl Connection {
         )
     }
 
+    // The allow-* options are opt-in, the full access mode doesn't turn them on.
+    fn is_opt_in_enabled_locally(allow_prefix_option: &str) -> bool {
+        is_opt_in_enabled(
+            &Config::get_option("access-mode"),
+            allow_prefix_option,
+            &Config::get_option(allow_prefix_option),
+        )
+    }
+
+    fn opt_in_permission(
+        allow_prefix_option: &str,
+        control_permissions: &Option<ControlPermissions>,
+    ) -> bool {
+        Self::control_permission(allow_prefix_option, control_permissions)
+            .unwrap_or_else(|| Self::is_opt_in_enabled_locally(allow_prefix_option))
+    }
+
     fn permission(
         enable_prefix_option: &str,
         control_permissions: &Option<ControlPermissions>,
     ) -> bool {
+        Self::control_permission(enable_prefix_option, control_permissions)
+            .unwrap_or_else(|| Self::is_permission_enabled_locally(enable_prefix_option))
+    }
+
+    // The permission set by the control permissions of the device, if any.
+    fn control_permission(
+        enable_prefix_option: &str,
+        control_permissions: &Option<ControlPermissions>,
+    ) -> Option<bool> {
         use hbb_common::rendezvous_proto::control_permissions::Permission;
         if let Some(control_permissions) = control_permissions {
             let permission = match enable_prefix_option {
@@ -1989,17 +2239,15 @@ impl Connection {
                 keys::OPTION_ENABLE_REMOTE_RESTART => Some(Permission::restart),
                 keys::OPTION_ENABLE_RECORD_SESSION => Some(Permission::recording),
                 keys::OPTION_ENABLE_BLOCK_INPUT => Some(Permission::block_input),
+                #[cfg(not(any(target_os = "android", target_os = "ios")))]
+                remote_exec::OPTION_ALLOW_REMOTE_EXEC => Some(Permission::remote_exec),
                 _ => None,
             };
             if let Some(permission) = permission {
-                if let Some(enabled) =
-                    crate::get_control_permission(control_permissions.permissions, permission)
-                {
-                    return enabled;
-                }
+                return crate::get_control_permission(control_permissions.permissions, permission);
             }
         }
-        Self::is_permission_enabled_locally(enable_prefix_option)
+        None
     }
 
     fn update_codec_on_login(&self) {
@@ -2145,6 +2393,27 @@ impl Connection {
                         sleep(1.).await;
                         return false;
                     }
+                    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+                    {
+                        self.terminal_owner = terminal_service::owner_of(
+                            self.terminal_user_token
+                                .as_ref()
+                                .and_then(|t| t.to_terminal_service_token()),
+                            &lr.os_login.username,
+                        );
+                        if terminal.attach {
+                            if !terminal_service::is_attachable(
+                                &self.terminal_service_id,
+                                &self.terminal_owner,
+                            ) {
+                                self.send_login_error("No terminal session to attach to")
+                                    .await;
+                                sleep(1.).await;
+                                return false;
+                            }
+                            self.terminal_attach = true;
+                        }
+                    }
 
                     #[cfg(not(any(target_os = "android", target_os = "ios")))]
                     if let Some(is_user) =
@@ -2177,25 +2446,103 @@ impl Connection {
                         pf.port = 3389;
                         is_rdp = true;
                     }
+                    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+                    let is_udp = if let Some(host) = pf
+                        .host
+                        .strip_prefix(crate::port_forward::udp::UDP_HOST_PREFIX)
+                    {
+                        pf.host = host.to_owned();
+                        true
+                    } else {
+                        false
+                    };
+                    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+                    let is_mux = if pf.host == crate::port_forward::mux::MUX_DYNAMIC_HOST {
+                        if !Self::opt_in_permission(
+                            crate::port_forward::OPTION_ALLOW_DYNAMIC_TUNNEL,
+                            &self.control_permissions,
+                        ) {
+                            self.send_login_error("No permission of dynamic IP tunneling")
+                                .await;
+                            sleep(1.).await;
+                            return false;
+                        }
+                        self.port_forward_dynamic = true;
+                        pf.host = "".to_owned();
+                        true
+                    } else if let Some(host) = pf
+                        .host
+                        .strip_prefix(crate::port_forward::mux::MUX_HOST_PREFIX)
+                    {
+                        pf.host = host.to_owned();
+                        true
+                    } else if let Some(bind) = pf
+                        .host
+                        .strip_prefix(crate::port_forward::mux::MUX_REVERSE_PREFIX)
+                    {
+                        if !Self::opt_in_permission(
+                            crate::port_forward::OPTION_ALLOW_REVERSE_TUNNEL,
+                            &self.control_permissions,
+                        ) {
+                            self.send_login_error("No permission of reverse IP tunneling")
+                                .await;
+                            sleep(1.).await;
+                            return false;
+                        }
+                        let allow_any = Self::opt_in_permission(
+                            crate::port_forward::OPTION_ALLOW_REVERSE_TUNNEL_ANY_ADDRESS,
+                            &self.control_permissions,
+                        );
+                        match crate::port_forward::reverse_listen_addr(bind, pf.port, allow_any) {
+                            Ok(addr) => self.port_forward_reverse = Some(addr),
+                            Err(err) => {
+                                self.send_login_error(err).await;
+                                sleep(1.).await;
+                                return false;
+                            }
+                        }
+                        pf.host = if bind.is_empty() { "127.0.0.1" } else { bind }.to_owned();
+                        true
+                    } else {
+                        false
+                    };
                     if pf.host.is_empty() {
                         pf.host = "localhost".to_owned();
                     }
                     let mut addr = format!("{}:{}", pf.host, pf.port);
                     self.port_forward_address = addr.clone();
-                    match timeout(3000, TcpStream::connect(&addr)).await {
-                        Ok(Ok(sock)) => {
-                            self.port_forward_socket = Some(Framed::new(sock, BytesCodec::new()));
-                        }
-                        _ => {
-                            if is_rdp {
-                                addr = "RDP".to_owned();
+                    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+                    if is_mux {
+                        // Streams are opened later, each of them connects on its own.
+                        self.port_forward_mux = Some(crate::port_forward::mux::Mux::new(false));
+                    }
+                    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+                    if is_udp {
+                        // Every association has a socket of its own, created with its first datagram.
+                        self.port_forward_udp =
+                            Some(crate::port_forward::udp::Relay::new(addr.clone()));
+                    }
+                    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+                    let connect_now = !is_mux && !is_udp;
+                    #[cfg(any(target_os = "android", target_os = "ios"))]
+                    let connect_now = true;
+                    if connect_now {
+                        match timeout(3000, TcpStream::connect(&addr)).await {
+                            Ok(Ok(sock)) => {
+                                self.port_forward_socket =
+                                    Some(Framed::new(sock, BytesCodec::new()));
+                            }
+                            _ => {
+                                if is_rdp {
+                                    addr = "RDP".to_owned();
+                                }
+                                self.send_login_error(format!(
+                                    "Failed to access remote {}, please make sure if it is open",
+                                    addr
+                                ))
+                                .await;
+                                return false;
                             }
-                            self.send_login_error(format!(
-                                "Failed to access remote {}, please make sure if it is open",
-                                addr
-                            ))
-                            .await;
-                            return false;
                         }
                     }
                 }
@@ -2386,9 +2733,11 @@ impl Connection {
                 }
             }
         } else if self.authorized {
-            if self.port_forward_socket.is_some() {
+            if self.is_port_forward() {
                 return true;
             }
+            // Recorded once the message is accepted.
+            let event = crate::common::get_record_event(&msg);
             match msg.union {
                 #[allow(unused_mut)]
                 Some(message::Union::MouseEvent(mut me)) => {
@@ -2396,11 +2745,14 @@ impl Connection {
                         return true;
                     }
                     #[cfg(any(target_os = "android", target_os = "ios"))]
+                    self.record_msg_event(&event);
+                    #[cfg(any(target_os = "android", target_os = "ios"))]
                     if let Err(e) = call_main_service_pointer_input("mouse", me.mask, me.x, me.y) {
                         log::debug!("call_main_service_pointer_input fail:{}", e);
                     }
                     #[cfg(not(any(target_os = "android", target_os = "ios")))]
                     if self.peer_keyboard_enabled() {
+                        self.record_msg_event(&event);
                         if is_left_up(&me) {
                             CLICK_TIME.store(get_time(), Ordering::SeqCst);
                         } else {
@@ -2476,6 +2828,7 @@ impl Connection {
                     if self.is_authed_view_camera_conn() {
                         return true;
                     }
+                    self.record_msg_event(&event);
                     let key = match me.mode.enum_value() {
                         Ok(KeyboardMode::Map) => {
                             Some(crate::keyboard::keycode_to_rdev_key(me.chr()))
@@ -2532,6 +2885,7 @@ impl Connection {
                         return true;
                     }
                     if self.peer_keyboard_enabled() {
+                        self.record_msg_event(&event);
                         if is_enter(&me) {
                             CLICK_TIME.store(get_time(), Ordering::SeqCst);
                         }
@@ -2589,6 +2943,7 @@ impl Connection {
                 }
                 Some(message::Union::Clipboard(cb)) => {
                     if self.clipboard {
+                        self.record_msg_event(&event);
                         #[cfg(not(any(target_os = "android", target_os = "ios")))]
                         update_clipboard(vec![cb], ClipboardSide::Host);
                         // ios as the controlled side is actually not supported for now.
@@ -2618,10 +2973,14 @@ impl Connection {
                 Some(message::Union::MultiClipboards(_mcb)) => {
                     #[cfg(not(any(target_os = "android", target_os = "ios")))]
                     if self.clipboard {
+                        self.record_msg_event(&event);
                         update_clipboard(_mcb.clipboards, ClipboardSide::Host);
                     }
                     #[cfg(target_os = "android")]
-                    crate::clipboard::handle_msg_multi_clipboards(_mcb);
+                    {
+                        self.record_msg_event(&event);
+                        crate::clipboard::handle_msg_multi_clipboards(_mcb);
+                    }
                 }
                 #[cfg(any(target_os = "windows", feature = "unix-file-copy-paste"))]
                 Some(message::Union::Cliprdr(clip)) => {
@@ -2788,8 +3147,9 @@ impl Connection {
                                         let od = can_enable_overwrite_detection(
                                             get_version_number(&self.lr.version),
                                         );
-                                        if crate::common::need_fs_cm_send_files() {
-                                            // Delegate file reading to CM on Windows
+                                        if crate::common::need_fs_cm_send_files() || s.delta {
+                                            // Delegate file reading to CM on Windows, and for
+                                            // delta transfers which encode the blocks from CM.
                                             self.cm_read_job_ids.insert(id);
                                             self.send_fs(ipc::FS::ReadFile {
                                                 path,
@@ -2910,6 +3270,7 @@ impl Connection {
                             Some(file_action::Union::Cancel(c)) => {
                                 self.send_fs(ipc::FS::CancelWrite { id: c.id });
                                 let _ = self.cm_read_job_ids.remove(&c.id);
+                                self.delta_encoders.retain(|k, _| k.0 != c.id);
                                 self.send_fs(ipc::FS::CancelRead {
                                     id: c.id,
                                     conn_id: self.inner.id(),
@@ -2939,6 +3300,37 @@ impl Connection {
                                     }
                                 }
                             }
+                            Some(file_action::Union::DeltaSignature(s)) => {
+                                if !self.cm_read_job_ids.contains(&s.id) {
+                                    log::debug!("Dropping delta signature of unknown job {}", s.id);
+                                } else {
+                                    match crate::file_delta::Signature::decode(&s.signature) {
+                                        Ok(signature) => {
+                                            self.delta_encoders.insert(
+                                                (s.id, s.file_num),
+                                                crate::file_delta::Encoder::new(signature),
+                                            );
+                                        }
+                                        Err(e) => {
+                                            self.send(fs::new_error(
+                                                s.id,
+                                                e.to_string(),
+                                                s.file_num,
+                                            ))
+                                            .await;
+                                        }
+                                    }
+                                }
+                            }
+                            Some(file_action::Union::Checksum(c)) => {
+                                // CM keeps the journals of the written files.
+                                self.send_fs(ipc::FS::Checksum {
+                                    id: c.id,
+                                    file_num: c.file_num,
+                                    path: c.path,
+                                    is_upload: c.is_upload,
+                                });
+                            }
                             Some(file_action::Union::Rename(r)) => {
                                 self.send_fs(ipc::FS::Rename {
                                     id: r.id,
@@ -2993,9 +3385,11 @@ impl Connection {
                 },
                 Some(message::Union::Misc(misc)) => match misc.union {
                     Some(misc::Union::SwitchDisplay(s)) => {
+                        self.record_msg_event(&event);
                         self.handle_switch_display(s).await;
                     }
                     Some(misc::Union::CaptureDisplays(displays)) => {
+                        self.record_msg_event(&event);
                         let add = displays.add.iter().map(|d| *d as usize).collect::<Vec<_>>();
                         let sub = displays.sub.iter().map(|d| *d as usize).collect::<Vec<_>>();
                         let set = displays.set.iter().map(|d| *d as usize).collect::<Vec<_>>();
@@ -3006,7 +3400,11 @@ impl Connection {
                         self.toggle_virtual_display(t).await;
                     }
                     Some(misc::Union::TogglePrivacyMode(t)) => {
+                        let on = t.on;
                         self.toggle_privacy_mode(t).await;
+                        if privacy_mode::is_in_privacy_mode() == on {
+                            self.record_msg_event(&event);
+                        }
                     }
                     Some(misc::Union::ChatMessage(c)) => {
                         self.send_to_cm(ipc::Data::ChatMessage { text: c.text });
@@ -3185,6 +3583,9 @@ impl Connection {
                         self.refresh_video_display(Some(request.display as usize));
                     }
                 }
+                Some(message::Union::WakeOnLanRequest(request)) => {
+                    self.handle_wake_on_lan(request).await;
+                }
                 Some(message::Union::TerminalAction(action)) => {
                     #[cfg(not(any(target_os = "android", target_os = "ios")))]
                     allow_err!(self.handle_terminal_action(action).await);
@@ -4118,6 +4519,12 @@ impl Connection {
         let data = ipc::Data::Close;
         self.tx_to_cm.send(data).ok();
         self.port_forward_socket.take();
+        #[cfg(not(any(target_os = "android", target_os = "ios")))]
+        {
+            self.port_forward_mux.take();
+            self.port_forward_listener.take();
+            self.port_forward_udp.take();
+        }
     }
 
     // The `reason` should be consistent with `check_if_retry` if not empty
@@ -4206,11 +4613,52 @@ impl Connection {
             return;
         }
 
+        self.flush_delta(id, Some(file_num)).await;
+        if let Some(encoder) = self.delta_encoders.get_mut(&(id, file_num)) {
+            let ops = if compressed {
+                encoder.push(&hbb_common::compress::decompress(&data))
+            } else {
+                encoder.push(&data)
+            };
+            if !ops.is_empty() {
+                // Literal data is compressed again if the blocks were.
+                let ops = if compressed {
+                    hbb_common::compress::compress(&ops)
+                } else {
+                    ops
+                };
+                self.send_file_block(id, file_num, ops, compressed).await;
+            }
+            return;
+        }
         // Forward file block to client
+        self.send_file_block(id, file_num, data.to_vec(), compressed)
+            .await;
+    }
+
+    // Send the remaining ops of the delta encoded files of the job, before `file_num` if given.
+    async fn flush_delta(&mut self, id: i32, file_num: Option<i32>) {
+        let keys: Vec<_> = self
+            .delta_encoders
+            .keys()
+            .filter(|k| k.0 == id && file_num.map(|n| k.1 < n).unwrap_or(true))
+            .cloned()
+            .collect();
+        for key in keys {
+            if let Some(mut encoder) = self.delta_encoders.remove(&key) {
+                let ops = encoder.finish();
+                if !ops.is_empty() {
+                    self.send_file_block(key.0, key.1, ops, false).await;
+                }
+            }
+        }
+    }
+
+    async fn send_file_block(&mut self, id: i32, file_num: i32, data: Vec<u8>, compressed: bool) {
         let mut block = FileTransferBlock::new();
         block.id = id;
         block.file_num = file_num;
-        block.data = data.to_vec().into();
+        block.data = data.into();
         block.compressed = compressed;
 
         let mut msg = Message::new();
@@ -4230,6 +4678,7 @@ impl Connection {
             );
             return;
         }
+        self.flush_delta(id, None).await;
 
         // Forward done message to client
         let mut done = FileTransferDone::new();
@@ -4253,6 +4702,7 @@ impl Connection {
             );
             return;
         }
+        self.delta_encoders.retain(|k, _| k.0 != id);
 
         // Forward error to client
         self.send(fs::new_error(id, err, file_num)).await;
@@ -4611,19 +5061,43 @@ impl Connection {
         try_empty_clipboard_files(ClipboardSide::Host, self.inner.id());
     }
 
-    #[cfg(all(target_os = "windows", feature = "flutter"))]
-    async fn send_printer_request(&mut self, data: Vec<u8>) {
+    async fn handle_wake_on_lan(&mut self, request: WakeOnLanRequest) {
+        let mut response = WakeOnLanResponse::new();
+        if !config::option2bool(
+            crate::lan::OPTION_ALLOW_WOL_RELAY,
+            &Config::get_option(crate::lan::OPTION_ALLOW_WOL_RELAY),
+        ) {
+            response.error = "Wake-on-LAN relay is disabled on this device".to_owned();
+        } else {
+            match crate::lan::relay_wol(&request.targets) {
+                Ok(sent) => {
+                    log::info!("Relayed {} wol packets for {}", sent, self.lr.my_id);
+                    response.sent = sent as _;
+                }
+                Err(e) => response.error = e.to_string(),
+            }
+        }
+        let mut msg_out = Message::new();
+        msg_out.set_wake_on_lan_response(response);
+        self.send(msg_out).await;
+    }
+
+    #[cfg(all(any(target_os = "windows", target_os = "linux"), feature = "flutter"))]
+    async fn send_printer_request(&mut self, data: Vec<u8>, options: crate::print_job::JobOptions) {
         // This path is only used to identify the printer job.
         let path = format!("RustDesk://FsJob//Printer/{}", get_time());
 
-        let msg = fs::new_send(0, fs::JobType::Printer, path.clone(), 1, false);
+        let mut msg = fs::new_send(0, fs::JobType::Printer, path.clone(), 1, false);
+        // The options are shown to the controlling side before it accepts the job.
+        msg.mut_file_action().mut_send().print_job =
+            hbb_common::protobuf::MessageField::some(crate::print_job::job_info(&data, &options));
         self.send(msg).await;
         self.printer_data
             .retain(|(t, _, _)| t.elapsed().as_secs() < 60);
         self.printer_data.push((Instant::now(), path, data));
     }
 
-    #[cfg(all(target_os = "windows", feature = "flutter"))]
+    #[cfg(all(any(target_os = "windows", target_os = "linux"), feature = "flutter"))]
     async fn send_remote_printing_disallowed(&mut self) {
         let mut msg_out = Message::new();
         let res = MessageBox {
@@ -4639,6 +5113,9 @@ impl Connection {
 
     #[cfg(not(any(target_os = "android", target_os = "ios")))]
     async fn update_terminal_persistence(&mut self, persistent: bool) {
+        if self.terminal_attach {
+            return;
+        }
         self.terminal_persistent = persistent;
         terminal_service::set_persistent(&self.terminal_service_id, persistent).ok();
     }
@@ -4654,13 +5131,42 @@ impl Connection {
         if self.terminal_service_id.is_empty() {
             self.terminal_service_id = terminal_service::generate_service_id();
         }
-        let s = Box::new(terminal_service::new(
-            self.terminal_service_id.clone(),
-            self.terminal_persistent,
-            user_token.to_terminal_service_token(),
-        ));
-        s.on_subscribe(self.inner.clone());
-        self.terminal_generic_service = Some(s);
+        let mut viewer = TerminalViewer::new();
+        viewer.conn_id = self.inner.id();
+        viewer.peer_id = self.lr.my_id.clone();
+        viewer.name = self.lr.my_name.clone();
+        let res = if self.terminal_attach {
+            terminal_service::attach(
+                &self.terminal_service_id,
+                &self.terminal_owner,
+                self.inner.clone(),
+                viewer,
+            )
+        } else {
+            terminal_service::new(
+                self.terminal_service_id.clone(),
+                self.terminal_persistent,
+                user_token.to_terminal_service_token(),
+                self.terminal_owner.clone(),
+                self.inner.clone(),
+                viewer,
+            )
+        };
+        let s = match res {
+            Ok(s) => s,
+            Err(err) => {
+                log::error!("Failed to start terminal service: {}", err);
+                let mut response = TerminalResponse::new();
+                let mut error = TerminalError::new();
+                error.message = err.to_string();
+                response.set_error(error);
+                let mut msg_out = Message::new();
+                msg_out.set_terminal_response(response);
+                self.send(msg_out).await;
+                return;
+            }
+        };
+        self.terminal_generic_service = Some(Box::new(s));
     }
 
     #[cfg(not(any(target_os = "android", target_os = "ios")))]
@@ -4670,13 +5176,28 @@ impl Connection {
             // unreacheable, but keep it for safety
             bail!("Terminal user token is not set.");
         };
+        if let Some(terminal_action::Union::Exec(exec)) = action.union {
+            self.handle_exec(exec, &user_token).await;
+            return Ok(());
+        }
+        if !Self::permission(keys::OPTION_ENABLE_TERMINAL, &self.control_permissions) {
+            let mut response = TerminalResponse::new();
+            let mut error = TerminalError::new();
+            error.message = "No permission of terminal".to_owned();
+            response.set_error(error);
+            let mut msg_out = Message::new();
+            msg_out.set_terminal_response(response);
+            self.send(msg_out).await;
+            return Ok(());
+        }
+        // Viewers attached to a service keep its persistence.
         let mut proxy = terminal_service::TerminalServiceProxy::new(
             self.terminal_service_id.clone(),
-            Some(self.terminal_persistent),
+            (!self.terminal_attach).then_some(self.terminal_persistent),
             user_token.to_terminal_service_token(),
         );
 
-        match proxy.handle_action(&action) {
+        match proxy.handle_action(&action, self.inner.id()) {
             Ok(Some(response)) => {
                 let mut msg_out = Message::new();
                 msg_out.set_terminal_response(response);
@@ -4698,6 +5219,55 @@ impl Connection {
 
         Ok(())
     }
+
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    async fn handle_exec(&mut self, exec: ExecCommand, user_token: &TerminalUserToken) {
+        // Running commands is allowed on top of the terminal, not instead of it.
+        let error = if !Self::permission(keys::OPTION_ENABLE_TERMINAL, &self.control_permissions) {
+            Some("No permission of terminal")
+        } else if !Self::opt_in_permission(
+            remote_exec::OPTION_ALLOW_REMOTE_EXEC,
+            &self.control_permissions,
+        ) {
+            Some("No permission of remote command execution")
+        } else if user_token.to_terminal_service_token().is_some() {
+            // The Windows service would run the command as SYSTEM instead of the logged-in user.
+            Some("Remote command execution is not supported when running as a service")
+        } else {
+            None
+        };
+        if let Some(error) = error {
+            let mut msg_out = Message::new();
+            msg_out
+                .set_terminal_response(remote_exec::error_response(exec.exec_id, error.to_owned()));
+            self.send(msg_out).await;
+            return;
+        }
+        log::info!(
+            "Connection {} runs command {}: {:?}",
+            self.inner.id(),
+            exec.exec_id,
+            exec.argv
+        );
+        let who = format!(
+            "{} ({}, conn {})",
+            self.lr.my_id,
+            self.lr.my_name,
+            self.inner.id()
+        );
+        let inner = self.inner.clone();
+        remote_exec::start(
+            self.inner.id(),
+            &self.terminal_service_id,
+            &who,
+            exec,
+            move |response| {
+                let mut msg_out = Message::new();
+                msg_out.set_terminal_response(response);
+                inner.clone().send(Arc::new(msg_out));
+            },
+        );
+    }
 }
 
 pub fn insert_switch_sides_uuid(id: String, uuid: uuid::Uuid) {
@@ -4707,6 +5277,11 @@ pub fn insert_switch_sides_uuid(id: String, uuid: uuid::Uuid) {
         .insert(id, (tokio::time::Instant::now(), uuid));
 }
 
+// An allow-* option is on only if it is set, the view access mode turns it off.
+fn is_opt_in_enabled(access_mode: &str, allow_prefix_option: &str, value: &str) -> bool {
+    access_mode != "view" && config::option2bool(allow_prefix_option, value)
+}
+
 #[cfg(not(any(target_os = "android", target_os = "ios")))]
 async fn start_ipc(
     mut rx_to_cm: mpsc::UnboundedReceiver<ipc::Data>,
@@ -5061,17 +5636,24 @@ fn start_wakelock_thread() -> std::sync::mpsc::Sender<(usize, usize)> {
     tx
 }
 
-#[cfg(all(target_os = "windows", feature = "flutter"))]
-pub fn on_printer_data(data: Vec<u8>) {
-    crate::server::AUTHED_CONNS
+#[cfg(all(any(target_os = "windows", target_os = "linux"), feature = "flutter"))]
+pub fn on_printer_data(data: Vec<u8>, options: crate::print_job::JobOptions) {
+    use crate::print_job;
+    let format = print_job::detect_format(&data);
+    let options = options.with_document(&data, format);
+    // The first controlling side which can print the job.
+    let Some(sender) = crate::server::AUTHED_CONNS
         .lock()
         .unwrap()
         .iter()
-        .filter(|c| c.printer)
+        .filter(|c| c.printer && print_job::is_accepted(format, &c.print_formats))
+        .map(|c| c.sender.clone())
         .next()
-        .map(|c| {
-            c.sender.send(Data::PrinterData(data)).ok();
-        });
+    else {
+        log::warn!("No controlling side can print the {:?} job", format);
+        return;
+    };
+    sender.send(Data::PrinterData(data, options)).ok();
 }
 
 #[cfg(windows)]
@@ -5099,8 +5681,13 @@ impl Drop for Connection {
         #[cfg(not(any(target_os = "android", target_os = "ios")))]
         self.release_pressed_modifiers();
 
-        if let Some(s) = self.terminal_generic_service.as_ref() {
-            s.join();
+        if let Some(_s) = self.terminal_generic_service.as_ref() {
+            #[cfg(not(any(target_os = "android", target_os = "ios")))]
+            terminal_service::detach(&self.terminal_service_id, _s, self.inner.id());
+        }
+        #[cfg(not(any(target_os = "android", target_os = "ios")))]
+        if self.terminal {
+            remote_exec::kill_all(self.inner.id());
         }
 
         #[cfg(target_os = "windows")]
@@ -5265,6 +5852,9 @@ pub struct AuthedConn {
     pub session_key: SessionKey,
     pub sender: mpsc::UnboundedSender<Data>,
     pub printer: bool,
+    // The print job formats the controlling side accepts, in preference order.
+    #[cfg(all(any(target_os = "windows", target_os = "linux"), feature = "flutter"))]
+    pub print_formats: Vec<hbb_common::message_proto::PrintFormat>,
 }
 
 mod raii {
@@ -5308,6 +5898,8 @@ mod raii {
                 session_key,
                 sender,
                 printer,
+                #[cfg(all(any(target_os = "windows", target_os = "linux"), feature = "flutter"))]
+                print_formats: crate::print_job::formats_of(&lr),
             });
             Self::check_wake_lock();
             use std::sync::Once;
@@ -5508,6 +6100,17 @@ mod test {
     #[allow(unused)]
     use super::*;
 
+    #[test]
+    fn test_is_opt_in_enabled() {
+        let option = "allow-remote-exec";
+        assert!(!is_opt_in_enabled("", option, ""));
+        assert!(!is_opt_in_enabled("full", option, ""));
+        assert!(!is_opt_in_enabled("full", option, "N"));
+        assert!(is_opt_in_enabled("full", option, "Y"));
+        assert!(is_opt_in_enabled("custom", option, "Y"));
+        assert!(!is_opt_in_enabled("view", option, "Y"));
+    }
+
     #[cfg(target_os = "macos")]
     #[test]
     fn retina() {
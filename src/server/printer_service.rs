@@ -1,25 +1,32 @@
 use super::service::{EmptyExtraFieldService, GenericService, Service};
-use hbb_common::{bail, dlopen::symbor::Library, log, ResultType};
-use std::{
-    sync::{Arc, Mutex},
-    thread,
-    time::Duration,
-};
+use crate::print_job::JobOptions;
+#[cfg(target_os = "windows")]
+use hbb_common::dlopen::symbor::Library;
+use hbb_common::{bail, log, ResultType};
+#[cfg(target_os = "windows")]
+use std::sync::{Arc, Mutex};
+use std::{thread, time::Duration};
 
 pub const NAME: &'static str = "remote-printer";
 
+#[cfg(target_os = "windows")]
 const LIB_NAME_PRINTER_DRIVER_ADAPTER: &str = "printer_driver_adapter";
 
 // Return 0 if success, otherwise return error code.
+#[cfg(target_os = "windows")]
 pub type Init = fn(tag_name: *const i8) -> i32;
+#[cfg(target_os = "windows")]
 pub type Uninit = fn();
 // dur_mills: Get the file generated in the last `dur_mills` milliseconds.
 // data: The raw prn data, xps format.
 // data_len: The length of the raw prn data.
+#[cfg(target_os = "windows")]
 pub type GetPrnData = fn(dur_mills: u32, data: *mut *mut i8, data_len: *mut u32);
 // Free the prn data allocated by GetPrnData().
+#[cfg(target_os = "windows")]
 pub type FreePrnData = fn(data: *mut i8);
 
+#[cfg(target_os = "windows")]
 macro_rules! make_lib_wrapper {
     ($($field:ident : $tp:ty),+) => {
         struct LibWrapper {
@@ -76,6 +83,7 @@ macro_rules! make_lib_wrapper {
     }
 }
 
+#[cfg(target_os = "windows")]
 make_lib_wrapper!(
     init: Init,
     uninit: Uninit,
@@ -83,10 +91,12 @@ make_lib_wrapper!(
     free_prn_data: FreePrnData
 );
 
+#[cfg(target_os = "windows")]
 lazy_static::lazy_static! {
     static ref LIB_WRAPPER: Arc<Mutex<LibWrapper>> = Default::default();
 }
 
+#[cfg(target_os = "windows")]
 fn get_lib_name() -> ResultType<String> {
     let exe_file = std::env::current_exe()?;
     if let Some(cur_dir) = exe_file.parent() {
@@ -105,6 +115,7 @@ fn get_lib_name() -> ResultType<String> {
     }
 }
 
+#[cfg(target_os = "windows")]
 pub fn init(app_name: &str) -> ResultType<()> {
     let lib_wrapper = LIB_WRAPPER.lock().unwrap();
     let Some(fn_init) = lib_wrapper.init.as_ref() else {
@@ -119,6 +130,7 @@ pub fn init(app_name: &str) -> ResultType<()> {
     Ok(())
 }
 
+#[cfg(target_os = "windows")]
 pub fn uninit() {
     let lib_wrapper = LIB_WRAPPER.lock().unwrap();
     if let Some(fn_uninit) = lib_wrapper.uninit.as_ref() {
@@ -126,24 +138,47 @@ pub fn uninit() {
     }
 }
 
-fn get_prn_data(dur_mills: u32) -> ResultType<Vec<u8>> {
+// The driver doesn't tell the options of a job, they are read from the document.
+#[cfg(target_os = "windows")]
+fn get_prn_data(dur_mills: u32) -> ResultType<(Vec<u8>, JobOptions)> {
     let lib_wrapper = LIB_WRAPPER.lock().unwrap();
     if let Some(fn_get_prn_data) = lib_wrapper.get_prn_data.as_ref() {
         let mut data = std::ptr::null_mut();
         let mut data_len = 0u32;
         fn_get_prn_data(dur_mills, &mut data, &mut data_len);
         if data.is_null() || data_len == 0 {
-            return Ok(Vec::new());
+            return Ok(Default::default());
         }
         let bytes =
             Vec::from(unsafe { std::slice::from_raw_parts(data as *const u8, data_len as usize) });
         lib_wrapper.free_prn_data.map(|f| f(data));
-        Ok(bytes)
+        Ok((bytes, Default::default()))
     } else {
         bail!("Failed to load func get_prn_file");
     }
 }
 
+// Linux: the jobs are left as PDF in the spool directory by the CUPS backend.
+#[cfg(target_os = "linux")]
+pub fn init(_app_name: &str) -> ResultType<()> {
+    if !remote_printer::Spool::default().exists() {
+        bail!("The remote printer is not installed");
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+pub fn uninit() {}
+
+#[cfg(target_os = "linux")]
+fn get_prn_data(_dur_mills: u32) -> ResultType<(Vec<u8>, JobOptions)> {
+    let Some(job) = remote_printer::Spool::default().take_job()? else {
+        return Ok(Default::default());
+    };
+    let options = JobOptions::from_cups(&job.title, &job.copies, &job.options);
+    Ok((job.data, options))
+}
+
 pub fn new(name: String) -> GenericService {
     let svc = EmptyExtraFieldService::new(name, false);
     GenericService::run(&svc.clone(), run);
@@ -151,11 +186,24 @@ pub fn new(name: String) -> GenericService {
 }
 
 fn run(sp: EmptyExtraFieldService) -> ResultType<()> {
+    // A job which can't be taken must not stop the service, the error is logged once.
+    let mut last_err = String::new();
     while sp.ok() {
-        let bytes = get_prn_data(1000)?;
-        if !bytes.is_empty() {
-            log::info!("Got prn data, data len: {}", bytes.len());
-            crate::server::on_printer_data(bytes);
+        match get_prn_data(1000) {
+            Ok((bytes, options)) => {
+                last_err.clear();
+                if !bytes.is_empty() {
+                    log::info!("Got prn data, data len: {}", bytes.len());
+                    crate::server::on_printer_data(bytes, options);
+                }
+            }
+            Err(e) => {
+                let err = e.to_string();
+                if err != last_err {
+                    log::error!("Failed to get prn data: {}", err);
+                    last_err = err;
+                }
+            }
         }
         thread::sleep(Duration::from_millis(300));
     }
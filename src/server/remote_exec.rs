@@ -0,0 +1,242 @@
+//! Non-interactive command execution for terminal connections.
+//!
+//! Unlike a terminal, a command runs without a pty: stdout and stderr are streamed back separately
+//! and the exit status is reported when the process is gone.
+
+use super::terminal_policy::{self, Launch, Policy};
+use hbb_common::{bail, config::Config, log, message_proto::*, ResultType};
+use std::{
+    collections::HashMap,
+    io::{Read, Write},
+    process::{Child, Command, Stdio},
+    sync::{mpsc, Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Permission of running commands in terminal connections, off by default.
+pub const OPTION_ALLOW_REMOTE_EXEC: &str = "allow-remote-exec";
+
+const MAX_RUNNING: usize = 16; // Per connection
+const READ_BUFFER_SIZE: usize = 16 * 1024;
+const WAIT_INTERVAL: Duration = Duration::from_millis(50);
+// Background processes of the command may keep its output open after it exits.
+const OUTPUT_GRACE: Duration = Duration::from_secs(1);
+
+lazy_static::lazy_static! {
+    // Running commands indexed by (conn_id, exec_id)
+    static ref RUNNING: Arc<Mutex<HashMap<(i32, i32), Arc<Mutex<Child>>>>> = Default::default();
+}
+
+/// Start `exec` of `who` in the background, its output and result are passed to `send`.
+/// The command is checked against the terminal policy and audited as the terminal input of `service_id`.
+pub fn start<F>(conn_id: i32, service_id: &str, who: &str, exec: ExecCommand, send: F)
+where
+    F: Fn(TerminalResponse) + Clone + Send + 'static,
+{
+    let exec_id = exec.exec_id;
+    let policy = Policy::load();
+    let launch = match policy.resolve_exec(&exec.argv, &exec.cwd) {
+        Ok(launch) => launch,
+        Err(e) => {
+            log::warn!("Command {} is refused: {}", exec_id, e);
+            send(error_response(exec_id, e));
+            return;
+        }
+    };
+    if policy.audit_input {
+        terminal_policy::write_audit(
+            service_id,
+            &format!("exec {}", exec_id),
+            who,
+            &[exec.argv.join(" ")],
+        );
+    }
+    let child = match spawn(conn_id, &exec, &launch) {
+        Ok(child) => child,
+        Err(e) => {
+            log::error!("Failed to run command {}: {}", exec_id, e);
+            send(result_response(exec_id, -1, false, e.to_string()));
+            return;
+        }
+    };
+    let (stdin, stdout, stderr) = {
+        let mut child = child.lock().unwrap();
+        (child.stdin.take(), child.stdout.take(), child.stderr.take())
+    };
+    let input = exec.stdin.to_vec();
+    if let Some(mut stdin) = stdin {
+        thread::spawn(move || {
+            if !input.is_empty() {
+                stdin.write_all(&input).ok();
+            }
+            // Dropping stdin closes it, the command sees the end of its input.
+        });
+    }
+    let (done_tx, done_rx) = mpsc::channel::<()>();
+    let mut readers = 0;
+    for (is_stderr, reader) in [
+        (false, stdout.map(|r| Box::new(r) as Box<dyn Read + Send>)),
+        (true, stderr.map(|r| Box::new(r) as Box<dyn Read + Send>)),
+    ] {
+        if let Some(reader) = reader {
+            let send = send.clone();
+            let done_tx = done_tx.clone();
+            thread::spawn(move || {
+                read_output(exec_id, is_stderr, reader, send);
+                done_tx.send(()).ok();
+            });
+            readers += 1;
+        }
+    }
+    let timeout = (exec.timeout_ms > 0).then(|| Duration::from_millis(exec.timeout_ms as _));
+    thread::spawn(move || {
+        let (exit_code, timed_out, error) = wait(&child, timeout);
+        // All output is sent before the result.
+        for _ in 0..readers {
+            if done_rx.recv_timeout(OUTPUT_GRACE).is_err() {
+                break;
+            }
+        }
+        RUNNING.lock().unwrap().remove(&(conn_id, exec_id));
+        log::info!(
+            "Command {} of connection {} exited with {}{}",
+            exec_id,
+            conn_id,
+            exit_code,
+            if timed_out { " (timed out)" } else { "" }
+        );
+        send(result_response(exec_id, exit_code, timed_out, error));
+    });
+}
+
+/// Kill the commands still running for a connection.
+pub fn kill_all(conn_id: i32) {
+    let children: Vec<_> = RUNNING
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|((id, _), _)| *id == conn_id)
+        .map(|(_, child)| child.clone())
+        .collect();
+    for child in children {
+        child.lock().unwrap().kill().ok();
+    }
+}
+
+fn spawn(conn_id: i32, exec: &ExecCommand, launch: &Launch) -> ResultType<Arc<Mutex<Child>>> {
+    let mut running = RUNNING.lock().unwrap();
+    if running.contains_key(&(conn_id, exec.exec_id)) {
+        bail!("Command {} is already running", exec.exec_id);
+    }
+    if running.keys().filter(|(id, _)| *id == conn_id).count() >= MAX_RUNNING {
+        bail!("Too many running commands, at most {}", MAX_RUNNING);
+    }
+    let mut cmd = Command::new(&launch.shell);
+    // The environment of the policy wins over the one asked for.
+    cmd.args(&launch.args)
+        .envs(exec.env.iter())
+        .envs(launch.env.iter().map(|(k, v)| (k, v)))
+        .current_dir(if launch.cwd.is_empty() {
+            Config::get_home()
+        } else {
+            launch.cwd.clone().into()
+        })
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    #[cfg(windows)]
+    {
+        use std::os::windows::process::CommandExt;
+        const CREATE_NO_WINDOW: u32 = 0x08000000;
+        cmd.creation_flags(CREATE_NO_WINDOW);
+    }
+    let child = Arc::new(Mutex::new(cmd.spawn()?));
+    running.insert((conn_id, exec.exec_id), child.clone());
+    Ok(child)
+}
+
+fn read_output<F>(exec_id: i32, is_stderr: bool, mut reader: Box<dyn Read + Send>, send: F)
+where
+    F: Fn(TerminalResponse),
+{
+    let mut buf = vec![0u8; READ_BUFFER_SIZE];
+    loop {
+        match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => {
+                let mut output = ExecOutput::new();
+                output.exec_id = exec_id;
+                let data = bytes::Bytes::from(buf[..n].to_vec());
+                if is_stderr {
+                    output.stderr = data;
+                } else {
+                    output.stdout = data;
+                }
+                let mut response = TerminalResponse::new();
+                response.set_exec_output(output);
+                send(response);
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => {
+                log::debug!("Command {} output read error: {}", exec_id, e);
+                break;
+            }
+        }
+    }
+}
+
+/// Wait for the child to exit, killing it after `timeout`.
+/// Returns the exit code, whether it timed out and an error message.
+fn wait(child: &Mutex<Child>, timeout: Option<Duration>) -> (i32, bool, String) {
+    let start = Instant::now();
+    let mut timed_out = false;
+    loop {
+        let status = child.lock().unwrap().try_wait();
+        match status {
+            Ok(Some(status)) => return (exit_code(&status), timed_out, "".to_owned()),
+            Ok(None) => {}
+            Err(e) => return (-1, timed_out, e.to_string()),
+        }
+        if !timed_out && timeout.is_some_and(|t| start.elapsed() > t) {
+            timed_out = true;
+            child.lock().unwrap().kill().ok();
+        }
+        thread::sleep(WAIT_INTERVAL);
+    }
+}
+
+fn exit_code(status: &std::process::ExitStatus) -> i32 {
+    if let Some(code) = status.code() {
+        return code;
+    }
+    // Killed by a signal, report it like a shell does.
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        if let Some(signal) = status.signal() {
+            return 128 + signal;
+        }
+    }
+    -1
+}
+
+pub fn error_response(exec_id: i32, error: String) -> TerminalResponse {
+    result_response(exec_id, -1, false, error)
+}
+
+fn result_response(
+    exec_id: i32,
+    exit_code: i32,
+    timed_out: bool,
+    error: String,
+) -> TerminalResponse {
+    let mut result = ExecResult::new();
+    result.exec_id = exec_id;
+    result.exit_code = exit_code;
+    result.timed_out = timed_out;
+    result.error = error;
+    let mut response = TerminalResponse::new();
+    response.set_exec_result(result);
+    response
+}
@@ -20,6 +20,7 @@
 //! - User token and SID handling
 //! - Helper process launching
 
+use super::terminal_policy::Launch;
 use hbb_common::{
     anyhow::{anyhow, Context, Result},
     log,
@@ -678,6 +679,7 @@ pub fn launch_terminal_helper_with_token(
     terminal_id: i32,
     rows: u16,
     cols: u16,
+    launch: &Launch,
 ) -> Result<HelperProcessInfo> {
     let exe_path =
         std::env::current_exe().map_err(|e| anyhow!("Failed to get current exe path: {}", e))?;
@@ -685,8 +687,13 @@ pub fn launch_terminal_helper_with_token(
     // Build command line arguments (without exe path to avoid escaping issues)
     // lpApplicationName will contain the exe path separately
     let cmd_args = format!(
-        "--terminal-helper {} {} {} {} {}",
-        input_pipe_name, output_pipe_name, rows, cols, terminal_id
+        "--terminal-helper {} {} {} {} {} {}",
+        input_pipe_name,
+        output_pipe_name,
+        rows,
+        cols,
+        terminal_id,
+        launch.encode()
     );
 
     log::debug!("Launching terminal helper for terminal {}", terminal_id);
@@ -786,7 +793,8 @@ pub fn is_helper_process_running(handle: HANDLE) -> bool {
 }
 
 /// Run terminal helper process
-/// Args: --terminal-helper <input_pipe_name> <output_pipe_name> <rows> <cols> <terminal_id>
+/// Args: --terminal-helper <input_pipe_name> <output_pipe_name> <rows> <cols> <terminal_id> [launch]
+/// `launch` is the encoded [`Launch`], the default shell is started without it.
 pub fn run_terminal_helper(args: &[String]) -> Result<()> {
     if args.len() < 5 {
         return Err(anyhow!(
@@ -828,10 +836,23 @@ pub fn run_terminal_helper(args: &[String]) -> Result<()> {
     let pty_system = portable_pty::native_pty_system();
     let pty_pair = pty_system.openpty(pty_size).context("Failed to open PTY")?;
 
-    let shell = get_default_shell();
-    log::debug!("Using shell: {}", shell);
-
-    let cmd = CommandBuilder::new(&shell);
+    let launch = args
+        .get(5)
+        .and_then(|s| Launch::decode(s))
+        .unwrap_or_else(|| Launch {
+            shell: get_default_shell(),
+            ..Default::default()
+        });
+    log::debug!("Using shell: {}", launch.shell);
+
+    let mut cmd = CommandBuilder::new(&launch.shell);
+    cmd.args(&launch.args);
+    if !launch.cwd.is_empty() {
+        cmd.cwd(&launch.cwd);
+    }
+    for (key, value) in &launch.env {
+        cmd.env(key, value);
+    }
     let mut child = pty_pair
         .slave
         .spawn_command(cmd)
@@ -0,0 +1,362 @@
+//! What the controlled side lets a terminal launch, set by the owner with server options.
+//!
+//! Lists are separated by new lines or `;`. Without any option the default shell is started
+//! in the home directory, as before.
+//!
+//! Commands run without a terminal follow the same policy, see [`Policy::resolve_exec`].
+
+use hbb_common::{
+    chrono,
+    config::{self, Config},
+    log,
+};
+use serde_derive::{Deserialize, Serialize};
+use std::{
+    fs::OpenOptions,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+/// Shells a client can choose, the first one is the default. The platform default if empty.
+pub const OPTION_TERMINAL_ALLOWED_SHELLS: &str = "terminal-allowed-shells";
+/// Directories a terminal can start in, the first one is the default. Only home if empty.
+pub const OPTION_TERMINAL_ALLOWED_DIRS: &str = "terminal-allowed-dirs";
+/// `KEY=VALUE` pairs set in every terminal, overriding the inherited environment.
+pub const OPTION_TERMINAL_ENV: &str = "terminal-env";
+/// Start shells as login shells, on by default on macOS only. Not used on Windows.
+pub const OPTION_TERMINAL_LOGIN_SHELL: &str = "terminal-login-shell";
+/// Write every line typed into a terminal, and every command run without one,
+/// to `terminal_audit.log` in the log directory.
+/// Everything typed is kept, including passwords entered at prompts.
+pub const OPTION_TERMINAL_AUDIT_INPUT: &str = "terminal-audit-input";
+
+const AUDIT_LOG_FILE: &str = "terminal_audit.log";
+const MAX_AUDIT_LINE: usize = 4096;
+
+/// The resolved command line of a terminal.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Launch {
+    pub shell: String,
+    pub args: Vec<String>,
+    // Empty for the home directory
+    pub cwd: String,
+    pub env: Vec<(String, String)>,
+}
+
+impl Launch {
+    /// Encoded as one command line argument of the terminal helper.
+    #[cfg(target_os = "windows")]
+    pub fn encode(&self) -> String {
+        crate::encode64(serde_json::to_string(self).unwrap_or_default())
+    }
+
+    #[cfg(target_os = "windows")]
+    pub fn decode(s: &str) -> Option<Self> {
+        let json = crate::decode64(s).ok()?;
+        serde_json::from_slice(&json).ok()
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Policy {
+    shells: Vec<String>,
+    dirs: Vec<String>,
+    env: Vec<(String, String)>,
+    login_shell: bool,
+    pub audit_input: bool,
+}
+
+impl Policy {
+    pub fn load() -> Self {
+        let login_shell = Config::get_option(OPTION_TERMINAL_LOGIN_SHELL);
+        Self {
+            shells: split_list(&Config::get_option(OPTION_TERMINAL_ALLOWED_SHELLS)),
+            dirs: split_list(&Config::get_option(OPTION_TERMINAL_ALLOWED_DIRS)),
+            env: split_list(&Config::get_option(OPTION_TERMINAL_ENV))
+                .iter()
+                .filter_map(|kv| kv.split_once('='))
+                .map(|(k, v)| (k.trim().to_owned(), v.to_owned()))
+                .collect(),
+            login_shell: if login_shell.is_empty() {
+                cfg!(target_os = "macos")
+            } else {
+                config::option2bool(OPTION_TERMINAL_LOGIN_SHELL, &login_shell)
+            },
+            audit_input: config::option2bool(
+                OPTION_TERMINAL_AUDIT_INPUT,
+                &Config::get_option(OPTION_TERMINAL_AUDIT_INPUT),
+            ),
+        }
+    }
+
+    /// The shells a client can choose, the default one first.
+    pub fn shells(&self, default_shell: &str) -> Vec<String> {
+        if self.shells.is_empty() {
+            vec![default_shell.to_owned()]
+        } else {
+            self.shells.clone()
+        }
+    }
+
+    /// Check the shell and the start directory asked by a client, empty for the defaults.
+    pub fn resolve(&self, shell: &str, cwd: &str, default_shell: &str) -> Result<Launch, String> {
+        let shells = self.shells(default_shell);
+        let shell = if shell.is_empty() {
+            shells[0].clone()
+        } else {
+            find_shell(&shells, shell).ok_or_else(|| {
+                format!(
+                    "Shell {} is not allowed, choose one of: {}",
+                    shell,
+                    shells.join(", ")
+                )
+            })?
+        };
+        let mut args = Vec::new();
+        if self.login_shell && !cfg!(target_os = "windows") {
+            args.push("-l".to_owned());
+        }
+        Ok(Launch {
+            shell,
+            args,
+            cwd: self.resolve_cwd(cwd)?,
+            env: self.env.clone(),
+        })
+    }
+
+    /// Check a command run without a terminal. If the shells are restricted, its program must be
+    /// one of them. The start directory is checked as for a terminal.
+    pub fn resolve_exec(&self, argv: &[String], cwd: &str) -> Result<Launch, String> {
+        let Some(program) = argv.first() else {
+            return Err("Empty command".to_owned());
+        };
+        let program = if self.shells.is_empty() {
+            program.clone()
+        } else {
+            find_shell(&self.shells, program).ok_or_else(|| {
+                format!(
+                    "Command {} is not allowed, choose one of: {}",
+                    program,
+                    self.shells.join(", ")
+                )
+            })?
+        };
+        Ok(Launch {
+            shell: program,
+            args: argv[1..].to_vec(),
+            cwd: self.resolve_cwd(cwd)?,
+            env: self.env.clone(),
+        })
+    }
+
+    fn resolve_cwd(&self, cwd: &str) -> Result<String, String> {
+        if cwd.is_empty() {
+            Ok(self.dirs.first().cloned().unwrap_or_default())
+        } else if self.dirs.iter().any(|dir| is_within(dir, cwd)) {
+            Ok(cwd.to_owned())
+        } else {
+            Err(format!("Start directory {} is not allowed", cwd))
+        }
+    }
+}
+
+// A shell can be given by its file name, e.g. "zsh" for "/bin/zsh".
+fn find_shell(shells: &[String], shell: &str) -> Option<String> {
+    shells
+        .iter()
+        .find(|s| same_path(s, shell) || file_name(s).is_some_and(|n| same_path(&n, shell)))
+        .cloned()
+}
+
+fn split_list(s: &str) -> Vec<String> {
+    s.split(|c| c == '\n' || c == ';')
+        .map(|s| s.trim())
+        .filter(|s| !s.is_empty())
+        .map(|s| s.to_owned())
+        .collect()
+}
+
+fn file_name(path: &str) -> Option<String> {
+    Path::new(path)
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+}
+
+fn same_path(a: &str, b: &str) -> bool {
+    if cfg!(target_os = "windows") {
+        a.eq_ignore_ascii_case(b)
+    } else {
+        a == b
+    }
+}
+
+/// Whether `path` is `dir` or inside it, after resolving `..` and links.
+fn is_within(dir: &str, path: &str) -> bool {
+    match (
+        std::fs::canonicalize(dir),
+        std::fs::canonicalize(PathBuf::from(path)),
+    ) {
+        (Ok(dir), Ok(path)) => path.starts_with(dir),
+        _ => false,
+    }
+}
+
+/// Collects the input of a terminal into the lines entered, roughly as a line editor would.
+#[derive(Default)]
+pub struct InputAudit {
+    line: Vec<u8>,
+    escape: Escape,
+}
+
+#[derive(Default, PartialEq)]
+enum Escape {
+    #[default]
+    None,
+    Start,
+    // Control sequence, ended by a byte in 0x40..=0x7e
+    Csi,
+    // Single character after ESC O, e.g. the keypad
+    Ss3,
+}
+
+impl InputAudit {
+    /// Feed input, returns the lines completed by it.
+    pub fn push(&mut self, data: &[u8]) -> Vec<String> {
+        let mut lines = Vec::new();
+        for &b in data {
+            match self.escape {
+                Escape::Start => {
+                    self.escape = match b {
+                        b'[' => Escape::Csi,
+                        b'O' => Escape::Ss3,
+                        _ => Escape::None,
+                    };
+                    continue;
+                }
+                Escape::Csi => {
+                    if (0x40..=0x7e).contains(&b) {
+                        self.escape = Escape::None;
+                    }
+                    continue;
+                }
+                Escape::Ss3 => {
+                    self.escape = Escape::None;
+                    continue;
+                }
+                Escape::None => {}
+            }
+            match b {
+                0x1b => self.escape = Escape::Start,
+                b'\r' | b'\n' => {
+                    if !self.line.is_empty() {
+                        lines.push(String::from_utf8_lossy(&self.line).to_string());
+                        self.line.clear();
+                    }
+                }
+                // Backspace, remove a whole utf-8 character
+                0x7f | 0x08 => {
+                    while let Some(b) = self.line.pop() {
+                        if b & 0xc0 != 0x80 {
+                            break;
+                        }
+                    }
+                }
+                // Ctrl+C and Ctrl+U drop the line
+                0x03 | 0x15 => self.line.clear(),
+                b'\t' => self.line.push(b),
+                b if b < 0x20 => {}
+                b => {
+                    if self.line.len() < MAX_AUDIT_LINE {
+                        self.line.push(b);
+                    }
+                }
+            }
+        }
+        lines
+    }
+}
+
+/// Append the lines entered by `who` to the audit log, `source` is e.g. `terminal 1` or `exec 2`.
+pub fn write_audit(service_id: &str, source: &str, who: &str, lines: &[String]) {
+    let path = Config::log_path().join(AUDIT_LOG_FILE);
+    let mut file = match OpenOptions::new().create(true).append(true).open(&path) {
+        Ok(file) => file,
+        Err(e) => {
+            log::error!("Failed to open terminal audit log {:?}: {}", path, e);
+            return;
+        }
+    };
+    let time = chrono::Local::now().format("%Y-%m-%d %H:%M:%S");
+    for line in lines {
+        let record = format!("{} {} {} {}: {}\n", time, service_id, source, who, line);
+        if let Err(e) = file.write_all(record.as_bytes()) {
+            log::error!("Failed to write terminal audit log: {}", e);
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve() {
+        let policy = Policy {
+            shells: split_list("/bin/bash; /usr/bin/fish"),
+            ..Default::default()
+        };
+        assert_eq!(
+            policy.resolve("", "", "/bin/zsh").unwrap().shell,
+            "/bin/bash"
+        );
+        assert_eq!(
+            policy.resolve("fish", "", "/bin/zsh").unwrap().shell,
+            "/usr/bin/fish"
+        );
+        assert!(policy.resolve("/bin/zsh", "", "/bin/zsh").is_err());
+        assert!(policy.resolve("", "/", "/bin/zsh").is_err());
+        let policy = Policy::default();
+        assert_eq!(
+            policy.resolve("", "", "/bin/zsh").unwrap().shell,
+            "/bin/zsh"
+        );
+    }
+
+    #[test]
+    fn test_resolve_exec() {
+        let argv = |s: &str| s.split(' ').map(|s| s.to_owned()).collect::<Vec<_>>();
+        let policy = Policy::default();
+        let launch = policy.resolve_exec(&argv("ls -a"), "").unwrap();
+        assert_eq!(
+            (launch.shell.as_str(), launch.args),
+            ("ls", vec!["-a".to_owned()])
+        );
+        assert!(policy.resolve_exec(&[], "").is_err());
+        // Only home without allowed directories
+        assert!(policy.resolve_exec(&argv("ls"), "/").is_err());
+        let policy = Policy {
+            shells: split_list("/bin/bash"),
+            ..Default::default()
+        };
+        assert!(policy.resolve_exec(&argv("rm -rf x"), "").is_err());
+        assert!(policy
+            .resolve_exec(&argv("/usr/bin/bash -c id"), "")
+            .is_err());
+        assert_eq!(
+            policy.resolve_exec(&argv("bash -c id"), "").unwrap().shell,
+            "/bin/bash"
+        );
+    }
+
+    #[test]
+    fn test_input_audit() {
+        let mut audit = InputAudit::default();
+        assert!(audit.push(b"ls -").is_empty());
+        assert_eq!(audit.push(b"l\x7fa\x1b[D\r"), vec!["ls -a"]);
+        assert_eq!(
+            audit.push(b"rm x\x03echo \xe4\xb8\xad\x7fok\n\r"),
+            vec!["echo ok"]
+        );
+    }
+}
@@ -0,0 +1,285 @@
+//! Recordings of terminal sessions in [asciicast v2](https://docs.asciinema.org/manual/asciicast/v2/) format.
+//!
+//! Every terminal of an incoming session gets its own `.cast` file next to the screen recordings.
+//! The file is a JSON header line followed by one `[time, code, data]` line per event,
+//! `o` for output, `i` for input and `r` for resize.
+
+use crate::hbbs_http::record_upload;
+use hbb_common::{
+    chrono,
+    config::{self, Config},
+    log, ResultType,
+};
+use scrap::record::RecordState;
+use std::{
+    fs::{File, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::mpsc::Sender,
+    time::{Duration, Instant, SystemTime},
+};
+
+pub const OPTION_RECORD_TERMINAL: &str = "allow-record-terminal";
+pub const OPTION_RECORD_TERMINAL_INPUT: &str = "allow-record-terminal-input";
+/// Recordings older than this many days are removed, 0 keeps them forever.
+pub const OPTION_TERMINAL_RECORD_KEEP_DAYS: &str = "terminal-record-keep-days";
+/// The oldest recordings are removed while all of them take more than this many MB, 0 for no limit.
+pub const OPTION_TERMINAL_RECORD_MAX_SIZE: &str = "terminal-record-max-size";
+
+const DEFAULT_KEEP_DAYS: u64 = 30;
+const DEFAULT_MAX_SIZE_MB: u64 = 1024;
+const FILE_PREFIX: &str = "incoming_";
+const FILE_EXT: &str = "cast";
+
+pub struct TerminalRecorder {
+    file: File,
+    filename: String,
+    start: Instant,
+    record_input: bool,
+    // Trailing bytes of an utf-8 sequence split across two reads.
+    pending: Vec<u8>,
+    written: bool,
+    tx: Option<Sender<RecordState>>,
+}
+
+impl TerminalRecorder {
+    /// Starts a recording if terminal recording is enabled, errors are logged and skip the recording.
+    pub fn start(terminal_id: i32, rows: u16, cols: u16, shell: &str) -> Option<Self> {
+        if !config::option2bool(
+            OPTION_RECORD_TERMINAL,
+            &Config::get_option(OPTION_RECORD_TERMINAL),
+        ) {
+            return None;
+        }
+        match Self::new(terminal_id, rows, cols, shell) {
+            Ok(r) => {
+                log::info!("Terminal {} is recorded to {}", terminal_id, r.filename);
+                Some(r)
+            }
+            Err(e) => {
+                log::error!("Failed to record terminal {}: {}", terminal_id, e);
+                None
+            }
+        }
+    }
+
+    fn new(terminal_id: i32, rows: u16, cols: u16, shell: &str) -> ResultType<Self> {
+        #[cfg(windows)]
+        let root = crate::platform::is_root();
+        #[cfg(not(windows))]
+        let root = false;
+        let dir = crate::ui_interface::video_save_directory(root);
+        if !Path::new(&dir).exists() {
+            std::fs::create_dir_all(&dir)?;
+        }
+        remove_expired(&dir);
+        let filename = PathBuf::from(&dir)
+            .join(format!(
+                "{}{}{}terminal{}.{}",
+                FILE_PREFIX,
+                Config::get_id(),
+                chrono::Local::now().format("_%Y%m%d%H%M%S%3f_"),
+                terminal_id,
+                FILE_EXT
+            ))
+            .to_string_lossy()
+            .to_string();
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&filename)?;
+        let timestamp = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        let header = serde_json::json!({
+            "version": 2,
+            "width": cols,
+            "height": rows,
+            "timestamp": timestamp,
+            "title": format!("Terminal {}", terminal_id),
+            "env": { "SHELL": shell },
+        });
+        file.write_all(format!("{}\n", header).as_bytes())?;
+        let tx = if record_upload::is_enable() {
+            let (tx, rx) = std::sync::mpsc::channel();
+            record_upload::run(rx);
+            tx.send(RecordState::NewFile(filename.clone())).ok();
+            Some(tx)
+        } else {
+            None
+        };
+        Ok(Self {
+            file,
+            filename,
+            start: Instant::now(),
+            record_input: config::option2bool(
+                OPTION_RECORD_TERMINAL_INPUT,
+                &Config::get_option(OPTION_RECORD_TERMINAL_INPUT),
+            ),
+            pending: Vec::new(),
+            written: false,
+            tx,
+        })
+    }
+
+    pub fn write_output(&mut self, data: &[u8]) {
+        let mut buf = std::mem::take(&mut self.pending);
+        buf.extend_from_slice(data);
+        let text = split_utf8(&mut buf);
+        self.pending = buf;
+        if !text.is_empty() {
+            self.write_event("o", &text);
+        }
+    }
+
+    pub fn write_input(&mut self, data: &[u8]) {
+        if self.record_input && !data.is_empty() {
+            self.write_event("i", &String::from_utf8_lossy(data));
+        }
+    }
+
+    pub fn write_resize(&mut self, rows: u16, cols: u16) {
+        self.write_event("r", &format!("{}x{}", cols, rows));
+    }
+
+    fn write_event(&mut self, code: &str, data: &str) {
+        let time = self.start.elapsed().as_micros() as f64 / 1_000_000.0;
+        let line = format!("{}\n", serde_json::json!([time, code, data]));
+        if let Err(e) = self.file.write_all(line.as_bytes()) {
+            log::error!(
+                "Failed to write terminal recording {}: {}",
+                self.filename,
+                e
+            );
+            return;
+        }
+        self.written = true;
+        self.send_state(RecordState::NewFrame);
+    }
+
+    fn send_state(&self, state: RecordState) {
+        self.tx.as_ref().map(|tx| tx.send(state));
+    }
+}
+
+impl Drop for TerminalRecorder {
+    fn drop(&mut self) {
+        if !self.pending.is_empty() {
+            let rest = String::from_utf8_lossy(&self.pending).to_string();
+            self.pending.clear();
+            self.write_event("o", &rest);
+        }
+        let state = if self.written {
+            RecordState::WriteTail
+        } else {
+            std::fs::remove_file(&self.filename).ok();
+            RecordState::RemoveFile
+        };
+        self.send_state(state);
+    }
+}
+
+/// Takes the longest valid utf-8 prefix out of `buf`, leaving an incomplete trailing sequence.
+/// Invalid bytes are replaced with U+FFFD.
+fn split_utf8(buf: &mut Vec<u8>) -> String {
+    let mut text = String::new();
+    let mut rest: &[u8] = buf;
+    loop {
+        match std::str::from_utf8(rest) {
+            Ok(s) => {
+                text.push_str(s);
+                rest = &[];
+                break;
+            }
+            Err(e) => {
+                let (valid, after) = rest.split_at(e.valid_up_to());
+                text.push_str(&String::from_utf8_lossy(valid));
+                match e.error_len() {
+                    Some(n) => {
+                        text.push(char::REPLACEMENT_CHARACTER);
+                        rest = &after[n..];
+                    }
+                    None => {
+                        rest = after;
+                        break;
+                    }
+                }
+            }
+        }
+    }
+    *buf = rest.to_vec();
+    text
+}
+
+fn option_u64(key: &str, default: u64) -> u64 {
+    let v = Config::get_option(key);
+    if v.is_empty() {
+        default
+    } else {
+        v.parse().unwrap_or(default)
+    }
+}
+
+/// Applies the retention limits to the terminal recordings in `dir`.
+/// Screen recordings in the same directory are left alone.
+fn remove_expired(dir: &str) {
+    let keep_days = option_u64(OPTION_TERMINAL_RECORD_KEEP_DAYS, DEFAULT_KEEP_DAYS);
+    let max_size = option_u64(OPTION_TERMINAL_RECORD_MAX_SIZE, DEFAULT_MAX_SIZE_MB) * 1024 * 1024;
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    let mut files: Vec<(PathBuf, SystemTime, u64)> = entries
+        .flatten()
+        .filter(|e| {
+            let name = e.file_name().to_string_lossy().to_string();
+            name.starts_with(FILE_PREFIX) && name.ends_with(&format!(".{}", FILE_EXT))
+        })
+        .filter_map(|e| {
+            let meta = e.metadata().ok()?;
+            if !meta.is_file() {
+                return None;
+            }
+            Some((e.path(), meta.modified().ok()?, meta.len()))
+        })
+        .collect();
+    // Newest first
+    files.sort_by(|a, b| b.1.cmp(&a.1));
+    let now = SystemTime::now();
+    let max_age = Duration::from_secs(keep_days * 24 * 3600);
+    let mut total = 0;
+    for (path, modified, len) in files {
+        total += len;
+        let expired = keep_days > 0
+            && now
+                .duration_since(modified)
+                .map(|age| age > max_age)
+                .unwrap_or(false);
+        if expired || (max_size > 0 && total > max_size) {
+            match std::fs::remove_file(&path) {
+                Ok(_) => log::info!("Removed terminal recording {:?}", path),
+                Err(e) => log::warn!("Failed to remove terminal recording {:?}: {}", path, e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_utf8() {
+        let s = "a中b".as_bytes();
+        let mut buf = s[..2].to_vec();
+        assert_eq!(split_utf8(&mut buf), "a");
+        assert_eq!(buf, &s[1..2]);
+        buf.extend_from_slice(&s[2..]);
+        assert_eq!(split_utf8(&mut buf), "中b");
+        assert!(buf.is_empty());
+        let mut buf = vec![b'x', 0xff, b'y'];
+        assert_eq!(split_utf8(&mut buf), "x\u{fffd}y");
+        assert!(buf.is_empty());
+    }
+}
@@ -0,0 +1,475 @@
+//! Scrollback of persistent terminal services kept on disk, so it survives restarts of the service.
+//!
+//! The output of a terminal is split into lines numbered from 0 for the whole life of the terminal.
+//! Lines are appended in compressed blocks to segment files
+//! `terminal_scrollback/<service id>/<terminal id>/<first line>.seg` in the config directory,
+//! the oldest segments are removed when a terminal has more than [`MAX_SEGMENTS`].
+//!
+//! A block is `[line count: u32][compressed size: u32][data]` in little endian,
+//! the uncompressed data is every line prefixed with its length as u32.
+
+use hbb_common::{bail, compress, config::Config, log, ResultType};
+use std::{
+    fs::{self, File, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime},
+};
+
+const DIR: &str = "terminal_scrollback";
+const SEGMENT_EXT: &str = "seg";
+const SEGMENT_SIZE: u64 = 1024 * 1024;
+const MAX_SEGMENTS: usize = 32;
+const BLOCK_HEADER_SIZE: usize = 8;
+// Lines are buffered up to this size or interval before a block is written.
+const BLOCK_SIZE: usize = 64 * 1024;
+const FLUSH_INTERVAL: Duration = Duration::from_secs(5);
+// Longer lines, e.g. progress bars redrawn with `\r`, are split.
+const MAX_LINE: usize = 16 * 1024;
+// Scrollback of services not resumed for this long is removed.
+const KEEP_DAYS: u64 = 7;
+// Limits of one fetch or search response
+const MAX_LINES: usize = 1000;
+const MAX_BYTES: usize = 512 * 1024;
+
+pub struct Scrollback {
+    dir: PathBuf,
+    // The segment being appended to and its size
+    segment: Option<(File, u64)>,
+    // Lines written to the segments
+    stored: u64,
+    pending: Vec<Vec<u8>>,
+    pending_size: usize,
+    // The last line until its newline is received
+    partial: Vec<u8>,
+    last_flush: Instant,
+}
+
+impl Scrollback {
+    /// Open the scrollback of a terminal, continuing the one left by a previous service process.
+    /// Errors are logged and skip the scrollback.
+    pub fn open(service_id: &str, terminal_id: i32) -> Option<Self> {
+        match Self::new(service_id, terminal_id) {
+            Ok(s) => Some(s),
+            Err(e) => {
+                log::error!(
+                    "Failed to open the scrollback of terminal {} of {}: {}",
+                    terminal_id,
+                    service_id,
+                    e
+                );
+                None
+            }
+        }
+    }
+
+    fn new(service_id: &str, terminal_id: i32) -> ResultType<Self> {
+        if !is_valid_name(service_id) {
+            bail!("Invalid service id");
+        }
+        remove_expired(service_id);
+        let dir = root().join(service_id).join(terminal_id.to_string());
+        create_dir(&dir)?;
+        let mut stored = 0;
+        let mut segment = None;
+        if let Some((first, path)) = segments(&dir).pop() {
+            let data = fs::read(&path)?;
+            let (blocks, valid_len) = parse_blocks(&data);
+            stored = first + blocks.iter().map(|(count, _)| *count as u64).sum::<u64>();
+            let file = OpenOptions::new().append(true).open(&path)?;
+            if valid_len < data.len() {
+                // The last block was cut by a crash.
+                file.set_len(valid_len as _)?;
+            }
+            if (valid_len as u64) < SEGMENT_SIZE {
+                segment = Some((file, valid_len as u64));
+            }
+            log::info!(
+                "Resumed the scrollback of terminal {} of {} at line {}",
+                terminal_id,
+                service_id,
+                stored
+            );
+        }
+        Ok(Self {
+            dir,
+            segment,
+            stored,
+            pending: Vec::new(),
+            pending_size: 0,
+            partial: Vec::new(),
+            last_flush: Instant::now(),
+        })
+    }
+
+    pub fn append(&mut self, data: &[u8]) {
+        for &b in data {
+            self.partial.push(b);
+            if b == b'\n' || self.partial.len() >= MAX_LINE {
+                let line = std::mem::take(&mut self.partial);
+                self.pending_size += line.len();
+                self.pending.push(line);
+            }
+        }
+        if self.pending_size >= BLOCK_SIZE {
+            self.flush();
+        }
+    }
+
+    /// Write the buffered lines if they are waiting for too long.
+    pub fn tick(&mut self) {
+        if !self.pending.is_empty() && self.last_flush.elapsed() > FLUSH_INTERVAL {
+            self.flush();
+        }
+    }
+
+    fn flush(&mut self) {
+        self.last_flush = Instant::now();
+        if self.pending.is_empty() {
+            return;
+        }
+        let lines = std::mem::take(&mut self.pending);
+        self.pending_size = 0;
+        if let Err(e) = self.write_block(&lines) {
+            log::error!("Failed to write scrollback {:?}: {}", self.dir, e);
+            // Drop the segment, the next block starts a new one.
+            self.segment = None;
+        }
+        // Lines are numbered even if they are lost, so the numbers stay stable.
+        self.stored += lines.len() as u64;
+    }
+
+    fn write_block(&mut self, lines: &[Vec<u8>]) -> ResultType<()> {
+        if self
+            .segment
+            .as_ref()
+            .map_or(true, |(_, size)| *size >= SEGMENT_SIZE)
+        {
+            let path = self
+                .dir
+                .join(format!("{:020}.{}", self.stored, SEGMENT_EXT));
+            let file = OpenOptions::new().create(true).append(true).open(&path)?;
+            let size = file.metadata()?.len();
+            self.segment = Some((file, size));
+            remove_old_segments(&self.dir);
+        }
+        let mut data = Vec::with_capacity(lines.iter().map(|l| l.len() + 4).sum());
+        for line in lines {
+            data.extend_from_slice(&(line.len() as u32).to_le_bytes());
+            data.extend_from_slice(line);
+        }
+        let compressed = compress::compress(&data);
+        let mut block = Vec::with_capacity(BLOCK_HEADER_SIZE + compressed.len());
+        block.extend_from_slice(&(lines.len() as u32).to_le_bytes());
+        block.extend_from_slice(&(compressed.len() as u32).to_le_bytes());
+        block.extend_from_slice(&compressed);
+        if let Some((file, size)) = self.segment.as_mut() {
+            file.write_all(&block)?;
+            *size += block.len() as u64;
+        }
+        Ok(())
+    }
+
+    /// The first line still kept and the number of the next line.
+    pub fn range(&mut self) -> (u64, u64) {
+        self.flush();
+        let first = segments(&self.dir)
+            .first()
+            .map_or(self.stored, |(first, _)| *first);
+        (first, self.stored)
+    }
+
+    /// Up to `count` lines from `from`, within the limits of one response.
+    pub fn fetch(&mut self, from: u64, count: usize) -> Vec<(u64, Vec<u8>)> {
+        self.flush();
+        let end = from.saturating_add(count.min(MAX_LINES) as u64);
+        let mut lines = Vec::new();
+        let mut size = 0;
+        for (first, path) in segments(&self.dir) {
+            let Ok(data) = fs::read(&path) else {
+                continue;
+            };
+            let mut line = first;
+            for (n, compressed) in parse_blocks(&data).0 {
+                let next = line + n as u64;
+                if next > from && line < end {
+                    for data in decode_block(compressed) {
+                        if line >= from && line < end {
+                            size += data.len();
+                            if size > MAX_BYTES && !lines.is_empty() {
+                                return lines;
+                            }
+                            lines.push((line, data));
+                        }
+                        line += 1;
+                    }
+                }
+                line = next;
+                if line >= end {
+                    return lines;
+                }
+            }
+        }
+        lines
+    }
+
+    /// Lines before `before` containing `query`, newest first, 0 to search from the end.
+    /// Escape sequences are ignored and the case does not matter.
+    pub fn search(&mut self, query: &str, before: u64, max: usize) -> Vec<(u64, Vec<u8>)> {
+        self.flush();
+        let query = query.to_lowercase();
+        let before = if before == 0 { u64::MAX } else { before };
+        let max = if max == 0 {
+            MAX_LINES
+        } else {
+            max.min(MAX_LINES)
+        };
+        let mut matches = Vec::new();
+        let mut size = 0;
+        for (first, path) in segments(&self.dir).into_iter().rev() {
+            if first >= before {
+                continue;
+            }
+            let Ok(data) = fs::read(&path) else {
+                continue;
+            };
+            let mut blocks = Vec::new();
+            let mut line = first;
+            for (n, compressed) in parse_blocks(&data).0 {
+                blocks.push((line, compressed));
+                line += n as u64;
+            }
+            for (first, compressed) in blocks.into_iter().rev() {
+                if first >= before {
+                    continue;
+                }
+                let lines = decode_block(compressed);
+                for (i, data) in lines.into_iter().enumerate().rev() {
+                    let line = first + i as u64;
+                    if line >= before || !strip_escapes(&data).to_lowercase().contains(&query) {
+                        continue;
+                    }
+                    size += data.len();
+                    if matches.len() >= max || size > MAX_BYTES {
+                        return matches;
+                    }
+                    matches.push((line, data));
+                }
+            }
+        }
+        matches
+    }
+
+    /// Remove the scrollback of a closed terminal.
+    pub fn remove(self) {
+        let dir = self.dir.clone();
+        drop(self);
+        fs::remove_dir_all(&dir).ok();
+    }
+}
+
+impl Drop for Scrollback {
+    fn drop(&mut self) {
+        if !self.partial.is_empty() {
+            let line = std::mem::take(&mut self.partial);
+            self.pending.push(line);
+        }
+        self.flush();
+    }
+}
+
+/// Remove the scrollback of every terminal of a service which is gone.
+pub fn remove_service(service_id: &str) {
+    if is_valid_name(service_id) {
+        let dir = root().join(service_id);
+        if dir.exists() {
+            log::info!("Removed the scrollback of terminal service {}", service_id);
+            fs::remove_dir_all(&dir).ok();
+        }
+    }
+}
+
+fn root() -> PathBuf {
+    Config::path(DIR)
+}
+
+// The service id comes from the client, it must not leave the scrollback directory.
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+fn create_dir(dir: &Path) -> ResultType<()> {
+    fs::create_dir_all(dir)?;
+    // The output may contain anything shown in the terminal.
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(root(), fs::Permissions::from_mode(0o700))?;
+    }
+    Ok(())
+}
+
+/// The segments of a terminal sorted by their first line.
+fn segments(dir: &Path) -> Vec<(u64, PathBuf)> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut segments: Vec<(u64, PathBuf)> = entries
+        .flatten()
+        .map(|e| e.path())
+        .filter(|p| p.extension().is_some_and(|ext| ext == SEGMENT_EXT))
+        .filter_map(|p| {
+            let first = p.file_stem()?.to_str()?.parse().ok()?;
+            Some((first, p))
+        })
+        .collect();
+    segments.sort_by_key(|(first, _)| *first);
+    segments
+}
+
+fn remove_old_segments(dir: &Path) {
+    let segments = segments(dir);
+    if segments.len() > MAX_SEGMENTS {
+        for (_, path) in &segments[..segments.len() - MAX_SEGMENTS] {
+            fs::remove_file(path).ok();
+        }
+    }
+}
+
+/// The line count and compressed data of the complete blocks, and the size they take.
+fn parse_blocks(data: &[u8]) -> (Vec<(u32, &[u8])>, usize) {
+    let mut blocks = Vec::new();
+    let mut pos = 0;
+    while data.len() - pos >= BLOCK_HEADER_SIZE {
+        let count = u32::from_le_bytes(data[pos..pos + 4].try_into().unwrap_or_default());
+        let len = u32::from_le_bytes(data[pos + 4..pos + 8].try_into().unwrap_or_default());
+        let start = pos + BLOCK_HEADER_SIZE;
+        let end = start + len as usize;
+        if end > data.len() {
+            break;
+        }
+        blocks.push((count, &data[start..end]));
+        pos = end;
+    }
+    (blocks, pos)
+}
+
+fn decode_block(compressed: &[u8]) -> Vec<Vec<u8>> {
+    let data = compress::decompress(compressed);
+    let mut lines = Vec::new();
+    let mut pos = 0;
+    while data.len() - pos >= 4 {
+        let len = u32::from_le_bytes(data[pos..pos + 4].try_into().unwrap_or_default()) as usize;
+        pos += 4;
+        if pos + len > data.len() {
+            break;
+        }
+        lines.push(data[pos..pos + len].to_vec());
+        pos += len;
+    }
+    lines
+}
+
+/// The text of a line without control characters and escape sequences.
+fn strip_escapes(line: &[u8]) -> String {
+    let text = String::from_utf8_lossy(line);
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\x1b' => match chars.next() {
+                // CSI, ended by a character in '@'..='~'
+                Some('[') => {
+                    for c in chars.by_ref() {
+                        if ('@'..='~').contains(&c) {
+                            break;
+                        }
+                    }
+                }
+                // OSC, ended by BEL or ST
+                Some(']') => {
+                    while let Some(c) = chars.next() {
+                        if c == '\x07' || (c == '\x1b' && chars.next_if_eq(&'\\').is_some()) {
+                            break;
+                        }
+                    }
+                }
+                _ => {}
+            },
+            c if c.is_control() && c != '\t' => {}
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Remove the scrollback of services, except `current`, not written for [`KEEP_DAYS`].
+fn remove_expired(current: &str) {
+    let Ok(entries) = fs::read_dir(root()) else {
+        return;
+    };
+    let max_age = Duration::from_secs(KEEP_DAYS * 24 * 3600);
+    for entry in entries.flatten() {
+        if entry.file_name().to_string_lossy() == current {
+            continue;
+        }
+        let path = entry.path();
+        let newest = fs::read_dir(&path)
+            .into_iter()
+            .flatten()
+            .flatten()
+            .flat_map(|terminal| segments(&terminal.path()))
+            .filter_map(|(_, p)| p.metadata().ok()?.modified().ok())
+            .max();
+        let expired = newest.map_or(true, |t| {
+            SystemTime::now()
+                .duration_since(t)
+                .is_ok_and(|age| age > max_age)
+        });
+        if expired {
+            log::info!("Removed expired terminal scrollback {:?}", path);
+            fs::remove_dir_all(&path).ok();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_blocks() {
+        let mut sb = Scrollback {
+            dir: std::env::temp_dir().join(format!("scrollback_test_{}", std::process::id())),
+            segment: None,
+            stored: 0,
+            pending: Vec::new(),
+            pending_size: 0,
+            partial: Vec::new(),
+            last_flush: Instant::now(),
+        };
+        fs::create_dir_all(&sb.dir).unwrap();
+        sb.append(b"make\r\n\x1b[31mError\x1b[0m: one\r\n");
+        sb.append(b"ok\r\nerror: two\r\npartial");
+        assert_eq!(sb.range(), (0, 4));
+        let lines = sb.fetch(1, 2);
+        assert_eq!(lines[0], (1, b"\x1b[31mError\x1b[0m: one\r\n".to_vec()));
+        assert_eq!(lines[1].0, 2);
+        let found: Vec<u64> = sb.search("error:", 0, 10).iter().map(|m| m.0).collect();
+        assert_eq!(found, vec![3, 1]);
+        let found: Vec<u64> = sb.search("error:", 3, 10).iter().map(|m| m.0).collect();
+        assert_eq!(found, vec![1]);
+        sb.remove();
+    }
+
+    #[test]
+    fn test_strip_escapes() {
+        assert_eq!(
+            strip_escapes(b"\x1b]0;title\x07a\x1b[1;32mb\x1b[0m\r\n"),
+            "ab"
+        );
+    }
+}
@@ -1,3 +1,6 @@
+use super::terminal_policy::{self, InputAudit, Policy};
+use super::terminal_recorder::TerminalRecorder;
+use super::terminal_scrollback::{self, Scrollback};
 use super::*;
 use hbb_common::{
     anyhow::{anyhow, Context, Result},
@@ -43,6 +46,10 @@ lazy_static::lazy_static! {
 
     // List of terminal child processes to check for zombies
     static ref TERMINAL_TASKS: Arc<Mutex<Vec<Box<dyn Child + Send + Sync>>>> = Arc::new(Mutex::new(Vec::new()));
+
+    // Output loops indexed by service_id, shared by all the connections attached to a service.
+    // Lock it before `TERMINAL_SERVICES` and the services.
+    static ref SERVICE_LOOPS: Arc<Mutex<HashMap<String, GenericService>>> = Default::default();
 }
 
 /// Service metadata that is sent to clients
@@ -91,11 +98,35 @@ pub fn is_service_specified_user(service_id: &str) -> Option<bool> {
     get_service(service_id).map(|s| s.lock().unwrap().is_specified_user)
 }
 
+/// The user a connection acts as in the terminals, a service is only shared by the connections of its owner.
+/// `os_username` is the administrator logged in with, if any.
+pub fn owner_of(user_token: Option<UserToken>, os_username: &str) -> String {
+    #[cfg(target_os = "windows")]
+    if let Some(token) = user_token {
+        return match super::terminal_helper::get_user_sid_from_token(token) {
+            Ok(sid) => format!("sid:{}", hex::encode(sid)),
+            Err(err) => {
+                log::error!("Failed to get the user of the terminal token: {}", err);
+                // Not shared with anyone.
+                format!("token:{}", token.as_raw())
+            }
+        };
+    }
+    #[cfg(target_os = "windows")]
+    if !os_username.is_empty() {
+        return format!("user:{}", os_username.to_lowercase());
+    }
+    let _ = (user_token, os_username);
+    // The user of this process.
+    "".to_owned()
+}
+
 /// Get or create a persistent terminal service
 fn get_or_create_service(
     service_id: String,
     is_persistent: bool,
     is_specified_user: bool,
+    owner: &str,
 ) -> Result<Arc<Mutex<PersistentTerminalService>>> {
     let mut services = TERMINAL_SERVICES.lock().unwrap();
 
@@ -119,9 +150,16 @@ fn get_or_create_service(
                 service_id.clone(),
                 is_persistent,
                 is_specified_user,
+                owner.to_owned(),
             )))
         })
         .clone();
+    if service.lock().unwrap().owner != owner {
+        return Err(anyhow!(
+            "Terminal service {} belongs to another user",
+            service_id
+        ));
+    }
 
     // Ensure cleanup task is running
     ensure_cleanup_task();
@@ -141,7 +179,9 @@ fn remove_service(service_id: &str) {
         for (_, session) in sessions.iter() {
             let mut session = session.lock().unwrap();
             session.stop();
+            session.scrollback = None;
         }
+        terminal_scrollback::remove_service(service_id);
     }
 }
 
@@ -318,23 +358,154 @@ pub fn get_service_name(source: VideoSource, idx: usize) -> String {
     format!("{}{}", source.service_name_prefix(), idx)
 }
 
+/// Start the output loop of a service for a connection, which becomes the only viewer and the driver.
+///
+/// If the loop is already run by another connection, eg. the previous one of a reconnecting peer,
+/// the connection joins it as a viewer instead.
 pub fn new(
     service_id: String,
     is_persistent: bool,
     user_token: Option<UserToken>,
-) -> GenericService {
+    owner: String,
+    conn: ConnInner,
+    viewer: TerminalViewer,
+) -> Result<GenericService> {
+    let mut loops = SERVICE_LOOPS.lock().unwrap();
+    if is_running(&loops, &service_id) {
+        return join(&loops, &service_id, &owner, conn, viewer);
+    }
     // Create the service with initial persistence setting
-    allow_err!(get_or_create_service(
+    let service = get_or_create_service(
         service_id.clone(),
         is_persistent,
-        user_token.is_some()
-    ));
+        user_token.is_some(),
+        &owner,
+    )?;
     let svc = TerminalService {
         sp: GenericService::new(service_id.clone(), false),
         user_token,
     };
-    GenericService::run(&svc.clone(), move |sp| run(sp, service_id.clone()));
-    svc.sp
+    svc.sp.on_subscribe(conn);
+    loops.insert(service_id.clone(), svc.sp.clone());
+    {
+        let mut service = service.lock().unwrap();
+        service.driver = viewer.conn_id;
+        service.viewer_owners = HashMap::from([(viewer.conn_id, owner)]);
+        service.viewers = vec![viewer];
+        notify_viewers(&svc.sp, &service);
+    }
+    let id = service_id.clone();
+    GenericService::run(&svc.clone(), move |sp| run(sp, id.clone()));
+    Ok(svc.sp)
+}
+
+fn is_running(loops: &HashMap<String, GenericService>, service_id: &str) -> bool {
+    loops
+        .get(service_id)
+        .map_or(false, |sp| sp.active() && sp.has_subscribes())
+}
+
+/// Whether the connections of `owner` can attach to the service, it must be running.
+pub fn is_attachable(service_id: &str, owner: &str) -> bool {
+    let loops = SERVICE_LOOPS.lock().unwrap();
+    !service_id.is_empty()
+        && is_running(&loops, service_id)
+        && get_service(service_id).map_or(false, |s| s.lock().unwrap().owner == owner)
+}
+
+/// Join the output loop of a running service as a viewer, the output is sent to every viewer.
+pub fn attach(
+    service_id: &str,
+    owner: &str,
+    conn: ConnInner,
+    viewer: TerminalViewer,
+) -> Result<GenericService> {
+    let loops = SERVICE_LOOPS.lock().unwrap();
+    join(&loops, service_id, owner, conn, viewer)
+}
+
+fn join(
+    loops: &HashMap<String, GenericService>,
+    service_id: &str,
+    owner: &str,
+    conn: ConnInner,
+    viewer: TerminalViewer,
+) -> Result<GenericService> {
+    let sp = match loops.get(service_id) {
+        Some(sp) if sp.active() && sp.has_subscribes() => sp.clone(),
+        _ => return Err(anyhow!("Terminal service {} is not running", service_id)),
+    };
+    let service =
+        get_service(service_id).ok_or_else(|| anyhow!("Service {} not found", service_id))?;
+    let mut service = service.lock().unwrap();
+    if service.owner != owner {
+        return Err(anyhow!(
+            "Terminal service {} belongs to another user",
+            service_id
+        ));
+    }
+    log::info!(
+        "Connection {} attached to terminal service {}",
+        viewer.conn_id,
+        service_id
+    );
+    sp.on_subscribe(conn);
+    if service.driver == 0 {
+        service.driver = viewer.conn_id;
+    }
+    service
+        .viewer_owners
+        .insert(viewer.conn_id, owner.to_owned());
+    service.viewers.push(viewer);
+    notify_viewers(&sp, &service);
+    Ok(sp)
+}
+
+/// Leave the output loop of a service, it is stopped when the last viewer leaves.
+pub fn detach(service_id: &str, sp: &GenericService, conn_id: i32) {
+    let mut loops = SERVICE_LOOPS.lock().unwrap();
+    sp.on_unsubscribe(conn_id);
+    if let Some(service) = get_service(service_id) {
+        let mut service = service.lock().unwrap();
+        service.viewers.retain(|v| v.conn_id != conn_id);
+        service.viewer_owners.remove(&conn_id);
+        if service.driver == conn_id {
+            // Control goes to the viewer of the owner attached the longest.
+            service.driver = service
+                .viewers
+                .iter()
+                .map(|v| v.conn_id)
+                .find(|id| service.is_owned_by(*id))
+                .unwrap_or(0);
+        }
+        if sp.has_subscribes() {
+            notify_viewers(sp, &service);
+        }
+    }
+    if sp.has_subscribes() {
+        return;
+    }
+    // The registered loop is another one if the service was taken over by a new connection.
+    if loops.get(service_id).map_or(false, |s| !s.has_subscribes()) {
+        loops.remove(service_id);
+    }
+    drop(loops);
+    sp.join();
+}
+
+/// Tell every viewer who is attached and who drives.
+fn notify_viewers(sp: &GenericService, service: &PersistentTerminalService) {
+    for viewer in service.viewers.iter() {
+        let mut viewers = TerminalViewers::new();
+        viewers.viewers = service.viewers.clone();
+        viewers.driver = service.driver;
+        viewers.conn_id = viewer.conn_id;
+        let mut response = TerminalResponse::new();
+        response.set_viewers(viewers);
+        let mut msg_out = Message::new();
+        msg_out.set_terminal_response(response);
+        sp.send_to(msg_out, viewer.conn_id);
+    }
 }
 
 fn run(sp: TerminalService, service_id: String) -> ResultType<()> {
@@ -463,6 +634,12 @@ pub struct TerminalSession {
     reader_thread: Option<thread::JoinHandle<()>>,
     writer_thread: Option<thread::JoinHandle<()>>,
     output_buffer: OutputBuffer,
+    // Audit recording of the session, if enabled on this side.
+    recorder: Option<TerminalRecorder>,
+    // Lines typed into the terminal, if input auditing is enabled.
+    input_audit: Option<InputAudit>,
+    // Output kept on disk for persistent services, beyond `output_buffer`.
+    scrollback: Option<Scrollback>,
     title: String,
     pid: u32,
     rows: u16,
@@ -491,6 +668,9 @@ impl TerminalSession {
             reader_thread: None,
             writer_thread: None,
             output_buffer: OutputBuffer::new(),
+            recorder: None,
+            input_audit: None,
+            scrollback: None,
             title: format!("Terminal {}", terminal_id),
             pid: 0,
             rows,
@@ -623,10 +803,22 @@ pub struct PersistentTerminalService {
     pub is_persistent: bool,
     needs_session_sync: bool,
     is_specified_user: bool,
+    // Connections watching the terminals, in the order they attached.
+    viewers: Vec<TerminalViewer>,
+    // The viewer whose input is accepted, 0 if not tracked.
+    driver: i32,
+    // The user the service belongs to, see `owner_of`, and the users of the viewers.
+    owner: String,
+    viewer_owners: HashMap<i32, String>,
 }
 
 impl PersistentTerminalService {
-    pub fn new(service_id: String, is_persistent: bool, is_specified_user: bool) -> Self {
+    pub fn new(
+        service_id: String,
+        is_persistent: bool,
+        is_specified_user: bool,
+        owner: String,
+    ) -> Self {
         Self {
             service_id,
             sessions: HashMap::new(),
@@ -635,6 +827,10 @@ impl PersistentTerminalService {
             is_persistent,
             needs_session_sync: false,
             is_specified_user,
+            viewers: Vec::new(),
+            driver: 0,
+            owner,
+            viewer_owners: HashMap::new(),
         }
     }
 
@@ -642,6 +838,23 @@ impl PersistentTerminalService {
         self.last_activity = Instant::now();
     }
 
+    fn is_driver(&self, conn_id: i32) -> bool {
+        self.driver == 0 || self.driver == conn_id
+    }
+
+    // Only the viewers of the owner may drive.
+    fn is_owned_by(&self, conn_id: i32) -> bool {
+        self.viewer_owners.get(&conn_id) == Some(&self.owner)
+    }
+
+    /// Who is behind a connection, for the input audit log.
+    fn viewer_name(&self, conn_id: i32) -> String {
+        match self.viewers.iter().find(|v| v.conn_id == conn_id) {
+            Some(v) => format!("{} ({}, conn {})", v.peer_id, v.name, conn_id),
+            None => format!("conn {}", conn_id),
+        }
+    }
+
     /// Get list of terminal metadata
     pub fn list_terminals(&self) -> Vec<(i32, String, u32, Instant)> {
         self.sessions
@@ -729,7 +942,12 @@ impl TerminalServiceProxy {
         &self.service_id
     }
 
-    pub fn handle_action(&mut self, action: &TerminalAction) -> Result<Option<TerminalResponse>> {
+    /// Handle an action of the connection `conn_id`, only the driver can change the terminals.
+    pub fn handle_action(
+        &mut self,
+        action: &TerminalAction,
+        conn_id: i32,
+    ) -> Result<Option<TerminalResponse>> {
         let service = match get_service(&self.service_id) {
             Some(s) => s,
             None => {
@@ -740,12 +958,20 @@ impl TerminalServiceProxy {
                 return Ok(Some(response));
             }
         };
-        service.lock().unwrap().update_activity();
+        let is_driver = {
+            let mut service = service.lock().unwrap();
+            service.update_activity();
+            service.is_driver(conn_id)
+        };
         match &action.union {
             Some(terminal_action::Union::Open(open)) => {
-                self.handle_open(&mut service.lock().unwrap(), open)
+                self.handle_open(&mut service.lock().unwrap(), open, is_driver)
             }
             Some(terminal_action::Union::Resize(resize)) => {
+                if !is_driver {
+                    // The size follows the driver's window.
+                    return Ok(None);
+                }
                 let session = service
                     .lock()
                     .unwrap()
@@ -755,25 +981,97 @@ impl TerminalServiceProxy {
                 self.handle_resize(session, resize)
             }
             Some(terminal_action::Union::Data(data)) => {
+                if !is_driver {
+                    log::debug!(
+                        "Drop input of connection {} to terminal {}, not the driver",
+                        conn_id,
+                        data.terminal_id
+                    );
+                    return Ok(None);
+                }
+                let (session, who) = {
+                    let service = service.lock().unwrap();
+                    (
+                        service.sessions.get(&data.terminal_id).cloned(),
+                        service.viewer_name(conn_id),
+                    )
+                };
+                self.handle_data(session, data, &who)
+            }
+            Some(terminal_action::Union::Close(close)) => {
+                if !is_driver {
+                    return Ok(Some(Self::not_driver_error(close.terminal_id)));
+                }
+                self.handle_close(&mut service.lock().unwrap(), close)
+            }
+            Some(terminal_action::Union::HandOver(hand_over)) => {
+                self.handle_hand_over(&service, hand_over, conn_id)
+            }
+            Some(terminal_action::Union::Scrollback(request)) => {
+                // Reading the history is allowed to every viewer.
                 let session = service
                     .lock()
                     .unwrap()
                     .sessions
-                    .get(&data.terminal_id)
+                    .get(&request.terminal_id)
                     .cloned();
-                self.handle_data(session, data)
-            }
-            Some(terminal_action::Union::Close(close)) => {
-                self.handle_close(&mut service.lock().unwrap(), close)
+                self.handle_scrollback(session, request)
             }
             _ => Ok(None),
         }
     }
 
+    fn not_driver_error(terminal_id: i32) -> TerminalResponse {
+        let mut response = TerminalResponse::new();
+        let mut error = TerminalError::new();
+        error.terminal_id = terminal_id;
+        error.message = "Only the driver can do this".to_string();
+        response.set_error(error);
+        response
+    }
+
+    fn handle_hand_over(
+        &self,
+        service: &Arc<Mutex<PersistentTerminalService>>,
+        hand_over: &TerminalHandOver,
+        conn_id: i32,
+    ) -> Result<Option<TerminalResponse>> {
+        let loops = SERVICE_LOOPS.lock().unwrap();
+        let mut service = service.lock().unwrap();
+        if service.driver != conn_id {
+            return Ok(Some(Self::not_driver_error(0)));
+        }
+        if !service
+            .viewers
+            .iter()
+            .any(|v| v.conn_id == hand_over.conn_id)
+        {
+            return Err(anyhow!("Connection {} is not attached", hand_over.conn_id));
+        }
+        if !service.is_owned_by(hand_over.conn_id) {
+            return Err(anyhow!(
+                "Connection {} is of another user",
+                hand_over.conn_id
+            ));
+        }
+        log::info!(
+            "Terminal service {} is handed over from {} to {}",
+            self.service_id,
+            conn_id,
+            hand_over.conn_id
+        );
+        service.driver = hand_over.conn_id;
+        if let Some(sp) = loops.get(&self.service_id) {
+            notify_viewers(sp, &service);
+        }
+        Ok(None)
+    }
+
     fn handle_open(
         &self,
         service: &mut PersistentTerminalService,
         open: &OpenTerminal,
+        is_driver: bool,
     ) -> Result<Option<TerminalResponse>> {
         let mut response = TerminalResponse::new();
 
@@ -813,13 +1111,35 @@ impl TerminalServiceProxy {
             return Ok(Some(response));
         }
 
+        if !is_driver {
+            return Ok(Some(Self::not_driver_error(open.terminal_id)));
+        }
+
+        let policy = Policy::load();
+        let default_shell = get_default_shell();
+        let shells = policy.shells(&default_shell);
+        let launch = match policy.resolve(&open.shell, &open.cwd, &default_shell) {
+            Ok(launch) => launch,
+            Err(e) => {
+                log::warn!("Refused to open terminal {}: {}", open.terminal_id, e);
+                let mut opened = TerminalOpened::new();
+                opened.terminal_id = open.terminal_id;
+                opened.success = false;
+                opened.message = e;
+                opened.service_id = service.service_id.clone();
+                opened.shells = shells;
+                response.set_opened(opened);
+                return Ok(Some(response));
+            }
+        };
+
         // Windows with user_token: use helper process to run shell as the logged-in user
         // This solves the ConPTY + CreateProcessAsUserW incompatibility issue where
         // vim, Claude Code, and other TUI applications hang when ConPTY is created
         // by SYSTEM service but shell runs as user via CreateProcessAsUserW.
         #[cfg(target_os = "windows")]
         if self.user_token.is_some() {
-            return self.handle_open_with_helper(service, open);
+            return self.handle_open_with_helper(service, open, launch, shells, policy.audit_input);
         }
 
         // Create new terminal session
@@ -842,26 +1162,25 @@ impl TerminalServiceProxy {
         let pty_system = portable_pty::native_pty_system();
         let pty_pair = pty_system.openpty(pty_size).context("Failed to open PTY")?;
 
-        // Use default shell for the platform
-        let shell = get_default_shell();
-        log::debug!("Using shell: {}", shell);
+        // The shell, its arguments and start directory allowed by the terminal policy
+        let shell = launch.shell.clone();
+        log::debug!("Using shell: {} {:?}", shell, launch.args);
 
-        #[allow(unused_mut)]
         let mut cmd = CommandBuilder::new(&shell);
+        // Login shells (-l, the default on macOS) load the user's shell profile
+        // (~/.zprofile, ~/.bash_profile), so PATH includes Homebrew paths on macOS.
+        cmd.args(&launch.args);
+        if !launch.cwd.is_empty() {
+            cmd.cwd(&launch.cwd);
+        }
 
         // macOS-specific terminal configuration
-        // 1. Use login shell (-l) to load user's shell profile (~/.zprofile, ~/.bash_profile)
-        //    This ensures PATH includes Homebrew paths (/opt/homebrew/bin, /usr/local/bin)
-        // 2. Set TERM environment variable for proper terminal behavior
-        //    This fixes issues with control sequences (e.g., Delete/Backspace keys)
-        //    macOS terminfo uses hex naming: '78' = 'x' for xterm entries
+        // Set TERM environment variable for proper terminal behavior
+        // This fixes issues with control sequences (e.g., Delete/Backspace keys)
+        // macOS terminfo uses hex naming: '78' = 'x' for xterm entries
         // Note: For Linux, `TERM` is set in src/platform/linux.rs try_start_server_()
         #[cfg(target_os = "macos")]
         {
-            // Start as login shell to load user environment (PATH, etc.)
-            cmd.arg("-l");
-            log::debug!("Added -l flag for macOS login shell");
-
             let term = if std::path::Path::new("/usr/share/terminfo/78/xterm-256color").exists() {
                 "xterm-256color"
             } else {
@@ -870,6 +1189,10 @@ impl TerminalServiceProxy {
             cmd.env("TERM", term);
             log::debug!("Set TERM={} for macOS PTY", term);
         }
+        // Forced by the policy, so it can also override TERM.
+        for (key, value) in &launch.env {
+            cmd.env(key, value);
+        }
 
         // Note: On Windows with user_token, we use helper mode (handle_open_with_helper)
         // which is dispatched earlier in this function. This code path is only reached
@@ -970,6 +1293,12 @@ impl TerminalServiceProxy {
         session.output_rx = Some(output_rx);
         session.reader_thread = Some(reader_thread);
         session.writer_thread = Some(writer_thread);
+        session.recorder =
+            TerminalRecorder::start(open.terminal_id, session.rows, session.cols, &shell);
+        session.input_audit = policy.audit_input.then(InputAudit::default);
+        if service.is_persistent {
+            session.scrollback = Scrollback::open(&service.service_id, open.terminal_id);
+        }
         session.is_opened = true;
 
         let mut opened = TerminalOpened::new();
@@ -978,6 +1307,7 @@ impl TerminalServiceProxy {
         opened.message = "Terminal opened".to_string();
         opened.pid = session.pid;
         opened.service_id = service.service_id.clone();
+        opened.shells = shells;
         if service.needs_session_sync {
             if !service.sessions.is_empty() {
                 opened.persistent_sessions = service.sessions.keys().cloned().collect();
@@ -1009,6 +1339,9 @@ impl TerminalServiceProxy {
         &self,
         service: &mut PersistentTerminalService,
         open: &OpenTerminal,
+        launch: terminal_policy::Launch,
+        shells: Vec<String>,
+        audit_input: bool,
     ) -> Result<Option<TerminalResponse>> {
         let mut response = TerminalResponse::new();
 
@@ -1060,6 +1393,7 @@ impl TerminalServiceProxy {
             open.terminal_id,
             open.rows as u16,
             open.cols as u16,
+            &launch,
         )?;
 
         // Use HelperProcessGuard for RAII cleanup - terminates process on error
@@ -1185,6 +1519,12 @@ impl TerminalServiceProxy {
         session.output_rx = Some(output_rx);
         session.reader_thread = Some(reader_thread);
         session.writer_thread = Some(writer_thread);
+        session.recorder =
+            TerminalRecorder::start(open.terminal_id, session.rows, session.cols, &launch.shell);
+        session.input_audit = audit_input.then(InputAudit::default);
+        if service.is_persistent {
+            session.scrollback = Scrollback::open(&service.service_id, open.terminal_id);
+        }
         session.is_opened = true;
         session.is_helper_mode = true;
         session.helper_process_handle = Some(SendableHandle::new(helper_raw_handle));
@@ -1195,6 +1535,7 @@ impl TerminalServiceProxy {
         opened.message = "Terminal opened (helper mode)".to_string();
         opened.pid = session.pid;
         opened.service_id = service.service_id.clone();
+        opened.shells = shells;
         if service.needs_session_sync {
             if !service.sessions.is_empty() {
                 opened.persistent_sessions = service.sessions.keys().cloned().collect();
@@ -1226,6 +1567,9 @@ impl TerminalServiceProxy {
             session.update_activity();
             session.rows = resize.rows as u16;
             session.cols = resize.cols as u16;
+            if let Some(recorder) = session.recorder.as_mut() {
+                recorder.write_resize(resize.rows as u16, resize.cols as u16);
+            }
 
             // Windows: handle helper mode vs direct PTY mode
             #[cfg(target_os = "windows")]
@@ -1275,10 +1619,25 @@ impl TerminalServiceProxy {
         &self,
         session: Option<Arc<Mutex<TerminalSession>>>,
         data: &TerminalData,
+        who: &str,
     ) -> Result<Option<TerminalResponse>> {
         if let Some(session_arc) = session {
             let mut session = session_arc.lock().unwrap();
             session.update_activity();
+            if let Some(recorder) = session.recorder.as_mut() {
+                recorder.write_input(&data.data);
+            }
+            if let Some(audit) = session.input_audit.as_mut() {
+                let lines = audit.push(&data.data);
+                if !lines.is_empty() {
+                    terminal_policy::write_audit(
+                        &self.service_id,
+                        &format!("terminal {}", data.terminal_id),
+                        who,
+                        &lines,
+                    );
+                }
+            }
             if let Some(input_tx) = &session.input_tx {
                 // Encode data for helper mode or send raw for direct PTY mode
                 #[cfg(target_os = "windows")]
@@ -1314,6 +1673,9 @@ impl TerminalServiceProxy {
         // Always close and remove the terminal
         if let Some(session_arc) = service.sessions.remove(&close.terminal_id) {
             let mut session = session_arc.lock().unwrap();
+            if let Some(scrollback) = session.scrollback.take() {
+                scrollback.remove();
+            }
             let exit_code = if let Some(mut child) = session.child.take() {
                 child.kill()?;
                 add_to_reaper(child);
@@ -1332,6 +1694,51 @@ impl TerminalServiceProxy {
         }
     }
 
+    /// Fetch a range of the scrollback, or search it if a query is given.
+    fn handle_scrollback(
+        &self,
+        session: Option<Arc<Mutex<TerminalSession>>>,
+        request: &TerminalScrollbackRequest,
+    ) -> Result<Option<TerminalResponse>> {
+        let Some(session_arc) = session else {
+            return Err(anyhow!("Terminal {} not found", request.terminal_id));
+        };
+        let mut session = session_arc.lock().unwrap();
+        let Some(scrollback) = session.scrollback.as_mut() else {
+            return Err(anyhow!(
+                "No scrollback is kept for terminal {}, it is not persistent",
+                request.terminal_id
+            ));
+        };
+        let lines = if request.query.is_empty() {
+            scrollback.fetch(request.from_line, request.count as usize)
+        } else {
+            scrollback.search(
+                &request.query,
+                request.from_line,
+                request.max_matches as usize,
+            )
+        };
+        let (first_line, end_line) = scrollback.range();
+        let mut response = TerminalResponse::new();
+        response.set_scrollback(TerminalScrollback {
+            terminal_id: request.terminal_id,
+            first_line,
+            end_line,
+            query: request.query.clone(),
+            lines: lines
+                .into_iter()
+                .map(|(line, data)| TerminalScrollbackLine {
+                    line,
+                    data: data.into(),
+                    ..Default::default()
+                })
+                .collect(),
+            ..Default::default()
+        });
+        Ok(Some(response))
+    }
+
     pub fn read_outputs(&self) -> Vec<TerminalResponse> {
         let service = match get_service(&self.service_id) {
             Some(s) => s,
@@ -1392,6 +1799,15 @@ impl TerminalServiceProxy {
                 // Update buffer after reading
                 for data in &received_data {
                     session.output_buffer.append(data);
+                    if let Some(recorder) = session.recorder.as_mut() {
+                        recorder.write_output(data);
+                    }
+                    if let Some(scrollback) = session.scrollback.as_mut() {
+                        scrollback.append(data);
+                    }
+                }
+                if let Some(scrollback) = session.scrollback.as_mut() {
+                    scrollback.tick();
                 }
 
                 // Process received data for responses
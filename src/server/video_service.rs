@@ -78,6 +78,10 @@ lazy_static::lazy_static! {
     pub static ref IS_UAC_RUNNING: Arc<Mutex<bool>> = Default::default();
     pub static ref IS_FOREGROUND_WINDOW_ELEVATED: Arc<Mutex<bool>> = Default::default();
     static ref SCREENSHOTS: Mutex<HashMap<usize, Screenshot>> = Default::default();
+    // The recorders of the incoming sessions, which also record the audio.
+    static ref INCOMING_RECORDERS: Mutex<Vec<std::sync::Weak<Mutex<Option<Recorder>>>>> = Default::default();
+    // The events are written in their own thread, not to lock the recorders in the connections.
+    static ref RECORD_EVENT_SENDER: std::sync::mpsc::Sender<String> = start_record_event_thread();
 }
 
 struct Screenshot {
@@ -1062,10 +1066,50 @@ fn get_recorder(
     } else {
         Default::default()
     };
+    if record_incoming {
+        let mut recorders = INCOMING_RECORDERS.lock().unwrap();
+        recorders.retain(|r| r.strong_count() > 0);
+        recorders.push(Arc::downgrade(&recorder));
+    }
 
     recorder
 }
 
+/// Write the audio sent to the peers to the recordings of the incoming sessions.
+pub fn record_audio(frame: &AudioFrame) {
+    let recorders = INCOMING_RECORDERS.lock().unwrap();
+    for recorder in recorders.iter().filter_map(|r| r.upgrade()) {
+        if let Some(r) = recorder.lock().unwrap().as_mut() {
+            r.write_audio(frame);
+        }
+    }
+}
+
+/// Add an event to the timelines of the recordings of the incoming sessions.
+pub fn record_event(text: String) {
+    RECORD_EVENT_SENDER.send(text).ok();
+}
+
+fn start_record_event_thread() -> std::sync::mpsc::Sender<String> {
+    let (tx, rx) = std::sync::mpsc::channel::<String>();
+    std::thread::spawn(move || {
+        while let Ok(text) = rx.recv() {
+            let recorders: Vec<_> = INCOMING_RECORDERS
+                .lock()
+                .unwrap()
+                .iter()
+                .filter_map(|r| r.upgrade())
+                .collect();
+            for recorder in recorders {
+                if let Some(r) = recorder.lock().unwrap().as_mut() {
+                    r.write_event(&text);
+                }
+            }
+        }
+    });
+    tx
+}
+
 #[cfg(target_os = "android")]
 fn check_change_scale(hardware: bool) -> ResultType<()> {
     use hbb_common::config::keys::OPTION_ENABLE_ANDROID_SOFTWARE_ENCODING_HALF_SCALE as SCALE_SOFT;
@@ -386,7 +386,7 @@ impl InvokeUiSession for SciterHandler {
         self.call("updateRecordStatus", &make_args!(start));
     }
 
-    fn printer_request(&self, id: i32, path: String) {
+    fn printer_request(&self, id: i32, path: String, _job: &PrintJobInfo) {
         self.call("printerRequest", &make_args!(id, path));
     }
 
@@ -439,6 +439,7 @@ impl<T: InvokeUiCM> IpcTaskRunner<T> {
 
         // for tmp use, without real conn id
         let mut write_jobs: Vec<fs::TransferJob> = Vec::new();
+        let mut journals = crate::file_journal::Journals::new(true);
         // File timer for processing read_jobs
         let mut file_timer =
             crate::rustdesk_interval(time::interval_at(Instant::now() + SEC30, SEC30));
@@ -534,10 +535,10 @@ impl<T: InvokeUiCM> IpcTaskRunner<T> {
                                     if let ipc::FS::WriteBlock { id, file_num, data: _, compressed } = fs {
                                         if let Ok(bytes) = self.stream.next_raw().await {
                                             fs = ipc::FS::WriteBlock{id, file_num, data:bytes.into(), compressed};
-                                            handle_fs(fs, &mut write_jobs, &mut self.read_jobs, &self.tx, Some(&tx_log), self.conn_id).await;
+                                            handle_fs(fs, &mut write_jobs, &mut journals, &mut self.read_jobs, &self.tx, Some(&tx_log), self.conn_id).await;
                                         }
                                     } else {
-                                        handle_fs(fs, &mut write_jobs, &mut self.read_jobs, &self.tx, Some(&tx_log), self.conn_id).await;
+                                        handle_fs(fs, &mut write_jobs, &mut journals, &mut self.read_jobs, &self.tx, Some(&tx_log), self.conn_id).await;
                                     }
                                     // Activate fast timer immediately when read jobs exist.
                                     // This ensures new jobs start processing without waiting for the slow 30s timer.
@@ -813,6 +814,7 @@ pub async fn start_listen<T: InvokeUiCM>(
 ) {
     let mut current_id = 0;
     let mut write_jobs: Vec<fs::TransferJob> = Vec::new();
+    let mut journals = crate::file_journal::Journals::new(true);
     loop {
         match rx.recv().await {
             Some(Data::Login {
@@ -864,6 +866,7 @@ pub async fn start_listen<T: InvokeUiCM>(
                 handle_fs(
                     fs,
                     &mut write_jobs,
+                    &mut journals,
                     &mut read_jobs_placeholder,
                     &tx,
                     None,
@@ -896,6 +899,7 @@ pub async fn start_listen<T: InvokeUiCM>(
 async fn handle_fs(
     fs: ipc::FS,
     write_jobs: &mut Vec<fs::TransferJob>,
+    journals: &mut crate::file_journal::Journals,
     read_jobs: &mut Vec<fs::TransferJob>,
     tx: &UnboundedSender<Data>,
     tx_log: Option<&UnboundedSender<String>>,
@@ -970,9 +974,12 @@ async fn handle_fs(
             );
             job.total_size = total_size;
             job.conn_id = conn_id;
+            // A resumed job has the same id.
+            journals.suspend(id);
             write_jobs.push(job);
         }
         ipc::FS::CancelWrite { id } => {
+            journals.cancel(id);
             if let Some(job) = fs::remove_job(id, write_jobs) {
                 job.remove_download_file();
                 if let Some(tx) = tx_log {
@@ -983,6 +990,8 @@ async fn handle_fs(
             }
         }
         ipc::FS::WriteDone { id, file_num } => {
+            // Kept for the checksum request of the peer, see `ipc::FS::Checksum`.
+            journals.finish(id);
             if let Some(job) = fs::remove_job(id, write_jobs) {
                 job.modify_time();
                 send_raw(fs::new_done(id, file_num), tx);
@@ -990,6 +999,7 @@ async fn handle_fs(
             }
         }
         ipc::FS::WriteError { id, file_num, err } => {
+            journals.suspend(id);
             if let Some(job) = fs::remove_job(id, write_jobs) {
                 tx_log.map(|tx| tx.send(serialize_transfer_job(&job, false, false, &err)));
                 send_raw(fs::new_error(job.id(), err, file_num), tx);
@@ -1002,17 +1012,16 @@ async fn handle_fs(
             compressed,
         } => {
             if let Some(job) = fs::get_job(id, write_jobs) {
-                if let Err(err) = job
-                    .write(FileTransferBlock {
-                        id,
-                        file_num,
-                        data,
-                        compressed,
-                        ..Default::default()
-                    })
-                    .await
-                {
-                    send_raw(fs::new_error(id, err, file_num), &tx);
+                let block = FileTransferBlock {
+                    id,
+                    file_num,
+                    data,
+                    compressed,
+                    ..Default::default()
+                };
+                match job.write(block.clone()).await {
+                    Ok(()) => journals.record(job, &block),
+                    Err(err) => send_raw(fs::new_error(id, err, file_num), &tx),
                 }
             }
         }
@@ -1044,6 +1053,7 @@ async fn handle_fs(
                         match is_write_need_confirmation(is_resume, &path, &digest) {
                             Ok(digest_result) => {
                                 job.set_digest(file_size, last_modified);
+                                journals.on_digest(id, file_num, file_size, last_modified);
                                 match digest_result {
                                     DigestCheckResult::IsSame => {
                                         req.set_skip(true);
@@ -1053,6 +1063,18 @@ async fn handle_fs(
                                     DigestCheckResult::NeedConfirm(mut digest) => {
                                         // upload to server, but server has the same file, request
                                         digest.is_upload = is_upload;
+                                        if is_resume
+                                            && digest.is_identical
+                                            && digest.transferred_size > 0
+                                        {
+                                            digest.transferred_size = journals.resume(
+                                                job,
+                                                file_num,
+                                                file_size,
+                                                last_modified,
+                                                digest.transferred_size,
+                                            );
+                                        }
                                         let mut msg_out = Message::new();
                                         let mut fr = FileResponse::new();
                                         fr.set_digest(digest);
@@ -1073,6 +1095,40 @@ async fn handle_fs(
                 }
             }
         }
+        ipc::FS::Checksum {
+            id,
+            file_num,
+            path,
+            is_upload,
+        } => {
+            let tx = tx.clone();
+            // Hashing a large file takes a while, do not block the writes of other jobs.
+            tokio::spawn(async move {
+                let result = if is_upload {
+                    Ok(crate::file_journal::take_finished(&path))
+                } else {
+                    match spawn_blocking(move || crate::file_journal::file_sha256(&path)).await {
+                        Ok(Ok(sha256)) => Ok(sha256),
+                        Ok(Err(e)) => Err(format!("{}", e)),
+                        Err(e) => Err(format!("task failed: {}", e)),
+                    }
+                };
+                let mut checksum = FileChecksum {
+                    id,
+                    file_num,
+                    ..Default::default()
+                };
+                match result {
+                    Ok(sha256) => checksum.sha256 = sha256,
+                    Err(err) => checksum.error = err,
+                }
+                let mut fr = FileResponse::new();
+                fr.set_checksum(checksum);
+                let mut msg_out = Message::new();
+                msg_out.set_file_response(fr);
+                send_raw(msg_out, &tx);
+            });
+        }
         ipc::FS::SendConfirm(bytes) => {
             if let Ok(r) = FileTransferSendConfirmRequest::parse_from_bytes(&bytes) {
                 if let Some(job) = fs::get_job(r.id, write_jobs) {
@@ -67,6 +67,8 @@ pub struct Session<T: InvokeUiSession> {
     pub last_change_display: Arc<Mutex<ChangeDisplayRecord>>,
     pub connection_round_state: Arc<Mutex<ConnectionRoundState>>,
     pub printer_names: Arc<RwLock<HashMap<i32, String>>>,
+    // The copies asked for by the print jobs.
+    pub print_copies: Arc<RwLock<HashMap<i32, u32>>>,
     // Indicate whether the session is reconnected.
     // Used to auto start file transfer after reconnection.
     pub reconnect_count: Arc<AtomicUsize>,
@@ -172,9 +174,11 @@ impl ChangeDisplayRecord {
 #[cfg(not(any(target_os = "android", target_os = "ios")))]
 impl SessionPermissionConfig {
     pub fn is_text_clipboard_required(&self) -> bool {
+        let lc = self.lc.read().unwrap();
         *self.server_clipboard_enabled.read().unwrap()
             && *self.server_keyboard_enabled.read().unwrap()
-            && !self.lc.read().unwrap().disable_clipboard.v
+            && !lc.disable_clipboard.v
+            && !lc.get_toggle_option(crate::clipboard::history::OPTION_MANUAL_CLIPBOARD_SYNC)
     }
 
     #[cfg(feature = "unix-file-copy-paste")]
@@ -408,11 +412,52 @@ impl<T: InvokeUiSession> Session<T> {
 
     #[cfg(not(target_os = "ios"))]
     pub fn is_text_clipboard_required(&self) -> bool {
+        self.is_clipboard_allowed() && !self.is_manual_clipboard_sync()
+    }
+
+    fn is_clipboard_allowed(&self) -> bool {
         *self.server_clipboard_enabled.read().unwrap()
             && *self.server_keyboard_enabled.read().unwrap()
             && !self.lc.read().unwrap().disable_clipboard.v
     }
 
+    pub fn is_manual_clipboard_sync(&self) -> bool {
+        self.lc
+            .read()
+            .unwrap()
+            .get_toggle_option(crate::clipboard::history::OPTION_MANUAL_CLIPBOARD_SYNC)
+    }
+
+    pub fn get_clipboard_history(&self) -> String {
+        serde_json::to_string(&crate::clipboard::history::list()).unwrap_or_default()
+    }
+
+    /// Paste an entry of the clipboard history to the peer, also if the clipboard isn't synced automatically.
+    pub fn paste_clipboard_history(&self, id: i32) {
+        if !self.is_clipboard_allowed() {
+            return;
+        }
+        let (version, platform) = self
+            .lc
+            .read()
+            .unwrap()
+            .peer_info
+            .as_ref()
+            .map(|pi| (pi.version.clone(), pi.platform.clone()))
+            .unwrap_or_default();
+        if let Some(msg) = crate::clipboard::history::get_msg(id, &version, &platform) {
+            self.send(Data::Message(msg));
+        }
+    }
+
+    /// Copy an entry of the clipboard history to the local clipboard.
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    pub fn copy_clipboard_history(&self, id: i32) {
+        if let Some(clipboards) = crate::clipboard::history::get(id) {
+            crate::clipboard::update_clipboard(clipboards, crate::clipboard::ClipboardSide::Client);
+        }
+    }
+
     #[cfg(any(target_os = "windows", feature = "unix-file-copy-paste"))]
     pub fn is_file_clipboard_required(&self) -> bool {
         *self.server_keyboard_enabled.read().unwrap()
@@ -785,11 +830,26 @@ impl<T: InvokeUiSession> Session<T> {
 
     // Terminal methods
     pub fn open_terminal(&self, terminal_id: i32, rows: u32, cols: u32) {
+        self.open_terminal_with_shell(terminal_id, rows, cols, "".to_owned(), "".to_owned());
+    }
+
+    /// Open a terminal running `shell` in `cwd`, both must be allowed by the peer.
+    /// Empty for the peer's defaults.
+    pub fn open_terminal_with_shell(
+        &self,
+        terminal_id: i32,
+        rows: u32,
+        cols: u32,
+        shell: String,
+        cwd: String,
+    ) {
         let mut action = TerminalAction::new();
         action.set_open(OpenTerminal {
             terminal_id,
             rows,
             cols,
+            shell,
+            cwd,
             ..Default::default()
         });
         let mut msg_out = Message::new();
@@ -833,6 +893,78 @@ impl<T: InvokeUiSession> Session<T> {
         self.send(Data::Message(msg_out));
     }
 
+    /// Ask the peer to send magic packets to the targets on its subnets,
+    /// the result comes back as a `WakeOnLanResponse`.
+    pub fn wake_on_lan(&self, targets: Vec<LanInterface>) {
+        let mut msg_out = Message::new();
+        msg_out.set_wake_on_lan_request(WakeOnLanRequest {
+            targets,
+            ..Default::default()
+        });
+        self.send(Data::Message(msg_out));
+    }
+
+    /// Run a command without a pty, the output comes back as `ExecOutput` and `ExecResult` responses.
+    pub fn exec_command(
+        &self,
+        exec_id: i32,
+        argv: Vec<String>,
+        env: HashMap<String, String>,
+        cwd: String,
+        stdin: Vec<u8>,
+        timeout_ms: u32,
+    ) {
+        let mut action = TerminalAction::new();
+        action.set_exec(ExecCommand {
+            exec_id,
+            argv,
+            env,
+            cwd,
+            stdin: bytes::Bytes::from(stdin),
+            timeout_ms,
+            ..Default::default()
+        });
+        let mut msg_out = Message::new();
+        msg_out.set_terminal_action(action);
+        self.send(Data::Message(msg_out));
+    }
+
+    /// Pass the input control of the shared terminals to another attached connection.
+    pub fn hand_over_terminal(&self, conn_id: i32) {
+        let mut action = TerminalAction::new();
+        action.set_hand_over(TerminalHandOver {
+            conn_id,
+            ..Default::default()
+        });
+        let mut msg_out = Message::new();
+        msg_out.set_terminal_action(action);
+        self.send(Data::Message(msg_out));
+    }
+
+    /// Fetch `count` lines of the scrollback from `from_line`, or with a `query`,
+    /// search the lines before `from_line` (0 for the end), newest first.
+    pub fn terminal_scrollback(
+        &self,
+        terminal_id: i32,
+        from_line: u64,
+        count: u32,
+        query: String,
+        max_matches: u32,
+    ) {
+        let mut action = TerminalAction::new();
+        action.set_scrollback(TerminalScrollbackRequest {
+            terminal_id,
+            from_line,
+            count,
+            query,
+            max_matches,
+            ..Default::default()
+        });
+        let mut msg_out = Message::new();
+        msg_out.set_terminal_action(action);
+        self.send(Data::Message(msg_out));
+    }
+
     pub fn capture_displays(&self, add: Vec<i32>, sub: Vec<i32>, set: Vec<i32>) {
         let mut misc = Misc::new();
         misc.set_capture_displays(CaptureDisplays {
@@ -1657,6 +1789,11 @@ impl<T: InvokeUiSession> Session<T> {
             true,
         )));
     }
+
+    pub fn printer_dismiss(&self, id: i32) {
+        self.printer_names.write().unwrap().remove(&id);
+        self.print_copies.write().unwrap().remove(&id);
+    }
 }
 
 pub trait InvokeUiSession: Send + Sync + Clone + 'static + Sized + Default {
@@ -1722,7 +1859,9 @@ pub trait InvokeUiSession: Send + Sync + Clone + 'static + Sized + Default {
     fn is_multi_ui_session(&self) -> bool;
     fn update_record_status(&self, start: bool);
     fn update_empty_dirs(&self, _res: ReadEmptyDirsResponse) {}
-    fn printer_request(&self, id: i32, path: String);
+    fn sync_dir_plan(&self, _id: i32, _plan: String) {}
+    fn handle_wake_on_lan_response(&self, _response: WakeOnLanResponse) {}
+    fn printer_request(&self, id: i32, path: String, job: &PrintJobInfo);
     fn handle_screenshot_resp(&self, sid: String, msg: String);
     fn handle_terminal_response(&self, response: TerminalResponse);
 }
@@ -1952,7 +2091,7 @@ pub async fn io_loop<T: InvokeUiSession>(handler: Session<T>, round: u32) {
             loop {
                 match receiver.recv().await {
                     Some(Data::AddPortForward((port, remote_host, remote_port))) => {
-                        if port <= 0 || remote_port <= 0 {
+                        if port <= 0 || remote_port < 0 {
                             continue;
                         }
                         let (sender, receiver) = mpsc::unbounded_channel::<Data>();
@@ -1992,10 +2131,10 @@ pub async fn io_loop<T: InvokeUiSession>(handler: Session<T>, round: u32) {
         } else {
             let port = handler.args[0].parse::<i32>().unwrap_or(0);
             if handler.args.len() != 3
-                || handler.args[2].parse::<i32>().unwrap_or(0) <= 0
+                || handler.args[2].parse::<i32>().map_or(true, |p| p < 0)
                 || port <= 0
             {
-                handler.on_error("Invalid arguments, usage:<br><br> rustdesk --port-forward remote-id listen-port remote-host remote-port");
+                handler.on_error("Invalid arguments, usage:<br><br> rustdesk --port-forward remote-id listen-port remote-host remote-port<br><br>remote-port 0 starts a SOCKS5 proxy");
             }
             let remote_host = handler.args[1].clone();
             let remote_port = handler.args[2].parse::<i32>().unwrap_or(0);
@@ -2027,6 +2166,13 @@ async fn start_one_port_forward<T: InvokeUiSession>(
     key: &str,
     token: &str,
 ) {
+    let opts = match crate::port_forward::ListenOptions::from_local_config() {
+        Ok(opts) => opts,
+        Err(err) => {
+            handler.on_error(&err.to_string());
+            return;
+        }
+    };
     if let Err(err) = crate::port_forward::listen(
         handler.get_id(),
         handler.password.clone(),
@@ -2038,6 +2184,7 @@ async fn start_one_port_forward<T: InvokeUiSession>(
         handler.lc.clone(),
         remote_host,
         remote_port,
+        opts,
     )
     .await
     {